@@ -2721,7 +2721,7 @@ rec {
           "usage" = [ "clap_builder/usage" ];
           "wrap_help" = [ "clap_builder/wrap_help" ];
         };
-        resolvedDefaultFeatures = [ "color" "default" "derive" "error-context" "help" "std" "string" "suggestions" "usage" ];
+        resolvedDefaultFeatures = [ "color" "default" "derive" "env" "error-context" "help" "std" "string" "suggestions" "usage" ];
       };
       "clap_builder" = rec {
         crateName = "clap_builder";
@@ -2760,7 +2760,7 @@ rec {
           "unstable-v5" = [ "deprecated" ];
           "wrap_help" = [ "help" "dep:terminal_size" ];
         };
-        resolvedDefaultFeatures = [ "color" "error-context" "help" "std" "string" "suggestions" "usage" ];
+        resolvedDefaultFeatures = [ "color" "env" "error-context" "help" "std" "string" "suggestions" "usage" ];
       };
       "clap_derive" = rec {
         crateName = "clap_derive";
@@ -2915,6 +2915,42 @@ rec {
         };
         resolvedDefaultFeatures = [ "default" "std" ];
       };
+      "console" = rec {
+        crateName = "console";
+        version = "0.16.6";
+        edition = "2021";
+        sha256 = "1qv4mgi3kyvasw15mj9lcn14skrqg6m4vbxmm0kc24scfxb4jsp9";
+        dependencies = [
+          {
+            name = "encode_unicode";
+            packageId = "encode_unicode";
+            target = { target, features }: (target."windows" or false);
+          }
+          {
+            name = "libc";
+            packageId = "libc";
+            optional = true;
+          }
+          {
+            name = "unicode-width";
+            packageId = "unicode-width";
+            optional = true;
+          }
+          {
+            name = "windows-sys";
+            packageId = "windows-sys 0.61.2";
+            target = { target, features }: (target."windows" or false);
+            features = [ "Win32_Foundation" "Win32_System_Console" "Win32_Storage_FileSystem" "Win32_UI_Input_KeyboardAndMouse" ];
+          }
+        ];
+        features = {
+          "default" = [ "unicode-width" "ansi-parsing" "std" ];
+          "std" = [ "dep:libc" "alloc" ];
+          "unicode-width" = [ "dep:unicode-width" ];
+          "windows-console-colors" = [ "ansi-parsing" ];
+        };
+        resolvedDefaultFeatures = [ "alloc" "ansi-parsing" "std" "unicode-width" ];
+      };
       "const-oid" = rec {
         crateName = "const-oid";
         version = "0.9.6";
@@ -4474,6 +4510,20 @@ rec {
         };
         resolvedDefaultFeatures = [ "default" "serde" "serde_support" ];
       };
+      "encode_unicode" = rec {
+        crateName = "encode_unicode";
+        version = "1.0.0";
+        edition = "2021";
+        sha256 = "1h5j7j7byi289by63s3w4a8b3g6l5ccdrws7a67nn07vdxj77ail";
+        authors = [
+          "Torbjørn Birch Moltu <t.b.moltu@lyse.net>"
+        ];
+        features = {
+          "ascii" = [ "dep:ascii" ];
+          "default" = [ "std" ];
+        };
+        resolvedDefaultFeatures = [ "default" "std" ];
+      };
       "encoding_rs" = rec {
         crateName = "encoding_rs";
         version = "0.8.35";
@@ -7748,6 +7798,52 @@ rec {
           "serde" = [ "dep:serde" ];
         };
       };
+      "indicatif" = rec {
+        crateName = "indicatif";
+        version = "0.18.6";
+        edition = "2021";
+        sha256 = "037vk2cr5b0iwri5023wjyww7d4gqpjcf8f0g6x1mv5lsrn80cwl";
+        dependencies = [
+          {
+            name = "console";
+            packageId = "console";
+            usesDefaultFeatures = false;
+            features = [ "ansi-parsing" "std" ];
+          }
+          {
+            name = "portable-atomic";
+            packageId = "portable-atomic";
+          }
+          {
+            name = "unicode-width";
+            packageId = "unicode-width";
+            optional = true;
+          }
+          {
+            name = "unit-prefix";
+            packageId = "unit-prefix";
+          }
+          {
+            name = "web-time";
+            packageId = "web-time";
+            optional = true;
+            target = { target, features }: ("wasm32" == target."arch" or null);
+          }
+        ];
+        features = {
+          "default" = [ "unicode-width" "wasmbind" ];
+          "futures" = [ "dep:futures-core" ];
+          "improved_unicode" = [ "unicode-segmentation" "unicode-width" ];
+          "in_memory" = [ "vt100" ];
+          "rayon" = [ "dep:rayon" ];
+          "tokio" = [ "dep:tokio" ];
+          "unicode-segmentation" = [ "dep:unicode-segmentation" ];
+          "unicode-width" = [ "dep:unicode-width" "console/unicode-width" ];
+          "vt100" = [ "dep:vt100" ];
+          "wasmbind" = [ "dep:web-time" ];
+        };
+        resolvedDefaultFeatures = [ "default" "unicode-width" "wasmbind" ];
+      };
       "inout" = rec {
         crateName = "inout";
         version = "0.1.4";
@@ -9127,7 +9223,7 @@ rec {
           "user" = [ "feature" ];
           "zerocopy" = [ "fs" "uio" ];
         };
-        resolvedDefaultFeatures = [ "default" "feature" "user" ];
+        resolvedDefaultFeatures = [ "default" "feature" "fs" "user" ];
       };
       "nom" = rec {
         crateName = "nom";
@@ -10713,7 +10809,7 @@ rec {
           "default" = [ "fallback" ];
           "serde" = [ "dep:serde" ];
         };
-        resolvedDefaultFeatures = [ "require-cas" ];
+        resolvedDefaultFeatures = [ "default" "fallback" "require-cas" ];
       };
       "portable-atomic-util" = rec {
         crateName = "portable-atomic-util";
@@ -16797,7 +16893,7 @@ rec {
           "uuid" = [ "dep:uuid" ];
           "validate-request" = [ "mime" ];
         };
-        resolvedDefaultFeatures = [ "default" "follow-redirect" "futures-util" "iri-string" "tower" "trace" "tracing" ];
+        resolvedDefaultFeatures = [ "default" "follow-redirect" "futures-util" "iri-string" "limit" "tower" "trace" "tracing" ];
       };
       "tower-layer" = rec {
         crateName = "tower-layer";
@@ -17503,6 +17599,21 @@ rec {
         };
         resolvedDefaultFeatures = [ "default" ];
       };
+      "unit-prefix" = rec {
+        crateName = "unit-prefix";
+        version = "0.5.2";
+        edition = "2018";
+        sha256 = "18xr6yhdvlxrv51y6js9npa3qhkzc5b1z4skr5kfzn7kkd449rc1";
+        libName = "unit_prefix";
+        authors = [
+          "Fabio Valentini <decathorpe@gmail.com>"
+          "Benjamin Sago <ogham@bsago.me>"
+        ];
+        features = {
+          "default" = [ "std" ];
+        };
+        resolvedDefaultFeatures = [ "default" "std" ];
+      };
       "universal-hash" = rec {
         crateName = "universal-hash";
         version = "0.5.1";
@@ -21751,10 +21862,14 @@ rec {
             name = "backon";
             packageId = "backon";
           }
+          {
+            name = "base64";
+            packageId = "base64 0.22.1";
+          }
           {
             name = "clap";
             packageId = "clap";
-            features = [ "derive" "string" ];
+            features = [ "derive" "string" "env" ];
           }
           {
             name = "colored";
@@ -21786,6 +21901,10 @@ rec {
             name = "httpsig-hyper";
             packageId = "httpsig-hyper";
           }
+          {
+            name = "indicatif";
+            packageId = "indicatif";
+          }
           {
             name = "inquire";
             packageId = "inquire";
@@ -21802,12 +21921,16 @@ rec {
           {
             name = "nix";
             packageId = "nix";
-            features = [ "user" ];
+            features = [ "fs" "user" ];
           }
           {
             name = "notify-rust";
             packageId = "notify-rust";
           }
+          {
+            name = "rand";
+            packageId = "rand 0.10.1";
+          }
           {
             name = "reqwest";
             packageId = "reqwest";
@@ -21862,6 +21985,16 @@ rec {
             packageId = "tokio";
             features = [ "full" ];
           }
+          {
+            name = "tokio-rustls";
+            packageId = "tokio-rustls";
+            usesDefaultFeatures = false;
+            features = [ "aws_lc_rs" "tls12" ];
+          }
+          {
+            name = "toml";
+            packageId = "toml 0.8.23";
+          }
           {
             name = "url";
             packageId = "url";
@@ -21896,6 +22029,17 @@ rec {
             packageId = "shadow-rs";
           }
         ];
+        devDependencies = [
+          {
+            name = "yeet-api";
+            packageId = "yeet-api";
+            rename = "api";
+            features = [ "hazard" ];
+          }
+        ];
+        features = {
+          "age-plugin" = [ "api/age-plugin" ];
+        };
 
       };
       "yeet-api" = rec {
@@ -22001,6 +22145,7 @@ rec {
           }
         ];
         features = {
+          "age-plugin" = [ "age/plugin" ];
           "hazard" = [ "dep:sqlx" ];
         };
         resolvedDefaultFeatures = [ "hazard" ];
@@ -22063,6 +22208,14 @@ rec {
             name = "futures";
             packageId = "futures";
           }
+          {
+            name = "hex";
+            packageId = "hex";
+          }
+          {
+            name = "hmac";
+            packageId = "hmac";
+          }
           {
             name = "httpsig-hyper";
             packageId = "httpsig-hyper";
@@ -22102,6 +22255,15 @@ rec {
             name = "rand";
             packageId = "rand 0.10.1";
           }
+          {
+            name = "regex";
+            packageId = "regex";
+          }
+          {
+            name = "reqwest";
+            packageId = "reqwest";
+            features = [ "json" "query" "multipart" ];
+          }
           {
             name = "serde";
             packageId = "serde";
@@ -22119,6 +22281,14 @@ rec {
             name = "splunk_hec";
             packageId = "splunk_hec";
           }
+          {
+            name = "sha2";
+            packageId = "sha2";
+          }
+          {
+            name = "socket2";
+            packageId = "socket2";
+          }
           {
             name = "sqlx";
             packageId = "sqlx";
@@ -22136,13 +22306,18 @@ rec {
           {
             name = "tower-http";
             packageId = "tower-http";
-            features = [ "trace" ];
+            features = [ "limit" "trace" ];
           }
           {
             name = "tracing-subscriber";
             packageId = "tracing-subscriber";
             features = [ "env-filter" ];
           }
+          {
+            name = "url";
+            packageId = "url";
+            features = [ "serde" ];
+          }
           {
             name = "uuid";
             packageId = "uuid";
@@ -22154,6 +22329,10 @@ rec {
             rename = "api";
             features = [ "hazard" ];
           }
+          {
+            name = "zstd";
+            packageId = "zstd";
+          }
         ];
         devDependencies = [
           {
@@ -22164,8 +22343,13 @@ rec {
             name = "paste";
             packageId = "paste";
           }
+          {
+            name = "tempfile";
+            packageId = "tempfile";
+          }
         ];
         features = {
+          "age-plugin" = [ "age/plugin" ];
           "axum-test" = [ "dep:axum-test" ];
         };
         resolvedDefaultFeatures = [ "axum-test" ];
@@ -23109,6 +23293,101 @@ rec {
           "no-panic" = [ "dep:no-panic" ];
         };
       };
+      "zstd" = rec {
+        crateName = "zstd";
+        version = "0.13.3";
+        edition = "2018";
+        sha256 = "12n0h4w9l526li7jl972rxpyf012jw3nwmji2qbjghv9ll8y67p9";
+        authors = [
+          "Alexandre Bury <alexandre.bury@gmail.com>"
+        ];
+        dependencies = [
+          {
+            name = "zstd-safe";
+            packageId = "zstd-safe";
+            usesDefaultFeatures = false;
+            features = [ "std" ];
+          }
+        ];
+        features = {
+          "arrays" = [ "zstd-safe/arrays" ];
+          "bindgen" = [ "zstd-safe/bindgen" ];
+          "debug" = [ "zstd-safe/debug" ];
+          "default" = [ "legacy" "arrays" "zdict_builder" ];
+          "experimental" = [ "zstd-safe/experimental" ];
+          "fat-lto" = [ "zstd-safe/fat-lto" ];
+          "legacy" = [ "zstd-safe/legacy" ];
+          "no_asm" = [ "zstd-safe/no_asm" ];
+          "pkg-config" = [ "zstd-safe/pkg-config" ];
+          "thin" = [ "zstd-safe/thin" ];
+          "thin-lto" = [ "zstd-safe/thin-lto" ];
+          "zdict_builder" = [ "zstd-safe/zdict_builder" ];
+          "zstdmt" = [ "zstd-safe/zstdmt" ];
+        };
+        resolvedDefaultFeatures = [ "arrays" "default" "legacy" "zdict_builder" ];
+      };
+      "zstd-safe" = rec {
+        crateName = "zstd-safe";
+        version = "7.3.0";
+        edition = "2018";
+        sha256 = "10kq3hik4yhm9n6ar9d02i3xm3llrnz402n8z7vdkfbdmd4hdn34";
+        libName = "zstd_safe";
+        authors = [
+          "Alexandre Bury <alexandre.bury@gmail.com>"
+        ];
+        dependencies = [
+          {
+            name = "zstd-sys";
+            packageId = "zstd-sys";
+            usesDefaultFeatures = false;
+          }
+        ];
+        features = {
+          "bindgen" = [ "zstd-sys/bindgen" ];
+          "debug" = [ "zstd-sys/debug" ];
+          "default" = [ "legacy" "arrays" "zdict_builder" ];
+          "experimental" = [ "zstd-sys/experimental" ];
+          "fat-lto" = [ "zstd-sys/fat-lto" ];
+          "legacy" = [ "zstd-sys/legacy" ];
+          "no_asm" = [ "zstd-sys/no_asm" ];
+          "pkg-config" = [ "zstd-sys/pkg-config" ];
+          "seekable" = [ "zstd-sys/seekable" ];
+          "std" = [ "zstd-sys/std" ];
+          "thin" = [ "zstd-sys/thin" ];
+          "thin-lto" = [ "zstd-sys/thin-lto" ];
+          "zdict_builder" = [ "zstd-sys/zdict_builder" ];
+          "zstdmt" = [ "zstd-sys/zstdmt" ];
+        };
+        resolvedDefaultFeatures = [ "arrays" "legacy" "std" "zdict_builder" ];
+      };
+      "zstd-sys" = rec {
+        crateName = "zstd-sys";
+        version = "2.1.1+zstd.1.5.7";
+        edition = "2018";
+        links = "zstd";
+        sha256 = "0y50xj2hmnbyzls0g8b6ja7bncxargzx0fz2069d1fzz5nprxv5f";
+        libName = "zstd_sys";
+        authors = [
+          "Alexandre Bury <alexandre.bury@gmail.com>"
+        ];
+        buildDependencies = [
+          {
+            name = "cc";
+            packageId = "cc";
+            features = [ "parallel" ];
+          }
+          {
+            name = "pkg-config";
+            packageId = "pkg-config";
+          }
+        ];
+        features = {
+          "bindgen" = [ "dep:bindgen" ];
+          "cmake" = [ "dep:cmake" ];
+          "default" = [ "legacy" "zdict_builder" ];
+        };
+        resolvedDefaultFeatures = [ "legacy" "std" "zdict_builder" ];
+      };
       "zvariant" = rec {
         crateName = "zvariant";
         version = "5.10.1";
@@ -24,8 +24,8 @@ in
     pkgs-unstable.cargo-tarpaulin
   ];
   env.DATABASE_URL = "sqlite:yeet.db";
-  env.YEET_CERT = "cert.pem";
-  env.YEET_CERT_KEY = "key.pem";
+  env.YEET_TLS_CERT = "cert.pem";
+  env.YEET_TLS_KEY = "key.pem";
   env.YEET_HOST = "0.0.0.0";
   # env.YEET_URL = "https://example.com";
   env.YEET_SPLUNK_URL = "http://localhost";
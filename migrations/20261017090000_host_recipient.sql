@@ -0,0 +1,5 @@
+-- age recipient the host enrolled with. Secrets are only ever encrypted for this recipient.
+-- Hosts enrolled before this column existed have none. The first recipient such a host fetches a
+-- secret with is pinned (trust on first use): until then whoever holds the host key picks it
+ALTER TABLE verification_attempts ADD COLUMN recipient TEXT;
+ALTER TABLE hosts ADD COLUMN recipient TEXT;
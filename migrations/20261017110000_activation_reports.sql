@@ -0,0 +1,10 @@
+-- Outcome of an activation as reported by the agent
+CREATE TABLE IF NOT EXISTS activation_reports
+(
+    id          INTEGER PRIMARY KEY NOT NULL,
+    host_id     INTEGER NOT NULL REFERENCES hosts(id)   ON DELETE CASCADE,
+    store_path  TEXT    NOT NULL,
+    success     INTEGER NOT NULL,
+    error       TEXT,
+    report_time TEXT    NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Hash chained log of admin mutations. `hash` covers `prev_hash` so rewriting an entry breaks every later one
+CREATE TABLE IF NOT EXISTS audit_log
+(
+    id          INTEGER PRIMARY KEY NOT NULL,
+    prev_hash   BLOB    NOT NULL,
+    hash        BLOB    NOT NULL UNIQUE,
+    actor       BLOB    NOT NULL,
+    action      TEXT    NOT NULL,
+    detail      TEXT    NOT NULL,
+    time        TEXT    NOT NULL
+);
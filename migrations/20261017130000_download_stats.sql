@@ -0,0 +1,10 @@
+-- Sizes of the store paths agents downloaded for an update
+CREATE TABLE IF NOT EXISTS download_stats
+(
+    id            INTEGER PRIMARY KEY NOT NULL,
+    host_id       INTEGER NOT NULL REFERENCES hosts(id)   ON DELETE CASCADE,
+    store_path    TEXT    NOT NULL,
+    nar_size      INTEGER NOT NULL,
+    closure_size  INTEGER NOT NULL,
+    report_time   TEXT    NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Whether hosts may detach themselves. A host specific permission can only grant detaching
+CREATE TABLE IF NOT EXISTS detach_settings
+(
+    id             INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    global_allowed INTEGER NOT NULL
+);
+-- Every host was allowed to detach so far
+INSERT INTO detach_settings (id, global_allowed) VALUES (1, 1);
+
+ALTER TABLE hosts ADD COLUMN detach_allowed INTEGER;
@@ -0,0 +1,2 @@
+-- The plaintext of compressed secrets was zstd compressed before the store key encrypted it
+ALTER TABLE secrets ADD COLUMN compressed INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Latest nixos-facter report of a host. Seeded on enrollment, replaced by `/system/facter`
+CREATE TABLE IF NOT EXISTS host_facter
+(
+    host_id     INTEGER PRIMARY KEY NOT NULL REFERENCES hosts(id) ON DELETE CASCADE,
+    facter      TEXT    NOT NULL,
+    report_time TEXT    NOT NULL
+);
@@ -0,0 +1,7 @@
+-- An alias is a secret without content of its own. Fetching it returns the content of `target_id`
+-- while the ACL and tags of the alias apply. Removing the target removes its aliases
+CREATE TABLE IF NOT EXISTS secret_aliases
+(
+    secret_id INTEGER PRIMARY KEY NOT NULL REFERENCES secrets(id) ON DELETE CASCADE,
+    target_id INTEGER NOT NULL REFERENCES secrets(id) ON DELETE CASCADE
+);
@@ -0,0 +1,14 @@
+-- Named sets of hosts e.g. to publish to all of them at once.
+-- Removing a host removes it from every group
+CREATE TABLE IF NOT EXISTS host_groups
+(
+    id      INTEGER PRIMARY KEY NOT NULL,
+    name    TEXT    NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS host_group_members
+(
+    group_id    INTEGER NOT NULL REFERENCES host_groups(id) ON DELETE CASCADE,
+    host_id     INTEGER NOT NULL REFERENCES hosts(id)       ON DELETE CASCADE,
+    PRIMARY KEY (group_id, host_id)
+);
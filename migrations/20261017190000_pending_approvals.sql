@@ -0,0 +1,9 @@
+-- Destructive actions waiting for a second admin under the two-person rule.
+-- `action` is the JSON of `api::ApprovalAction`. The same action is only pending once
+CREATE TABLE IF NOT EXISTS pending_approvals
+(
+    id          INTEGER PRIMARY KEY NOT NULL,
+    action      TEXT    NOT NULL UNIQUE,
+    proposer    INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    proposed    TEXT    NOT NULL
+);
@@ -0,0 +1,3 @@
+-- A sealed secret was encrypted for the recipient of `sealed_to` by the client. The server can not
+-- decrypt it and hands it out verbatim, only to that host
+ALTER TABLE secrets ADD COLUMN sealed_to INTEGER REFERENCES hosts(id) ON DELETE CASCADE;
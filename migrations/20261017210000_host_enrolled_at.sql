@@ -0,0 +1,6 @@
+-- When the host was added. Hosts that existed before get the earliest time the server knows of
+ALTER TABLE hosts ADD COLUMN enrolled_at TEXT;
+UPDATE hosts SET enrolled_at = COALESCE(
+    (SELECT MIN(update_time) FROM state_history WHERE host_id = hosts.id),
+    last_ping
+);
@@ -0,0 +1,2 @@
+-- Optional label like `pem`, `env` or `json`. The agent picks the file extension of the secret from it
+ALTER TABLE secrets ADD COLUMN content_type TEXT;
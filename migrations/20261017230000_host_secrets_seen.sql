@@ -0,0 +1,2 @@
+-- Comma separated ids of the secrets the host could fetch at its last check. `NULL` until the first check
+ALTER TABLE hosts ADD COLUMN secrets_seen TEXT;
@@ -0,0 +1,3 @@
+-- The version the agent held back at its last check. Both `NULL` unless the agent is soaking
+ALTER TABLE hosts ADD COLUMN soaking_store_path TEXT;
+ALTER TABLE hosts ADD COLUMN soaking_until TEXT;
@@ -40,7 +40,7 @@ let
           type = lib.types.str;
           default = "0";
           description = ''
-            User of the decrypted secret.
+            User of the decrypted secret. A username or a numeric uid.
           '';
         };
         group = lib.mkOption {
@@ -50,13 +50,21 @@ let
             users.''${config.owner}.group or "0"
           '';
           description = ''
-            Group of the decrypted secret.
+            Group of the decrypted secret. A group name or a numeric gid.
           '';
         };
         symlink = lib.mkEnableOption "symlinking secrets to their destination" // {
           readOnly = true; # currently only symlinking is supported (TODO)
           default = true;
         };
+        size = lib.mkOption {
+          type = lib.types.nullOr lib.types.ints.unsigned;
+          default = null;
+          description = ''
+            Expected size of the decrypted secret in bytes. The agent refuses to deploy a secret of
+            another size.
+          '';
+        };
       };
     }
   );
@@ -116,34 +124,133 @@ in
       description = "Seconds to wait between updates";
     };
 
+    jitter = lib.mkOption {
+      type = lib.types.ints.between 0 100;
+      default = 10;
+      description = "Randomly vary `sleep` by up to this percentage so hosts do not check in at the same time";
+    };
+
     facter = lib.mkOption {
       type = lib.types.bool;
       default = false;
       description = "Collect information about the system with `nixos-facter`";
     };
 
+    facterInterval = lib.mkOption {
+      type = lib.types.nullOr lib.types.ints.positive;
+      default = null;
+      example = 86400;
+      description = "Seconds between re-submitting the `nixos-facter` output to the server. Requires `facter`";
+    };
+
     key = lib.mkOption {
       type = lib.types.str;
       default = "/etc/ssh/ssh_host_ed25519_key";
       description = "ED25519 key used as the hosts identity";
     };
 
+    notifications = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [ "desktop" ];
+      example = [
+        "systemd"
+        "https://hooks.example.com/yeet"
+      ];
+      description = "Where activations are reported: `systemd`, `desktop` or the url of a webhook";
+    };
+
+    gcAfterUpdate = lib.mkOption {
+      type = lib.types.nullOr (lib.types.strMatching "[0-9]+d");
+      default = null;
+      example = "7d";
+      description = "Delete generations older than this and collect garbage after every successful update";
+    };
+
+    soakMinutes = lib.mkOption {
+      type = lib.types.nullOr lib.types.ints.positive;
+      default = null;
+      example = 60;
+      description = "Activate a new version only after the server requested it for this many minutes";
+    };
+
+    serverRecipient = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "age1...";
+      description = "Age recipient of the server store key. The agent refuses to fetch secrets from a server with a different one";
+    };
+
+    varlinkTcp = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "[::]:4338";
+      description = "Also serve the read-only varlink methods `Status` and `Config` on this TCP address over mutual TLS. Requires `varlinkTcpCert`, `varlinkTcpKey` and `varlinkTcpClientCa`";
+    };
+
+    varlinkTcpCert = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "/run/secrets/yeet-varlink.pem";
+      description = "PEM certificate chain the agent presents on `varlinkTcp`";
+    };
+
+    varlinkTcpKey = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "/run/secrets/yeet-varlink.key";
+      description = "PEM private key of `varlinkTcpCert`";
+    };
+
+    varlinkTcpClientCa = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "/etc/yeet/varlink-clients.pem";
+      description = "PEM CA certificates. Only clients with a certificate signed by one of them can connect to `varlinkTcp`";
+    };
+
+    waitForTimeSync = lib.mkOption {
+      type = lib.types.bool;
+      default = false;
+      description = "Start the agent only after the clock was synchronized. Hosts without a working RTC otherwise sign requests with a wrong time until NTP catches up";
+    };
+
+    activationMethod = lib.mkOption {
+      type = lib.types.str;
+      default = "switch-to-configuration";
+      example = "nixos-rebuild";
+      description = "How new systems are activated: `switch-to-configuration`, `nixos-rebuild` or the absolute path of an executable that gets the store path as its only argument";
+    };
+
     package = lib.mkPackageOption pkgs "yeet" { };
   };
 
   config = lib.mkIf cfg.enable {
+    assertions = [
+      {
+        assertion = lib.all (option: (cfg.varlinkTcp == null) == (option == null)) [
+          cfg.varlinkTcpCert
+          cfg.varlinkTcpKey
+          cfg.varlinkTcpClientCa
+        ];
+        message = "services.yeet.varlinkTcp, varlinkTcpCert, varlinkTcpKey and varlinkTcpClientCa have to be set together";
+      }
+    ];
+
     users.groups = {
       yeet = { };
     };
 
     systemd.services.yeet = {
       description = "Yeet Deploy Agent";
-      wants = [ "network-online.target" ];
-      after = [ "network-online.target" ];
+      wants = [ "network-online.target" ] ++ lib.optional cfg.waitForTimeSync "time-sync.target";
+      after = [ "network-online.target" ] ++ lib.optional cfg.waitForTimeSync "time-sync.target";
       path = [ config.nix.package ];
       wantedBy = [ "multi-user.target" ];
 
-      environment.USER = "root";
+      environment = {
+        USER = "root";
+        YEET_ACTIVATION_METHOD = cfg.activationMethod;
+      };
 
       # don't stop the service if the unit disappears
       unitConfig.X-StopOnRemoval = false;
@@ -154,8 +261,18 @@ in
         Restart = "always";
         RestartSec = 5;
         RuntimeDirectory = "yeet";
+        NotifyAccess = "main";
         ExecStart = ''
-          ${lib.getExe cfg.package} agent --sleep ${toString cfg.sleep} --server ${cfg.server} --key ${cfg.key} ${lib.optionalString cfg.facter "--facter"}
+          ${lib.getExe cfg.package} agent --sleep ${toString cfg.sleep} --jitter ${toString cfg.jitter} --server ${cfg.server} --key ${cfg.key} ${lib.optionalString cfg.facter "--facter"} ${
+            lib.optionalString (cfg.facterInterval != null) "--facter-interval ${toString cfg.facterInterval}"
+          } ${
+            lib.concatMapStringsSep " " (backend: "--notify ${lib.escapeShellArg backend}") cfg.notifications
+          } ${lib.optionalString (cfg.gcAfterUpdate != null) "--gc-after-update ${cfg.gcAfterUpdate}"} ${
+            lib.optionalString (cfg.soakMinutes != null) "--soak-minutes ${toString cfg.soakMinutes}"
+          } ${lib.optionalString (cfg.serverRecipient != null) "--server-recipient ${lib.escapeShellArg cfg.serverRecipient}"} ${
+            lib.optionalString (cfg.varlinkTcp != null)
+              "--varlink-tcp ${lib.escapeShellArg cfg.varlinkTcp} --varlink-tcp-cert ${lib.escapeShellArg cfg.varlinkTcpCert} --varlink-tcp-key ${lib.escapeShellArg cfg.varlinkTcpKey} --varlink-tcp-client-ca ${lib.escapeShellArg cfg.varlinkTcpClientCa}"
+          }
         '';
       };
     };
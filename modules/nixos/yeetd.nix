@@ -22,7 +22,7 @@ in
     host = lib.mkOption {
       type = lib.types.str;
       default = "localhost";
-      description = "The listen host for HTTP API";
+      description = "The listen address for HTTP API. Use a comma separated list like `::,0.0.0.0` to listen on multiple addresses";
     };
 
     user = mkOption {
@@ -33,6 +33,12 @@ in
       '';
     };
 
+    dataDir = lib.mkOption {
+      type = lib.types.str;
+      default = "/var/lib/yeet";
+      description = "Directory for `yeet.db`, `age.key` and a relative `stateLocation`. Created with mode 0700 if missing";
+    };
+
     stateLocation = lib.mkOption {
       type = lib.types.str;
       default = "/var/lib/yeetd/state.json";
@@ -53,6 +59,62 @@ in
       description = "Whether to open the immich port in the firewall";
     };
 
+    compressSecrets = lib.mkOption {
+      type = lib.types.bool;
+      default = false;
+      description = "Store new and rotated secrets zstd compressed when that makes them smaller";
+    };
+
+    storeRecipients = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [ ];
+      example = [ "age1..." ];
+      description = "Store key recipients of other server replicas. New and rotated secrets are encrypted for them as well";
+    };
+
+    storeIdentities = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [ ];
+      example = [ "/run/secrets/yeetd-old-store.key" ];
+      description = "Paths of retired age identity files the server still decrypts secrets with e.g. a previous store key. New secrets are not encrypted for them";
+    };
+
+    hostnamePattern = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "[a-z0-9_-]+";
+      description = "Regex hostnames have to match instead of the DNS rules. Path separators and whitespace are always rejected";
+    };
+
+    twoPersonRule = lib.mkOption {
+      type = lib.types.bool;
+      default = false;
+      description = "Removing a host or deleting a secret only proposes it. A second admin has to confirm with `/approval/confirm`";
+    };
+
+    secretPeek = lib.mkOption {
+      type = lib.types.bool;
+      default = false;
+      description = "Allow admins with `all_tag` to read the plaintext a host would receive with `yeet secret peek`. Every use is audited";
+    };
+
+    webhooksFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "/run/secrets/yeetd-webhooks.json";
+      description = ''
+        JSON list of webhooks like `[{ "url": "https://...", "events": [ "HostFailed" ], "secret": "..." }]`.
+        Events are `HostActivated`, `HostFailed`, `HostStale` and `SecretUpdated`.
+        Keep it out of the nix store when it contains secrets
+      '';
+    };
+
+    webhookTimeout = lib.mkOption {
+      type = lib.types.ints.positive;
+      default = 5000;
+      description = "Milliseconds after which a webhook delivery is cancelled";
+    };
+
     group = mkOption {
       type = types.str;
       default = "yeet";
@@ -75,8 +137,21 @@ in
 
       environment.YEET_PORT = "${toString cfg.port}";
       environment.YEET_HOST = "${cfg.host}";
+      environment.YEET_DATA_DIR = "${cfg.dataDir}";
       environment.YEET_STATE = "${cfg.stateLocation}";
       environment.YEET_INIT_KEY = "${toString cfg.initKey}";
+      environment.YEET_COMPRESS_SECRETS = lib.boolToString cfg.compressSecrets;
+      environment.YEET_TWO_PERSON_RULE = lib.boolToString cfg.twoPersonRule;
+      environment.YEET_SECRET_PEEK = lib.boolToString cfg.secretPeek;
+      environment.YEET_STORE_RECIPIENTS = lib.mkIf (cfg.storeRecipients != [ ]) (
+        lib.concatStringsSep "," cfg.storeRecipients
+      );
+      environment.YEET_HOSTNAME_PATTERN = lib.mkIf (cfg.hostnamePattern != null) cfg.hostnamePattern;
+      environment.YEET_WEBHOOKS = lib.mkIf (cfg.webhooksFile != null) cfg.webhooksFile;
+      environment.YEET_WEBHOOK_TIMEOUT_MS = toString cfg.webhookTimeout;
+      environment.YEET_STORE_IDENTITIES = lib.mkIf (cfg.storeIdentities != [ ]) (
+        lib.concatStringsSep "," cfg.storeIdentities
+      );
 
       serviceConfig = {
         StateDirectoryMode = "0700";
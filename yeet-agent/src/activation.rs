@@ -0,0 +1,117 @@
+//! How a new system gets activated. Configurable with `yeet agent --activation-method`
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActivationMethod {
+    /// `bin/switch-to-configuration switch` of the new system or `activate` on darwin
+    #[default]
+    SwitchToConfiguration,
+    /// `nixos-rebuild switch --store-path`. Sets the system profile itself
+    NixosRebuild,
+    /// Runs the executable with the store path as the only argument
+    Custom(PathBuf),
+}
+
+impl ActivationMethod {
+    pub fn command(&self, store_path: &api::StorePath) -> Command {
+        match self {
+            Self::SwitchToConfiguration if cfg!(target_os = "macos") => {
+                Command::new(Path::new(store_path).join("activate"))
+            }
+            Self::SwitchToConfiguration => {
+                let mut command =
+                    Command::new(Path::new(store_path).join("bin/switch-to-configuration"));
+                command.arg("switch");
+                command
+            }
+            Self::NixosRebuild => {
+                let mut command = Command::new("nixos-rebuild");
+                command.args(["switch", "--store-path", store_path]);
+                command
+            }
+            Self::Custom(executable) => {
+                let mut command = Command::new(executable);
+                command.arg(store_path);
+                command
+            }
+        }
+    }
+
+    /// Whether the agent has to point the system profile to the new system before activating
+    pub fn needs_system_profile(&self) -> bool {
+        match self {
+            Self::SwitchToConfiguration | Self::Custom(_) => true,
+            Self::NixosRebuild => false,
+        }
+    }
+}
+
+impl FromStr for ActivationMethod {
+    type Err = String;
+
+    /// `switch-to-configuration`, `nixos-rebuild` or the absolute path of an executable
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        match method {
+            "switch-to-configuration" => Ok(Self::SwitchToConfiguration),
+            "nixos-rebuild" => Ok(Self::NixosRebuild),
+            path if Path::new(path).is_absolute() => Ok(Self::Custom(PathBuf::from(path))),
+            other => Err(format!(
+                "{other} is neither `switch-to-configuration`, `nixos-rebuild` nor an absolute path"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_activation {
+    use std::{ffi::OsStr, path::PathBuf};
+
+    use super::ActivationMethod;
+
+    #[test]
+    fn parse_method() {
+        assert_eq!(
+            "switch-to-configuration".parse::<ActivationMethod>(),
+            Ok(ActivationMethod::SwitchToConfiguration)
+        );
+        assert_eq!(
+            "nixos-rebuild".parse::<ActivationMethod>(),
+            Ok(ActivationMethod::NixosRebuild)
+        );
+        assert_eq!(
+            "/etc/yeet/activate".parse::<ActivationMethod>(),
+            Ok(ActivationMethod::Custom(PathBuf::from(
+                "/etc/yeet/activate"
+            )))
+        );
+        "activate".parse::<ActivationMethod>().unwrap_err();
+    }
+
+    #[test]
+    fn commands() {
+        let store_path = "/nix/store/abc-nixos-system".to_owned();
+
+        let command = ActivationMethod::NixosRebuild.command(&store_path);
+        assert_eq!(command.get_program(), "nixos-rebuild");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["switch", "--store-path", "/nix/store/abc-nixos-system"]
+        );
+
+        let command =
+            ActivationMethod::Custom(PathBuf::from("/etc/yeet/activate")).command(&store_path);
+        assert_eq!(command.get_program(), "/etc/yeet/activate");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [OsStr::new("/nix/store/abc-nixos-system")]
+        );
+    }
+}
@@ -1,30 +1,73 @@
 use std::{
-    ffi::OsStr,
+    collections::HashSet,
+    ffi::{OsStr, OsString},
     fs::{
         self, File, Permissions, read_dir, read_link, read_to_string, remove_dir_all, remove_file,
     },
-    io::{self, BufRead as _, BufReader, Write as _},
-    os::unix::fs::{PermissionsExt as _, chown, symlink},
+    io::{self, Write as _},
+    os::unix::fs::{MetadataExt as _, PermissionsExt as _, chown, symlink},
     path::{Path, PathBuf},
     process::Command,
-    sync::OnceLock,
-    time::Duration,
+    sync::{Mutex, OnceLock, RwLock},
+    time::{Duration, Instant},
 };
 
 use api::{get_secret_key, get_verify_key};
-use backon::{ConstantBuilder, Retryable as _};
+use backon::{ExponentialBuilder, Retryable as _};
 use ed25519_dalek::VerifyingKey;
 use httpsig_hyper::prelude::SecretKey;
-use log::{error, info};
+use log::{debug, error, info};
 use rootcause::{Report, bail, prelude::ResultExt as _, report};
 use tempfile::NamedTempFile;
 use tokio::time;
-use url::Url;
 use yeet::nix;
 
-use crate::{cli_args::AgentConfig, notification, varlink, version::get_active_version};
+use crate::{
+    cli,
+    cli_args::AgentConfig,
+    failure, notification, section, varlink,
+    version::{self, get_active_version},
+};
 
-static VERIFICATION_CODE: OnceLock<u32> = OnceLock::new();
+/// Code of the current verification attempt. Gets replaced once the server
+/// no longer knows about the attempt (e.g. because it expired)
+static VERIFICATION_CODE: Mutex<Option<u32>> = Mutex::new(None);
+/// Facts are only collected once and then reused for every verification attempt
+static NIXOS_FACTER: OnceLock<Option<String>> = OnceLock::new();
+/// Read once instead of on every download. `invalidate_trusted_public_keys` forces a re-read
+static TRUSTED_PUBLIC_KEYS: KeyCache = KeyCache::new();
+/// Holds the `trusted-public-keys` the downloads are verified with
+const NIX_CONF: &str = "/etc/nix/nix.conf";
+/// Trusted if `nix.conf` has no `trusted-public-keys`
+const NIXOS_CACHE_KEY: &str = "cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=";
+/// Guards against include cycles in `nix.conf`
+const MAX_NIX_CONF_INCLUDES: u8 = 8;
+/// The age identity the agent enrolls with, relative to `AgentConfig::secret_base`.
+/// The server only encrypts secrets for its recipient.
+/// This is always a x25519 identity. The `age-plugin` feature only covers the server store key,
+/// hosts can not enroll with a plugin identity (e.g. `age-plugin-yubikey`)
+const AGE_IDENTITY: &str = "age.key";
+/// Every secret generation is a directory in here, relative to `AgentConfig::secret_base`
+const SECRET_GENERATIONS: &str = "secret.d";
+/// Links to the active generation, relative to `AgentConfig::secret_base`
+const SECRET_LINK: &str = "secret";
+/// Written once the server detached this host, relative to `AgentConfig::secret_base`.
+/// The agent does not poll the server while it exists
+const DETACHED_MARKER: &str = "DETACHED";
+/// Holds the store path that is being activated, relative to `AgentConfig::secret_base`.
+/// Only left behind if the agent was stopped during the activation
+const LAST_ATTEMPTED: &str = "last-attempted";
+/// Room for file system metadata on top of the content of the secrets
+const GENERATION_OVERHEAD: u64 = 64 * 1024;
+/// Retries after failures double up to this delay
+const MAX_RETRY_DELAY: Duration = Duration::from_mins(5);
+/// Percentage by which retries are randomly varied so that agents do not retry in lockstep
+const RETRY_JITTER: u8 = 20;
+/// The profile `activate` points to the new system unless home-manager is activated
+const SYSTEM_PROFILE: &str = "/nix/var/nix/profiles/system";
+/// The last action that was executed successfully, relative to `AgentConfig::secret_base`.
+/// Replayed if the server is unreachable on startup
+const LAST_ACTION: &str = "last-action.json";
 
 /// When running the agent should do these things in order:
 /// 1. Check if agent is active aka if the key is enrolled with `/system/verify`
@@ -33,51 +76,334 @@ static VERIFICATION_CODE: OnceLock<u32> = OnceLock::new();
 ///    pull the verify endpoint in a time intervall
 /// 2. Continuosly pull the system endpoint and execute based on the provided
 pub async fn agent(config: &AgentConfig, sleep: u64, facter: bool) -> Result<(), Report> {
-    let key = get_secret_key(&config.key)?;
-    let pub_key = get_verify_key(&config.key)?;
+    let (server, key, pub_key, identity) = startup(config).await?;
+
+    // the agent loop reports what it holds back, the varlink status passes it on
+    let (soak_status, soaking) = tokio::sync::watch::channel(None);
 
     log::info!("Spawning varlink daemon");
     {
         let config = config.clone();
+        let server = server.clone();
         let key = key.clone();
         tokio::task::spawn_local(async move {
-            if let Err(err) = varlink::start_service(config, key).await {
+            if let Err(err) = varlink::start_service(config, server, key, soaking).await {
                 log::error!("Varlink failure:\n{err}");
             }
         })
     };
 
-    (|| async { agent_loop(config, &key, pub_key, sleep, facter).await })
-        .retry(
-            ConstantBuilder::new()
-                .without_max_times()
-                .with_delay(Duration::from_secs(sleep)),
-        )
+    let detached = detached_marker(&config.secret_base);
+    loop {
+        wait_while_detached(&detached, sleep).await;
+
+        let looped = (|| async {
+            agent_loop(
+                config,
+                &server,
+                &key,
+                &identity,
+                pub_key,
+                sleep,
+                facter,
+                &soak_status,
+            )
+            .await
+        })
+        .retry(retry_backoff(sleep))
+        .when(|err| {
+            !SecretFetchError::is_no_access(err) && !SecretFetchError::is_server_mismatch(err)
+        })
+        .adjust(|_, dur| dur.map(|dur| jittered(dur, RETRY_JITTER, &mut rand::rng())))
         .notify(|err: &Report, dur: Duration| {
-            error!("{err} - retrying in {dur:?}");
+            let now = jiff::Timestamp::now();
+            let next = now
+                .checked_add(dur)
+                .map_or_else(|_| "later".to_owned(), |next| next.to_string());
+            error!("{err} - retrying in {dur:?} at {next}");
+            if let Some(skew) = failure::clock_skew(err, now) {
+                error!("{}", failure::clock_hint(skew));
+            }
         })
-        .await?;
+        .await;
+        // a misconfigured ACL will not fix itself. Check back rarely until an admin grants access
+        match looped {
+            Ok(()) => {}
+            Err(err) if SecretFetchError::is_no_access(&err) => {
+                error!("{err} - grant access to the secret. Checking again in {MAX_RETRY_DELAY:?}");
+                time::sleep(MAX_RETRY_DELAY).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Everything before the first poll. Nothing is written outside of `AgentConfig::secret_base`,
+/// so an agent without root only needs its own base
+async fn startup(
+    config: &AgentConfig,
+) -> Result<(api::Server, SecretKey, VerifyingKey, age::x25519::Identity), Report> {
+    let server = api::Server::new(config.server.clone(), config.timeouts())?;
+    let key = get_secret_key(&config.key)?;
+    let pub_key = get_verify_key(&config.key)?;
+    let identity = age_identity(&config.secret_base.join(AGE_IDENTITY))?;
+
+    if !api::is_healthy(&server).await {
+        info!("Server is not reachable. Restoring the last known action");
+        if let Err(err) = restore_last_action(&config.secret_base.join(LAST_ACTION), config).await {
+            error!("Could not restore the last known action: {err}");
+        }
+    }
+
+    match api::server_info(&server).await {
+        Ok(server) => info!(
+            "Connected to yeetd {} supporting {}",
+            server.version,
+            server.features.join(", ")
+        ),
+        Err(err) => debug!("Could not get the server info. The server might predate it: {err}"),
+    }
 
+    if let Err(err) = report_interrupted_activation(config, &server, &key).await {
+        error!("Could not report the interrupted activation: {err}");
+    }
+
+    Ok((server, key, pub_key, identity))
+}
+
+pub fn detached_marker(secret_base: &Path) -> PathBuf {
+    secret_base.join(DETACHED_MARKER)
+}
+
+fn write_detached(secret_base: &Path) -> Result<(), Report> {
+    let marker = detached_marker(secret_base);
+    fs::create_dir_all(secret_base)?;
+    File::create(&marker).attach(marker.display().to_string())?;
+    info!("Detached by the server");
     Ok(())
 }
 
+/// Removes the detached marker so the agent polls the server again.
+/// Returns false if the agent was not detached
+pub fn reset_detached(secret_base: &Path) -> Result<bool, Report> {
+    let marker = detached_marker(secret_base);
+    match remove_file(&marker) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(report!(err)
+            .attach(marker.display().to_string())
+            .into_dynamic()),
+    }
+}
+
+/// Only checks the local marker. The server is not contacted until it is removed
+async fn wait_while_detached(marker: &Path, sleep: u64) {
+    if !marker.exists() {
+        return;
+    }
+    info!(
+        "Detached by the server. Run `yeet agent reset` or `yeet attach` to poll the server again"
+    );
+    while marker.exists() {
+        time::sleep(Duration::from_secs(sleep)).await;
+    }
+    info!("Detached marker removed. Polling the server again");
+}
+
+#[expect(
+    clippy::too_many_arguments,
+    reason = "everything the loop needs from `agent`"
+)]
 async fn agent_loop(
     config: &AgentConfig,
+    server: &api::Server,
     key: &SecretKey,
+    identity: &age::x25519::Identity,
     pub_key: VerifyingKey,
     sleep: u64,
     facter: bool,
+    soak_status: &tokio::sync::watch::Sender<Option<api::Soak>>,
 ) -> Result<(), Report> {
-    let verified = api::is_host_verified(&config.server, key) //TODO unwrap
+    let verified = api::is_host_verified(server, key) //TODO unwrap
         .await?
         .is_success();
 
     if !verified {
-        if let Some(code) = VERIFICATION_CODE.get() {
-            bail!("Verification requested but not yet approved. Code: {code}");
+        request_verification(server, key, identity, pub_key, facter).await?;
+    }
+    info!("Verified!");
+
+    let mut last_facter: Option<time::Instant> = None;
+    let mut soak: Option<Soak> = None;
+    loop {
+        if let Some(interval) = config.facter_interval
+            && last_facter.is_none_or(|last| last.elapsed() >= Duration::from_secs(interval))
+        {
+            report_facter(server, key).await;
+            last_facter = Some(time::Instant::now());
+        }
+
+        let soaking = soak.as_ref().and_then(|soak| {
+            soak.status(config.soak_minutes, Instant::now(), jiff::Timestamp::now())
+        });
+        soak_status.send_replace(soaking.clone());
+        let api::SystemCheck {
+            action,
+            poll_after,
+            secrets_updated,
+        } = api::check_system_polled(
+            server,
+            key,
+            version::version_request(config.home_manager_activation, soaking)?,
+        )
+        .await?;
+
+        info!("{action:#?}");
+
+        if !hold_back(&mut soak, &action, config.soak_minutes, Instant::now()) {
+            agent_action(action.clone(), config, server, key, identity).await?;
+            match action {
+                api::AgentAction::Nothing | api::AgentAction::WaitForNextVersion { .. } => {
+                    // a switch fetches the secrets anyway
+                    if secrets_updated {
+                        refresh_secrets(config, server, key, identity).await;
+                    }
+                }
+                api::AgentAction::Detach | api::AgentAction::SwitchTo(_) => {
+                    if let Err(err) =
+                        write_last_action(&config.secret_base.join(LAST_ACTION), &action)
+                    {
+                        error!("Could not cache the last action: {err}");
+                    }
+                }
+            }
+            // stop polling. `agent` waits until the marker is removed
+            if action == api::AgentAction::Detach {
+                return Ok(());
+            }
+        }
+        let pause = poll_pause(&action, poll_after, sleep, config.jitter, &mut rand::rng());
+        time::sleep(pause).await;
+    }
+}
+
+/// A version the server switched to that is held back until `AgentConfig::soak_minutes` passed
+struct Soak {
+    store_path: api::StorePath,
+    since: Instant,
+}
+
+impl Soak {
+    /// Zero once `soak_minutes` passed since the server switched to `store_path`
+    fn remaining(&self, soak_minutes: u64, now: Instant) -> Duration {
+        Duration::from_mins(soak_minutes).saturating_sub(now.saturating_duration_since(self.since))
+    }
+
+    /// Reported with every check while the version is still held back
+    fn status(
+        &self,
+        soak_minutes: Option<u64>,
+        now: Instant,
+        wall_clock: jiff::Timestamp,
+    ) -> Option<api::Soak> {
+        let remaining = self.remaining(soak_minutes?, now);
+        if remaining.is_zero() {
+            return None;
+        }
+        Some(api::Soak {
+            store_path: self.store_path.clone(),
+            until: wall_clock.checked_add(remaining).ok()?,
+        })
+    }
+}
+
+/// Whether the agent keeps polling without executing `action`. The soak restarts whenever the
+/// server switches to a different store path and ends once the server stops switching
+fn hold_back(
+    soak: &mut Option<Soak>,
+    action: &api::AgentAction,
+    soak_minutes: Option<u64>,
+    now: Instant,
+) -> bool {
+    let (api::AgentAction::SwitchTo(version), Some(minutes)) = (action, soak_minutes) else {
+        *soak = None;
+        return false;
+    };
+    let soak = match soak {
+        Some(soak) if soak.store_path == version.store_path => soak,
+        Some(_) | None => soak.insert(Soak {
+            store_path: version.store_path.clone(),
+            since: now,
+        }),
+    };
+    let remaining = soak.remaining(minutes, now);
+    if remaining.is_zero() {
+        return false;
+    }
+    info!(
+        "Soaking {}. Activating in {}s",
+        version.store_path,
+        remaining.as_secs()
+    );
+    true
+}
+
+/// `sleep` seconds varied randomly by up to `jitter` percent in both directions
+fn jittered_sleep<R: rand::RngExt + ?Sized>(sleep: u64, jitter: u8, rng: &mut R) -> Duration {
+    jittered(Duration::from_secs(sleep), jitter, rng)
+}
+
+/// The server's suggestion is followed exactly so the agent checks in right when the next
+/// version is due. Otherwise the pause from `api::POLL_AFTER_HEADER` or the jittered `sleep`
+fn poll_pause<R: rand::RngExt + ?Sized>(
+    action: &api::AgentAction,
+    poll_after: Option<Duration>,
+    sleep: u64,
+    jitter: u8,
+    rng: &mut R,
+) -> Duration {
+    match *action {
+        api::AgentAction::WaitForNextVersion {
+            suggest_poll_in_secs,
+        } => Duration::from_secs(suggest_poll_in_secs),
+        api::AgentAction::Nothing | api::AgentAction::Detach | api::AgentAction::SwitchTo(_) => {
+            poll_after.unwrap_or_else(|| jittered_sleep(sleep, jitter, rng))
         }
+    }
+}
+
+/// `delay` varied randomly by up to `jitter` percent in both directions
+fn jittered<R: rand::RngExt + ?Sized>(delay: Duration, jitter: u8, rng: &mut R) -> Duration {
+    let delay = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX);
+    let band = delay
+        .saturating_mul(u64::from(jitter.min(100)))
+        .div_euclid(100);
+    let offset = rng.random_range(0..=band.saturating_mul(2));
+    Duration::from_millis(delay.saturating_sub(band).saturating_add(offset))
+}
+
+/// Starts retrying after `sleep` seconds and doubles the delay up to `MAX_RETRY_DELAY`
+fn retry_backoff(sleep: u64) -> ExponentialBuilder {
+    ExponentialBuilder::new()
+        .with_factor(2.0)
+        .with_min_delay(Duration::from_secs(sleep))
+        .with_max_delay(MAX_RETRY_DELAY)
+        .without_max_times()
+}
 
-        let nixos_facter = if facter {
+/// Creates a new verification attempt unless the server still has a pending one for our key.
+/// This always returns an error because the agent has to wait for the approval
+async fn request_verification(
+    server: &api::Server,
+    key: &SecretKey,
+    identity: &age::x25519::Identity,
+    pub_key: VerifyingKey,
+    facter: bool,
+) -> Result<(), Report> {
+    let nixos_facter = if let Some(facts) = NIXOS_FACTER.get() {
+        facts.clone()
+    } else {
+        let facts = if facter {
             info!("Collecting nixos-facter information");
             let facts = Some(nix::facter()?);
             info!("Done collecting facts");
@@ -85,92 +411,546 @@ async fn agent_loop(
         } else {
             None
         };
+        NIXOS_FACTER.get_or_init(|| facts).clone()
+    };
 
-        let code = api::add_verification_attempt(
-            &config.server,
-            key,
-            api::VerificationAttempt {
-                key: pub_key,
-                nixos_facter,
-            },
-        )
-        .await?;
-        let _code = VERIFICATION_CODE.set(code as u32); // Bad so sad if we can't set it
-        info!("Your verification code is: {code}");
-        bail!("Waiting for verification");
+    let attempt = api::add_verification_attempt(
+        server,
+        key,
+        api::VerificationAttempt {
+            key: pub_key,
+            nixos_facter,
+            recipient: Some(identity.to_public().to_string()),
+        },
+    )
+    .await;
+
+    match attempt {
+        Ok(code) => {
+            if set_verification_code(code as u32).is_some() {
+                info!("Previous verification attempt expired");
+            }
+            info!("Your verification code is: {code}");
+            bail!("Waiting for verification");
+        }
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::CONFLICT,
+            ..
+        }) => match verification_code() {
+            Some(code) => bail!("Verification requested but not yet approved. Code: {code}"),
+            None => bail!(
+                "A verification attempt with an unknown code is pending. Waiting for it to expire"
+            ),
+        },
+        Err(err) => Err(err.into()),
     }
-    info!("Verified!");
+}
 
-    loop {
-        let action = api::check_system(
-            &config.server,
-            key,
-            api::VersionRequest {
-                store_path: get_active_version()?,
-            },
-        )
-        .await?;
+/// Reads the age identity at `path` or creates a new one if it does not exist yet
+fn age_identity(path: &Path) -> Result<age::x25519::Identity, Report> {
+    if path.exists() {
+        return read_to_string(path)?
+            .trim()
+            .parse()
+            .map_err(|err: &str| report!("{err}").attach(path.display().to_string()));
+    }
 
-        info!("{action:#?}");
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    cli::key::write_age_identity(path, false)
+}
 
-        agent_action(action, &config.server, key).await?;
-        time::sleep(Duration::from_secs(sleep)).await;
+fn write_last_action(path: &Path, action: &api::AgentAction) -> Result<(), Report> {
+    fs::write(path, serde_json::to_vec(action)?).attach(path.display().to_string())?;
+    Ok(())
+}
+
+fn read_last_action(path: &Path) -> Result<Option<api::AgentAction>, Report> {
+    match read_to_string(path) {
+        Ok(action) => Ok(Some(serde_json::from_str(&action)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Reports a failure for an activation the agent was stopped in e.g. by the OOM killer.
+/// The marker stays until the server got the report
+async fn report_interrupted_activation(
+    config: &AgentConfig,
+    server: &api::Server,
+    key: &SecretKey,
+) -> Result<(), Report> {
+    let path = config.secret_base.join(LAST_ATTEMPTED);
+    let last_attempted = match read_to_string(&path) {
+        Ok(store_path) => store_path.trim().to_owned(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(report!(err)
+                .attach(path.display().to_string())
+                .into_dynamic());
+        }
+    };
+    let active = get_active_version(config.home_manager_activation)?;
+    if let Some(report) = interrupted_activation(last_attempted, &active) {
+        log::warn!("The activation of {} was interrupted", report.store_path);
+        api::report_activation(server, key, report).await?;
+    }
+    remove_file(&path).attach(path.display().to_string())?;
+    Ok(())
+}
+
+/// The activation of `last_attempted` did not finish unless it is active now
+fn interrupted_activation(
+    last_attempted: api::StorePath,
+    active: &api::StorePath,
+) -> Option<api::ActivationReport> {
+    (last_attempted != *active).then(|| api::ActivationReport {
+        store_path: last_attempted,
+        success: false,
+        error: Some("activation was interrupted".to_owned()),
+    })
+}
+
+/// Which store path to activate when the server is unreachable on startup.
+/// Only the last known-good generation is re-asserted and only if it is not active already
+fn offline_switch(
+    last_action: Option<api::AgentAction>,
+    active: &api::StorePath,
+) -> Option<api::StorePath> {
+    match last_action? {
+        api::AgentAction::SwitchTo(version) if &version.store_path != active => {
+            Some(version.store_path)
+        }
+        api::AgentAction::SwitchTo(_)
+        | api::AgentAction::Nothing
+        | api::AgentAction::Detach
+        | api::AgentAction::WaitForNextVersion { .. } => None,
     }
 }
 
-async fn agent_action(action: api::AgentAction, url: &Url, key: &SecretKey) -> Result<(), Report> {
+/// Re-asserts the cached action. The store path was realised before so no server is needed
+async fn restore_last_action(path: &Path, config: &AgentConfig) -> Result<(), Report> {
+    let active = get_active_version(config.home_manager_activation)?;
+    let Some(store_path) = offline_switch(read_last_action(path)?, &active) else {
+        info!("Nothing to restore");
+        return Ok(());
+    };
+    info!("Switching back to the last known version {store_path}");
+    switch_to(&store_path, config).await
+}
+
+/// Returns the code that got replaced
+fn set_verification_code(code: u32) -> Option<u32> {
+    VERIFICATION_CODE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .replace(code)
+}
+
+fn verification_code() -> Option<u32> {
+    *VERIFICATION_CODE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+async fn agent_action(
+    action: api::AgentAction,
+    config: &AgentConfig,
+    server: &api::Server,
+    key: &SecretKey,
+    identity: &age::x25519::Identity,
+) -> Result<(), Report> {
     match action {
-        api::AgentAction::Nothing | api::AgentAction::Detach => {}
+        api::AgentAction::Nothing | api::AgentAction::WaitForNextVersion { .. } => {}
+        api::AgentAction::Detach => write_detached(&config.secret_base)?,
         api::AgentAction::SwitchTo(remote_store_path) => {
-            update(&remote_store_path, url, key).await?;
+            update(&remote_store_path, config, server, key, identity).await?;
         }
     }
     Ok(())
 }
 
+/// Print the local state the agent starts from and what it would do with it.
+/// The server is not contacted and nothing is changed
+pub fn simulate(secret_base: &Path, home_manager: bool) -> Result<(), Report> {
+    let active =
+        get_active_version(home_manager).map_err(|err| err.format_current_context().to_string());
+    section::print_sections(&[simulation(active, secret_base)?]);
+    Ok(())
+}
+
+fn simulation(
+    active: Result<String, String>,
+    secret_base: &Path,
+) -> Result<section::Section, Report> {
+    let mut items = vec![(
+        "Current version".to_owned(),
+        active.unwrap_or_else(|err| format!("unknown: {err}")),
+    )];
+
+    match read_link(secret_base.join(SECRET_LINK)) {
+        Ok(generation) => {
+            let mut secrets = read_dir(&generation)
+                .attach(generation.display().to_string())?
+                .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+                .collect::<Result<Vec<_>, io::Error>>()?;
+            secrets.sort();
+            items.push((
+                "Secret generation".to_owned(),
+                generation.display().to_string(),
+            ));
+            items.push(("Secrets".to_owned(), secrets.join("\n")));
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            items.push(("Secrets".to_owned(), "none".to_owned()));
+        }
+        Err(err) => {
+            return Err(report!(err)
+                .attach(secret_base.display().to_string())
+                .into_dynamic());
+        }
+    }
+
+    if let Some(last_action) = read_last_action(&secret_base.join(LAST_ACTION))? {
+        items.push(("Last action".to_owned(), format!("{last_action:?}")));
+    }
+
+    // `agent_action(Nothing)` leaves the system and the secrets untouched
+    let next = if detached_marker(secret_base).exists() {
+        "None. Detached, the server is not polled"
+    } else {
+        "Nothing. Keeps the current version and secrets"
+    };
+    items.push(("Next action".to_owned(), next.to_owned()));
+
+    Ok(("Simulation".to_owned(), items))
+}
+
+/// Caches the `trusted-public-keys` of a `nix.conf`
+struct KeyCache(RwLock<OnceLock<Vec<String>>>);
+
+impl KeyCache {
+    const fn new() -> Self {
+        Self(RwLock::new(OnceLock::new()))
+    }
+
+    fn get(&self, nix_conf: &Path) -> Result<Vec<String>, Report> {
+        let cache = self
+            .0
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(keys) = cache.get() {
+            return Ok(keys.clone());
+        }
+        let keys = read_trusted_public_keys(nix_conf)?;
+        Ok(cache.get_or_init(|| keys).clone())
+    }
+
+    fn invalidate(&self) {
+        *self
+            .0
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = OnceLock::new();
+    }
+}
+
 fn trusted_public_keys() -> Result<Vec<String>, Report> {
-    let file = File::open("/etc/nix/nix.conf")?;
-    Ok(BufReader::new(file)
-        .lines()
-        .map_while(Result::ok)
-        .find(|line| line.starts_with("trusted-public-keys"))
-        .unwrap_or(String::from(
-            "cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=",
-        ))
-        .split_whitespace()
-        .skip(2)
-        .map(str::to_owned)
-        .collect())
+    TRUSTED_PUBLIC_KEYS.get(Path::new(NIX_CONF))
+}
+
+/// The next download reads the keys from `nix.conf` again e.g. after they were rotated
+pub fn invalidate_trusted_public_keys() {
+    TRUSTED_PUBLIC_KEYS.invalidate();
+    info!("Trusted public keys are read from {NIX_CONF} again on the next update");
+}
+
+fn read_trusted_public_keys(nix_conf: &Path) -> Result<Vec<String>, Report> {
+    Ok(read_nix_conf_keys(nix_conf)?.keys())
+}
+
+/// The public keys configured by a `nix.conf` and its includes
+#[derive(Debug, Default, PartialEq, Eq)]
+struct NixConfKeys {
+    /// `None` keeps the default of nix, the cache.nixos.org key
+    trusted: Option<Vec<String>>,
+    extra: Vec<String>,
+}
+
+impl NixConfKeys {
+    fn keys(self) -> Vec<String> {
+        let mut keys = self
+            .trusted
+            .unwrap_or_else(|| vec![NIXOS_CACHE_KEY.to_owned()]);
+        keys.extend(self.extra);
+        keys
+    }
+}
+
+/// A line of a `nix.conf` that matters for the trusted public keys
+#[derive(Debug, PartialEq, Eq)]
+enum NixConfLine<'line> {
+    TrustedPublicKeys(Vec<&'line str>),
+    ExtraTrustedPublicKeys(Vec<&'line str>),
+    /// `!include` ignores missing files
+    Include {
+        path: &'line str,
+        optional: bool,
+    },
+}
+
+/// Accepts `name = value`, `name=value`, indentation and trailing comments
+fn parse_nix_conf_line(line: &str) -> Option<NixConfLine<'_>> {
+    let line = line.split_once('#').map_or(line, |(line, _)| line).trim();
+    if let Some(path) = line.strip_prefix("!include ") {
+        return Some(NixConfLine::Include {
+            path: path.trim(),
+            optional: true,
+        });
+    }
+    if let Some(path) = line.strip_prefix("include ") {
+        return Some(NixConfLine::Include {
+            path: path.trim(),
+            optional: false,
+        });
+    }
+    let (name, value) = line.split_once('=')?;
+    let keys = value.split_whitespace().collect();
+    match name.trim() {
+        "trusted-public-keys" => Some(NixConfLine::TrustedPublicKeys(keys)),
+        "extra-trusted-public-keys" => Some(NixConfLine::ExtraTrustedPublicKeys(keys)),
+        _ => None,
+    }
+}
+
+fn read_nix_conf_keys(nix_conf: &Path) -> Result<NixConfKeys, Report> {
+    let mut keys = NixConfKeys::default();
+    collect_nix_conf_keys(nix_conf, &mut keys, 0)?;
+    Ok(keys)
+}
+
+/// Like nix a later `trusted-public-keys` replaces earlier ones while the extra keys add up.
+/// Relative includes are resolved against the directory of the including file
+fn collect_nix_conf_keys(nix_conf: &Path, keys: &mut NixConfKeys, depth: u8) -> Result<(), Report> {
+    if depth > MAX_NIX_CONF_INCLUDES {
+        bail!("Includes of {} are nested too deep", nix_conf.display());
+    }
+    let content = read_to_string(nix_conf).attach(nix_conf.display().to_string())?;
+    for line in content.lines() {
+        match parse_nix_conf_line(line) {
+            Some(NixConfLine::TrustedPublicKeys(trusted)) => {
+                keys.trusted = Some(trusted.into_iter().map(str::to_owned).collect());
+            }
+            Some(NixConfLine::ExtraTrustedPublicKeys(extra)) => {
+                keys.extra.extend(extra.into_iter().map(str::to_owned));
+            }
+            Some(NixConfLine::Include { path, optional }) => {
+                let include = nix_conf.parent().unwrap_or(Path::new("/")).join(path);
+                if optional && !include.exists() {
+                    continue;
+                }
+                collect_nix_conf_keys(&include, keys, depth.saturating_add(1))?;
+            }
+            None => {}
+        }
+    }
+    Ok(())
 }
 
-async fn update(version: &api::RemoteStorePath, url: &Url, key: &SecretKey) -> Result<(), Report> {
-    download(version, url, key).await?;
-    let current_gen = read_link("/etc/yeet/secret");
-    get_secrets(version, url, key).await?;
-    let next_gen = read_link("/etc/yeet/secret");
+/// What `download` passes to `nix-store`: the local keys and the key the version was published
+/// with
+fn download_keys(mut trusted: Vec<String>, public_key: Option<&str>) -> Vec<String> {
+    trusted.extend(public_key.map(str::to_owned));
+    trusted.sort();
+    trusted.dedup();
+    trusted
+}
+
+/// Read from `nix.conf` without the cache. A running agent may still use keys read earlier
+pub fn resolved_trusted_keys(public_key: Option<&str>) -> Result<TrustedKeys, Report> {
+    let nix_conf = read_nix_conf_keys(Path::new(NIX_CONF))?;
+    let fallback = nix_conf.trusted.is_none();
+    let local = nix_conf.keys();
+    Ok(TrustedKeys {
+        nix_conf: PathBuf::from(NIX_CONF),
+        fallback,
+        download: download_keys(local.clone(), public_key),
+        local,
+    })
+}
+
+/// Output of `yeet debug trusted-keys`
+pub struct TrustedKeys {
+    pub nix_conf: PathBuf,
+    /// `nix.conf` has no `trusted-public-keys` so `local` holds the cache.nixos.org key
+    pub fallback: bool,
+    pub local: Vec<String>,
+    /// Passed to `nix-store --realise`
+    pub download: Vec<String>,
+}
+
+async fn update(
+    version: &api::RemoteStorePath,
+    config: &AgentConfig,
+    server: &api::Server,
+    key: &SecretKey,
+    identity: &age::x25519::Identity,
+) -> Result<(), Report> {
+    let secret_base = config.secret_base.as_path();
+    // before `download` fetches the netrc secret
+    if let Some(pinned) = &config.server_recipient {
+        check_server_recipient(pinned, &api::server_age_key(server, key).await?)?;
+    }
+    let downloaded = download(version, server, key, identity).await?;
+    report_download_stats(server, key, &version.store_path, &downloaded).await;
+    let link = secret_base.join(SECRET_LINK);
+    let current_gen = read_link(&link);
+    get_secrets(&version.store_path, server, key, identity, secret_base).await?;
+    let next_gen = read_link(&link);
 
-    let activation_err = activate(&version.store_path);
+    let last_attempted = secret_base.join(LAST_ATTEMPTED);
+    fs::create_dir_all(secret_base)?;
+    fs::write(&last_attempted, &version.store_path).attach(last_attempted.display().to_string())?;
+    let activation_err = activate(&version.store_path, config);
+    let success = get_active_version(config.home_manager_activation)? == version.store_path;
+    remove_file(&last_attempted).attach(last_attempted.display().to_string())?;
+    report_activation(
+        server,
+        key,
+        api::ActivationReport {
+            store_path: version.store_path.clone(),
+            success,
+            error: activation_err.as_ref().err().map(ToString::to_string),
+        },
+    )
+    .await;
     // switch did not go correct
-    if get_active_version()? == version.store_path {
+    if success {
         if let Ok(next_gen) = next_gen {
-            let _err = remove_all_dirs_unless(
-                next_gen.parent().unwrap_or(Path::new("/etc/yeet/secret.d")),
-                next_gen.file_name().unwrap_or_default(),
-            );
+            clean_generations(secret_base, &next_gen);
         }
     } else {
-        // Restore last gen if there was one
-        if let Ok(current_gen) = current_gen {
-            let _err = remove_file("/etc/yeet/secret");
-            symlink(current_gen, "/etc/yeet/secret")?;
+        rollback_generation(
+            secret_base,
+            current_gen.as_deref().ok(),
+            next_gen.as_deref().ok(),
+        )?;
+        activation_err?;
+    }
+    notification::notify_all(
+        &config.notifications,
+        &notification::ActivationEvent::new(&version.store_path),
+    )
+    .await;
+    if let Some(older_than) = config.gc_after_update.clone() {
+        spawn_garbage_collection(older_than);
+    }
+    Ok(())
+}
+
+/// Fetches the secrets of the active version again after the server reported that the secrets
+/// this host can fetch changed. Nothing is activated. A failure is only logged since the system
+/// itself is fine and the next switch fetches the secrets again
+async fn refresh_secrets(
+    config: &AgentConfig,
+    server: &api::Server,
+    key: &SecretKey,
+    identity: &age::x25519::Identity,
+) {
+    info!("The secrets of this host changed. Fetching them again");
+    let refreshed = async {
+        if let Some(pinned) = &config.server_recipient {
+            check_server_recipient(pinned, &api::server_age_key(server, key).await?)?;
         }
-        // Delete the generation that was just created
-        if let Ok(next_gen) = next_gen {
-            remove_dir_all(&next_gen)?;
+        let store_path = get_active_version(config.home_manager_activation)?;
+        if store_path == api::NOT_ACTIVATED {
+            return Ok(());
         }
-        activation_err?;
+        get_secrets(&store_path, server, key, identity, &config.secret_base).await?;
+        if let Ok(current) = read_link(config.secret_base.join(SECRET_LINK)) {
+            clean_generations(&config.secret_base, &current);
+        }
+        Ok::<_, Report>(())
+    };
+    if let Err(err) = refreshed.await {
+        error!("Could not fetch the changed secrets: {err}");
+    }
+}
+
+/// Only for the host record. A failed report never blocks the activation
+async fn report_download_stats(
+    server: &api::Server,
+    key: &SecretKey,
+    store_path: &api::StorePath,
+    info: &nix::PathInfo,
+) {
+    let stats = api::DownloadStats {
+        store_path: store_path.clone(),
+        nar_size: info.nar_size,
+        closure_size: info.closure_size.unwrap_or(info.nar_size),
+    };
+    if let Err(err) = api::report_download_stats(server, key, stats).await {
+        error!("Could not report the download size to the server: {err}");
+    }
+}
+
+/// Keeps the server up to date with hardware changes. A failed report is retried after the interval
+async fn report_facter(server: &api::Server, key: &SecretKey) {
+    info!("Collecting nixos-facter information");
+    let nixos_facter = match nix::facter() {
+        Ok(facts) => facts,
+        Err(err) => {
+            error!("Could not collect nixos-facter information: {err}");
+            return;
+        }
+    };
+    if let Err(err) = api::report_facter(server, key, nixos_facter).await {
+        error!("Could not report the nixos-facter information to the server: {err}");
+    }
+}
+
+/// The server only learns about the outcome. A failed report never fails the update
+async fn report_activation(server: &api::Server, key: &SecretKey, report: api::ActivationReport) {
+    if let Err(err) = api::report_activation(server, key, report).await {
+        error!("Could not report the activation to the server: {err}");
+    }
+}
+
+/// Runs in the background so the agent keeps polling while the store is cleaned up
+fn spawn_garbage_collection(older_than: String) {
+    tokio::task::spawn_blocking(move || match nix::collect_garbage(&older_than) {
+        Ok(freed) => info!("Collected garbage older than {older_than}: {freed}"),
+        Err(err) => error!("Could not collect garbage: {err}"),
+    });
+}
+
+/// Remove every generation except `keep`
+fn clean_generations(secret_base: &Path, keep: &Path) {
+    let generations = secret_base.join(SECRET_GENERATIONS);
+    if let Err(err) = remove_all_dirs_unless(
+        keep.parent().unwrap_or(&generations),
+        keep.file_name().unwrap_or_default(),
+    ) {
+        log::error!("Could not clean up old secret generations: {err}");
+    }
+}
+
+/// Point the link back to `current` and delete `next` after a failed switch
+fn rollback_generation(
+    secret_base: &Path,
+    current: Option<&Path>,
+    next: Option<&Path>,
+) -> Result<(), Report> {
+    // Restore last gen if there was one
+    if let Some(current) = current {
+        replace_symlink(current, secret_base.join(SECRET_LINK))?;
+    }
+    // Delete the generation that was just created. Unchanged secrets keep the current one
+    if let Some(next) = next
+        && current != Some(next)
+    {
+        remove_dir_all(next)?;
     }
-    notification::notify_all()?;
     Ok(())
 }
 
@@ -179,32 +959,63 @@ fn remove_all_dirs_unless<P: AsRef<Path>>(
     dirname: &OsStr,
 ) -> Result<(), rootcause::Report> {
     for dir in read_dir(base)? {
-        if let Ok(dir) = dir
-            && dir.file_name() != dirname
+        let dir = dir?;
+        if dir.file_name() != dirname
+            && let Err(err) = remove_dir_all(dir.path())
         {
-            let _err = remove_dir_all(dir.path());
+            log::error!("Could not remove {}: {err}", dir.path().display());
         }
     }
 
     Ok(())
 }
 
-pub fn switch_to(store_path: &api::StorePath) -> Result<(), Report> {
-    activate(store_path)?;
-    notification::notify_all()?;
+/// Points `link` to `target`. An existing `link` is replaced
+fn replace_symlink<P: AsRef<Path>, Q: AsRef<Path>>(target: P, link: Q) -> Result<(), io::Error> {
+    match remove_file(&link) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    symlink(target, link)
+}
+
+pub async fn switch_to(store_path: &api::StorePath, config: &AgentConfig) -> Result<(), Report> {
+    activate(store_path, config)?;
+    notification::notify_all(
+        &config.notifications,
+        &notification::ActivationEvent::new(store_path),
+    )
+    .await;
+    Ok(())
+}
+
+/// Fetches the secrets of `store_path` for `yeet agent switch`. Unlike an update there is no
+/// version from the server, so only the secrets come from it
+pub async fn fetch_secrets(
+    store_path: &api::StorePath,
+    config: &AgentConfig,
+) -> Result<(), Report> {
+    let server = api::Server::new(config.server.clone(), config.timeouts())?;
+    let key = get_secret_key(&config.key)?;
+    let identity = age_identity(&config.secret_base.join(AGE_IDENTITY))?;
+    if let Some(pinned) = &config.server_recipient {
+        check_server_recipient(pinned, &api::server_age_key(&server, &key).await?)?;
+    }
+    get_secrets(store_path, &server, &key, &identity, &config.secret_base).await?;
+    if let Ok(current) = read_link(config.secret_base.join(SECRET_LINK)) {
+        clean_generations(&config.secret_base, &current);
+    }
     Ok(())
 }
 
 async fn download(
     version: &api::RemoteStorePath,
-    url: &Url,
+    server: &api::Server,
     key: &SecretKey,
-) -> Result<(), Report> {
+    identity: &age::x25519::Identity,
+) -> Result<nix::PathInfo, Report> {
     info!("Downloading {}", version.store_path);
-    let mut keys = trusted_public_keys()?;
-    keys.push(version.public_key.clone());
-    keys.sort();
-    keys.dedup();
+    let keys = download_keys(trusted_public_keys()?, Some(&version.public_key));
 
     let mut command = Command::new("nix-store");
     command.stderr(io::stderr()).stdout(io::stdout());
@@ -225,10 +1036,18 @@ async fn download(
     // Even if we do not end up using the temp file we create it outside of the if scope.
     // Else it would get dropped before nix-store can use it
     let mut netrc_file = NamedTempFile::new().context("Could not create netrc temp file")?;
-    let netrc = match api::get_secret(url, key, "netrc".into()).await {
-        Ok(secret) => secret,
-        Err(err) => {
-            log::error!("could not get netrc secret: {err}");
+    let netrc = match api::get_secret(server, key, identity, "netrc".into()).await {
+        Ok(api::SecretLookup::Found { content, .. }) => Some(content),
+        Ok(api::SecretLookup::NotFound) => {
+            debug!("No netrc secret on the server. Downloading without credentials");
+            None
+        }
+        Ok(api::SecretLookup::NoAccess) => {
+            log::warn!("The netrc secret exists but this host is not allowed to read it");
+            None
+        }
+        Err(err) => {
+            log::error!("could not get netrc secret: {err}");
             None
         }
     };
@@ -259,17 +1078,51 @@ async fn download(
             ))
             .into_dynamic());
     }
-    Ok(())
+    verify_download(version)
+}
+
+/// nix accepts a path signed by any trusted key. Make sure it is the key the server sent
+fn verify_download(version: &api::RemoteStorePath) -> Result<nix::PathInfo, Report> {
+    let verified = nix::path_info(&version.store_path).and_then(|info| {
+        nix::verify_path_signature(&version.store_path, &info, &version.public_key)?;
+        Ok(info)
+    });
+    if let Err(err) = verified {
+        let deleted = Command::new("nix-store")
+            .args(["--delete", &version.store_path])
+            .output();
+        if !deleted.is_ok_and(|deleted| deleted.status.success()) {
+            error!("Could not delete {}", version.store_path);
+        }
+        return Err(err
+            .context("Downloaded store path failed verification")
+            .into_dynamic());
+    }
+    verified
+}
+
+/// A server with a different store key might be someone else entirely
+fn check_server_recipient(pinned: &str, server: &str) -> Result<(), SecretFetchError> {
+    if pinned.trim() == server.trim() {
+        Ok(())
+    } else {
+        Err(SecretFetchError::ServerMismatch {
+            pinned: pinned.trim().to_owned(),
+            server: server.trim().to_owned(),
+        })
+    }
 }
 
 async fn get_secrets(
-    version: &api::RemoteStorePath,
-    url: &Url,
+    store_path: &str,
+    server: &api::Server,
     key: &SecretKey,
+    identity: &age::x25519::Identity,
+    secret_base: &Path,
 ) -> Result<(), Report> {
     // find out which secrets are required for this derivation
     let nix_secrets: api::Secrets = {
-        let path = Path::new(&version.store_path).join("yeet-secrets.json");
+        let path = Path::new(store_path).join("yeet-secrets.json");
         if !path.exists() {
             log::info!(
                 "No yeet-secrets.json file found at {}",
@@ -284,59 +1137,260 @@ async fn get_secrets(
     let mut secrets = Vec::new();
     for (secret, definition) in nix_secrets {
         log::info!("Fetching secret {secret}");
-        let Some(secret) = api::get_secret(url, key, secret.clone()).await? else {
-            rootcause::bail!("Secret {secret} not found! Unable to switch to derivation");
-        };
-        secrets.push((definition, secret));
+        let (content, content_type) =
+            match api::get_secret(server, key, identity, secret.clone()).await? {
+                api::SecretLookup::Found {
+                    content,
+                    content_type,
+                } => (content, content_type),
+                api::SecretLookup::NotFound => {
+                    return Err(SecretFetchError::NotFound(secret).into());
+                }
+                api::SecretLookup::NoAccess => {
+                    return Err(SecretFetchError::NoAccess(secret).into());
+                }
+            };
+        secrets.push(FetchedSecret {
+            definition,
+            content,
+            content_type,
+        });
     }
 
-    // get next generation number
-    // This basically reads `/etc/yeet/secret` as u32 and if it fails it returns 0 (first gen)
-    let generation = {
-        let link = read_link("/etc/yeet/secret"); // this will return a path like `/etc/yeet/secret.d/1`
-        let gen_str = link.ok().and_then(|path| {
-            path.file_name()
-                .map(|path| path.to_string_lossy().to_string())
-        });
-        log::info!("Current Generation: {gen_str:?}");
-        let gen_num = gen_str
-            .and_then(|str| str.parse::<u32>().ok().map(|i| i.wrapping_add(1)))
-            .unwrap_or(0);
-        log::info!("Creating new Generation {gen_num}");
-        PathBuf::from(format!("/etc/yeet/secret.d/{gen_num}"))
+    store_secrets(secret_base, secrets)
+}
+
+/// A secret from `yeet-secrets.json` with what the server returned for it
+struct FetchedSecret {
+    definition: api::Secret,
+    content: Vec<u8>,
+    content_type: Option<String>,
+}
+
+/// File extension for a content type set with `yeet secret create --content-type`
+fn default_extension(content_type: &str) -> Option<&'static str> {
+    match content_type {
+        "pem" => Some("pem"),
+        "env" | "dotenv" => Some("env"),
+        "json" => Some("json"),
+        "yaml" | "yml" => Some("yaml"),
+        "toml" => Some("toml"),
+        "txt" | "text" => Some("txt"),
+        _ => None,
+    }
+}
+
+/// `file_name` with the default extension of `content_type` unless it already has an extension
+fn typed_name(file_name: &OsStr, content_type: Option<&str>) -> Option<OsString> {
+    let extension = default_extension(content_type?)?;
+    if Path::new(file_name).extension().is_some() {
+        return None;
+    }
+    let mut typed = file_name.to_owned();
+    typed.push(".");
+    typed.push(extension);
+    Some(typed)
+}
+
+/// Symlinks from the typed name to the file of each secret. The file keeps the name from
+/// `yeet-secrets.json` because the nix module points `path` at it. A secret that already has
+/// the typed name wins over the link
+fn typed_links(secrets: &[FetchedSecret]) -> Vec<(OsString, OsString)> {
+    let names: HashSet<&OsStr> = secrets
+        .iter()
+        .filter_map(|secret| Path::new(&secret.definition.name).file_name())
+        .collect();
+    secrets
+        .iter()
+        .filter_map(|secret| {
+            let name = Path::new(&secret.definition.name).file_name()?;
+            let typed = typed_name(name, secret.content_type.as_deref())?;
+            (!names.contains(typed.as_os_str())).then(|| (name.to_owned(), typed))
+        })
+        .collect()
+}
+
+/// Writes a new generation unless the current one already holds exactly these secrets
+fn store_secrets(secret_base: &Path, secrets: Vec<FetchedSecret>) -> Result<(), Report> {
+    // a wrong secret must not even match the current generation
+    for secret in &secrets {
+        check_integrity(&secret.definition, &secret.content)?;
+    }
+    let link = secret_base.join(SECRET_LINK);
+    if let Ok(current) = read_link(&link)
+        && generation_matches(&current, &secrets)
+    {
+        log::info!(
+            "Secrets did not change. Keeping generation {}",
+            current.display()
+        );
+        return Ok(());
+    }
+
+    preflight(
+        &secret_base.join(SECRET_GENERATIONS),
+        secrets
+            .iter()
+            .map(|secret| secret.content.len() as u64)
+            .sum::<u64>()
+            .saturating_add(GENERATION_OVERHEAD),
+    )?;
+
+    write_generation(&next_generation(secret_base), secrets, &link)
+}
+
+/// Whether `generation` holds exactly `secrets` with the same content, mode, owner and links
+fn generation_matches(generation: &Path, secrets: &[FetchedSecret]) -> bool {
+    let Ok(files) = read_dir(generation) else {
+        return false;
     };
+    let links = typed_links(secrets);
+    files.count() == secrets.len().saturating_add(links.len())
+        && links.iter().all(|(name, typed)| {
+            read_link(generation.join(typed)).is_ok_and(|target| target == Path::new(name))
+        })
+        && secrets.iter().all(|secret| {
+            secret_matches(generation, &secret.definition, &secret.content).unwrap_or_else(|err| {
+                debug!("Could not compare secret {}: {err}", secret.definition.name);
+                false
+            })
+        })
+}
+
+fn secret_matches(generation: &Path, secret: &api::Secret, content: &[u8]) -> Result<bool, Report> {
+    let file = generation.join(
+        Path::new(&secret.name)
+            .file_name()
+            .ok_or(report!("Invalid secret name: {}", secret.name))?,
+    );
+    let metadata = fs::symlink_metadata(&file)?;
+    Ok(metadata.is_file()
+        && metadata.mode() & 0o7777 == u32::from_str_radix(&secret.mode, 8)?
+        && (metadata.uid(), metadata.gid()) == deployed_owner(secret, user_owned())?
+        && fs::read(&file)? == content)
+}
 
-    // create new generation
-    let genration_result = create_generation(&generation, secrets);
-    if genration_result.is_err() {
-        if let Err(result) =
-            remove_dir_all(&generation).attach(generation.to_string_lossy().to_string())
+/// Without root the secrets can only belong to the user running the agent e.g. with home-manager
+fn user_owned() -> bool {
+    !::nix::unistd::Uid::effective().is_root()
+}
+
+/// The uid and gid `secret` ends up with. `user_owned` secrets keep the agent's own ids
+fn deployed_owner(secret: &api::Secret, user_owned: bool) -> Result<(u32, u32), SecretDeployError> {
+    if user_owned {
+        return Ok((
+            ::nix::unistd::Uid::effective().as_raw(),
+            ::nix::unistd::Gid::effective().as_raw(),
+        ));
+    }
+    Ok((resolve_uid(&secret.owner)?, resolve_gid(&secret.group)?))
+}
+
+/// Path of the generation after the one `secret` currently links to
+fn next_generation(secret_base: &Path) -> PathBuf {
+    // This basically reads `secret` as u32 and if it fails it returns 0 (first gen)
+    let link = read_link(secret_base.join(SECRET_LINK)); // this will return a path like `secret.d/1`
+    let gen_str = link.ok().and_then(|path| {
+        path.file_name()
+            .map(|path| path.to_string_lossy().to_string())
+    });
+    log::info!("Current Generation: {gen_str:?}");
+    let gen_num = gen_str
+        .and_then(|str| str.parse::<u32>().ok().map(|i| i.wrapping_add(1)))
+        .unwrap_or(0);
+    log::info!("Creating new Generation {gen_num}");
+    secret_base
+        .join(SECRET_GENERATIONS)
+        .join(gen_num.to_string())
+}
+
+/// Fail fast with a clear message before anything is written to `base`
+fn preflight(base: &Path, required: u64) -> Result<(), Report> {
+    fs::create_dir_all(base)
+        .and_then(|()| tempfile::tempfile_in(base))
+        .context(format!(
+            "{} is not writable. Run the agent as root or point `--secret-base` to a writable directory",
+            base.display()
+        ))?;
+
+    let stat = ::nix::sys::statvfs::statvfs(base).attach(base.display().to_string())?;
+    let available = (stat.blocks_available() as u64).saturating_mul(stat.fragment_size() as u64);
+    if available < required {
+        bail!(
+            "Not enough space in {}: {required} bytes required but only {available} bytes available",
+            base.display()
+        );
+    }
+    Ok(())
+}
+
+/// Removes a partially written generation unless it was completed with `keep`
+struct PartialGeneration<'path>(Option<&'path Path>);
+
+impl PartialGeneration<'_> {
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for PartialGeneration<'_> {
+    fn drop(&mut self) {
+        if let Some(generation) = self.0
+            && let Err(err) = remove_dir_all(generation)
         {
-            log::error!("could not remove generation: {result:?}");
+            log::error!(
+                "Could not remove the partial generation {}: {err}",
+                generation.display()
+            );
         }
-        genration_result?;
     }
+}
 
-    // switch to new generation
-    let _err = remove_file("/etc/yeet/secret");
-    symlink(&generation, "/etc/yeet/secret")?;
-
+/// Write the secrets to `generation` and point `link` to it.
+/// On any error the generation is removed again
+fn write_generation(
+    generation: &Path,
+    secrets: Vec<FetchedSecret>,
+    link: &Path,
+) -> Result<(), Report> {
+    let partial = PartialGeneration(Some(generation));
+    create_generation(generation, secrets)?;
+    replace_symlink(generation, link)?;
+    partial.keep();
     Ok(())
 }
 
+/// Writes the secrets to a sibling temp directory and renames it to `generation` once every
+/// file is synced. A crash never leaves a half written generation behind, only a `.tmp_`
+/// directory that `clean_generations` removes
 fn create_generation(
     generation: &Path,
-    secrets: Vec<(api::Secret, Vec<u8>)>,
+    secrets: Vec<FetchedSecret>,
 ) -> Result<(), rootcause::Report> {
-    fs::create_dir_all(generation)?;
-    fs::set_permissions(generation, fs::Permissions::from_mode(0o751))?;
+    let generations = generation.parent().ok_or(rootcause::report!(
+        "Invalid generation: {}",
+        generation.display()
+    ))?;
+    fs::create_dir_all(generations)?;
+    let temp = tempfile::Builder::new()
+        .prefix(".tmp_")
+        .tempdir_in(generations)?;
+    let user_owned = user_owned();
+    let mode = if user_owned { 0o700 } else { 0o751 };
+    fs::set_permissions(temp.path(), fs::Permissions::from_mode(mode))?;
 
-    for (secret, content) in secrets {
+    let links = typed_links(&secrets);
+    for FetchedSecret {
+        definition: secret,
+        content,
+        ..
+    } in secrets
+    {
         let file_name = {
             let file_name = Path::new(&secret.name)
                 .file_name()
                 .ok_or(rootcause::report!("Invalid secret name: {}", secret.name))?;
-            generation.join(file_name)
+            temp.path().join(file_name)
         };
         let mut secret_file = File::create_new(&file_name)?;
 
@@ -346,52 +1400,1076 @@ fn create_generation(
         )?))?;
 
         secret_file.write_all(&content)?;
-        secret_file.flush()?;
+        secret_file.sync_all()?;
+        if secret.size.is_some() {
+            check_integrity(&secret, &fs::read(&file_name)?)
+                .attach(format!("Written file: {}", file_name.display()))?;
+        }
 
-        chown(
-            &file_name,
-            Some(secret.owner.parse()?),
-            Some(secret.owner.parse()?),
-        )
-        .attach(format!("File to chown: {}", file_name.to_string_lossy()))?;
+        if user_owned {
+            continue;
+        }
+        let (uid, gid) = deployed_owner(&secret, user_owned)?;
+        chown(&file_name, Some(uid), Some(gid))
+            .attach(format!("File to chown: {}", file_name.to_string_lossy()))?;
     }
+    // relative so they still point into the generation after the rename
+    for (name, typed) in links {
+        symlink(name, temp.path().join(typed))?;
+    }
+    File::open(temp.path())?.sync_all()?;
+
+    // Left over from a crash after the rename but before the link was switched
+    if generation.exists() {
+        log::warn!("Replacing stale generation {}", generation.display());
+        remove_dir_all(generation)?;
+    }
+    fs::rename(temp.keep(), generation)?;
+    File::open(generations)?.sync_all()?;
 
     Ok(())
 }
 
-fn set_system_profile(store_path: &api::StorePath) -> Result<(), Report> {
-    info!("Setting system profile to {store_path}");
-    let profile = Command::new("nix-env")
-        .args([
-            "--profile",
-            "/nix/var/nix/profiles/system",
-            "--set",
-            store_path,
-        ])
-        .output()?;
-    if !profile.status.success() {
-        bail!("{}", String::from_utf8(profile.stderr)?);
+#[derive(thiserror::Error, Debug)]
+pub enum SecretFetchError {
+    /// The secret might still be uploaded. Retried with the usual backoff
+    #[error("Secret `{0}` not found! Unable to switch to derivation")]
+    NotFound(String),
+    /// Only an admin can fix the ACL. Retrying sooner would not help
+    #[error("This host is not allowed to read secret `{0}`! Unable to switch to derivation")]
+    NoAccess(String),
+    /// Never retried. The agent stops until the pin or the server is fixed
+    #[error(
+        "The server store key {server} does not match the pinned {pinned}! Refusing to fetch secrets"
+    )]
+    ServerMismatch { pinned: String, server: String },
+}
+
+impl SecretFetchError {
+    /// Whether `report` failed because the host is missing from the ACL of a secret
+    fn is_no_access(report: &Report) -> bool {
+        report.iter_reports().any(|sub| {
+            matches!(
+                sub.downcast_current_context::<SecretFetchError>(),
+                Some(SecretFetchError::NoAccess(_))
+            )
+        })
+    }
+
+    /// Whether `report` failed because the server does not have the pinned recipient
+    fn is_server_mismatch(report: &Report) -> bool {
+        report.iter_reports().any(|sub| {
+            matches!(
+                sub.downcast_current_context::<SecretFetchError>(),
+                Some(SecretFetchError::ServerMismatch { .. })
+            )
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SecretDeployError {
+    #[error("User `{0}` does not exist. Add it to the system before deploying the secret")]
+    UnknownUser(String),
+    #[error("Group `{0}` does not exist. Add it to the system before deploying the secret")]
+    UnknownGroup(String),
+    #[error("Could not look up the owner of the secret")]
+    Lookup(#[from] ::nix::errno::Errno),
+    #[error("Secret `{name}` has {actual} bytes instead of the expected {expected}")]
+    SizeMismatch {
+        name: String,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+/// Compares `content` with the optional `size` from `yeet-secrets.json`.
+/// Catches a server that returned the wrong data for a secret
+fn check_integrity(secret: &api::Secret, content: &[u8]) -> Result<(), SecretDeployError> {
+    let actual = content.len() as u64;
+    if let Some(expected) = secret.size
+        && expected != actual
+    {
+        return Err(SecretDeployError::SizeMismatch {
+            name: secret.name.clone(),
+            expected,
+            actual,
+        });
     }
     Ok(())
 }
 
-#[cfg(target_os = "macos")]
-fn activate(store_path: &api::StorePath) -> Result<(), Report> {
-    set_system_profile(store_path)?;
-    info!("Activating {}", store_path);
-    Command::new(Path::new(&store_path).join("activate"))
-        .spawn()?
-        .wait()?;
+/// Secrets may be owned by a numeric uid or a username
+fn resolve_uid(owner: &str) -> Result<u32, SecretDeployError> {
+    if let Ok(uid) = owner.parse() {
+        return Ok(uid);
+    }
+    ::nix::unistd::User::from_name(owner)?
+        .map(|user| user.uid.as_raw())
+        .ok_or_else(|| SecretDeployError::UnknownUser(owner.to_owned()))
+}
+
+/// Secrets may be owned by a numeric gid or a group name
+fn resolve_gid(group: &str) -> Result<u32, SecretDeployError> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    ::nix::unistd::Group::from_name(group)?
+        .map(|group| group.gid.as_raw())
+        .ok_or_else(|| SecretDeployError::UnknownGroup(group.to_owned()))
+}
+
+fn set_profile(profile: &Path, store_path: &api::StorePath) -> Result<(), Report> {
+    info!("Setting {} to {store_path}", profile.display());
+    let output = Command::new("nix-env")
+        .arg("--profile")
+        .arg(profile)
+        .args(["--set", store_path])
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8(output.stderr)?);
+    }
     Ok(())
 }
 
-#[cfg(target_os = "linux")]
-fn activate(store_path: &api::StorePath) -> Result<(), Report> {
+/// Home-manager generations bring their own `activate` script that works without root
+fn activate(store_path: &api::StorePath, config: &AgentConfig) -> Result<(), Report> {
     info!("Activating {store_path}");
-    set_system_profile(store_path)?;
-    Command::new(Path::new(&store_path).join("bin/switch-to-configuration"))
-        .arg("switch")
-        .spawn()?
-        .wait()?;
+    let mut command = if config.home_manager_activation {
+        set_profile(&version::home_manager_profile()?, store_path)?;
+        Command::new(Path::new(store_path).join("activate"))
+    } else {
+        if config.activation_method.needs_system_profile() {
+            set_profile(Path::new(SYSTEM_PROFILE), store_path)?;
+        }
+        config.activation_method.command(store_path)
+    };
+    let status = command.spawn()?.wait()?;
+    if !status.success() {
+        bail!("Activation of {store_path} failed: {status}");
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod test_agent {
+    use std::{
+        ffi::OsStr,
+        fs,
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn remove_all_dirs_unless() {
+        let base = tempfile::tempdir().unwrap();
+        for generation in ["0", "1", "2"] {
+            fs::create_dir_all(base.path().join(generation)).unwrap();
+        }
+
+        super::remove_all_dirs_unless(base.path(), OsStr::new("2")).unwrap();
+
+        let left: Vec<_> = fs::read_dir(base.path())
+            .unwrap()
+            .map(|dir| dir.unwrap().file_name())
+            .collect();
+        assert_eq!(left, vec!["2"]);
+    }
+
+    #[test]
+    fn replace_verification_code() {
+        assert_eq!(super::set_verification_code(111_111), None);
+        assert_eq!(super::set_verification_code(222_222), Some(111_111));
+        assert_eq!(super::verification_code(), Some(222_222));
+    }
+
+    #[test]
+    fn replace_symlink() {
+        let base = tempfile::tempdir().unwrap();
+        let link = base.path().join("secret");
+
+        super::replace_symlink(base.path().join("0"), &link).unwrap();
+        super::replace_symlink(base.path().join("1"), &link).unwrap();
+
+        assert_eq!(fs::read_link(&link).unwrap(), base.path().join("1"));
+    }
+
+    #[test]
+    fn preflight() {
+        let base = tempfile::tempdir().unwrap();
+        let generations = base.path().join("secret.d");
+
+        super::preflight(&generations, 1024).unwrap();
+        assert!(generations.is_dir());
+
+        // more than any disk can hold
+        super::preflight(&generations, u64::MAX).unwrap_err();
+
+        // a file in the way can not be fixed by running as root
+        let blocked = base.path().join("file");
+        fs::write(&blocked, b"").unwrap();
+        super::preflight(&blocked.join("secret.d"), 0).unwrap_err();
+    }
+
+    #[test]
+    fn partial_generation_cleanup() {
+        let base = tempfile::tempdir().unwrap();
+        let generation = base.path().join("0");
+        let link = base.path().join("secret");
+        let secret = |name: &str, owner: &str| super::FetchedSecret {
+            definition: api::Secret {
+                name: name.to_owned(),
+                path: format!("/run/{name}"),
+                mode: "0400".to_owned(),
+                owner: owner.to_owned(),
+                group: owner.to_owned(),
+                symlink: true,
+                size: None,
+            },
+            content: b"content".to_vec(),
+            content_type: None,
+        };
+
+        // the second secret fails after the first one was already written
+        super::write_generation(
+            &generation,
+            vec![secret("first", "0"), secret("second", "yeet-no-such-user")],
+            &link,
+        )
+        .unwrap_err();
+
+        assert!(!generation.exists());
+        fs::symlink_metadata(&link).unwrap_err();
+        // the temp directory is gone as well
+        let left: Vec<_> = fs::read_dir(base.path())
+            .unwrap()
+            .map(|dir| dir.unwrap().file_name())
+            .collect();
+        assert!(left.is_empty(), "{left:?}");
+    }
+
+    #[test]
+    fn trusted_public_keys_are_cached() {
+        let base = tempfile::tempdir().unwrap();
+        let nix_conf = base.path().join("nix.conf");
+        fs::write(&nix_conf, "trusted-public-keys = first:key= second:key=\n").unwrap();
+        let cache = super::KeyCache::new();
+
+        assert_eq!(
+            cache.get(&nix_conf).unwrap(),
+            vec!["first:key=", "second:key="]
+        );
+
+        // rotated keys are only picked up after invalidating the cache
+        fs::write(&nix_conf, "trusted-public-keys = rotated:key=\n").unwrap();
+        assert_eq!(
+            cache.get(&nix_conf).unwrap(),
+            vec!["first:key=", "second:key="]
+        );
+        fs::remove_file(&nix_conf).unwrap();
+        assert_eq!(
+            cache.get(&nix_conf).unwrap(),
+            vec!["first:key=", "second:key="]
+        );
+
+        cache.invalidate();
+        cache.get(&nix_conf).unwrap_err();
+        fs::write(&nix_conf, "trusted-public-keys = rotated:key=\n").unwrap();
+        assert_eq!(cache.get(&nix_conf).unwrap(), vec!["rotated:key="]);
+    }
+
+    #[test]
+    fn soak() {
+        let switch_to = |store_path: &str| {
+            api::AgentAction::SwitchTo(api::RemoteStorePath {
+                public_key: "cache:key=".to_owned(),
+                store_path: store_path.to_owned(),
+                substitutor: "https://cache.example.com".to_owned(),
+            })
+        };
+        let start = Instant::now();
+        let mut soak = None;
+
+        assert!(super::hold_back(
+            &mut soak,
+            &switch_to("/nix/store/a"),
+            Some(10),
+            start
+        ));
+        assert!(super::hold_back(
+            &mut soak,
+            &switch_to("/nix/store/a"),
+            Some(10),
+            start + Duration::from_mins(9)
+        ));
+        // a different version restarts the soak
+        assert!(super::hold_back(
+            &mut soak,
+            &switch_to("/nix/store/b"),
+            Some(10),
+            start + Duration::from_mins(9)
+        ));
+        assert!(super::hold_back(
+            &mut soak,
+            &switch_to("/nix/store/b"),
+            Some(10),
+            start + Duration::from_mins(18)
+        ));
+        let wall_clock: jiff::Timestamp = "2026-10-17T12:00:00Z".parse().unwrap();
+        assert_eq!(
+            soak.as_ref()
+                .unwrap()
+                .status(Some(10), start + Duration::from_mins(18), wall_clock),
+            Some(api::Soak {
+                store_path: "/nix/store/b".to_owned(),
+                until: "2026-10-17T12:01:00Z".parse().unwrap(),
+            })
+        );
+        assert!(!super::hold_back(
+            &mut soak,
+            &switch_to("/nix/store/b"),
+            Some(10),
+            start + Duration::from_mins(19)
+        ));
+        // nothing is reported once the soak passed
+        assert_eq!(
+            soak.as_ref()
+                .unwrap()
+                .status(Some(10), start + Duration::from_mins(19), wall_clock),
+            None
+        );
+        assert_eq!(
+            soak.as_ref()
+                .unwrap()
+                .status(None, start + Duration::from_mins(18), wall_clock),
+            None
+        );
+
+        // the server no longer switches
+        assert!(!super::hold_back(
+            &mut soak,
+            &api::AgentAction::Nothing,
+            Some(10),
+            start
+        ));
+        assert!(soak.is_none());
+        assert!(!super::hold_back(
+            &mut soak,
+            &switch_to("/nix/store/a"),
+            None,
+            start
+        ));
+    }
+
+    #[test]
+    fn parse_nix_conf_line() {
+        use super::NixConfLine;
+
+        for line in [
+            "trusted-public-keys = first:key= second:key=",
+            "trusted-public-keys=first:key= second:key=",
+            "  trusted-public-keys   =   first:key=  second:key=  ",
+            "trusted-public-keys = first:key= second:key= # from the cache",
+        ] {
+            assert_eq!(
+                super::parse_nix_conf_line(line),
+                Some(NixConfLine::TrustedPublicKeys(vec![
+                    "first:key=",
+                    "second:key="
+                ])),
+                "{line}"
+            );
+        }
+        assert_eq!(
+            super::parse_nix_conf_line("extra-trusted-public-keys=extra:key="),
+            Some(NixConfLine::ExtraTrustedPublicKeys(vec!["extra:key="]))
+        );
+        assert_eq!(
+            super::parse_nix_conf_line("include /etc/nix/machine.conf"),
+            Some(NixConfLine::Include {
+                path: "/etc/nix/machine.conf",
+                optional: false
+            })
+        );
+        assert_eq!(
+            super::parse_nix_conf_line("!include local.conf"),
+            Some(NixConfLine::Include {
+                path: "local.conf",
+                optional: true
+            })
+        );
+        for line in [
+            "",
+            "# trusted-public-keys = first:key=",
+            "substituters = https://cache.nixos.org",
+            "trusted-public-keys-file = /etc/keys",
+        ] {
+            assert_eq!(super::parse_nix_conf_line(line), None, "{line}");
+        }
+    }
+
+    #[test]
+    fn read_nix_conf_keys() {
+        let base = tempfile::tempdir().unwrap();
+        let nix_conf = base.path().join("nix.conf");
+
+        // nix falls back to the cache.nixos.org key
+        fs::write(&nix_conf, "extra-trusted-public-keys = extra:key=\n").unwrap();
+        let keys = super::read_nix_conf_keys(&nix_conf).unwrap();
+        assert_eq!(keys.trusted, None);
+        assert_eq!(keys.keys(), vec![super::NIXOS_CACHE_KEY, "extra:key="]);
+
+        fs::write(
+            &nix_conf,
+            "trusted-public-keys = first:key=\n\
+             extra-trusted-public-keys = extra:key=\n\
+             include machine.conf\n\
+             !include missing.conf\n",
+        )
+        .unwrap();
+        fs::write(
+            base.path().join("machine.conf"),
+            "trusted-public-keys=machine:key=\nextra-trusted-public-keys=other:key=\n",
+        )
+        .unwrap();
+        assert_eq!(
+            super::read_nix_conf_keys(&nix_conf).unwrap().keys(),
+            vec!["machine:key=", "extra:key=", "other:key="]
+        );
+
+        // required includes have to exist
+        fs::write(&nix_conf, "include missing.conf\n").unwrap();
+        super::read_nix_conf_keys(&nix_conf).unwrap_err();
+
+        fs::write(&nix_conf, "include nix.conf\n").unwrap();
+        super::read_nix_conf_keys(&nix_conf).unwrap_err();
+    }
+
+    #[test]
+    fn download_keys() {
+        let local = vec!["second:key=".to_owned(), "first:key=".to_owned()];
+        assert_eq!(
+            super::download_keys(local.clone(), Some("published:key=")),
+            vec!["first:key=", "published:key=", "second:key="]
+        );
+        // the published key is often trusted locally already
+        assert_eq!(
+            super::download_keys(local.clone(), Some("first:key=")),
+            vec!["first:key=", "second:key="]
+        );
+        assert_eq!(
+            super::download_keys(local, None),
+            vec!["first:key=", "second:key="]
+        );
+    }
+
+    #[test]
+    fn resolve_owner() {
+        assert_eq!(super::resolve_uid("1234").unwrap(), 1234);
+        assert_eq!(super::resolve_uid("root").unwrap(), 0);
+        assert_eq!(super::resolve_gid("root").unwrap(), 0);
+
+        let err = super::resolve_uid("yeet-no-such-user").unwrap_err();
+        assert!(
+            matches!(err, super::SecretDeployError::UnknownUser(user) if user == "yeet-no-such-user")
+        );
+        let err = super::resolve_gid("yeet-no-such-group").unwrap_err();
+        assert!(
+            matches!(err, super::SecretDeployError::UnknownGroup(group) if group == "yeet-no-such-group")
+        );
+    }
+
+    #[test]
+    fn deployed_owner() {
+        let secret = api::Secret {
+            name: "token".to_owned(),
+            path: "/run/token".to_owned(),
+            mode: "0400".to_owned(),
+            owner: "yeet-no-such-user".to_owned(),
+            group: "yeet-no-such-group".to_owned(),
+            symlink: true,
+            size: None,
+        };
+        // without root the owner from the definition is never resolved
+        assert_eq!(
+            super::deployed_owner(&secret, true).unwrap(),
+            (
+                ::nix::unistd::geteuid().as_raw(),
+                ::nix::unistd::getegid().as_raw()
+            )
+        );
+        super::deployed_owner(&secret, false).unwrap_err();
+
+        let root = api::Secret {
+            owner: "root".to_owned(),
+            group: "0".to_owned(),
+            ..secret
+        };
+        assert_eq!(super::deployed_owner(&root, false).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn simulation() {
+        let base = tempfile::tempdir().unwrap();
+        let value = |section: &crate::section::Section, key: &str| {
+            section
+                .1
+                .iter()
+                .find(|(item, _)| item == key)
+                .map(|(_, value)| value.clone())
+        };
+
+        let empty = super::simulation(Err("no system".to_owned()), base.path()).unwrap();
+        assert_eq!(
+            value(&empty, "Current version").unwrap(),
+            "unknown: no system"
+        );
+        assert_eq!(value(&empty, "Secrets").unwrap(), "none");
+        assert_eq!(value(&empty, "Last action"), None);
+
+        let generation = base.path().join("secret.d").join("0");
+        fs::create_dir_all(&generation).unwrap();
+        fs::write(generation.join("token"), b"token").unwrap();
+        fs::write(generation.join("netrc"), b"netrc").unwrap();
+        std::os::unix::fs::symlink(&generation, base.path().join("secret")).unwrap();
+        fs::File::create(base.path().join("DETACHED")).unwrap();
+        super::write_last_action(
+            &base.path().join(super::LAST_ACTION),
+            &api::AgentAction::Nothing,
+        )
+        .unwrap();
+
+        let provisioned =
+            super::simulation(Ok("/nix/store/abc-nixos-system".to_owned()), base.path()).unwrap();
+        assert_eq!(
+            value(&provisioned, "Current version").unwrap(),
+            "/nix/store/abc-nixos-system"
+        );
+        assert_eq!(value(&provisioned, "Secrets").unwrap(), "netrc\ntoken");
+        assert_eq!(value(&provisioned, "Last action").unwrap(), "Nothing");
+        assert!(
+            value(&provisioned, "Next action")
+                .unwrap()
+                .contains("Detached")
+        );
+    }
+
+    #[test]
+    fn unchanged_secrets_keep_generation() {
+        let base = tempfile::tempdir().unwrap();
+        let link = base.path().join("secret");
+        let owner = ::nix::unistd::getuid().to_string();
+        let secrets = |content: &[u8], mode: &str| {
+            vec![super::FetchedSecret {
+                definition: api::Secret {
+                    name: "token".to_owned(),
+                    path: "/run/token".to_owned(),
+                    mode: mode.to_owned(),
+                    owner: owner.clone(),
+                    group: ::nix::unistd::getgid().to_string(),
+                    symlink: true,
+                    size: None,
+                },
+                content: content.to_vec(),
+                content_type: None,
+            }]
+        };
+        let generations = || {
+            let mut generations: Vec<_> = fs::read_dir(base.path().join("secret.d"))
+                .unwrap()
+                .map(|dir| dir.unwrap().file_name())
+                .collect();
+            generations.sort();
+            generations
+        };
+
+        super::store_secrets(base.path(), secrets(b"token", "0400")).unwrap();
+        let first = fs::read_link(&link).unwrap();
+
+        // nothing changed: no new generation and the link stays
+        super::store_secrets(base.path(), secrets(b"token", "0400")).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), first);
+        assert_eq!(generations(), vec!["0"]);
+
+        // a failed switch must not delete the generation that is still in use
+        super::rollback_generation(base.path(), Some(&first), Some(&first)).unwrap();
+        assert!(first.is_dir());
+
+        // changed content or permissions create a new generation
+        super::store_secrets(base.path(), secrets(b"rotated", "0400")).unwrap();
+        assert_eq!(fs::read(link.join("token")).unwrap(), b"rotated");
+        super::store_secrets(base.path(), secrets(b"rotated", "0440")).unwrap();
+        assert_eq!(generations(), vec!["0", "1", "2"]);
+
+        // a removed secret as well
+        super::store_secrets(base.path(), Vec::new()).unwrap();
+        assert_eq!(generations(), vec!["0", "1", "2", "3"]);
+    }
+
+    #[test]
+    fn check_integrity() {
+        let secret = |size| api::Secret {
+            name: "token".to_owned(),
+            path: "/run/token".to_owned(),
+            mode: "0400".to_owned(),
+            owner: "0".to_owned(),
+            group: "0".to_owned(),
+            symlink: true,
+            size,
+        };
+
+        super::check_integrity(&secret(None), b"token").unwrap();
+        super::check_integrity(&secret(Some(5)), b"token").unwrap();
+
+        assert!(matches!(
+            super::check_integrity(&secret(Some(6)), b"token"),
+            Err(super::SecretDeployError::SizeMismatch {
+                expected: 6,
+                actual: 5,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn corrupt_secret_creates_no_generation() {
+        let base = tempfile::tempdir().unwrap();
+        let secrets = vec![super::FetchedSecret {
+            definition: api::Secret {
+                name: "token".to_owned(),
+                path: "/run/token".to_owned(),
+                mode: "0400".to_owned(),
+                owner: ::nix::unistd::getuid().to_string(),
+                group: ::nix::unistd::getgid().to_string(),
+                symlink: true,
+                size: Some(5),
+            },
+            content: b"garbage".to_vec(),
+            content_type: None,
+        }];
+
+        super::store_secrets(base.path(), secrets).unwrap_err();
+        assert!(!base.path().join("secret").exists());
+        assert!(!base.path().join("secret.d").join("0").exists());
+    }
+
+    #[test]
+    fn default_extension() {
+        assert_eq!(super::default_extension("pem"), Some("pem"));
+        assert_eq!(super::default_extension("yml"), Some("yaml"));
+        assert_eq!(super::default_extension("binary"), None);
+
+        let typed = |name: &str, content_type| super::typed_name(OsStr::new(name), content_type);
+        assert_eq!(typed("cert", Some("pem")).unwrap(), "cert.pem");
+        assert_eq!(typed("app", Some("dotenv")).unwrap(), "app.env");
+        // an extension from the nix config is kept
+        assert_eq!(typed("cert.crt", Some("pem")), None);
+        assert_eq!(typed("cert", Some("binary")), None);
+        assert_eq!(typed("cert", None), None);
+    }
+
+    #[test]
+    fn typed_secrets_are_linked() {
+        let base = tempfile::tempdir().unwrap();
+        let link = base.path().join("secret");
+        let owner = ::nix::unistd::getuid().to_string();
+        let secret = |name: &str, content_type: Option<&str>| super::FetchedSecret {
+            definition: api::Secret {
+                name: name.to_owned(),
+                path: format!("/run/{name}"),
+                mode: "0400".to_owned(),
+                owner: owner.clone(),
+                group: ::nix::unistd::getgid().to_string(),
+                symlink: true,
+                size: None,
+            },
+            content: name.as_bytes().to_vec(),
+            content_type: content_type.map(str::to_owned),
+        };
+
+        super::store_secrets(
+            base.path(),
+            vec![
+                secret("cert", Some("pem")),
+                secret("app", Some("env")),
+                secret("app.env", None),
+            ],
+        )
+        .unwrap();
+        // the file keeps its name so `path` from the nix module still works
+        assert_eq!(fs::read(link.join("cert")).unwrap(), b"cert");
+        assert_eq!(fs::read(link.join("cert.pem")).unwrap(), b"cert");
+        assert_eq!(
+            fs::read_link(link.join("cert.pem")).unwrap(),
+            Path::new("cert")
+        );
+        // a secret with the typed name wins over the link
+        assert_eq!(fs::read(link.join("app.env")).unwrap(), b"app.env");
+        let first = fs::read_link(&link).unwrap();
+
+        // the same secrets keep the generation, a changed content type does not
+        super::store_secrets(
+            base.path(),
+            vec![
+                secret("cert", Some("pem")),
+                secret("app", Some("env")),
+                secret("app.env", None),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), first);
+        super::store_secrets(
+            base.path(),
+            vec![
+                secret("cert", None),
+                secret("app", Some("env")),
+                secret("app.env", None),
+            ],
+        )
+        .unwrap();
+        assert_ne!(fs::read_link(&link).unwrap(), first);
+        fs::symlink_metadata(link.join("cert.pem")).unwrap_err();
+    }
+
+    #[test]
+    fn stale_generation_is_replaced() {
+        let base = tempfile::tempdir().unwrap();
+        let generation = base.path().join("secret.d").join("0");
+        let link = base.path().join("secret");
+        let owner = ::nix::unistd::getuid().to_string();
+
+        // a crash left a generation with a file the new one also writes
+        fs::create_dir_all(&generation).unwrap();
+        fs::write(generation.join("token"), b"stale").unwrap();
+
+        super::write_generation(
+            &generation,
+            vec![super::FetchedSecret {
+                definition: api::Secret {
+                    name: "token".to_owned(),
+                    path: "/run/token".to_owned(),
+                    mode: "0600".to_owned(),
+                    owner: owner.clone(),
+                    group: owner,
+                    symlink: true,
+                    size: None,
+                },
+                content: b"fresh".to_vec(),
+                content_type: None,
+            }],
+            &link,
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(link.join("token")).unwrap(), b"fresh");
+        let left: Vec<_> = fs::read_dir(base.path().join("secret.d"))
+            .unwrap()
+            .map(|dir| dir.unwrap().file_name())
+            .collect();
+        assert_eq!(left, vec!["0"]);
+    }
+
+    #[test]
+    fn jittered_sleep() {
+        use rand::SeedableRng as _;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let sleeps: Vec<_> = std::iter::repeat_with(|| super::jittered_sleep(30, 10, &mut rng))
+            .take(1000)
+            .collect();
+        assert!(
+            sleeps.iter().all(|sleep| {
+                (Duration::from_secs(27)..=Duration::from_secs(33)).contains(sleep)
+            })
+        );
+        // the check-ins actually spread out
+        assert!(sleeps.iter().any(|sleep| *sleep < Duration::from_secs(30)));
+        assert!(sleeps.iter().any(|sleep| *sleep > Duration::from_secs(30)));
+
+        // the same seed yields the same sleeps
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        assert_eq!(
+            Some(&super::jittered_sleep(30, 10, &mut rng)),
+            sleeps.first()
+        );
+
+        assert_eq!(
+            super::jittered_sleep(30, 0, &mut rng),
+            Duration::from_secs(30)
+        );
+        // never more than 100 percent
+        assert!(super::jittered_sleep(30, 255, &mut rng) <= Duration::from_mins(1));
+    }
+
+    #[test]
+    fn poll_pause() {
+        use rand::SeedableRng as _;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let wait = api::AgentAction::WaitForNextVersion {
+            suggest_poll_in_secs: 600,
+        };
+        // the suggestion replaces the sleep and is not jittered
+        assert_eq!(
+            super::poll_pause(&wait, None, 30, 10, &mut rng),
+            Duration::from_mins(10)
+        );
+        assert_eq!(
+            super::poll_pause(&api::AgentAction::Nothing, None, 30, 0, &mut rng),
+            Duration::from_secs(30)
+        );
+        // the header replaces the sleep for this iteration and is not jittered either
+        assert_eq!(
+            super::poll_pause(
+                &api::AgentAction::Nothing,
+                Some(Duration::from_secs(90)),
+                30,
+                10,
+                &mut rng
+            ),
+            Duration::from_secs(90)
+        );
+    }
+
+    #[test]
+    fn no_access_is_not_retried() {
+        use rootcause::prelude::ResultExt as _;
+
+        use super::SecretFetchError;
+
+        let no_access = Err::<(), _>(SecretFetchError::NoAccess("netrc".to_owned()))
+            .context("Could not switch")
+            .unwrap_err()
+            .into_dynamic();
+        assert!(SecretFetchError::is_no_access(&no_access));
+
+        let not_found =
+            rootcause::report!(SecretFetchError::NotFound("netrc".to_owned())).into_dynamic();
+        assert!(!SecretFetchError::is_no_access(&not_found));
+    }
+
+    #[test]
+    fn server_recipient_pin() {
+        use super::SecretFetchError;
+
+        let recipient = age::x25519::Identity::generate().to_public().to_string();
+        let other = age::x25519::Identity::generate().to_public().to_string();
+
+        super::check_server_recipient(&recipient, &format!("{recipient}\n")).unwrap();
+
+        let mismatch = super::check_server_recipient(&recipient, &other).unwrap_err();
+        assert!(matches!(
+            &mismatch,
+            SecretFetchError::ServerMismatch { pinned, server } if *pinned == recipient && *server == other
+        ));
+        let mismatch = rootcause::report!(mismatch).into_dynamic();
+        assert!(SecretFetchError::is_server_mismatch(&mismatch));
+        assert!(!SecretFetchError::is_no_access(&mismatch));
+    }
+
+    #[test]
+    fn retry_backoff() {
+        use backon::BackoffBuilder as _;
+        use rand::SeedableRng as _;
+
+        let delays: Vec<_> = super::retry_backoff(30).build().take(7).collect();
+        assert_eq!(
+            delays,
+            [30, 60, 120, 240, 300, 300, 300].map(Duration::from_secs)
+        );
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let retries: Vec<_> = std::iter::repeat_with(|| {
+            super::jittered(Duration::from_mins(5), super::RETRY_JITTER, &mut rng)
+        })
+        .take(1000)
+        .collect();
+        assert!(
+            retries
+                .iter()
+                .all(|retry| (Duration::from_mins(4)..=Duration::from_mins(6)).contains(retry))
+        );
+    }
+
+    #[test]
+    fn last_action() {
+        let base = tempfile::tempdir().unwrap();
+        let path = base.path().join("last-action.json");
+        assert_eq!(super::read_last_action(&path).unwrap(), None);
+
+        let action = api::AgentAction::SwitchTo(api::RemoteStorePath {
+            public_key: "cache:key".to_owned(),
+            store_path: "/nix/store/known-good".to_owned(),
+            substitutor: "https://cache.example".to_owned(),
+        });
+        super::write_last_action(&path, &action).unwrap();
+        assert_eq!(
+            super::read_last_action(&path).unwrap(),
+            Some(action.clone())
+        );
+
+        // a rebooted host that runs something else goes back to the known-good version
+        assert_eq!(
+            super::offline_switch(Some(action.clone()), &"/nix/store/other".to_owned()),
+            Some("/nix/store/known-good".to_owned())
+        );
+        // no switch if the version is already active
+        assert_eq!(
+            super::offline_switch(Some(action), &"/nix/store/known-good".to_owned()),
+            None
+        );
+        // a detached host is left alone
+        assert_eq!(
+            super::offline_switch(
+                Some(api::AgentAction::Detach),
+                &"/nix/store/other".to_owned()
+            ),
+            None
+        );
+        assert_eq!(
+            super::offline_switch(None, &"/nix/store/other".to_owned()),
+            None
+        );
+    }
+
+    #[test]
+    fn interrupted_activation() {
+        // killed before the new system became active
+        assert_eq!(
+            super::interrupted_activation(
+                "/nix/store/new".to_owned(),
+                &"/nix/store/old".to_owned()
+            ),
+            Some(api::ActivationReport {
+                store_path: "/nix/store/new".to_owned(),
+                success: false,
+                error: Some("activation was interrupted".to_owned()),
+            })
+        );
+        // killed after the switch went through
+        assert_eq!(
+            super::interrupted_activation(
+                "/nix/store/new".to_owned(),
+                &"/nix/store/new".to_owned()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn age_identity() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let base = tempfile::tempdir().unwrap();
+        let path = base.path().join("yeet").join("age.key");
+
+        let created = super::age_identity(&path).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        // the identity is stable across restarts
+        let loaded = super::age_identity(&path).unwrap();
+        assert_eq!(
+            created.to_public().to_string(),
+            loaded.to_public().to_string()
+        );
+    }
+
+    #[test]
+    fn generations_under_base() {
+        let base = tempfile::tempdir().unwrap();
+        let generations = base.path().join("secret.d");
+        let link = base.path().join("secret");
+        let owner = ::nix::unistd::getuid().to_string();
+        let secret = |content: &[u8]| {
+            vec![super::FetchedSecret {
+                definition: api::Secret {
+                    name: "token".to_owned(),
+                    path: "/run/token".to_owned(),
+                    mode: "0600".to_owned(),
+                    owner: owner.clone(),
+                    group: owner.clone(),
+                    symlink: true,
+                    size: None,
+                },
+                content: content.to_vec(),
+                content_type: None,
+            }]
+        };
+
+        let first = super::next_generation(base.path());
+        assert_eq!(first, generations.join("0"));
+        super::write_generation(&first, secret(b"first"), &link).unwrap();
+
+        let second = super::next_generation(base.path());
+        assert_eq!(second, generations.join("1"));
+        super::write_generation(&second, secret(b"second"), &link).unwrap();
+        assert_eq!(fs::read(link.join("token")).unwrap(), b"second");
+
+        // a failed switch goes back to the previous generation
+        super::rollback_generation(base.path(), Some(&first), Some(&second)).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), first);
+        assert_eq!(fs::read(link.join("token")).unwrap(), b"first");
+
+        // a successful switch only keeps the active generation
+        let third = super::next_generation(base.path());
+        assert_eq!(third, generations.join("1"));
+        super::write_generation(&third, secret(b"third"), &link).unwrap();
+        super::clean_generations(base.path(), &third);
+        let left: Vec<_> = fs::read_dir(&generations)
+            .unwrap()
+            .map(|dir| dir.unwrap().file_name())
+            .collect();
+        assert_eq!(left, vec!["1"]);
+    }
+
+    #[test]
+    fn detached_marker() {
+        let base = tempfile::tempdir().unwrap();
+        let marker = super::detached_marker(base.path());
+
+        assert!(!super::reset_detached(base.path()).unwrap());
+
+        super::write_detached(base.path()).unwrap();
+        assert!(marker.exists());
+        // detaching twice is fine
+        super::write_detached(base.path()).unwrap();
+
+        assert!(super::reset_detached(base.path()).unwrap());
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    async fn startup_in_secret_base() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("agent.key");
+        crate::cli::key::write_keypair(&key, false).unwrap();
+        let base = dir.path().join("home/.config/yeet/secrets");
+        // a home-manager agent without a reachable server
+        let config: crate::cli_args::AgentConfig = toml::from_str(&format!(
+            "server = \"http://127.0.0.1:1\"\nsleep = 30\nfacter = false\nkey = {key:?}\nsecret_base = {base:?}\nhome_manager_activation = true\nconnect_timeout = 1\nrequest_timeout = 1"
+        ))
+        .unwrap();
+
+        let (_, _, _, identity) = super::startup(&config).await.unwrap();
+
+        let identity_path = base.join(super::AGE_IDENTITY);
+        assert_eq!(
+            fs::metadata(&identity_path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        assert_eq!(
+            super::age_identity(&identity_path).unwrap().to_public(),
+            identity.to_public()
+        );
+        let written: Vec<_> = fs::read_dir(&base)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(written, vec![super::AGE_IDENTITY]);
+    }
+}
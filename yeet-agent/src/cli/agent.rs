@@ -0,0 +1,214 @@
+//! Prepare a new agent. `yeet agent init` generates the ed25519 identity of the agent
+
+use std::{
+    fs::{read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+use clap::Subcommand;
+use colored::Colorize as _;
+use log::{info, warn};
+use rootcause::{Report, bail, prelude::ResultExt as _};
+use ssh_key::HashAlg;
+use yeet::nix;
+
+use crate::{
+    activation::ActivationMethod,
+    agent,
+    cli::{common, key},
+    cli_args::{
+        self, AgentConfig, Config, DEFAULT_CONNECT_TIMEOUT, DEFAULT_REQUEST_TIMEOUT,
+        DEFAULT_SECRET_BASE,
+    },
+    notification, section, varlink,
+};
+
+/// Default of `yeet agent --sleep`
+const DEFAULT_SLEEP: u64 = 30;
+/// Default of `yeet agent --jitter`
+const DEFAULT_JITTER: u8 = 10;
+
+#[derive(Subcommand)]
+pub enum AgentCommands {
+    /// Generate a new ed25519 key for the agent
+    Init {
+        /// Where to store the private key.
+        /// The public key is stored next to it with a `.pub` suffix
+        #[arg(long)]
+        key_output: PathBuf,
+
+        /// Replace an existing key
+        #[arg(long)]
+        overwrite: bool,
+
+        /// Also write an agent config skeleton using the new key. Requires a server url
+        #[arg(long)]
+        config_output: Option<PathBuf>,
+    },
+    /// Let an agent that was detached by the server poll the server again
+    Reset {
+        /// `--secret-base` of the agent. The detached marker is stored in there
+        #[arg(long)]
+        secret_base: Option<PathBuf>,
+
+        /// The agent runs with `--home-manager-activation`. Its secret base defaults to
+        /// `~/.config/yeet/secrets`
+        #[arg(long)]
+        home_manager_activation: bool,
+    },
+    /// Let the running agent delete old generations and collect garbage
+    Gc {
+        /// Delete generations older than this many days
+        #[arg(long, default_value = "7d", value_parser = nix::parse_gc_age)]
+        older_than: String,
+    },
+    /// Let the running agent read the trusted public keys from `/etc/nix/nix.conf` again
+    InvalidateKeyCache,
+    /// Activate a store path that is already on this host without asking the server.
+    /// For local testing and emergency deployments
+    Switch {
+        /// The system or home-manager generation to activate
+        #[arg(long)]
+        store_path: api::StorePath,
+
+        /// Agent config to use instead of asking the running agent for its config
+        #[arg(long)]
+        agent_config: Option<PathBuf>,
+
+        /// Keep the current secrets. The server is not contacted at all
+        #[arg(long)]
+        no_secrets: bool,
+    },
+}
+
+pub async fn handle_command(command: AgentCommands, config: &Config) -> Result<(), Report> {
+    match command {
+        AgentCommands::Init {
+            key_output,
+            overwrite,
+            config_output,
+        } => init(config, &key_output, overwrite, config_output.as_deref()).await,
+        AgentCommands::Reset {
+            secret_base,
+            home_manager_activation,
+        } => reset(&cli_args::secret_base(
+            secret_base,
+            home_manager_activation,
+        )?),
+        AgentCommands::Gc { older_than } => gc(older_than).await,
+        AgentCommands::InvalidateKeyCache => {
+            varlink::invalidate_key_cache().await?;
+            info!("The agent reads the trusted public keys again on the next update");
+            Ok(())
+        }
+        AgentCommands::Switch {
+            store_path,
+            agent_config,
+            no_secrets,
+        } => switch(&store_path, agent_config.as_deref(), no_secrets).await,
+    }
+}
+
+async fn switch(
+    store_path: &api::StorePath,
+    agent_config: Option<&Path>,
+    no_secrets: bool,
+) -> Result<(), Report> {
+    if !Path::new(store_path).exists() {
+        bail!("{store_path} does not exist. Copy it to this host first e.g. with `nix copy`");
+    }
+    let config = match agent_config {
+        Some(path) => toml::from_str::<AgentConfig>(&read_to_string(path)?)
+            .attach(format!("Config file: {}", path.display()))?,
+        None => varlink::config().await?,
+    };
+
+    if no_secrets {
+        info!("Keeping the current secrets");
+    } else {
+        agent::fetch_secrets(store_path, &config).await?;
+    }
+    agent::switch_to(store_path, &config).await?;
+
+    info!("Switched to {store_path}");
+    warn!("Unless the host is detached the agent switches to the version of the server again");
+    Ok(())
+}
+
+fn reset(secret_base: &Path) -> Result<(), Report> {
+    if agent::reset_detached(secret_base)? {
+        info!("Agent reset. It polls the server again within its sleep interval");
+        warn!("Unless the host is attached again the server detaches it on the next poll");
+    } else {
+        info!("Agent is not detached. Nothing to reset");
+    }
+    Ok(())
+}
+
+async fn gc(older_than: String) -> Result<(), Report> {
+    info!("Collecting garbage older than {older_than}...");
+    let freed = varlink::gc(older_than).await?;
+    info!("{freed}");
+    Ok(())
+}
+
+async fn init(
+    config: &Config,
+    key_output: &Path,
+    overwrite: bool,
+    config_output: Option<&Path>,
+) -> Result<(), Report> {
+    if key_output.exists() {
+        if !overwrite {
+            bail!(
+                "{} already exists. Use `--overwrite` to replace it",
+                key_output.display()
+            );
+        }
+        warn!(
+            "Overwriting {}. Hosts using the old key have to be verified again",
+            key_output.display()
+        );
+    }
+
+    let public_key = key::write_keypair(key_output, overwrite)?;
+    let public_key_output = key::public_key_path(key_output);
+
+    if let Some(config_output) = config_output {
+        let agent_config = AgentConfig {
+            server: common::get_server_url(config).await?,
+            sleep: DEFAULT_SLEEP,
+            jitter: DEFAULT_JITTER,
+            facter: false,
+            facter_interval: None,
+            key: std::path::absolute(key_output)?,
+            secret_base: PathBuf::from(DEFAULT_SECRET_BASE),
+            notifications: notification::default_backends(),
+            gc_after_update: None,
+            activation_method: ActivationMethod::default(),
+            home_manager_activation: false,
+            soak_minutes: None,
+            server_recipient: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            varlink_tcp: None,
+            varlink_tcp_cert: None,
+            varlink_tcp_key: None,
+            varlink_tcp_client_ca: None,
+        };
+        write(config_output, toml::to_string(&agent_config)?)
+            .attach(format!("Config file: {}", config_output.display()))?;
+        info!("Agent config written to {}", config_output.display());
+    }
+
+    section::print_sections(&[section::section!(
+        "Agent Key".bold().underline() => [
+            "Private Key", key_output.display(),
+            "Public Key", public_key_output.display(),
+            "Fingerprint", public_key.fingerprint(HashAlg::Sha256),
+            "YEET_INIT_KEY", std::path::absolute(&public_key_output)?.display(),
+        ]
+    )]);
+
+    Ok(())
+}
@@ -1,20 +1,24 @@
 use std::{
-    fs::File,
+    fs::{File, read_to_string},
     io::Write as _,
     path::{Path, PathBuf},
 };
 
+use colored::Colorize as _;
+use httpsig_hyper::prelude::SecretKey;
 use inquire::validator::Validation;
-use log::info;
-use rootcause::Report;
+use log::{info, warn};
+use rootcause::{Report, prelude::ResultExt as _};
+use serde::Deserialize;
 
 use crate::{
     cli::{self, common},
     cli_args::Config,
+    section,
     sig::ssh,
 };
 
-pub async fn approve(config: &Config) -> Result<(), Report> {
+pub async fn approve(config: &Config, no_secrets: bool) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let secret_key = &ssh::key_by_url(&url)?;
 
@@ -30,11 +34,42 @@ pub async fn approve(config: &Config) -> Result<(), Report> {
 
     info!("Approved");
 
-    if nixos_facter.is_none() {
-        return Ok(());
+    if let Some(nixos_facter) = nixos_facter {
+        write_facter(&nixos_facter)?;
+    }
+
+    let secrets = if no_secrets {
+        Vec::new()
+    } else {
+        cli::secret::picker_secrets(&url, secret_key).await?
+    };
+    if grant_secrets(no_secrets, &secrets) {
+        // TODO: allow to limit the host
+        cli::secret::allow_for(&url, secret_key, secrets).await?;
+    }
+    Ok(())
+}
+
+/// Decide if the operator is asked which secrets the new host may access
+fn grant_secrets(no_secrets: bool, secrets: &[api::SecretName]) -> bool {
+    if no_secrets {
+        info!("Not granting access to any secrets (`--no-secrets`)");
+        return false;
+    }
+    if secrets.is_empty() {
+        info!("No secrets defined yet. Nothing to grant");
+        return false;
+    }
+    true
+}
+
+/// Show the nixos-facter report and write it to a file chosen by the operator
+fn write_facter(nixos_facter: &str) -> Result<(), Report> {
+    // Give the operator a chance to check if this is the expected machine
+    match serde_json::from_str::<Facter>(nixos_facter) {
+        Ok(facter) => section::print_sections(&[facter.summary()]),
+        Err(err) => warn!("Could not parse the nixos-facter report: {err}"),
     }
-    #[expect(clippy::unwrap_used)] // we checked
-    let nixos_facter = nixos_facter.unwrap();
 
     // Get file to write facter data
     let facter_output = {
@@ -62,8 +97,302 @@ pub async fn approve(config: &Config) -> Result<(), Report> {
 
     File::create_new(&facter_output)?.write_all(nixos_facter.as_bytes())?;
     info!("File {} written", facter_output.as_os_str().display());
+    Ok(())
+}
+
+/// One line of the `--batch` csv file
+#[derive(Debug, PartialEq, Eq)]
+struct BatchEntry {
+    code: u32,
+    hostname: String,
+    facter_output: Option<PathBuf>,
+}
+
+/// Parse the `code,hostname,facter_output_path` csv. A header line and empty lines are skipped
+fn parse_batch(content: &str) -> Result<Vec<BatchEntry>, Report> {
+    let mut entries = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (number == 0 && line.starts_with("code")) {
+            continue;
+        }
+
+        let mut columns = line.split(',').map(str::trim);
+        let (Some(code), Some(hostname)) = (columns.next(), columns.next()) else {
+            rootcause::bail!(
+                "Line {}: expected `code,hostname,facter_output_path`",
+                number.saturating_add(1)
+            );
+        };
+        if hostname.is_empty() {
+            rootcause::bail!("Line {}: hostname is required", number.saturating_add(1));
+        }
+
+        entries.push(BatchEntry {
+            code: code.parse().attach(format!(
+                "Line {}: invalid code `{code}`",
+                number.saturating_add(1)
+            ))?,
+            hostname: hostname.to_owned(),
+            facter_output: columns
+                .next()
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+        });
+    }
+    Ok(entries)
+}
+
+/// Approve every host in `batch`. Failures do not stop the remaining approvals.
+/// A report of all approvals is printed at the end
+pub async fn approve_batch(config: &Config, batch: &Path, parallel: bool) -> Result<(), Report> {
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
+
+    let entries = parse_batch(&read_to_string(batch).attach(batch.display().to_string())?)?;
+
+    let results = if parallel {
+        futures_util::future::join_all(
+            entries
+                .iter()
+                .map(|entry| approve_entry(&url, secret_key, entry)),
+        )
+        .await
+    } else {
+        let mut results = Vec::new();
+        for entry in &entries {
+            results.push(approve_entry(&url, secret_key, entry).await);
+        }
+        results
+    };
+
+    let mut approved = Vec::new();
+    let mut failed = Vec::new();
+    for (entry, result) in entries.iter().zip(results) {
+        match result {
+            Ok(()) => approved.push((entry.hostname.clone(), entry.code.to_string())),
+            Err(err) => failed.push((entry.hostname.clone(), err.to_string())),
+        }
+    }
+
+    section::print_sections(&[
+        ("Approved".green().bold().to_string(), approved),
+        ("Failed".red().bold().to_string(), failed.clone()),
+    ]);
 
-    // TODO: allow to limit the host
-    cli::secret::allow(config).await?;
+    if !failed.is_empty() {
+        rootcause::bail!("{} of {} approvals failed", failed.len(), entries.len());
+    }
     Ok(())
 }
+
+async fn approve_entry(
+    url: &url::Url,
+    secret_key: &SecretKey,
+    entry: &BatchEntry,
+) -> Result<(), Report> {
+    info!("Approving {} with code {}...", entry.hostname, entry.code);
+    let nixos_facter = api::accept_attempt(url, secret_key, entry.code, &entry.hostname).await?;
+    if let (Some(output), Some(nixos_facter)) = (&entry.facter_output, nixos_facter) {
+        File::create_new(output)
+            .attach(output.display().to_string())?
+            .write_all(nixos_facter.as_bytes())?;
+        info!("File {} written", output.display());
+    }
+    Ok(())
+}
+
+/// The subset of the nixos-facter report that is shown before writing it to disk
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Facter {
+    system: Option<String>,
+    hardware: FacterHardware,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FacterHardware {
+    cpu: Vec<FacterCpu>,
+    memory: Vec<FacterMemory>,
+    disk: Vec<serde_json::Value>,
+    network_interface: Vec<FacterNetworkInterface>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FacterCpu {
+    architecture: Option<String>,
+    model_name: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FacterMemory {
+    resources: Vec<FacterResource>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FacterResource {
+    #[serde(rename = "type")]
+    kind: String,
+    range: u64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FacterNetworkInterface {
+    unix_device_names: Vec<String>,
+}
+
+impl Facter {
+    fn summary(&self) -> section::Section {
+        let cpu = self.hardware.cpu.first();
+        let architecture = cpu
+            .and_then(|cpu| cpu.architecture.clone())
+            .or_else(|| self.system.clone())
+            .unwrap_or("Unknown".to_owned());
+        let model = cpu
+            .and_then(|cpu| cpu.model_name.clone())
+            .unwrap_or("Unknown".to_owned());
+
+        let memory: u64 = self
+            .hardware
+            .memory
+            .iter()
+            .flat_map(|memory| &memory.resources)
+            .filter(|resource| resource.kind == "phys_mem")
+            .map(|resource| resource.range)
+            .sum();
+
+        let mut interfaces: Vec<_> = self
+            .hardware
+            .network_interface
+            .iter()
+            .flat_map(|interface| interface.unix_device_names.clone())
+            .collect();
+        interfaces.sort();
+        interfaces.dedup();
+
+        section::section!(
+            "Hardware".bold().underline() => [
+                "Architecture", architecture,
+                "CPU", format!("{model} ({} threads)", self.hardware.cpu.len()),
+                "Memory", format!("{} MiB", memory.div_euclid(1024 * 1024)),
+                "Disks", self.hardware.disk.len(),
+                "Network", interfaces.join("\n"),
+            ]
+        )
+    }
+}
+
+#[cfg(test)]
+mod test_approve {
+    use std::path::PathBuf;
+
+    use super::{BatchEntry, Facter};
+
+    #[test]
+    fn parse_batch() {
+        let entries = super::parse_batch(
+            "code,hostname,facter_output_path
+            123456, host-a, hosts/host-a/facter.json
+
+            654321,host-b,
+            111111,host-c",
+        )
+        .unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                BatchEntry {
+                    code: 123_456,
+                    hostname: "host-a".to_owned(),
+                    facter_output: Some(PathBuf::from("hosts/host-a/facter.json")),
+                },
+                BatchEntry {
+                    code: 654_321,
+                    hostname: "host-b".to_owned(),
+                    facter_output: None,
+                },
+                BatchEntry {
+                    code: 111_111,
+                    hostname: "host-c".to_owned(),
+                    facter_output: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_batch_invalid() {
+        super::parse_batch("abc,host-a,").unwrap_err();
+        super::parse_batch("123456").unwrap_err();
+        super::parse_batch("123456,,facter.json").unwrap_err();
+    }
+
+    #[test]
+    fn grant_secrets() {
+        let secret: api::SecretName =
+            serde_json::from_str(r#"{ "id": 1, "name": "my-secret", "tags": [], "hosts": [] }"#)
+                .unwrap();
+
+        assert!(super::grant_secrets(false, std::slice::from_ref(&secret)));
+        // the flag skips the allow step even when there are secrets
+        assert!(!super::grant_secrets(true, &[secret]));
+        // nothing to ask for without secrets
+        assert!(!super::grant_secrets(false, &[]));
+    }
+
+    #[test]
+    fn facter_summary() {
+        let facter: Facter = serde_json::from_str(
+            r#"{
+                "version": 1,
+                "system": "x86_64-linux",
+                "hardware": {
+                    "cpu": [
+                        { "architecture": "x86_64", "model_name": "Some CPU" },
+                        { "architecture": "x86_64", "model_name": "Some CPU" }
+                    ],
+                    "memory": [
+                        { "resources": [
+                            { "type": "phys_mem", "range": 8589934592 },
+                            { "type": "mem", "base": 0, "range": 8000000000 }
+                        ] }
+                    ],
+                    "disk": [ { "model": "disk a" }, { "model": "disk b" } ],
+                    "network_interface": [
+                        { "unix_device_names": ["lo"] },
+                        { "unix_device_names": ["eth0"] }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (_, items) = facter.summary();
+        let value = |key: &str| {
+            items
+                .iter()
+                .find(|(item, _)| item == key)
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+        assert_eq!(value("Architecture"), "x86_64");
+        assert_eq!(value("CPU"), "Some CPU (2 threads)");
+        assert_eq!(value("Memory"), "8192 MiB");
+        assert_eq!(value("Disks"), "2");
+        assert_eq!(value("Network"), "eth0\nlo");
+    }
+
+    #[test]
+    fn facter_summary_missing_fields() {
+        let facter: Facter = serde_json::from_str(r#"{ "system": "aarch64-linux" }"#).unwrap();
+
+        let (_, items) = facter.summary();
+        assert!(items.contains(&("Architecture".to_owned(), "aarch64-linux".to_owned())));
+    }
+}
@@ -0,0 +1,110 @@
+//! Answers of the server that rarely change, cached in the XDG cache directory.
+//! Entries are keyed by server url and revalidated with the version the server sent
+
+use std::{
+    collections::HashMap,
+    fs::{read_to_string, write},
+    path::Path,
+};
+
+use httpsig_hyper::prelude::SecretKey;
+use rootcause::{Report, prelude::ResultExt as _};
+
+const RECIPIENT_CACHE: &str = "server_recipients.json";
+
+type Recipients = HashMap<String, api::ServerRecipient>;
+
+/// The recipient of `/secret/server_key`. A cached recipient is only revalidated which skips
+/// sending it again if it did not change
+pub async fn server_recipient(url: &url::Url, key: &SecretKey) -> Result<String, Report> {
+    let path = match xdg::BaseDirectories::with_prefix("yeet").place_cache_file(RECIPIENT_CACHE) {
+        Ok(path) => path,
+        Err(err) => {
+            log::warn!("Not caching the server recipient: {err}");
+            return Ok(api::server_age_key(url, key).await?);
+        }
+    };
+
+    let mut recipients = load(&path);
+    let fresh = api::server_recipient(url, key, recipients.get(url.as_str())).await?;
+    if remember(&mut recipients, url, &fresh)
+        && let Err(err) = store(&path, &recipients)
+    {
+        log::warn!("Could not cache the server recipient: {err}");
+    }
+    Ok(fresh.recipient)
+}
+
+/// A missing or unreadable cache is treated as empty
+fn load(path: &Path) -> Recipients {
+    read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn store(path: &Path, recipients: &Recipients) -> Result<(), Report> {
+    write(path, serde_json::to_string(recipients)?)
+        .attach(format!("Cache file: {}", path.display()))?;
+    Ok(())
+}
+
+/// `true` if the cache changed and has to be written
+fn remember(recipients: &mut Recipients, url: &url::Url, fresh: &api::ServerRecipient) -> bool {
+    if fresh.version.is_empty() {
+        return recipients.remove(url.as_str()).is_some();
+    }
+    recipients.insert(url.to_string(), fresh.clone()).as_ref() != Some(fresh)
+}
+
+#[cfg(test)]
+mod test_cache {
+    use std::str::FromStr as _;
+
+    fn recipient(version: &str) -> api::ServerRecipient {
+        api::ServerRecipient {
+            recipient: format!("age1{version}"),
+            version: api::recipient_version(version),
+        }
+    }
+
+    #[test]
+    fn cache_hit_and_invalidation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(super::RECIPIENT_CACHE);
+        let url = url::Url::from_str("https://yeet.example").unwrap();
+        let other = url::Url::from_str("https://other.example").unwrap();
+
+        let mut recipients = super::load(&path);
+        assert!(recipients.is_empty());
+        assert!(super::remember(&mut recipients, &url, &recipient("a")));
+        assert!(super::remember(&mut recipients, &other, &recipient("b")));
+        super::store(&path, &recipients).unwrap();
+
+        // the server answered `304` so the cached entry is returned unchanged
+        let mut recipients = super::load(&path);
+        assert_eq!(recipients.get(url.as_str()), Some(&recipient("a")));
+        assert!(!super::remember(&mut recipients, &url, &recipient("a")));
+
+        // the recipient changed on the server
+        assert!(super::remember(&mut recipients, &url, &recipient("c")));
+        assert_eq!(recipients.get(url.as_str()), Some(&recipient("c")));
+        assert_eq!(recipients.get(other.as_str()), Some(&recipient("b")));
+
+        // a server without `ETag` is not cached
+        let unversioned = api::ServerRecipient {
+            recipient: "age1d".to_owned(),
+            version: String::new(),
+        };
+        assert!(super::remember(&mut recipients, &url, &unversioned));
+        assert!(!recipients.contains_key(url.as_str()));
+    }
+
+    #[test]
+    fn corrupt_cache_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(super::RECIPIENT_CACHE);
+        std::fs::write(&path, "not json").unwrap();
+        assert!(super::load(&path).is_empty());
+    }
+}
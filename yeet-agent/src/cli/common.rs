@@ -1,17 +1,22 @@
 use crate::{cli_args::Config, varlink};
 
+/// Resolves the server url in the following order:
+/// 1. `--url`
+/// 2. `YEET_URL`
+/// 3. The server remembered with `yeet config set-server` in `agent.toml`
+/// 4. The server of the local agent
 pub async fn get_server_url(config: &Config) -> Result<url::Url, rootcause::Report> {
-    let agent_url = {
-        let agent_config = varlink::config().await;
-        if let Err(err) = &agent_config {
+    if let Some(url) = config.url.clone() {
+        return Ok(url);
+    }
+
+    match varlink::config().await {
+        Ok(agent_config) => Ok(agent_config.server),
+        Err(err) => {
             log::error!("Could not get agent config: {err}");
+            Err(rootcause::report!(
+                "`--url` required. Use `yeet config set-server --url <url>` to remember it"
+            ))
         }
-        agent_config.ok().map(|config| config.server)
-    };
-
-    config
-        .url
-        .clone()
-        .or(agent_url)
-        .ok_or(rootcause::report!("`--url` required for publish"))
+    }
 }
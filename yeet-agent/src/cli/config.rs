@@ -0,0 +1,181 @@
+//! Local CLI configuration stored in `~/.config/yeet/agent.toml`.
+//! Later sources win: `agent.toml` < `YEET_*` environment variables < flags.
+//! The server of the local agent is only asked if none of them names a server
+
+use std::{
+    fs::{read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Subcommand};
+use colored::Colorize as _;
+use figment::{
+    Figment, Provider,
+    providers::{Format as _, Serialized, Toml},
+};
+use log::info;
+use rootcause::{Report, prelude::ResultExt as _};
+use url::Url;
+
+use crate::{
+    cli::common,
+    cli_args::{ClapConfig, Config},
+    section,
+};
+
+pub const CONFIG_FILE: &str = "agent.toml";
+/// Key of the server in `CONFIG_FILE`. The same as `--url` and `YEET_URL`
+const SERVER_KEY: &str = "url";
+
+#[derive(Args)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommands,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Remember the server given with `--url` for future invocations
+    SetServer,
+    /// Forget the remembered server
+    UnsetServer,
+    /// Show the resolved configuration
+    Show,
+}
+
+/// Merges `file` (usually `CONFIG_FILE`), `env` (usually `YEET_*`) and `flags` in that order
+pub fn resolve(
+    file: Option<&Path>,
+    env: impl Provider,
+    flags: ClapConfig,
+) -> Result<Config, Report> {
+    Ok(Figment::new()
+        .merge(Toml::file(file.unwrap_or(Path::new(""))))
+        .merge(env)
+        .merge(Serialized::defaults(flags))
+        .extract()?)
+}
+
+fn config_file() -> Option<PathBuf> {
+    xdg::BaseDirectories::with_prefix("yeet").find_config_file(CONFIG_FILE)
+}
+
+/// The whole `CONFIG_FILE` so that keys other than the server are kept when it is written
+fn load_table() -> Result<toml::Table, Report> {
+    let Some(path) = config_file() else {
+        return Ok(toml::Table::new());
+    };
+    let content = read_to_string(&path).attach(format!("Config file: {}", path.display()))?;
+    Ok(toml::from_str(&content).attach(format!("Config file: {}", path.display()))?)
+}
+
+fn store_table(table: &toml::Table) -> Result<(), Report> {
+    let path = xdg::BaseDirectories::with_prefix("yeet")
+        .place_config_file(CONFIG_FILE)
+        .context("Could not create the yeet config directory")?;
+    write(&path, toml::to_string(table)?).attach(format!("Config file: {}", path.display()))?;
+    info!("Config written to {}", path.display());
+    Ok(())
+}
+
+/// `flag_url` is `--url` as given. A server from `YEET_URL` or the config file is not persisted
+pub async fn handle_command(
+    args: ConfigArgs,
+    config: &Config,
+    flag_url: Option<Url>,
+) -> Result<(), Report> {
+    match args.command {
+        ConfigCommands::SetServer => set_server(flag_url),
+        ConfigCommands::UnsetServer => unset_server(),
+        ConfigCommands::Show => show(config).await,
+    }
+}
+
+fn set_server(flag_url: Option<Url>) -> Result<(), Report> {
+    let url = flag_url.ok_or(rootcause::report!("`--url` required to set the server"))?;
+
+    let mut table = load_table()?;
+    info!("Remembering {url} as server");
+    table.insert(SERVER_KEY.to_owned(), toml::Value::String(url.to_string()));
+    store_table(&table)
+}
+
+fn unset_server() -> Result<(), Report> {
+    let mut table = load_table()?;
+    if table.remove(SERVER_KEY).is_none() {
+        info!("No server set");
+        return Ok(());
+    }
+    store_table(&table)
+}
+
+async fn show(config: &Config) -> Result<(), Report> {
+    let remembered = load_table()?
+        .get(SERVER_KEY)
+        .and_then(toml::Value::as_str)
+        .map(str::to_owned);
+    let not_set = || "Not set".italic().to_string();
+
+    let server = match common::get_server_url(config).await {
+        Ok(url) => url.to_string(),
+        Err(_) => not_set(),
+    };
+
+    section::print_sections(&[section::section!(
+        "Config".bold().underline() => [
+            "Server", server,
+            "Remembered Server", remembered.unwrap_or_else(not_set),
+            "Cachix", config.cachix.clone().unwrap_or_else(not_set),
+            "Config File", xdg::BaseDirectories::with_prefix("yeet")
+                .get_config_file(CONFIG_FILE)
+                .map_or_else(not_set, |path| path.display().to_string()),
+        ]
+    )]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_config {
+    use figment::providers::{Data, Format as _, Toml};
+
+    use crate::cli_args::ClapConfig;
+
+    fn flags(url: Option<&str>) -> ClapConfig {
+        ClapConfig {
+            url: url.map(|url| url.parse().unwrap()),
+            cachix: None,
+            cachix_key: None,
+        }
+    }
+
+    /// Stands in for `YEET_*`
+    fn env(url: Option<&str>) -> Data<Toml> {
+        Toml::string(&url.map_or_else(String::new, |url| format!("url = \"{url}\"")))
+    }
+
+    fn server(file: Option<&str>, env_url: Option<&str>, flag: Option<&str>) -> Option<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(super::CONFIG_FILE);
+        if let Some(url) = file {
+            std::fs::write(&path, format!("url = \"{url}\"\ncachix = \"cache\"")).unwrap();
+        }
+        super::resolve(Some(&path), env(env_url), flags(flag))
+            .unwrap()
+            .url
+            .map(|url| url.to_string())
+    }
+
+    #[test]
+    fn precedence() {
+        let file = Some("https://file.example.com/");
+        let env = Some("https://env.example.com/");
+        let flag = Some("https://flag.example.com/");
+
+        assert_eq!(server(file, env, flag).as_deref(), flag);
+        assert_eq!(server(file, env, None).as_deref(), env);
+        assert_eq!(server(file, None, None).as_deref(), file);
+        // the local agent is only asked without any of them
+        assert_eq!(server(None, None, None), None);
+    }
+}
@@ -0,0 +1,48 @@
+//! Show what the agent derives from the local system. Helps to diagnose failing updates
+
+use clap::{Args, Subcommand};
+use colored::Colorize as _;
+use rootcause::Report;
+
+use crate::{agent, section};
+
+#[derive(Args)]
+pub struct DebugArgs {
+    #[command(subcommand)]
+    pub command: DebugCommands,
+}
+
+#[derive(Subcommand)]
+pub enum DebugCommands {
+    /// Print the trusted public keys the agent passes to `nix-store` when downloading
+    TrustedKeys {
+        /// The key a version was published with. The agent trusts it on top of the local keys
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+}
+
+pub fn handle_command(args: DebugArgs) -> Result<(), Report> {
+    match args.command {
+        DebugCommands::TrustedKeys { public_key } => trusted_keys(public_key.as_deref()),
+    }
+}
+
+fn trusted_keys(public_key: Option<&str>) -> Result<(), Report> {
+    let keys = agent::resolved_trusted_keys(public_key)?;
+    let default = if keys.fallback {
+        "yes, there is no trusted-public-keys"
+    } else {
+        "no"
+    };
+    section::print_sections(&[section::section!(
+        "Trusted Keys".bold().underline() => [
+            "nix.conf", keys.nix_conf.display(),
+            "Default key", default,
+            "Local keys", keys.local.join("\n"),
+            "Published with", public_key.unwrap_or("-"),
+            "Passed to nix-store", keys.download.join("\n"),
+        ]
+    )]);
+    Ok(())
+}
@@ -65,8 +65,21 @@ pub async fn detach(
                     .context(error)
                     .into_dynamic());
             }
-            #[expect(clippy::unreachable, reason = "Can only happen on varlink status")]
-            YeetDaemonError::NoCurrentSystem => unreachable!(),
+            YeetDaemonError::DetachNotAllowed => {
+                return Err(report!(
+                    "The server does not allow this host to detach. Ask an admin to grant it"
+                )
+                .into_dynamic());
+            }
+            #[expect(
+                clippy::unreachable,
+                reason = "Can only happen on varlink status or gc"
+            )]
+            YeetDaemonError::NoCurrentSystem
+            | YeetDaemonError::GarbageCollectionFailed { .. }
+            | YeetDaemonError::InvalidGcAge { .. } => {
+                unreachable!()
+            }
         },
     }
     Ok(())
@@ -1,7 +1,13 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
 use clap::{Args, Subcommand};
 use colored::Colorize as _;
+use http::StatusCode;
 use log::info;
-use rootcause::Report;
+use rootcause::{Report, bail, prelude::ResultExt as _};
 
 use crate::{
     cli::common,
@@ -18,25 +24,78 @@ pub struct HostArgs {
 
 #[derive(Subcommand)]
 pub enum HostCommands {
+    /// List all hosts
+    #[command(visible_alias = "ls")]
+    List,
+    /// Show the status of hosts
+    Show {
+        /// Select hosts and show everything known about them
+        #[arg(long)]
+        full: bool,
+    },
     /// Rename an existing yeet host
+    #[command(visible_alias = "mv")]
     Rename,
     /// Delete an host including all authentication info
+    #[command(visible_alias = "rm")]
     Remove,
     /// Add a tag to this host
     Tag,
     /// Remove a tag from this host
     RemoveTag,
+    /// Register hosts before their first check-in. They skip the verification code
+    Import {
+        /// JSON list of `{"hostname": .., "key": .., "recipient": ..}`.
+        /// `key` is the public key of the agent, `recipient` its optional age recipient
+        #[arg(long)]
+        file: PathBuf,
+    },
 }
 
 pub async fn handle_command(args: HostArgs, config: &Config) -> Result<(), rootcause::Report> {
     match args.command {
+        HostCommands::List => hosts(config, false).await,
+        HostCommands::Show { full } => hosts(config, full).await,
         HostCommands::Remove => remove(config).await,
         HostCommands::Rename => rename(config).await,
         HostCommands::Tag => tag(config).await,
         HostCommands::RemoveTag => remove_tag(config).await,
+        HostCommands::Import { file } => import(config, &file).await,
     }
 }
 
+async fn import(config: &Config, file: &Path) -> Result<(), Report> {
+    let hosts: Vec<api::HostImport> = serde_json::from_str(
+        &fs::read_to_string(file).attach(format!("Import file: {}", file.display()))?,
+    )?;
+
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
+
+    let results = api::import_hosts(&url, secret_key, hosts).await?;
+
+    let mut failed = 0_usize;
+    let results = results
+        .into_iter()
+        .map(|import| {
+            let status = match import.result {
+                Ok(id) => format!("Imported ({id})").green().to_string(),
+                Err(err) => {
+                    failed = failed.saturating_add(1);
+                    err.red().to_string()
+                }
+            };
+            (import.hostname, status)
+        })
+        .collect();
+    section::print_sections(&[("Import".bold().underline().to_string(), results)]);
+
+    if failed > 0 {
+        bail!("{failed} host(s) could not be imported");
+    }
+    Ok(())
+}
+
 pub async fn remove(config: &Config) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let secret_key = &ssh::key_by_url(&url)?;
@@ -81,9 +140,11 @@ pub async fn remove(config: &Config) -> Result<(), Report> {
 
     // no takies backsies past this point
 
-    api::delete_key(&url, secret_key, selected_host.key).await?;
-
-    info!("Deleted!");
+    if api::delete_key(&url, secret_key, selected_host.key).await? == StatusCode::ACCEPTED {
+        info!("Proposed! A second admin has to confirm the removal");
+    } else {
+        info!("Deleted!");
+    }
 
     Ok(())
 }
@@ -102,6 +163,11 @@ pub async fn rename(config: &Config) -> Result<(), Report> {
 
         inquire::Select::new("Which host do you want to rename>", hostnames).prompt()?
     };
+    let taken: Vec<_> = hosts
+        .iter()
+        .map(|host| host.hostname.clone())
+        .filter(|hostname| *hostname != selected_host)
+        .collect();
     #[expect(
         clippy::unwrap_used,
         reason = "we fed the hosts into the select. inquire ensure a selection"
@@ -111,7 +177,17 @@ pub async fn rename(config: &Config) -> Result<(), Report> {
         .find(|host| host.hostname == selected_host)
         .unwrap();
 
-    let new_name = inquire::Text::new("What should the new name be?").prompt()?;
+    let new_name = inquire::Text::new("What should the new name be?")
+        .with_validator(move |name: &str| {
+            Ok(if taken.iter().any(|hostname| hostname == name) {
+                inquire::validator::Validation::Invalid(
+                    format!("{name} is already taken by another host").into(),
+                )
+            } else {
+                inquire::validator::Validation::Valid
+            })
+        })
+        .prompt()?;
 
     // The user has to confirm the action
     let confirm = inquire::Confirm::new(&format!(
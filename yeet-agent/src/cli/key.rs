@@ -0,0 +1,208 @@
+//! Generate the keys a host needs. The ed25519 signing key authenticates the host (see
+//! `api::get_secret_key`/`api::get_verify_key`) and the age identity decrypts its secrets
+
+use std::{
+    fs::{OpenOptions, Permissions, set_permissions, write},
+    io::Write as _,
+    os::unix::fs::{OpenOptionsExt as _, PermissionsExt as _},
+    path::{Path, PathBuf},
+};
+
+use age::secrecy::ExposeSecret as _;
+use clap::{Args, Subcommand};
+use colored::Colorize as _;
+use ed25519_dalek::{
+    SigningKey,
+    pkcs8::{EncodePrivateKey as _, spki::der::pem::LineEnding},
+};
+use log::info;
+use rootcause::{Report, prelude::ResultExt as _};
+use ssh_key::{HashAlg, public::Ed25519PublicKey};
+
+use crate::section;
+
+#[derive(Args)]
+pub struct KeyArgs {
+    #[command(subcommand)]
+    pub command: KeyCommands,
+}
+
+#[derive(Subcommand)]
+pub enum KeyCommands {
+    /// Generate an age identity and optionally an ed25519 signing key
+    Gen {
+        /// Where to store the age identity
+        #[arg(long, default_value = "age.key")]
+        age_output: PathBuf,
+
+        /// Also generate an ed25519 signing key at this path.
+        /// The public key is stored next to it with a `.pub` suffix
+        #[arg(long)]
+        signing_key_output: Option<PathBuf>,
+
+        /// Replace existing keys
+        #[arg(long)]
+        overwrite: bool,
+    },
+}
+
+pub fn handle_command(args: KeyArgs) -> Result<(), Report> {
+    match args.command {
+        KeyCommands::Gen {
+            age_output,
+            signing_key_output,
+            overwrite,
+        } => generate(&age_output, signing_key_output.as_deref(), overwrite),
+    }
+}
+
+fn generate(
+    age_output: &Path,
+    signing_key_output: Option<&Path>,
+    overwrite: bool,
+) -> Result<(), Report> {
+    let identity = write_age_identity(age_output, overwrite)?;
+    let mut sections = vec![section::section!(
+        "Age Identity".bold().underline() => [
+            "Identity", age_output.display(),
+            "Recipient", identity.to_public(),
+        ]
+    )];
+
+    if let Some(signing_key_output) = signing_key_output {
+        let public_key = write_keypair(signing_key_output, overwrite)?;
+        sections.push(section::section!(
+            "Signing Key".bold().underline() => [
+                "Private Key", signing_key_output.display(),
+                "Verify Key", public_key.to_openssh()?,
+                "Fingerprint", public_key.fingerprint(HashAlg::Sha256),
+            ]
+        ));
+    }
+
+    section::print_sections(&sections);
+    Ok(())
+}
+
+/// Generate a new age identity and write it to `path`
+pub fn write_age_identity(path: &Path, overwrite: bool) -> Result<age::x25519::Identity, Report> {
+    let identity = age::x25519::Identity::generate();
+    write_private_key(
+        path,
+        overwrite,
+        format!("{}\n", identity.to_string().expose_secret()).as_bytes(),
+    )
+    .attach(format!("Key file: {}", path.display()))?;
+    info!("Age identity written to {}", path.display());
+    Ok(identity)
+}
+
+/// Generate a new ed25519 key. The private key is written to `key_output` (PKCS#8 PEM)
+/// and the public key to `key_output.pub` (OpenSSH)
+pub fn write_keypair(key_output: &Path, overwrite: bool) -> Result<ssh_key::PublicKey, Report> {
+    let key = SigningKey::from_bytes(&rand::random());
+    write_private_key(
+        key_output,
+        overwrite,
+        key.to_pkcs8_pem(LineEnding::LF)?.as_bytes(),
+    )
+    .attach(format!("Key file: {}", key_output.display()))?;
+    info!("Private key written to {}", key_output.display());
+
+    let public_key = ssh_key::PublicKey::from(Ed25519PublicKey(key.verifying_key().to_bytes()));
+    let public_key_output = public_key_path(key_output);
+    write(&public_key_output, public_key.to_openssh()?)
+        .attach(format!("Key file: {}", public_key_output.display()))?;
+    info!("Public key written to {}", public_key_output.display());
+    Ok(public_key)
+}
+
+/// Write the private key readable only by the owner
+fn write_private_key(path: &Path, overwrite: bool, key: &[u8]) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(overwrite)
+        .truncate(overwrite)
+        .create_new(!overwrite)
+        .mode(0o600)
+        .open(path)?;
+    // `mode` only applies to newly created files
+    set_permissions(path, Permissions::from_mode(0o600))?;
+    file.write_all(key)
+}
+
+/// `key` -> `key.pub`
+pub fn public_key_path(key: &Path) -> PathBuf {
+    let mut path = key.as_os_str().to_owned();
+    path.push(".pub");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod test_key {
+    use std::{
+        fs::{metadata, read_to_string},
+        os::unix::fs::PermissionsExt as _,
+        path::Path,
+    };
+
+    #[test]
+    fn public_key_path() {
+        assert_eq!(
+            super::public_key_path(Path::new("/etc/yeet/agent.key")),
+            Path::new("/etc/yeet/agent.key.pub")
+        );
+    }
+
+    #[test]
+    fn write_private_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.key");
+
+        super::write_private_key(&path, false, b"first").unwrap();
+        assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // without overwrite an existing key is never replaced
+        super::write_private_key(&path, false, b"second").unwrap_err();
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+        super::write_private_key(&path, true, b"second").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+    }
+
+    #[test]
+    fn write_keypair() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.key");
+
+        let public_key = super::write_keypair(&path, false).unwrap();
+
+        assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        api::get_secret_key(&path).unwrap();
+        // the printed key, the private key and the `.pub` file all describe the same key
+        let verify_key = api::get_verify_key(&path).unwrap();
+        assert_eq!(
+            public_key.key_data().ed25519().unwrap().0,
+            verify_key.to_bytes()
+        );
+        assert_eq!(
+            api::get_verify_key(super::public_key_path(&path)).unwrap(),
+            verify_key
+        );
+    }
+
+    #[test]
+    fn write_age_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("age.key");
+
+        let identity = super::write_age_identity(&path, false).unwrap();
+
+        assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let stored: age::x25519::Identity = read_to_string(&path).unwrap().trim().parse().unwrap();
+        assert_eq!(
+            stored.to_public().to_string(),
+            identity.to_public().to_string()
+        );
+    }
+}
@@ -1,17 +1,71 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeSet, HashMap},
+    env::current_dir,
+    path::{Path, PathBuf},
+};
 
-use log::info;
+use clap::Args;
+use log::{info, warn};
 use rootcause::{Report, bail, prelude::ResultExt as _, report};
-use yeet::{cachix, nix};
+use yeet::{cachix, nix, progress::Phase};
 
 use crate::{cli::common, cli_args::Config, sig::ssh};
 
+#[derive(Args)]
+pub struct PublishArgs {
+    /// Path to a flake. Repeat to publish the hosts of multiple flakes at once
+    #[arg(long = "flake", alias = "path", default_value = current_dir().unwrap().into_os_string())]
+    pub flakes: Vec<PathBuf>,
+
+    /// Hosts to build - default is all. With multiple flakes each host is built from the
+    /// flake that defines it
+    #[arg(long)]
+    pub host: Vec<String>,
+
+    /// Sets the `NIXOS_VARIANT` variable when building NixOS. You have to set `system.nixos.variantName = lib.maybeEnv "NIXOS_VARIANT" "No VARIANT"`
+    #[arg(long)]
+    pub variant: Option<String>,
+
+    /// Which hosts should be built? Defaults to current ARCH
+    #[arg(
+        long,
+        default_value_t = std::env::consts::ARCH == "aarch64",
+        default_missing_value = (std::env::consts::ARCH == "aarch64").to_string(),
+        num_args = 0..=1,
+        require_equals = false)]
+    pub darwin: bool,
+
+    /// Only update the server without pushing to cachix. Meant for CI pipelines that
+    /// already push the closures in a separate step. Agents fail to update if the
+    /// paths are not in the cache
+    #[arg(long)]
+    pub no_push: bool,
+
+    /// Publish a closure built elsewhere instead of building, e.g. `web=/nix/store/...`.
+    /// Repeat for multiple hosts. The paths have to be in the local nix store
+    #[arg(
+        long,
+        value_parser = parse_prebuilt,
+        conflicts_with_all = ["flakes", "host", "variant", "darwin"])]
+    pub prebuilt: Vec<(String, String)>,
+
+    /// Do not show progress while building and pushing. It is also hidden when stderr is not
+    /// a terminal
+    #[arg(long)]
+    pub quiet: bool,
+}
+
 pub async fn publish(
     config: &Config,
-    path: PathBuf,
-    host: Vec<String>,
-    variant: Option<String>,
-    darwin: bool,
+    PublishArgs {
+        flakes,
+        host,
+        variant,
+        darwin,
+        no_push,
+        prebuilt,
+        quiet,
+    }: PublishArgs,
 ) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let secret_key = &ssh::key_by_url(&url)?;
@@ -33,24 +87,25 @@ pub async fn publish(
             .ok_or(report!("Cachix cache has no public signing keys"))?
     };
 
-    let host = if host.is_empty() {
-        nix::get_hosts(&path.to_string_lossy(), darwin)?
+    let hosts = if prebuilt.is_empty() {
+        build_flakes(&flakes, &host, variant.as_ref(), darwin, quiet)?
     } else {
-        host
+        prebuilt_hosts(prebuilt)?
     };
 
-    info!("Building {host:?}");
-
-    let hosts = nix::build_hosts(&path.to_string_lossy(), host, darwin, variant)?;
-
-    if hosts.is_empty() {
-        bail!("No hosts found - did you commit your files?")
+    // hosts of different flakes may share a closure
+    let closures = hosts.values().collect::<BTreeSet<_>>();
+    if no_push {
+        warn!(
+            "Not pushing {closures:?}. Agents will fail to update if the paths are not in the {cachix} cache"
+        );
+    } else {
+        info!("Pushing {closures:?}");
+        let phase = Phase::start(format!("Pushing to {cachix}"), quiet);
+        cachix::push_paths(closures, &cachix).await?;
+        phase.finish();
     }
 
-    info!("Pushing {hosts:?}");
-
-    cachix::push_paths(hosts.values(), &cachix).await?;
-
     api::update_hosts(
         &url,
         secret_key,
@@ -63,3 +118,176 @@ pub async fn publish(
     .await?;
     Ok(())
 }
+
+fn build_flakes(
+    flakes: &[PathBuf],
+    host: &[String],
+    variant: Option<&String>,
+    darwin: bool,
+    quiet: bool,
+) -> Result<HashMap<String, String>, Report> {
+    let mut hosts = HashMap::new();
+    for flake in flakes {
+        let flake_hosts = hosts_of_flake(flake, host, flakes.len() > 1, darwin)?;
+        if flake_hosts.is_empty() {
+            continue;
+        }
+
+        info!("Building {flake_hosts:?} from {}", flake.display());
+        let phase = Phase::start(format!("Building {}", flake.display()), quiet);
+        let builds = nix::build_hosts(
+            &flake.to_string_lossy(),
+            flake_hosts,
+            darwin,
+            variant.cloned(),
+        )?;
+        phase.finish();
+        merge_builds(&mut hosts, flake, builds)?;
+    }
+
+    if let Some(missing) = host.iter().find(|host| !hosts.contains_key(*host)) {
+        bail!("Host {missing} is not defined in any of the flakes")
+    }
+
+    if hosts.is_empty() {
+        bail!("No hosts found - did you commit your files?")
+    }
+    Ok(hosts)
+}
+
+/// Closures built elsewhere e.g. by CI. Only paths in the local store can be pushed
+fn prebuilt_hosts(prebuilt: Vec<(String, String)>) -> Result<HashMap<String, String>, Report> {
+    let mut hosts = HashMap::new();
+    for (host, closure) in prebuilt {
+        if hosts.contains_key(&host) {
+            bail!("Host {host} is given more than once");
+        }
+        nix::path_info(&closure).context(format!("{closure} is not in the nix store"))?;
+        hosts.insert(host, closure);
+    }
+    Ok(hosts)
+}
+
+/// Parses `host=/nix/store/...` for `--prebuilt`
+pub fn parse_prebuilt(arg: &str) -> Result<(String, String), String> {
+    let Some((host, closure)) = arg.split_once('=') else {
+        return Err(format!("{arg} is not of the form `host=/nix/store/...`"));
+    };
+    if host.is_empty() {
+        return Err(format!("{arg} has no host"));
+    }
+    if !Path::new(closure).is_absolute() {
+        return Err(format!("{closure} is not an absolute store path"));
+    }
+    Ok((host.to_owned(), closure.to_owned()))
+}
+
+/// Without `--host` the user picks from each flake. With multiple flakes only the requested
+/// hosts that the flake actually defines are built
+fn hosts_of_flake(
+    flake: &Path,
+    host: &[String],
+    multiple_flakes: bool,
+    darwin: bool,
+) -> Result<Vec<String>, Report> {
+    let flake = flake.to_string_lossy();
+    if host.is_empty() {
+        return nix::get_hosts(&flake, darwin);
+    }
+    if !multiple_flakes {
+        return Ok(host.to_vec());
+    }
+    Ok(nix::list_hosts(&flake, darwin)?
+        .into_iter()
+        .filter(|defined| host.contains(defined))
+        .collect())
+}
+
+/// A hostname has to be unique across all flakes. Otherwise it is unclear which build wins
+fn merge_builds(
+    hosts: &mut HashMap<String, String>,
+    flake: &Path,
+    builds: HashMap<String, String>,
+) -> Result<(), Report> {
+    for (host, closure) in builds {
+        if hosts.contains_key(&host) {
+            bail!(
+                "Host {host} from {} is already defined in another flake",
+                flake.display()
+            );
+        }
+        hosts.insert(host, closure);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_publish {
+    use std::{collections::HashMap, path::Path};
+
+    use super::{merge_builds, parse_prebuilt};
+
+    fn builds(hosts: &[(&str, &str)]) -> HashMap<String, String> {
+        hosts
+            .iter()
+            .map(|(host, closure)| ((*host).to_owned(), (*closure).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn merge_flakes() {
+        let mut hosts = HashMap::new();
+        merge_builds(
+            &mut hosts,
+            Path::new("team-a"),
+            builds(&[("web", "/nix/store/web"), ("db", "/nix/store/shared")]),
+        )
+        .unwrap();
+        merge_builds(
+            &mut hosts,
+            Path::new("team-b"),
+            builds(&[("cache", "/nix/store/shared")]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            hosts,
+            builds(&[
+                ("web", "/nix/store/web"),
+                ("db", "/nix/store/shared"),
+                ("cache", "/nix/store/shared")
+            ])
+        );
+    }
+
+    #[test]
+    fn duplicate_host() {
+        let mut hosts = builds(&[("web", "/nix/store/web")]);
+        merge_builds(
+            &mut hosts,
+            Path::new("team-b"),
+            builds(&[("web", "/nix/store/other")]),
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn prebuilt_pairs() {
+        assert_eq!(
+            parse_prebuilt("web=/nix/store/abc-nixos-system-web").unwrap(),
+            (
+                "web".to_owned(),
+                "/nix/store/abc-nixos-system-web".to_owned()
+            )
+        );
+        // only the first `=` separates host and path
+        assert_eq!(
+            parse_prebuilt("web=/nix/store/a=b").unwrap().1,
+            "/nix/store/a=b"
+        );
+        parse_prebuilt("/nix/store/abc-nixos-system-web").unwrap_err();
+        parse_prebuilt("=/nix/store/abc-nixos-system-web").unwrap_err();
+        parse_prebuilt("web=").unwrap_err();
+        parse_prebuilt("web=result").unwrap_err();
+    }
+}
@@ -1,15 +1,27 @@
 use std::{
     collections::HashMap,
     fs::{File, read_to_string},
+    io::Write as _,
+    path::{Path, PathBuf},
 };
 
 use clap::{Args, Subcommand};
 use colored::Colorize as _;
+use http::StatusCode;
+use httpsig_hyper::prelude::SecretKey;
 use inquire::validator::Validation;
 use log::info;
-use rootcause::Report;
+use rootcause::{Report, bail, prelude::ResultExt as _};
 
-use crate::{cli::common, cli_args::Config, section, sig::ssh};
+use crate::{
+    cli::{cache, common},
+    cli_args::Config,
+    section,
+    sig::ssh,
+};
+
+/// Secrets fetched per request for the interactive pickers
+const SECRET_PAGE: usize = 100;
 
 #[derive(Args)]
 pub struct SecretArgs {
@@ -19,10 +31,45 @@ pub struct SecretArgs {
 
 #[derive(Subcommand)]
 pub enum SecretCommands {
-    /// Add or Update a secret
-    Create,
+    /// Add or Update a secret. Replacing an existing secret keeps its acl
+    Create {
+        /// Label like `pem`, `env` or `json`. The agent links the secret with a matching file
+        /// extension
+        #[arg(long)]
+        content_type: Option<String>,
+        /// Fail if a secret with the name exists
+        #[arg(long, conflicts_with = "update_only")]
+        create_only: bool,
+        /// Fail unless a secret with the name exists
+        #[arg(long)]
+        update_only: bool,
+    },
+    /// Replace the content of an existing secret. Hosts keep their access
+    Rotate {
+        /// Name of the secret
+        #[arg(long)]
+        name: String,
+        /// File with the new content
+        #[arg(long)]
+        file: PathBuf,
+    },
     /// Rename an existing secret
     Rename,
+    /// Make the content of a secret available under another name with its own acl
+    Alias {
+        /// Name of the secret that holds the content
+        #[arg(long)]
+        target: String,
+        /// Name of the alias
+        #[arg(long)]
+        name: String,
+    },
+    /// Delete an alias. The secret it points to is kept
+    RemoveAlias {
+        /// Name of the alias
+        #[arg(long)]
+        name: String,
+    },
     /// Delete a secret
     Remove,
     /// Allow a `host` to access a `secret`
@@ -33,51 +80,150 @@ pub enum SecretCommands {
     Tag,
     /// Remove tags
     RemoveTag,
+    /// Test if the server can still decrypt secrets. The content is never revealed
+    Check {
+        /// Only check this secret. Checks all secrets by default
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Show which hosts can fetch a secret
+    Show {
+        /// Name of the secret
+        #[arg(long)]
+        secret: String,
+    },
+    /// Explain if a host can fetch a secret
+    CheckAccess {
+        /// Hostname of the host
+        #[arg(long)]
+        host: String,
+        /// Name of the secret
+        #[arg(long)]
+        secret: String,
+    },
+    /// Print the plaintext a host would receive for a secret. The server has to allow this and
+    /// every use is audited
+    Peek {
+        /// Hostname of the host
+        #[arg(long)]
+        host: String,
+        /// Name of the secret
+        #[arg(long)]
+        secret: String,
+    },
+    /// Compare which secrets two hosts can fetch
+    Diff {
+        /// Hostnames of the two hosts to compare
+        #[arg(long = "host", num_args = 1, required = true)]
+        hosts: Vec<String>,
+    },
 }
 
 pub async fn handle_command(args: SecretArgs, config: &Config) -> Result<(), rootcause::Report> {
     match args.command {
-        SecretCommands::Create => create(config).await,
+        SecretCommands::Create {
+            content_type,
+            create_only,
+            update_only,
+        } => {
+            let mode = if create_only {
+                api::AddSecretMode::CreateOnly
+            } else if update_only {
+                api::AddSecretMode::UpdateOnly
+            } else {
+                api::AddSecretMode::Upsert
+            };
+            create(config, content_type, mode).await
+        }
+        SecretCommands::Rotate { name, file } => rotate(config, &name, &file).await,
         SecretCommands::Rename => rename(config).await,
+        SecretCommands::Alias { target, name } => alias(config, &target, &name).await,
+        SecretCommands::RemoveAlias { name } => remove_alias(config, &name).await,
         SecretCommands::Remove => remove(config).await,
         SecretCommands::Allow => allow(config).await,
         SecretCommands::Block => deny(config).await,
         SecretCommands::Tag => tag(config).await,
         SecretCommands::RemoveTag => remove_tag(config).await,
+        SecretCommands::Check { name } => check(config, name).await,
+        SecretCommands::Show { secret } => show(config, &secret).await,
+        SecretCommands::CheckAccess { host, secret } => check_access(config, &host, &secret).await,
+        SecretCommands::Peek { host, secret } => peek(config, &host, &secret).await,
+        SecretCommands::Diff { hosts } => diff(config, &hosts).await,
     }
 }
 
-async fn create(config: &Config) -> Result<(), Report> {
+async fn create(
+    config: &Config,
+    content_type: Option<String>,
+    mode: api::AddSecretMode,
+) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let secret_key = &ssh::key_by_url(&url)?;
 
-    let recipient: age::x25519::Recipient = {
-        let recipient = api::server_age_key(&url, secret_key).await?;
-        recipient
-            .parse()
+    let name = inquire::Text::new("What should the name of the secret be?").prompt()?;
+
+    let path = inquire::Text::new("Secret File:")
+        .with_validator(|path: &str| {
+            Ok(match File::open(path) {
+                Ok(_) => Validation::Valid,
+                Err(err) => Validation::Invalid(format!("Not a valid file: {err}").into()),
+            })
+        })
+        .prompt()?;
+    let secret = encrypt_for_server(&url, secret_key, Path::new(&path)).await?;
+
+    let added = api::put_secret(
+        &url,
+        secret_key,
+        api::AddSecretRequest {
+            name: name.clone(),
+            secret,
+            mode,
+            content_type,
+        },
+    )
+    .await?;
+    if added.created {
+        log::info!("Secret {name} created!");
+    } else {
+        log::warn!("Secret {name} already existed. Its content was replaced");
+    }
+
+    allow(config).await?;
+
+    Ok(())
+}
+
+/// Encrypt the trimmed content of `path` with the (cached) recipient from `/secret/server_key`
+async fn encrypt_for_server(
+    url: &url::Url,
+    secret_key: &SecretKey,
+    path: &Path,
+) -> Result<Vec<u8>, Report> {
+    let recipient = {
+        let recipient = cache::server_recipient(url, secret_key).await?;
+        api::parse_recipient(&recipient)
             .map_err(|err| rootcause::report!("Could not parse the server recipient key: {err}"))?
     };
 
-    let name = inquire::Text::new("What should the name of the secret be?").prompt()?;
+    let bytes = read_to_string(path).attach(path.display().to_string())?;
+    Ok(api::encrypt_for(&*recipient, bytes.trim().as_bytes())?)
+}
 
-    let secret = {
-        let path = inquire::Text::new("Secret File:")
-            .with_validator(|path: &str| {
-                Ok(match File::open(path) {
-                    Ok(_) => Validation::Valid,
-                    Err(err) => Validation::Invalid(format!("Not a valid file: {err}").into()),
-                })
-            })
-            .prompt()?;
-        let bytes = read_to_string(path)?;
+async fn rotate(config: &Config, name: &str, file: &Path) -> Result<(), Report> {
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
 
-        age::encrypt(&recipient, bytes.trim().as_bytes())
-    }?;
+    let secret = api::list_secrets(&url, secret_key)
+        .await?
+        .into_iter()
+        .find(|secret| secret.name == name)
+        .ok_or(rootcause::report!("Secret {name} does not exist"))?;
 
-    api::create_secret(&url, secret_key, &name, &secret).await?;
-    log::info!("Secret {name} created!");
+    let secret_content = encrypt_for_server(&url, secret_key, file).await?;
 
-    allow(config).await?;
+    api::rotate_secret(&url, secret_key, secret.id, &secret_content).await?;
+    log::info!("Secret {name} rotated!");
 
     Ok(())
 }
@@ -86,7 +232,7 @@ async fn rename(config: &Config) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let secret_key = &ssh::key_by_url(&url)?;
 
-    let secret_list = api::list_secrets(&url, secret_key).await?;
+    let secret_list = picker_secrets(&url, secret_key).await?;
 
     let secret =
         inquire::Select::new("Which secret do you want to rename?", secret_list).prompt()?;
@@ -101,11 +247,43 @@ async fn rename(config: &Config) -> Result<(), Report> {
     Ok(())
 }
 
+async fn alias(config: &Config, target: &str, name: &str) -> Result<(), Report> {
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
+
+    let target_secret = api::list_secrets(&url, secret_key)
+        .await?
+        .into_iter()
+        .find(|secret| secret.name == target)
+        .ok_or(rootcause::report!("Secret {target} does not exist"))?;
+
+    api::create_alias(&url, secret_key, target_secret.id, name).await?;
+    log::info!("Secret {name} now serves the content of {target}!");
+
+    Ok(())
+}
+
+async fn remove_alias(config: &Config, name: &str) -> Result<(), Report> {
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
+
+    let alias = api::list_secrets(&url, secret_key)
+        .await?
+        .into_iter()
+        .find(|secret| secret.name == name)
+        .ok_or(rootcause::report!("Secret {name} does not exist"))?;
+
+    api::delete_alias(&url, secret_key, alias.id).await?;
+    log::info!("Alias {name} removed!");
+
+    Ok(())
+}
+
 async fn remove(config: &Config) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let secret_key = &ssh::key_by_url(&url)?;
 
-    let secret_list = api::list_secrets(&url, secret_key).await?;
+    let secret_list = picker_secrets(&url, secret_key).await?;
 
     let secret =
         inquire::Select::new("Which secret do you want to delete?", secret_list).prompt()?;
@@ -124,8 +302,11 @@ async fn remove(config: &Config) -> Result<(), Report> {
 
     log::info!("Deleting...");
 
-    api::delete_secret(&url, secret_key, secret.id).await?;
-    log::info!("Done!");
+    if api::delete_secret(&url, secret_key, secret.id).await? == StatusCode::ACCEPTED {
+        log::info!("Proposed! A second admin has to confirm the deletion");
+    } else {
+        log::info!("Done!");
+    }
 
     Ok(())
 }
@@ -134,13 +315,42 @@ pub async fn allow(config: &Config) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let secret_key = &ssh::key_by_url(&url)?;
 
-    let secret_list = api::list_secrets(&url, secret_key).await?;
+    let secret_list = picker_secrets(&url, secret_key).await?;
+    allow_for(&url, secret_key, secret_list).await
+}
+
+/// Secrets to pick from sorted by name. Fetched page by page so large stores are not served at once
+pub async fn picker_secrets(
+    url: &url::Url,
+    secret_key: &SecretKey,
+) -> Result<Vec<api::SecretName>, Report> {
+    let mut secrets = Vec::new();
+    loop {
+        let query = api::ListSecretsQuery {
+            prefix: None,
+            limit: Some(SECRET_PAGE),
+            offset: Some(secrets.len()),
+        };
+        let page = api::list_secrets_page(url, secret_key, &query).await?;
+        let last = page.secrets.len() < SECRET_PAGE;
+        secrets.extend(page.secrets);
+        if last || secrets.len() >= page.total {
+            return Ok(secrets);
+        }
+    }
+}
 
+/// Ask which of `secret_list` should be accessible by which hosts
+pub async fn allow_for(
+    url: &url::Url,
+    secret_key: &SecretKey,
+    secret_list: Vec<api::SecretName>,
+) -> Result<(), Report> {
     let secrets =
         inquire::MultiSelect::new("Which secret do you want to modify?", secret_list.clone())
             .prompt()?;
 
-    let mut hosts = api::list_hosts(&url, secret_key).await?;
+    let mut hosts = api::list_hosts(url, secret_key).await?;
     let hostnames = {
         let mut hostnames: Vec<_> = hosts.iter().map(|host| host.hostname.clone()).collect();
         hostnames.sort();
@@ -156,15 +366,16 @@ pub async fn allow(config: &Config) -> Result<(), Report> {
 
     log::info!("Allowing {hosts:?} to access {secrets:?}...");
 
-    for host in hosts {
-        for secret in &secrets {
-            let response = api::allow_host(&url, secret_key, secret.id, host.id).await;
-            if let Err(err) = response {
-                log::error!(
-                    "Error adding access for {} from {secret}:\n{err}",
-                    host.hostname
-                );
-            }
+    let pairs: Vec<_> = hosts
+        .iter()
+        .flat_map(|host| secrets.iter().map(move |secret| (host, secret)))
+        .collect();
+    for (index, err) in set_acl(url, secret_key, &pairs, true).await? {
+        if let Some((host, secret)) = pairs.get(index) {
+            log::error!(
+                "Error adding access for {} from {secret}:\n{err}",
+                host.hostname
+            );
         }
     }
     log::info!("Done!");
@@ -172,12 +383,32 @@ pub async fn allow(config: &Config) -> Result<(), Report> {
     Ok(())
 }
 
+/// Applies the acl change for all `(host, secret)` pairs in one request.
+/// Returns the failed operations by index into `pairs`
+async fn set_acl(
+    url: &url::Url,
+    secret_key: &SecretKey,
+    pairs: &[(&api::Host, &api::SecretName)],
+    allow: bool,
+) -> Result<Vec<(usize, String)>, Report> {
+    let operations = pairs
+        .iter()
+        .map(|(host, secret)| api::AclSecretRequest {
+            secret: secret.id,
+            host: host.id,
+            allow,
+        })
+        .collect();
+    let result = api::batch_acl(url, secret_key, api::BatchAclSecretRequest { operations }).await?;
+    Ok(result.failed)
+}
+
 async fn deny(config: &Config) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let secret_key = &ssh::key_by_url(&url)?;
 
     let selected_secrets = {
-        let secret_list = api::list_secrets(&url, secret_key).await?;
+        let secret_list = picker_secrets(&url, secret_key).await?;
         inquire::MultiSelect::new("Which secret do you want to modify?", secret_list).prompt()?
     };
 
@@ -209,15 +440,16 @@ async fn deny(config: &Config) -> Result<(), Report> {
 
     log::info!("Denying {hosts:?} to access {selected_secrets:?}...");
 
-    for host in hosts {
-        for secret in &selected_secrets {
-            let response = api::block_host(&url, secret_key, secret.id, host.id).await;
-            if let Err(err) = response {
-                log::error!(
-                    "Error removing access for {} from {secret}:\n{err}",
-                    host.id
-                );
-            }
+    let pairs: Vec<_> = hosts
+        .iter()
+        .flat_map(|host| selected_secrets.iter().map(move |secret| (host, secret)))
+        .collect();
+    for (index, err) in set_acl(&url, secret_key, &pairs, false).await? {
+        if let Some((host, secret)) = pairs.get(index) {
+            log::error!(
+                "Error removing access for {} from {secret}:\n{err}",
+                host.id
+            );
         }
     }
     log::info!("Done!");
@@ -281,11 +513,173 @@ pub async fn list(config: &Config) -> Result<(), Report> {
     Ok(())
 }
 
+async fn check(config: &Config, name: Option<String>) -> Result<(), Report> {
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
+
+    let names = match name {
+        Some(name) => vec![name],
+        None => api::list_secrets(&url, secret_key)
+            .await?
+            .into_iter()
+            .map(|secret| secret.name)
+            .collect(),
+    };
+
+    let mut failed = 0_usize;
+    let mut results = Vec::new();
+    for name in names {
+        let status = match api::check_secret(&url, secret_key, &name).await {
+            Ok(_) => "Ok".green().to_string(),
+            Err(err) => {
+                failed = failed.saturating_add(1);
+                err.to_string().red().to_string()
+            }
+        };
+        results.push((name, status));
+    }
+
+    section::print_sections(&[("Secrets".bold().underline().to_string(), results)]);
+
+    if failed > 0 {
+        bail!("{failed} secret(s) can not be decrypted by the server");
+    }
+    Ok(())
+}
+
+async fn show(config: &Config, secret: &str) -> Result<(), Report> {
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
+
+    let content_type = api::list_secrets(&url, secret_key)
+        .await?
+        .into_iter()
+        .find(|listed| listed.name == secret)
+        .and_then(|listed| listed.content_type);
+    let acl = api::acl_by_secret(&url, secret_key, secret).await?;
+    let mut hosts: Vec<String> = api::list_hosts(&url, secret_key)
+        .await?
+        .into_iter()
+        .filter(|host| acl.contains(&host.id))
+        .map(|host| host.hostname)
+        .collect();
+    hosts.sort();
+
+    section::print_sections(&[section::section!(
+        format!("{secret}:").bold().underline() => [
+            "Content type", content_type.unwrap_or_default(),
+            "Hosts", hosts.join("\n"),
+        ]
+    )]);
+    Ok(())
+}
+
+async fn check_access(config: &Config, host: &str, secret: &str) -> Result<(), Report> {
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
+
+    let access = api::check_secret_access(&url, secret_key, host, secret).await?;
+
+    section::print_sections(&[section::section!(
+        format!("{host} -> {secret}").bold().underline() => [
+            "Allowed", if access.allowed { "yes".green() } else { "no".red() },
+            "Reason", access.reason.unwrap_or_default(),
+        ]
+    )]);
+    Ok(())
+}
+
+async fn peek(config: &Config, host: &str, secret: &str) -> Result<(), Report> {
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
+
+    let lookup = api::secret_as_host(
+        &url,
+        secret_key,
+        api::SecretAccessQuery {
+            host: host.to_owned(),
+            secret: secret.to_owned(),
+        },
+    )
+    .await?;
+    match lookup {
+        api::SecretLookup::NotFound => bail!("Secret {secret} does not exist"),
+        api::SecretLookup::NoAccess => bail!("{host} has no access to {secret}"),
+        api::SecretLookup::Found { content, .. } => {
+            log::warn!("Reading {secret} as {host} was recorded in the audit log");
+            std::io::stdout().write_all(&content)?;
+        }
+    }
+    Ok(())
+}
+
+/// Secrets partitioned by which of two hosts can fetch them
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SecretDiff {
+    only_a: Vec<String>,
+    only_b: Vec<String>,
+    both: Vec<String>,
+}
+
+fn diff_access(
+    secrets: &[api::SecretName],
+    host_a: api::HostID,
+    host_b: api::HostID,
+) -> SecretDiff {
+    let mut diff = SecretDiff::default();
+    for secret in secrets {
+        let name = secret.name.clone();
+        match (
+            secret.hosts.contains(&host_a),
+            secret.hosts.contains(&host_b),
+        ) {
+            (true, true) => diff.both.push(name),
+            (true, false) => diff.only_a.push(name),
+            (false, true) => diff.only_b.push(name),
+            (false, false) => {}
+        }
+    }
+    diff.only_a.sort();
+    diff.only_b.sort();
+    diff.both.sort();
+    diff
+}
+
+async fn diff(config: &Config, hostnames: &[String]) -> Result<(), Report> {
+    let [hostname_a, hostname_b] = hostnames else {
+        bail!("`--host` has to be given exactly twice");
+    };
+    let url = common::get_server_url(config).await?;
+    let secret_key = &ssh::key_by_url(&url)?;
+
+    let hosts = api::list_hosts(&url, secret_key).await?;
+    let host_id = |hostname: &str| {
+        hosts
+            .iter()
+            .find(|host| host.hostname == hostname)
+            .map(|host| host.id)
+            .ok_or(rootcause::report!("Host {hostname} does not exist"))
+    };
+    let (host_a, host_b) = (host_id(hostname_a)?, host_id(hostname_b)?);
+
+    let secrets = api::list_secrets(&url, secret_key).await?;
+    let diff = diff_access(&secrets, host_a, host_b);
+
+    section::print_sections(&[section::section!(
+        format!("{hostname_a} <-> {hostname_b}").bold().underline() => [
+            format!("Only {hostname_a}"), diff.only_a.join("\n"),
+            format!("Only {hostname_b}"), diff.only_b.join("\n"),
+            "Both", diff.both.join("\n"),
+        ]
+    )]);
+    Ok(())
+}
+
 async fn tag(config: &Config) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let key = &ssh::key_by_url(&url)?;
 
-    let secrets = api::list_secrets(&url, key).await?;
+    let secrets = picker_secrets(&url, key).await?;
 
     let secrets = inquire::MultiSelect::new("Which secrets do you want to tag?", secrets)
         .with_validator(
@@ -336,7 +730,7 @@ async fn remove_tag(config: &Config) -> Result<(), Report> {
     let url = common::get_server_url(config).await?;
     let key = &ssh::key_by_url(&url)?;
 
-    let secrets = api::list_secrets(&url, key).await?;
+    let secrets = picker_secrets(&url, key).await?;
 
     let secrets = inquire::MultiSelect::new("Which secrets do you want to modify?", secrets)
         .with_validator(
@@ -389,3 +783,56 @@ async fn remove_tag(config: &Config) -> Result<(), Report> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test_secret {
+    use super::{SecretDiff, diff_access};
+
+    fn secret(name: &str, hosts: &[i64]) -> api::SecretName {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": name,
+            "tags": [],
+            "hosts": hosts,
+        }))
+        .unwrap()
+    }
+
+    fn host(id: i64) -> api::HostID {
+        serde_json::from_value(serde_json::json!(id)).unwrap()
+    }
+
+    #[test]
+    fn three_way_partition() {
+        let secrets = [
+            secret("wifi", &[1, 2]),
+            secret("db", &[1]),
+            secret("backup", &[2, 3]),
+            secret("api", &[1, 3]),
+            secret("unused", &[3]),
+            secret("vpn", &[2, 1]),
+        ];
+
+        assert_eq!(
+            diff_access(&secrets, host(1), host(2)),
+            SecretDiff {
+                only_a: vec!["api".to_owned(), "db".to_owned()],
+                only_b: vec!["backup".to_owned()],
+                both: vec!["vpn".to_owned(), "wifi".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn same_host() {
+        let secrets = [secret("wifi", &[1]), secret("db", &[2])];
+
+        assert_eq!(
+            diff_access(&secrets, host(1), host(1)),
+            SecretDiff {
+                both: vec!["wifi".to_owned()],
+                ..SecretDiff::default()
+            }
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use clap::{Args, Subcommand};
 use colored::Colorize as _;
 use log::info;
@@ -243,3 +245,60 @@ pub async fn list_users(config: &Config) -> Result<(), Report> {
 
     Ok(())
 }
+
+pub async fn whoami(config: &Config, other: Option<&Path>) -> Result<(), Report> {
+    let url = common::get_server_url(config).await?;
+    let key = &ssh::key_by_url(&url)?;
+
+    let explanation = api::auth::explain(
+        &url,
+        key,
+        api::auth::ExplainRequest {
+            key: other.map(api::get_verify_key).transpose()?,
+        },
+    )
+    .await?;
+
+    let owner = match &explanation.owner {
+        api::auth::KeyOwner::User(user) => {
+            let tags = if user.all_tag {
+                "ALL TAG".red().bold().to_string()
+            } else {
+                user.tags
+                    .iter()
+                    .map(|tag| format!("#{}", tag.name))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            section::section!("User".bold().underline() => [
+                "Name", user.username,
+                "Level", user.level,
+                "Tags", tags,
+            ])
+        }
+        api::auth::KeyOwner::Host(host) => {
+            section::section!("Host".bold().underline() => [
+                "Hostname", host.hostname,
+                "ID", host.id,
+            ])
+        }
+        api::auth::KeyOwner::Unknown => {
+            section::section!("Unknown".bold().underline() => [
+                "Key", api::key_fingerprint(&explanation.key),
+            ])
+        }
+    };
+
+    let permissions = explanation
+        .permissions
+        .iter()
+        .map(|permission| (permission.action.clone(), permission.scope.to_string()))
+        .collect();
+
+    section::print_sections(&[
+        owner,
+        ("Permissions".bold().underline().to_string(), permissions),
+    ]);
+
+    Ok(())
+}
@@ -1,10 +1,18 @@
-use std::{env::current_dir, path::PathBuf};
+use std::{env::current_dir, net::SocketAddr, path::PathBuf, time::Duration};
 
 use build::CLAP_LONG_VERSION;
 use clap::{Args, Parser, Subcommand};
+use rootcause::{Report, report};
 use serde::{Deserialize, Serialize};
 use shadow_rs::shadow;
 use url::Url;
+use yeet::nix;
+
+use crate::{
+    activation::ActivationMethod,
+    cli::publish,
+    notification::{self, NotificationBackend},
+};
 
 shadow!(build);
 
@@ -12,6 +20,10 @@ shadow!(build);
 #[clap(long_version = CLAP_LONG_VERSION)]
 #[command(version, about, long_about = None)]
 pub struct Yeet {
+    /// Print the full error report instead of a one line summary.
+    /// Known failures exit with 3 (forbidden), 4 (not found) or 5 (server unreachable)
+    #[arg(long, global = true)]
+    pub verbose: bool,
     #[command(flatten)]
     pub config: ClapConfig,
     #[command(subcommand)]
@@ -47,11 +59,105 @@ pub struct Config {
 pub struct AgentConfig {
     pub server: Url,
     pub sleep: u64,
+    /// Percentage by which `sleep` is randomly varied
+    #[serde(default)]
+    pub jitter: u8,
     pub facter: bool,
+    /// Seconds between re-submitting the nixos-facter output after the enrollment
+    #[serde(default)]
+    pub facter_interval: Option<u64>,
     pub key: PathBuf,
+    /// Holds the `secret` link, the `secret.d` generations, the age identity and the last action
+    #[serde(default = "default_secret_base")]
+    pub secret_base: PathBuf,
+    /// Where activations are reported
+    #[serde(default = "notification::default_backends")]
+    pub notifications: Vec<NotificationBackend>,
+    /// Collect garbage older than this after a successful update e.g. `7d`
+    #[serde(default)]
+    pub gc_after_update: Option<String>,
+    /// How a new system is activated
+    #[serde(default)]
+    pub activation_method: ActivationMethod,
+    /// Deploy a standalone home-manager of the user running the agent instead of the system
+    #[serde(default)]
+    pub home_manager_activation: bool,
+    /// Minutes a new version has to stay requested by the server before it is activated
+    #[serde(default)]
+    pub soak_minutes: Option<u64>,
+    /// Expected recipient of the server store key. Secrets are only fetched from this server
+    #[serde(default)]
+    pub server_recipient: Option<String>,
+    /// Seconds until the connection to the server has to be established
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Seconds a request to the server may take including the response
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
+    /// Also serve the read-only varlink methods on this TCP address. Only the unix socket by default
+    #[serde(default)]
+    pub varlink_tcp: Option<SocketAddr>,
+    /// PEM certificate chain the agent presents on `varlink_tcp`
+    #[serde(default)]
+    pub varlink_tcp_cert: Option<PathBuf>,
+    /// PEM private key of `varlink_tcp_cert`
+    #[serde(default)]
+    pub varlink_tcp_key: Option<PathBuf>,
+    /// PEM certificates of the CAs a client on `varlink_tcp` has to present a certificate of
+    #[serde(default)]
+    pub varlink_tcp_client_ca: Option<PathBuf>,
+}
+
+impl AgentConfig {
+    /// For `api::Server::new`
+    #[must_use]
+    pub fn timeouts(&self) -> api::Timeouts {
+        api::Timeouts {
+            connect: Duration::from_secs(self.connect_timeout),
+            request: Duration::from_secs(self.request_timeout),
+        }
+    }
+}
+
+/// Default of `yeet agent --connect-timeout`
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+/// Default of `yeet agent --request-timeout`
+pub const DEFAULT_REQUEST_TIMEOUT: u64 = 60;
+
+fn default_connect_timeout() -> u64 {
+    DEFAULT_CONNECT_TIMEOUT
+}
+
+fn default_request_timeout() -> u64 {
+    DEFAULT_REQUEST_TIMEOUT
+}
+
+/// Default of `yeet agent --secret-base`
+pub const DEFAULT_SECRET_BASE: &str = "/etc/yeet";
+/// Default of `yeet agent --secret-base` with `--home-manager-activation`, relative to the
+/// home directory
+pub const HOME_MANAGER_SECRET_BASE: &str = ".config/yeet/secrets";
+
+fn default_secret_base() -> PathBuf {
+    PathBuf::from(DEFAULT_SECRET_BASE)
+}
+
+/// `yeet agent --secret-base` or its default
+pub fn secret_base(secret_base: Option<PathBuf>, home_manager: bool) -> Result<PathBuf, Report> {
+    match secret_base {
+        Some(secret_base) => Ok(secret_base),
+        None if home_manager => Ok(std::env::home_dir()
+            .ok_or(report!("Home-manager activation needs a home directory"))?
+            .join(HOME_MANAGER_SECRET_BASE)),
+        None => Ok(default_secret_base()),
+    }
 }
 
 #[derive(Subcommand)]
+#[expect(
+    clippy::large_enum_variant,
+    reason = "Parsed once at startup and moved into the running command"
+)]
 pub enum Commands {
     #[command(hide = true)]
     /// Used to notify all users
@@ -63,49 +169,127 @@ pub enum Commands {
         #[arg(index = 1)]
         query: String,
     },
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Agent {
+        #[command(subcommand)]
+        command: Option<crate::cli::agent::AgentCommands>,
+
         /// URL of the Yeet Server
-        #[arg(long)]
-        server: Url,
+        #[arg(long, required_unless_present = "simulate")]
+        server: Option<Url>,
 
         /// Path to ed25519 key which is used for authentication
+        #[arg(long, required_unless_present = "simulate")]
+        key: Option<PathBuf>,
+
+        /// Print the current version, the deployed secrets and what the agent would do.
+        /// Only reads local state. The server is not contacted and nothing is changed
         #[arg(long)]
-        key: PathBuf,
+        simulate: bool,
 
         /// Seconds to wait between updates.
-        /// Lower bound, may be higher between switching versions
+        /// Lower bound, may be higher between switching versions.
+        /// Also the first delay after an error. Further retries back off up to 5 minutes.
+        /// The server can override it for one check with the `X-Yeet-Poll-After` header (max 1h)
         #[arg(short, long, default_value = "30")]
         sleep: u64,
 
+        /// Randomly vary `sleep` by up to this percentage.
+        /// Spreads the check-ins of many hosts e.g. after a server restart
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+        jitter: u8,
+
         /// Collect facter with nixos-facter
         #[arg(long)]
         facter: bool,
-    },
-    /// Approve a pending key verification with the corresponding code
-    Approve,
-    /// Build and then publish some or all hosts in a flake
-    Publish {
-        /// Path to flake
-        #[arg(long, default_value = current_dir().unwrap().into_os_string())]
-        path: PathBuf,
 
-        /// Hosts to build - default is all
-        #[arg(long)]
-        host: Vec<String>,
+        /// Re-submit the nixos-facter output every this many seconds once verified.
+        /// Keeps the server up to date with hardware changes
+        #[arg(long, requires = "facter")]
+        facter_interval: Option<u64>,
 
-        /// Sets the `NIXOS_VARIANT` variable when building NixOS. You have to set `system.nixos.variantName = lib.maybeEnv "NIXOS_VARIANT" "No VARIANT"`
+        /// Directory for the secrets and the local state of the agent. `secret` links to the
+        /// active generation in `secret.d`, next to the age identity and the last action.
+        /// Defaults to `/etc/yeet` or `~/.config/yeet/secrets` with `--home-manager-activation`
         #[arg(long)]
-        variant: Option<String>,
+        secret_base: Option<PathBuf>,
 
-        /// Which hosts should be built? Defaults to current ARCH
+        /// Report activations to `systemd`, `desktop` or the url of a webhook. Can be repeated
+        #[arg(long = "notify", default_values = ["desktop"])]
+        notifications: Vec<NotificationBackend>,
+
+        /// Delete generations older than this many days after a successful update e.g. `7d`
+        #[arg(long, env = "YEET_GC_AFTER_UPDATE", value_parser = nix::parse_gc_age)]
+        gc_after_update: Option<String>,
+
+        /// Activate new systems with `switch-to-configuration`, `nixos-rebuild` or the absolute
+        /// path of an executable that gets the store path as its only argument
         #[arg(
             long,
-            default_value_t = std::env::consts::ARCH == "aarch64",
-            default_missing_value = (std::env::consts::ARCH == "aarch64").to_string(),
-            num_args = 0..=1,
-            require_equals = false)]
-        darwin: bool,
+            env = "YEET_ACTIVATION_METHOD",
+            default_value = "switch-to-configuration"
+        )]
+        activation_method: ActivationMethod,
+
+        /// Deploy a standalone home-manager of the current user instead of the system.
+        /// Runs the `activate` script of the generation and does not need root
+        #[arg(long, conflicts_with = "activation_method")]
+        home_manager_activation: bool,
+
+        /// Activate a new version only after the server requested it for this many minutes.
+        /// A different version restarts the wait
+        #[arg(long)]
+        soak_minutes: Option<u64>,
+
+        /// Refuse to fetch secrets unless the server store key has this age recipient.
+        /// It is served at `/secret/server_key`
+        #[arg(long, env = "YEET_SERVER_RECIPIENT")]
+        server_recipient: Option<String>,
+
+        /// Seconds until the connection to the server has to be established
+        #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT)]
+        connect_timeout: u64,
+
+        /// Seconds a request to the server may take. A hung request fails and is retried with
+        /// the usual backoff
+        #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT)]
+        request_timeout: u64,
+
+        /// Also serve the read-only varlink methods `Status` and `Config` on this TCP address
+        /// e.g. `[::]:4338` for a central management tool. Clients have to authenticate with a
+        /// certificate signed by `--varlink-tcp-client-ca`. Only the unix socket by default
+        #[arg(long, requires_all = ["varlink_tcp_cert", "varlink_tcp_key", "varlink_tcp_client_ca"])]
+        varlink_tcp: Option<SocketAddr>,
+
+        /// PEM certificate chain the agent presents on `--varlink-tcp`
+        #[arg(long, requires = "varlink_tcp")]
+        varlink_tcp_cert: Option<PathBuf>,
+
+        /// PEM private key of `--varlink-tcp-cert`
+        #[arg(long, requires = "varlink_tcp")]
+        varlink_tcp_key: Option<PathBuf>,
+
+        /// PEM certificates of the CAs that sign the client certificates on `--varlink-tcp`
+        #[arg(long, requires = "varlink_tcp")]
+        varlink_tcp_client_ca: Option<PathBuf>,
+    },
+    /// Approve a pending key verification with the corresponding code
+    Approve {
+        /// Approve all hosts listed in a csv file with the columns `code,hostname,facter_output_path`
+        /// `facter_output_path` may be empty
+        #[arg(long)]
+        batch: Option<PathBuf>,
+
+        /// Approve all hosts of the batch concurrently
+        #[arg(long, requires = "batch")]
+        parallel: bool,
+
+        /// Do not ask which secrets the host may access
+        #[arg(long, conflicts_with = "batch")]
+        no_secrets: bool,
     },
+    /// Build and then publish some or all hosts in a flake
+    Publish(publish::PublishArgs),
 
     /// Query the status of all or your local hosts
     /// Requires either admin credentials or sudo
@@ -135,12 +319,14 @@ pub enum Commands {
     /// Attach your system to the server
     Attach,
 
-    /// Query the status of all hosts
+    /// Deprecated: use `yeet host list` or `yeet host show --full`
+    #[command(hide = true)]
     Hosts {
         /// Filter for some hosts
         #[arg(long)]
         full: bool,
     },
+    /// Manage hosts
     Host(crate::cli::host::HostArgs),
     /// List all secrets
     Secrets,
@@ -148,9 +334,21 @@ pub enum Commands {
     /// List all users
     Users,
     User(crate::cli::user::UserArgs),
+    /// Show who the server thinks you are and what you are allowed to do
+    Whoami {
+        /// Explain this public key instead of your own (requires `all_tag`)
+        #[arg(long)]
+        key: Option<PathBuf>,
+    },
     /// List all tags
     Tags,
     Tag(crate::cli::tag::TagArgs),
+    /// Manage the local cli configuration
+    Config(crate::cli::config::ConfigArgs),
+    /// Generate keys for new hosts
+    Key(crate::cli::key::KeyArgs),
+    /// Inspect what the agent derives from the local system
+    Debug(crate::cli::debug::DebugArgs),
     /// These are the raw subcommands to execute functions on the server
     Server(ServerArgs),
 }
@@ -166,6 +364,40 @@ pub struct ServerArgs {
 
 #[derive(Subcommand)]
 pub enum ServerCommands {
+    /// Create the configuration for a new yeet server. Does not start the server
+    Init {
+        /// Do not prompt. Everything not given as an argument uses its default
+        #[arg(long)]
+        non_interactive: bool,
+
+        /// Environment file the configuration is written to
+        #[arg(long, default_value = "yeet-server.env")]
+        output: PathBuf,
+
+        /// Port the server listens on
+        #[arg(long, default_value_t = 4337)]
+        port: u16,
+
+        /// Address the server listens on
+        #[arg(long, default_value_t = std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST))]
+        host: std::net::IpAddr,
+
+        /// TLS certificate (PEM)
+        #[arg(long)]
+        cert: Option<PathBuf>,
+
+        /// TLS certificate key (PEM)
+        #[arg(long)]
+        cert_key: Option<PathBuf>,
+
+        /// Use an existing ed25519 key for the first admin instead of generating one
+        #[arg(long)]
+        admin_key: Option<PathBuf>,
+
+        /// Where to store the generated admin key
+        #[arg(long, default_value = "admin.key", conflicts_with = "admin_key")]
+        admin_key_output: PathBuf,
+    },
     /// Update a host e.g. push a new `store_path` TODO: batch update
     Update {
         /// Name of the host
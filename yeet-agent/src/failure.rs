@@ -0,0 +1,202 @@
+//! Maps failed commands to exit codes scripts can tell apart
+
+use std::process::ExitCode;
+
+use http::StatusCode;
+use rootcause::Report;
+
+/// Failures with a dedicated exit code. Everything else exits with `1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The server rejected the key or its permissions
+    Forbidden,
+    /// The server does not know the requested resource
+    NotFound,
+    /// The server could not be reached
+    Network,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::Forbidden => 3,
+            Failure::NotFound => 4,
+            Failure::Network => 5,
+        }
+    }
+
+    fn from_status(code: StatusCode) -> Option<Self> {
+        match code {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Some(Failure::Forbidden),
+            StatusCode::NOT_FOUND => Some(Failure::NotFound),
+            _ => None,
+        }
+    }
+
+    fn from_reqwest(error: &reqwest::Error) -> Option<Self> {
+        if error.is_connect() || error.is_timeout() {
+            return Some(Failure::Network);
+        }
+        error.status().and_then(Failure::from_status)
+    }
+}
+
+/// Find the first known failure in the report and describe it in one line
+pub fn classify(report: &Report) -> Option<(Failure, String)> {
+    report.iter_reports().find_map(|sub| {
+        let failure = if let Some(error) = sub.downcast_current_context::<api::ResponseError>() {
+            match error {
+                api::ResponseError::ServerError { code, .. } => Failure::from_status(*code),
+                api::ResponseError::ReqwestError(error) => Failure::from_reqwest(error),
+                api::ResponseError::URLParseError(_)
+                | api::ResponseError::SignatureParamError(_)
+                | api::ResponseError::SignatureError(_)
+                | api::ResponseError::AgeError(_) => None,
+            }
+        } else if let Some(error) = sub.downcast_current_context::<reqwest::Error>() {
+            Failure::from_reqwest(error)
+        } else {
+            None
+        }?;
+
+        let cause = sub.format_current_context().to_string();
+        let top = report.format_current_context().to_string();
+        let message = if top == cause {
+            cause
+        } else {
+            format!("{top}: {cause}")
+        };
+        Some((failure, message))
+    })
+}
+
+/// How far the clock of this host is off if the server rejected a request and sent a time that
+/// is more than `api::MAX_CLOCK_SKEW` away from `local`
+pub fn clock_skew(report: &Report, local: jiff::Timestamp) -> Option<jiff::SignedDuration> {
+    report.iter_reports().find_map(|sub| {
+        sub.downcast_current_context::<api::ResponseError>()?
+            .clock_skew(local)
+    })
+}
+
+/// Explains why every signed request fails instead of leaving only the rejection
+pub fn clock_hint(skew: jiff::SignedDuration) -> String {
+    let direction = if skew.is_negative() {
+        "ahead of"
+    } else {
+        "behind"
+    };
+    format!(
+        "The clock of this host is {:#} {direction} the server. Check your clock e.g. with \
+         `timedatectl status`",
+        skew.abs()
+    )
+}
+
+/// Print the failure and pick the exit code.
+/// Unknown failures and `--verbose` print the full report
+#[expect(clippy::print_stderr, reason = "Replaces the report printed by `main`")]
+#[expect(clippy::use_debug, reason = "The debug format is the full report")]
+pub fn report(report: &Report, verbose: bool) -> ExitCode {
+    let Some((failure, message)) = classify(report) else {
+        eprintln!("Error: {report:?}");
+        return ExitCode::FAILURE;
+    };
+
+    if verbose {
+        eprintln!("Error: {report:?}");
+    } else {
+        eprintln!("Error: {message}");
+    }
+    if let Some(skew) = clock_skew(report, jiff::Timestamp::now()) {
+        eprintln!("{}", clock_hint(skew));
+    }
+    ExitCode::from(failure.exit_code())
+}
+
+#[cfg(test)]
+mod test_failure {
+    use http::StatusCode;
+    use rootcause::{Report, prelude::ResultExt as _};
+
+    use super::Failure;
+
+    fn server_error(code: StatusCode) -> Report {
+        Err::<(), _>(api::ResponseError::ServerError {
+            code,
+            error: "Key is not allowed".to_owned(),
+            server_time: None,
+        })
+        .context("Could not fetch hosts")
+        .unwrap_err()
+        .into_dynamic()
+    }
+
+    #[test]
+    fn server_errors() {
+        let (failure, message) = super::classify(&server_error(StatusCode::FORBIDDEN)).unwrap();
+        assert_eq!(failure, Failure::Forbidden);
+        assert_eq!(failure.exit_code(), 3);
+        assert!(message.starts_with("Could not fetch hosts: "));
+        assert!(message.ends_with(": Key is not allowed"));
+
+        assert_eq!(
+            super::classify(&server_error(StatusCode::UNAUTHORIZED)).map(|(failure, _)| failure),
+            Some(Failure::Forbidden)
+        );
+        assert_eq!(
+            super::classify(&server_error(StatusCode::NOT_FOUND)).map(|(failure, _)| failure),
+            Some(Failure::NotFound)
+        );
+        assert_eq!(
+            super::classify(&server_error(StatusCode::BAD_REQUEST)),
+            None
+        );
+        assert_eq!(
+            super::classify(&rootcause::report!("Something else").into_dynamic()),
+            None
+        );
+    }
+
+    #[test]
+    fn clock_skew() {
+        let rejected = |server_time: jiff::Timestamp| {
+            Err::<(), _>(api::ResponseError::ServerError {
+                code: StatusCode::BAD_REQUEST,
+                error: "Signature verification failed".to_owned(),
+                server_time: Some(server_time),
+            })
+            .context("Could not check for updates")
+            .unwrap_err()
+            .into_dynamic()
+        };
+        let now: jiff::Timestamp = "2026-10-17T12:00:00Z".parse().unwrap();
+
+        assert_eq!(super::clock_skew(&rejected(now), now), None);
+        assert_eq!(
+            super::clock_skew(&server_error(StatusCode::FORBIDDEN), now),
+            None
+        );
+
+        let booted_in_1970: jiff::Timestamp = "1970-01-01T00:00:00Z".parse().unwrap();
+        let skew = super::clock_skew(&rejected(now), booted_in_1970).unwrap();
+        assert!(skew.is_positive());
+        assert!(super::clock_hint(skew).contains("behind the server"));
+
+        let skew = super::clock_skew(&rejected(booted_in_1970), now).unwrap();
+        assert!(super::clock_hint(skew).contains("ahead of the server"));
+    }
+
+    #[tokio::test]
+    async fn unreachable_server() {
+        let error = reqwest::get("http://127.0.0.1:1").await.unwrap_err();
+        let report = Err::<(), _>(api::ResponseError::ReqwestError(error))
+            .context("Could not fetch hosts")
+            .unwrap_err()
+            .into_dynamic();
+
+        let (failure, _) = super::classify(&report).unwrap();
+        assert_eq!(failure, Failure::Network);
+        assert_eq!(failure.exit_code(), 5);
+    }
+}
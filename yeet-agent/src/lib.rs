@@ -1,2 +1,3 @@
 pub mod cachix;
 pub mod nix;
+pub mod progress;
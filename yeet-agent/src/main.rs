@@ -1,13 +1,13 @@
 //! # Yeet Agent
 
-use std::io::{IsTerminal as _, Write as _};
+use std::{
+    io::{IsTerminal as _, Write as _},
+    process::ExitCode,
+};
 
 use clap::Parser as _;
 use colored::Colorize as _;
-use figment::{
-    Figment,
-    providers::{Env, Format as _, Serialized, Toml},
-};
+use figment::providers::Env;
 use rootcause::{
     Report, ReportRef,
     handlers::{ContextFormattingStyle, FormattingFunction},
@@ -17,18 +17,25 @@ use rootcause::{
 
 use crate::cli_args::{AgentConfig, Commands, Config, Yeet};
 
+mod activation;
 mod agent;
 mod cli_args;
+mod failure;
 mod section;
 mod server_cli;
 mod sig {
     pub mod ssh;
 }
 mod cli {
+    pub mod agent;
     pub mod approve;
+    pub mod cache;
     pub mod common;
+    pub mod config;
+    pub mod debug;
     pub mod detach;
     pub mod host;
+    pub mod key;
 
     pub mod osquery;
     pub mod publish;
@@ -60,16 +67,7 @@ impl ContextFormatterHook<clap::Error> for ClapDisplayHook {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Report> {
-    Hooks::new()
-        .context_formatter::<clap::Error, _>(ClapDisplayHook)
-        .report_formatter(
-            rootcause::hooks::builtin_hooks::report_formatter::DefaultReportFormatter::ASCII,
-        )
-        .install()
-        .expect("failed to install hooks");
-
+fn init_logger() {
     let mut log_builder =
         env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
 
@@ -82,80 +80,183 @@ async fn main() -> Result<(), Report> {
         });
     }
     log_builder.init();
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    Hooks::new()
+        .context_formatter::<clap::Error, _>(ClapDisplayHook)
+        .report_formatter(
+            rootcause::hooks::builtin_hooks::report_formatter::DefaultReportFormatter::ASCII,
+        )
+        .install()
+        .expect("failed to install hooks");
+
+    init_logger();
 
+    let args = match Yeet::try_parse() {
+        Ok(args) => args,
+        Err(err) => return failure::report(&rootcause::report!(err).into_dynamic(), true),
+    };
+
+    let verbose = args.verbose;
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(report) => failure::report(&report, verbose),
+    }
+}
+
+async fn run(args: Yeet) -> Result<(), Report> {
     let xdg_dirs = xdg::BaseDirectories::with_prefix("yeet");
-    let args = Yeet::try_parse()?;
-
-    let config: Config = Figment::new()
-        .merge(Toml::file(
-            xdg_dirs.find_config_file("agent.toml").unwrap_or_default(),
-        ))
-        .merge(Serialized::defaults(args.config))
-        .merge(Env::prefixed("YEET_"))
-        .extract()?;
-
-    let command = match args.command {
-        Commands::Nodes => cli::osquery::show_nodes(&config).await,
-        Commands::Query { query } => cli::osquery::query(&config, query).await,
-        Commands::Secret(args) => cli::secret::handle_command(args, &config).await,
-        Commands::Secrets => cli::secret::list(&config).await,
-        Commands::User(args) => cli::user::handle_command(args, &config).await,
-        Commands::Users => cli::user::list_users(&config).await,
-        Commands::Tag(args) => cli::tag::handle_command(args, &config).await,
-        Commands::Host(args) => cli::host::handle_command(args, &config).await,
-        Commands::Hosts { full } => cli::host::hosts(&config, full).await,
-        Commands::Tags => cli::tag::list_tags(&config).await,
+
+    let flag_url = args.config.url.clone();
+    let config = cli::config::resolve(
+        xdg_dirs
+            .find_config_file(cli::config::CONFIG_FILE)
+            .as_deref(),
+        Env::prefixed("YEET_"),
+        args.config,
+    )?;
+
+    let command = dispatch(args.command, &config, flag_url).await;
+
+    if command.is_err() {
+        log_server_health(&config).await;
+    }
+    command
+}
+
+#[expect(
+    clippy::too_many_lines,
+    reason = "One arm per subcommand, the agent arm maps every flag"
+)]
+async fn dispatch(
+    command: Commands,
+    config: &Config,
+    flag_url: Option<url::Url>,
+) -> Result<(), Report> {
+    match command {
+        Commands::Nodes => cli::osquery::show_nodes(config).await,
+        Commands::Query { query } => cli::osquery::query(config, query).await,
+        Commands::Secret(args) => cli::secret::handle_command(args, config).await,
+        Commands::Secrets => cli::secret::list(config).await,
+        Commands::User(args) => cli::user::handle_command(args, config).await,
+        Commands::Users => cli::user::list_users(config).await,
+        Commands::Whoami { key } => cli::user::whoami(config, key.as_deref()).await,
+        Commands::Tag(args) => cli::tag::handle_command(args, config).await,
+        Commands::Host(args) => cli::host::handle_command(args, config).await,
+        Commands::Hosts { full } => {
+            log::warn!(
+                "`yeet hosts` is deprecated. Use `yeet host list` or `yeet host show --full`"
+            );
+            cli::host::hosts(config, full).await
+        }
+        Commands::Tags => cli::tag::list_tags(config).await,
+        Commands::Config(args) => cli::config::handle_command(args, config, flag_url).await,
+        Commands::Key(args) => cli::key::handle_command(args),
+        Commands::Debug(args) => cli::debug::handle_command(args),
         Commands::Detach {
             version,
             darwin,
             path,
         } => cli::detach::detach(version, path, darwin).await,
         Commands::Attach => cli::detach::attach().await,
-        Commands::Approve => cli::approve::approve(&config).await,
+        Commands::Approve {
+            batch: None,
+            no_secrets,
+            ..
+        } => cli::approve::approve(config, no_secrets).await,
+        Commands::Approve {
+            batch: Some(batch),
+            parallel,
+            ..
+        } => cli::approve::approve_batch(config, &batch, parallel).await,
         Commands::Notify => notification::notify(),
         Commands::Agent {
-            server,
+            command: Some(command),
+            ..
+        } => cli::agent::handle_command(command, config).await,
+        Commands::Agent {
+            command: None,
+            simulate: true,
+            secret_base,
+            home_manager_activation,
+            ..
+        } => agent::simulate(
+            &cli_args::secret_base(secret_base, home_manager_activation)?,
+            home_manager_activation,
+        ),
+        Commands::Agent {
+            command: None,
+            server: Some(server),
+            key: Some(key),
             sleep,
+            jitter,
             facter,
-            key,
+            facter_interval,
+            secret_base,
+            notifications,
+            gc_after_update,
+            activation_method,
+            home_manager_activation,
+            soak_minutes,
+            server_recipient,
+            connect_timeout,
+            request_timeout,
+            varlink_tcp,
+            varlink_tcp_cert,
+            varlink_tcp_key,
+            varlink_tcp_client_ca,
+            simulate: false,
         } => {
             let config = AgentConfig {
                 server,
                 sleep,
+                jitter,
                 facter,
+                facter_interval,
                 key,
+                secret_base: cli_args::secret_base(secret_base, home_manager_activation)?,
+                notifications,
+                gc_after_update,
+                activation_method,
+                home_manager_activation,
+                soak_minutes,
+                server_recipient,
+                connect_timeout,
+                request_timeout,
+                varlink_tcp,
+                varlink_tcp_cert,
+                varlink_tcp_key,
+                varlink_tcp_client_ca,
             };
             agent::agent(&config, sleep, facter).await
         }
+        Commands::Agent { .. } => Err(rootcause::report!("`--server` and `--key` are required")),
         Commands::Status { json } => status::status(json).await,
-        Commands::Publish {
-            path,
-            host,
-            darwin,
-            variant,
-        } => cli::publish::publish(&config, path, host, variant, darwin).await,
-        Commands::Server(args) => server_cli::handle_server_commands(args, &config).await,
+        Commands::Publish(args) => cli::publish::publish(config, args).await,
+        Commands::Server(args) => server_cli::handle_server_commands(args, config).await,
+    }
+}
+
+/// Tell the user if the server is reachable after a failed command
+async fn log_server_health(config: &Config) {
+    // local commands like `agent init` may fail without any server configured
+    let Ok(url) = cli::common::get_server_url(config).await else {
+        return;
     };
 
-    match command {
-        Ok(()) => Ok(()),
-        Err(err) => {
-            let url = cli::common::get_server_url(&config).await?;
-
-            if api::is_healthy(&url).await {
-                log::info!(
-                    "{} {}",
-                    url.domain().unwrap_or_default().bold().underline(),
-                    "is up".green().bold()
-                );
-            } else {
-                log::info!(
-                    "{} {}",
-                    url.domain().unwrap_or_default().bold().underline(),
-                    "is not reachable".red().bold()
-                );
-            }
-            Err(err)
-        }
+    if api::is_healthy(&url).await {
+        log::info!(
+            "{} {}",
+            url.domain().unwrap_or_default().bold().underline(),
+            "is up".green().bold()
+        );
+    } else {
+        log::info!(
+            "{} {}",
+            url.domain().unwrap_or_default().bold().underline(),
+            "is not reachable".red().bold()
+        );
     }
 }
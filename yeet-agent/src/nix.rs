@@ -6,6 +6,7 @@ use std::{
     process::{Command, Stdio},
 };
 
+use base64::{Engine as _, prelude::BASE64_STANDARD};
 use inquire::{list_option::ListOption, validator::Validation};
 use rootcause::{Report, bail, prelude::ResultExt as _, report};
 use serde::{Deserialize, Serialize};
@@ -125,6 +126,168 @@ pub fn facter() -> Result<String, Report> {
     Ok(facter)
 }
 
+/// `--delete-older-than` of `nix-collect-garbage` in days e.g. `7d`
+pub fn parse_gc_age(age: &str) -> Result<String, String> {
+    match age.strip_suffix('d').map(str::parse::<u32>) {
+        Some(Ok(_)) => Ok(age.to_owned()),
+        _ => Err(format!("{age} is not a number of days like `7d`")),
+    }
+}
+
+/// Delete generations older than `older_than` and collect the garbage.
+/// Returns the summary of `nix-collect-garbage` e.g. `42 store paths deleted, 1.2 GiB freed`
+pub fn collect_garbage(older_than: &str) -> Result<String, Report> {
+    let output = Command::new("nix-collect-garbage")
+        .args(["--delete-older-than", older_than])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Could not spawn `nix-collect-garbage`")?
+        .wait_with_output()
+        .context("Could not wait for `nix-collect-garbage`")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!("nix-collect-garbage failed: {}", stderr.trim());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(freed_summary(&stdout)
+        .or_else(|| freed_summary(&stderr))
+        .unwrap_or("Nothing freed")
+        .to_owned())
+}
+
+fn freed_summary(output: &str) -> Option<&str> {
+    output
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| line.ends_with("freed"))
+}
+
+/// The parts of `nix path-info --json` a binary cache signature covers
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PathInfo {
+    pub nar_hash: String,
+    pub nar_size: u64,
+    #[serde(default)]
+    pub references: Vec<String>,
+    #[serde(default)]
+    pub signatures: Vec<String>,
+    /// Only present with `--closure-size`
+    #[serde(default)]
+    pub closure_size: Option<u64>,
+}
+
+pub fn path_info(store_path: &str) -> Result<PathInfo, Report> {
+    let output = Command::new("nix")
+        .args([
+            "--extra-experimental-features",
+            "nix-command",
+            "path-info",
+            "--json",
+            "--size",
+            "--closure-size",
+            store_path,
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Could not spawn `nix path-info`")?
+        .wait_with_output()
+        .context("Could not wait for `nix path-info`")?;
+    if !output.status.success() {
+        bail!(
+            "nix path-info failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    parse_path_info(&output.stdout, store_path)
+}
+
+/// Older nix versions print a list of infos with a `path` field, newer ones an object keyed by path
+fn parse_path_info(json: &[u8], store_path: &str) -> Result<PathInfo, Report> {
+    let info = match serde_json::from_slice::<Value>(json)? {
+        Value::Array(infos) => infos
+            .into_iter()
+            .find(|info| info.get("path").and_then(Value::as_str) == Some(store_path)),
+        Value::Object(mut infos) => infos.remove(store_path),
+        Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_) => None,
+    }
+    .ok_or(report!("`nix path-info` knows nothing about {store_path}"))?;
+    Ok(serde_json::from_value(info)?)
+}
+
+/// Check that `info` carries a valid signature of `public_key` (`<name>:<base64 ed25519 key>`)
+pub fn verify_path_signature(
+    store_path: &str,
+    info: &PathInfo,
+    public_key: &str,
+) -> Result<(), Report> {
+    let (key_name, key) = public_key
+        .split_once(':')
+        .ok_or(report!("{public_key} is not a nix public key"))?;
+    let key = ed25519_dalek::VerifyingKey::try_from(BASE64_STANDARD.decode(key)?.as_slice())
+        .context("Invalid nix public key")?;
+    let fingerprint = fingerprint(store_path, info)?;
+
+    let valid = info
+        .signatures
+        .iter()
+        .filter_map(|signature| signature.strip_prefix(key_name)?.strip_prefix(':'))
+        .filter_map(|signature| BASE64_STANDARD.decode(signature).ok())
+        .filter_map(|signature| ed25519_dalek::Signature::from_slice(&signature).ok())
+        .any(|signature| {
+            key.verify_strict(fingerprint.as_bytes(), &signature)
+                .is_ok()
+        });
+    if !valid {
+        bail!("{store_path} is not signed by {key_name}");
+    }
+    Ok(())
+}
+
+/// What nix signs: `1;<store path>;<nar hash>;<nar size>;<references>`
+fn fingerprint(store_path: &str, info: &PathInfo) -> Result<String, Report> {
+    Ok(format!(
+        "1;{store_path};{};{};{}",
+        nix32_nar_hash(&info.nar_hash)?,
+        info.nar_size,
+        info.references.join(",")
+    ))
+}
+
+/// Signatures cover the `sha256:<nix32>` form. Newer nix versions print SRI hashes instead
+fn nix32_nar_hash(nar_hash: &str) -> Result<String, Report> {
+    if nar_hash.starts_with("sha256:") {
+        return Ok(nar_hash.to_owned());
+    }
+    let Some(hash) = nar_hash.strip_prefix("sha256-") else {
+        bail!("Unsupported nar hash {nar_hash}");
+    };
+    Ok(format!("sha256:{}", nix32(&BASE64_STANDARD.decode(hash)?)))
+}
+
+/// The base32 variant nix uses for hashes
+fn nix32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+    let len = bytes.len().saturating_mul(8).div_ceil(5);
+    (0..len)
+        .rev()
+        .map(|char_index| {
+            let bit = char_index.saturating_mul(5);
+            let (byte, shift) = (bit >> 3_u32, (bit & 7) as u32);
+            let low = bytes.get(byte).map_or(0, |low| u16::from(*low) >> shift);
+            let high = bytes
+                .get(byte.saturating_add(1))
+                .map_or(0, |high| u16::from(*high) << (8_u32.saturating_sub(shift)));
+            ALPHABET
+                .get(usize::from((low | high) & 0x1f))
+                .map_or('0', |&char| char::from(char))
+        })
+        .collect()
+}
+
 pub fn list_hosts(flake_path: &str, darwin: bool) -> Result<Vec<String>, Report> {
     let flavor = if darwin {
         "darwinConfigurations"
@@ -246,3 +409,118 @@ pub fn nixos_variant_name() -> Result<String, Report> {
         .to_owned();
     Ok(output)
 }
+
+#[cfg(test)]
+mod test_nix {
+    use base64::{Engine as _, prelude::BASE64_STANDARD};
+    use ed25519_dalek::{Signer as _, SigningKey};
+
+    use super::{
+        PathInfo, fingerprint, freed_summary, nix32_nar_hash, parse_gc_age, parse_path_info,
+        verify_path_signature,
+    };
+
+    const STORE_PATH: &str = "/nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1";
+
+    #[test]
+    fn gc_age() {
+        assert_eq!(parse_gc_age("7d"), Ok("7d".to_owned()));
+        parse_gc_age("7").unwrap_err();
+        parse_gc_age("7h").unwrap_err();
+        parse_gc_age("d").unwrap_err();
+    }
+
+    #[test]
+    fn gc_summary() {
+        let output = "removing old generations of profile /nix/var/nix/profiles/system\n\
+                      deleting '/nix/store/abc-foo'\n\
+                      42 store paths deleted, 1.20 GiB freed\n";
+        assert_eq!(
+            freed_summary(output),
+            Some("42 store paths deleted, 1.20 GiB freed")
+        );
+        assert_eq!(freed_summary("finding garbage collector roots...\n"), None);
+    }
+
+    #[test]
+    fn sri_to_nix32() {
+        // sha256 of the empty string
+        assert_eq!(
+            nix32_nar_hash("sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=").unwrap(),
+            "sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"
+        );
+        assert_eq!(
+            nix32_nar_hash("sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73").unwrap(),
+            "sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"
+        );
+        nix32_nar_hash("md5-1B2M2Y8AsgTpgAmY7PhCfg==").unwrap_err();
+    }
+
+    #[test]
+    fn path_info_formats() {
+        let info = PathInfo {
+            nar_hash: "sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73".to_owned(),
+            nar_size: 120,
+            references: vec![STORE_PATH.to_owned()],
+            signatures: vec!["cache:c2ln".to_owned()],
+            closure_size: Some(4096),
+        };
+        let fields = r#""narHash": "sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73",
+            "narSize": 120,
+            "references": ["/nix/store/sbldylj3clbkc0aqvjjzfa6slp4zdvlj-hello-2.12.1"],
+            "signatures": ["cache:c2ln"],
+            "closureSize": 4096"#;
+
+        let list = format!(r#"[{{ "path": "{STORE_PATH}", {fields} }}]"#);
+        assert_eq!(parse_path_info(list.as_bytes(), STORE_PATH).unwrap(), info);
+        let object = format!(r#"{{ "{STORE_PATH}": {{ {fields} }} }}"#);
+        assert_eq!(
+            parse_path_info(object.as_bytes(), STORE_PATH).unwrap(),
+            info
+        );
+        parse_path_info(object.as_bytes(), "/nix/store/other").unwrap_err();
+    }
+
+    #[test]
+    fn path_signature() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = format!(
+            "cache.example.com-1:{}",
+            BASE64_STANDARD.encode(key.verifying_key().as_bytes())
+        );
+        let mut info = PathInfo {
+            nar_hash: "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_owned(),
+            nar_size: 120,
+            references: vec![STORE_PATH.to_owned()],
+            signatures: Vec::new(),
+            closure_size: None,
+        };
+        let signature = key.sign(fingerprint(STORE_PATH, &info).unwrap().as_bytes());
+
+        // unsigned
+        verify_path_signature(STORE_PATH, &info, &public_key).unwrap_err();
+
+        // signed by another key with the same name
+        let other = SigningKey::from_bytes(&[8; 32])
+            .sign(fingerprint(STORE_PATH, &info).unwrap().as_bytes());
+        info.signatures = vec![format!(
+            "cache.example.com-1:{}",
+            BASE64_STANDARD.encode(other.to_bytes())
+        )];
+        verify_path_signature(STORE_PATH, &info, &public_key).unwrap_err();
+
+        info.signatures.push(format!(
+            "cache.example.com-1:{}",
+            BASE64_STANDARD.encode(signature.to_bytes())
+        ));
+        verify_path_signature(STORE_PATH, &info, &public_key).unwrap();
+
+        // the signature does not cover another store path
+        verify_path_signature(
+            "/nix/store/00000000000000000000000000000000-hello-2.12.1",
+            &info,
+            &public_key,
+        )
+        .unwrap_err();
+    }
+}
@@ -1,9 +1,199 @@
-use std::fs;
+use std::{
+    ffi::OsStr,
+    os::unix::{ffi::OsStrExt as _, net::UnixDatagram},
+    str::FromStr,
+    time::Duration,
+};
 
+use log::{debug, error};
 use rootcause::Report;
+use serde::{Deserialize, Serialize};
 use tokio::process::Command;
+use url::Url;
 use yeet::nix;
 
+/// Sent to every configured backend after a new system was activated
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ActivationEvent {
+    pub store_path: api::StorePath,
+    /// `VARIANT` of the os-release of the new system
+    pub variant: Option<String>,
+    pub timestamp: jiff::Timestamp,
+}
+
+impl ActivationEvent {
+    pub fn new(store_path: &api::StorePath) -> Self {
+        Self {
+            store_path: store_path.clone(),
+            variant: nix::nixos_variant_name()
+                .ok()
+                .filter(|variant| !variant.is_empty()),
+            timestamp: jiff::Timestamp::now(),
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "System has been updated to `{}`",
+            self.variant.as_ref().unwrap_or(&self.store_path)
+        )
+    }
+}
+
+pub trait Notifier {
+    async fn notify(&self, event: &ActivationEvent) -> Result<(), Report>;
+}
+
+/// Reports the activation as the unit status. Does nothing outside of systemd
+pub struct SystemdNotify;
+
+impl Notifier for SystemdNotify {
+    async fn notify(&self, event: &ActivationEvent) -> Result<(), Report> {
+        let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+            debug!("NOTIFY_SOCKET is not set. Not running under systemd");
+            return Ok(());
+        };
+        sd_notify(&socket, &format!("STATUS={}", event.summary()))
+    }
+}
+
+fn sd_notify(socket: &OsStr, state: &str) -> Result<(), Report> {
+    let datagram = UnixDatagram::unbound()?;
+    if let Some(name) = socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::{linux::net::SocketAddrExt as _, unix::net::SocketAddr};
+            datagram.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        rootcause::bail!(
+            "Abstract socket {} is only supported on linux",
+            String::from_utf8_lossy(name)
+        );
+    } else {
+        datagram.send_to(state.as_bytes(), socket)?;
+    }
+    Ok(())
+}
+
+/// Desktop notification for every logged in user
+pub struct DesktopNotify;
+
+impl Notifier for DesktopNotify {
+    #[cfg(target_os = "linux")]
+    async fn notify(&self, _event: &ActivationEvent) -> Result<(), Report> {
+        let user_dirs = {
+            let dirs = std::fs::read_dir("/run/user")?;
+            dirs.flatten()
+                .map(|dir| dir.path())
+                .filter_map(|path| {
+                    path.file_name()
+                        .map(|file_name| file_name.to_string_lossy().to_string())
+                })
+                .flat_map(|file_name| file_name.parse::<u32>())
+        };
+
+        // `yeet notify` has to run as the user to reach its session bus
+        for user in user_dirs {
+            let dbus_address = format!("unix:path=/run/user/{user}/bus");
+            let current_exe = std::env::current_exe().unwrap_or_else(|_| "yeet".into());
+            let _cmd = Command::new(current_exe)
+                .arg("notify")
+                .uid(user)
+                .env("DBUS_SESSION_BUS_ADDRESS", &dbus_address)
+                .spawn();
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn notify(&self, event: &ActivationEvent) -> Result<(), Report> {
+        let script = format!(
+            "display notification {:?} with title \"Yeet\"",
+            event.summary()
+        );
+        let status = Command::new("osascript")
+            .arg("-e")
+            .arg(script)
+            .status()
+            .await?;
+        if !status.success() {
+            rootcause::bail!("osascript failed: {status}");
+        }
+        Ok(())
+    }
+}
+
+/// POSTs the event as JSON
+pub struct WebhookNotify {
+    pub url: Url,
+}
+
+impl Notifier for WebhookNotify {
+    async fn notify(&self, event: &ActivationEvent) -> Result<(), Report> {
+        reqwest::Client::new()
+            .post(self.url.clone())
+            .timeout(Duration::from_secs(10))
+            .json(event)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Configurable with `yeet agent --notify`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationBackend {
+    Systemd,
+    Desktop,
+    Webhook { url: Url },
+}
+
+impl NotificationBackend {
+    async fn notify(&self, event: &ActivationEvent) -> Result<(), Report> {
+        match self {
+            Self::Systemd => SystemdNotify.notify(event).await,
+            Self::Desktop => DesktopNotify.notify(event).await,
+            Self::Webhook { url } => WebhookNotify { url: url.clone() }.notify(event).await,
+        }
+    }
+}
+
+impl FromStr for NotificationBackend {
+    type Err = String;
+
+    /// `systemd`, `desktop` or the url of a webhook
+    fn from_str(backend: &str) -> Result<Self, Self::Err> {
+        match backend {
+            "systemd" => Ok(Self::Systemd),
+            "desktop" => Ok(Self::Desktop),
+            url => Url::parse(url)
+                .ok()
+                .filter(|url| matches!(url.scheme(), "http" | "https"))
+                .map(|url| Self::Webhook { url })
+                .ok_or_else(|| {
+                    format!("{url} is neither `systemd`, `desktop` nor a http(s) webhook url")
+                }),
+        }
+    }
+}
+
+pub fn default_backends() -> Vec<NotificationBackend> {
+    vec![NotificationBackend::Desktop]
+}
+
+/// Notify every backend. A failing backend never fails the activation
+pub async fn notify_all(backends: &[NotificationBackend], event: &ActivationEvent) {
+    for backend in backends {
+        if let Err(err) = backend.notify(event).await {
+            error!("Could not notify {backend:?}: {err}");
+        }
+    }
+}
+
+/// Runs as the user that gets notified. Spawned by `DesktopNotify`
 pub fn notify() -> Result<(), Report> {
     let variant = nix::nixos_variant_name()?;
 
@@ -15,28 +205,44 @@ pub fn notify() -> Result<(), Report> {
     Ok(())
 }
 
-pub fn notify_all() -> Result<(), Report> {
-    let user_dirs = {
-        let dirs = fs::read_dir("/run/user")?;
-        dirs.flatten()
-            // .into_iter()
-            .map(|dir| dir.path())
-            .filter_map(|path| {
-                path.file_name()
-                    .map(|file_name| file_name.to_string_lossy().to_string())
+#[cfg(test)]
+mod test_notification {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::{NotificationBackend, sd_notify};
+
+    #[test]
+    fn parse_backend() {
+        assert_eq!(
+            "systemd".parse::<NotificationBackend>(),
+            Ok(NotificationBackend::Systemd)
+        );
+        assert_eq!(
+            "desktop".parse::<NotificationBackend>(),
+            Ok(NotificationBackend::Desktop)
+        );
+        assert_eq!(
+            "https://example.com/hook".parse::<NotificationBackend>(),
+            Ok(NotificationBackend::Webhook {
+                url: "https://example.com/hook".parse().unwrap()
             })
-            .flat_map(|file_name| file_name.parse::<u32>())
-    };
-
-    for user in user_dirs {
-        let dbus_address = format!("unix:path=/run/user/{user}/bus");
-        let current_exe = std::env::current_exe().unwrap_or_else(|_| "yeet".into());
-        let _cmd = Command::new(current_exe)
-            .arg("notify")
-            .uid(user)
-            .env("DBUS_SESSION_BUS_ADDRESS", &dbus_address)
-            // .env("DISPLAY", ":0")
-            .spawn();
+        );
+        "ftp://example.com"
+            .parse::<NotificationBackend>()
+            .unwrap_err();
+        "slack".parse::<NotificationBackend>().unwrap_err();
+    }
+
+    #[test]
+    fn notify_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let socket = UnixDatagram::bind(&path).unwrap();
+
+        sd_notify(path.as_os_str(), "STATUS=updated").unwrap();
+
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(buf.get(..len), Some(b"STATUS=updated".as_slice()));
     }
-    Ok(())
 }
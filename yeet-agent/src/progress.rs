@@ -0,0 +1,64 @@
+//! Spinners with the elapsed time around long running phases like building or pushing closures.
+//! Nothing is drawn with `--quiet` or when stderr is not a terminal, e.g. in CI logs
+
+use std::{borrow::Cow, io::IsTerminal as _, time::Duration};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Whether progress is drawn at all
+#[must_use]
+pub fn enabled(quiet: bool, terminal: bool) -> bool {
+    !quiet && terminal
+}
+
+/// A running phase. Finish it to keep the message with the elapsed time on screen
+pub struct Phase {
+    bar: ProgressBar,
+}
+
+impl Phase {
+    pub fn start(message: impl Into<Cow<'static, str>>, quiet: bool) -> Self {
+        Self::with_terminal(message, quiet, std::io::stderr().is_terminal())
+    }
+
+    fn with_terminal(message: impl Into<Cow<'static, str>>, quiet: bool, terminal: bool) -> Self {
+        let bar = if enabled(quiet, terminal) {
+            let bar = ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template("{spinner} {msg} [{elapsed}]")
+                    .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+            );
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_message(message);
+        Self { bar }
+    }
+
+    #[must_use]
+    pub fn is_hidden(&self) -> bool {
+        self.bar.is_hidden()
+    }
+
+    pub fn finish(self) {
+        self.bar.finish();
+    }
+}
+
+#[cfg(test)]
+mod test_progress {
+    use super::Phase;
+
+    #[test]
+    fn disabled_without_terminal() {
+        assert!(!super::enabled(false, false));
+        assert!(!super::enabled(true, true));
+        assert!(super::enabled(false, true));
+
+        let phase = Phase::with_terminal("Building", false, false);
+        assert!(phase.is_hidden());
+        phase.finish();
+        assert!(Phase::with_terminal("Building", true, true).is_hidden());
+    }
+}
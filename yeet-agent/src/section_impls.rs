@@ -29,10 +29,31 @@ impl DisplaySection for api::Host {
             items.push(("Current version".to_owned(), version.clone()));
         }
 
-        if let Some(update) = &self.latest_update
-            && self.version != self.latest_update
-        {
-            items.push(("Next version".to_owned(), update.clone()));
+        if let Some(update) = self.pending_update() {
+            items.push(("Update pending".to_owned(), update.clone()));
+        }
+
+        if let Some(soak) = &self.soaking {
+            items.push((
+                "Soaking".to_owned(),
+                format!(
+                    "{} until {}",
+                    soak.store_path,
+                    soak.until.strftime(TIME_FORMAT)
+                ),
+            ));
+        }
+
+        if let Some(size) = self.last_download_size {
+            items.push((
+                "Last update".to_owned(),
+                format!("downloaded {}", api::byte_size(size)),
+            ));
+        }
+
+        if let Some(reported) = self.last_facter {
+            let reported = api::time_diff(reported, jiff::Unit::Second, 30_f64, jiff::Unit::Second);
+            items.push(("Facts reported".to_owned(), reported));
         }
 
         {
@@ -45,10 +66,46 @@ impl DisplaySection for api::Host {
             items.push(("Last seen".to_owned(), last_seen.clone()));
         };
 
+        items.push(("Timeline".to_owned(), timeline(self)));
+
+        if !self.history.is_empty() {
+            items.push(("History".to_owned(), deploy_history(&self.history)));
+        }
+
         (self.hostname.underline().to_string(), items)
     }
 }
 
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+fn timeline(host: &api::Host) -> String {
+    let updated = host.last_updated_at.map_or_else(
+        || "never".to_owned(),
+        |updated| updated.strftime(TIME_FORMAT).to_string(),
+    );
+    format!(
+        "enrolled {}\nupdated  {updated}",
+        host.enrolled_at.strftime(TIME_FORMAT)
+    )
+}
+
+/// One line per activation, newest first
+fn deploy_history(history: &[api::Deployment]) -> String {
+    history
+        .iter()
+        .map(|deployment| {
+            let time = deployment.time.strftime(TIME_FORMAT);
+            let outcome = match (&deployment.error, deployment.success) {
+                (_, true) => "ok".green().to_string(),
+                (Some(error), false) => format!("{}: {error}", "failed".red()),
+                (None, false) => "failed".red().to_string(),
+            };
+            format!("{time} {} {outcome}", deployment.store_path)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 #[expect(clippy::unwrap_used)]
 impl DisplaySectionItem for api::Node {
     fn as_section_item(&self) -> (String, String) {
@@ -68,3 +125,102 @@ impl DisplaySectionItem for api::User {
         (left.to_owned(), right.trim().to_owned())
     }
 }
+
+#[cfg(test)]
+mod test_section_impls {
+    use crate::section::DisplaySection as _;
+
+    fn host(history: Vec<api::Deployment>) -> api::Host {
+        api::Host {
+            id: api::HostID::new(1),
+            key: ed25519_dalek::SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            hostname: "host-a".to_owned(),
+            state: api::ProvisionState::Provisioned,
+            last_ping: jiff::Timestamp::now(),
+            version: Some("/nix/store/b".to_owned()),
+            latest_update: Some("/nix/store/b".to_owned()),
+            last_download_size: None,
+            last_facter: None,
+            soaking: None,
+            tags: Vec::new(),
+            enrolled_at: "2026-10-01T08:00:00Z".parse().unwrap(),
+            last_updated_at: None,
+            history,
+        }
+    }
+
+    #[test]
+    fn host_timeline() {
+        let timeline = |host: &api::Host| {
+            let (_, items) = host.as_section();
+            items
+                .into_iter()
+                .find_map(|(key, value)| (key == "Timeline").then_some(value))
+                .unwrap()
+        };
+
+        let mut host = host(Vec::new());
+        assert_eq!(
+            timeline(&host),
+            "enrolled 2026-10-01 08:00:00\nupdated  never"
+        );
+
+        host.last_updated_at = Some("2026-10-17T12:30:00Z".parse().unwrap());
+        assert_eq!(
+            timeline(&host),
+            "enrolled 2026-10-01 08:00:00\nupdated  2026-10-17 12:30:00"
+        );
+    }
+
+    #[test]
+    fn host_pending_update() {
+        let pending = |host: &api::Host| {
+            let (_, items) = host.as_section();
+            items
+                .into_iter()
+                .find_map(|(key, value)| (key == "Update pending").then_some(value))
+        };
+
+        let mut host = host(Vec::new());
+        assert_eq!(pending(&host), None);
+
+        host.latest_update = Some("/nix/store/c".to_owned());
+        assert_eq!(pending(&host).as_deref(), Some("/nix/store/c"));
+
+        host.version = None;
+        assert_eq!(pending(&host).as_deref(), Some("/nix/store/c"));
+
+        host.latest_update = None;
+        assert_eq!(pending(&host), None);
+    }
+
+    #[test]
+    fn host_history() {
+        colored::control::set_override(false);
+
+        let (_, items) = host(Vec::new()).as_section();
+        assert!(items.iter().all(|(key, _)| key != "History"));
+
+        let (_, items) = host(vec![
+            api::Deployment {
+                store_path: "/nix/store/b".to_owned(),
+                success: true,
+                error: None,
+                time: "2026-10-17T12:30:00Z".parse().unwrap(),
+            },
+            api::Deployment {
+                store_path: "/nix/store/a".to_owned(),
+                success: false,
+                error: Some("switch-to-configuration failed".to_owned()),
+                time: "2026-10-17T12:00:00Z".parse().unwrap(),
+            },
+        ])
+        .as_section();
+        let (_, history) = items.iter().find(|(key, _)| key == "History").unwrap();
+        assert_eq!(
+            history,
+            "2026-10-17 12:30:00 /nix/store/b ok\n2026-10-17 12:00:00 /nix/store/a failed: \
+             switch-to-configuration failed"
+        );
+    }
+}
@@ -1,26 +1,62 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write as _,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
 
-use api::get_secret_key;
-use rootcause::Report;
+use api::{get_secret_key, get_verify_key};
+use colored::Colorize as _;
+use log::info;
+use rootcause::{Report, bail, prelude::ResultExt as _};
 
-use crate::cli_args::{Config, ServerArgs, ServerCommands};
+use crate::{
+    cli::key,
+    cli_args::{Config, ServerArgs, ServerCommands},
+    section,
+};
 
 pub async fn handle_server_commands(args: ServerArgs, config: &Config) -> Result<(), Report> {
-    let url = &config
-        .url
-        .clone()
-        .ok_or(rootcause::report!("`--url` required for server commands"))?;
-
-    let httpsig_key = &args.httpsig_key.clone().ok_or(rootcause::report!(
-        "`--httpsig_key` required for server commands"
-    ))?;
     match args.command {
+        ServerCommands::Init {
+            non_interactive,
+            output,
+            port,
+            host,
+            cert,
+            cert_key,
+            admin_key,
+            admin_key_output,
+        } => {
+            let setup = ServerSetup {
+                port,
+                host,
+                cert,
+                cert_key,
+                admin_key: admin_key.map_or(AdminKey::Generate(admin_key_output), AdminKey::Import),
+            };
+            let setup = if non_interactive {
+                setup
+            } else {
+                setup.prompt()?
+            };
+            init(&setup, &output)
+        }
         ServerCommands::Update {
             host,
             store_path,
             public_key,
             substitutor,
         } => {
+            let url = &config
+                .url
+                .clone()
+                .ok_or(rootcause::report!("`--url` required for server commands"))?;
+
+            let httpsig_key = &args.httpsig_key.clone().ok_or(rootcause::report!(
+                "`--httpsig_key` required for server commands"
+            ))?;
             api::update_hosts(
                 url,
                 &get_secret_key(httpsig_key)?,
@@ -31,7 +67,182 @@ pub async fn handle_server_commands(args: ServerArgs, config: &Config) -> Result
                 },
             )
             .await?;
+            Ok(())
         }
     }
+}
+
+enum AdminKey {
+    Generate(PathBuf),
+    Import(PathBuf),
+}
+
+/// Everything `yeet server init` needs to know to write the server configuration
+struct ServerSetup {
+    port: u16,
+    host: IpAddr,
+    cert: Option<PathBuf>,
+    cert_key: Option<PathBuf>,
+    admin_key: AdminKey,
+}
+
+impl ServerSetup {
+    /// Ask for every setting, using the current values as defaults
+    fn prompt(self) -> Result<Self, Report> {
+        let port = inquire::CustomType::<u16>::new("Port:")
+            .with_default(self.port)
+            .prompt()?;
+        let host = inquire::CustomType::<IpAddr>::new("Listen address:")
+            .with_default(self.host)
+            .with_help_message("Use :: or 0.0.0.0 to listen on all interfaces")
+            .prompt()?;
+
+        let cert = prompt_path("TLS certificate (PEM):", self.cert.as_deref())?;
+        let cert_key = prompt_path("TLS certificate key (PEM):", self.cert_key.as_deref())?;
+
+        let admin_key = match self.admin_key {
+            AdminKey::Import(path) => AdminKey::Import(path),
+            AdminKey::Generate(default) => {
+                let generate = inquire::Confirm::new("Generate a new admin key?")
+                    .with_default(true)
+                    .prompt()?;
+                if generate {
+                    AdminKey::Generate(prompt_path("Store the admin key at:", Some(&default))?)
+                } else {
+                    AdminKey::Import(prompt_path("Path to the admin key:", None)?)
+                }
+            }
+        };
+
+        Ok(Self {
+            port,
+            host,
+            cert: Some(cert),
+            cert_key: Some(cert_key),
+            admin_key,
+        })
+    }
+
+    /// Content of the environment file that `yeetd` reads its configuration from
+    fn env_file(&self, cert: &Path, cert_key: &Path) -> String {
+        format!(
+            "# Generated by `yeet server init`\nYEET_PORT={}\nYEET_HOST={}\nYEET_TLS_CERT={}\nYEET_TLS_KEY={}\n",
+            self.port,
+            self.host,
+            cert.display(),
+            cert_key.display()
+        )
+    }
+}
+
+fn prompt_path(message: &str, default: Option<&Path>) -> Result<PathBuf, Report> {
+    let mut prompt = inquire::Text::new(message);
+    let default = default.map(|path| path.display().to_string());
+    if let Some(default) = &default {
+        prompt = prompt.with_default(default);
+    }
+    Ok(PathBuf::from(prompt.prompt()?))
+}
+
+/// Write the server configuration and tell the user how to run the server
+fn init(setup: &ServerSetup, output: &Path) -> Result<(), Report> {
+    let (Some(cert), Some(cert_key)) = (&setup.cert, &setup.cert_key) else {
+        bail!("`--cert` and `--cert-key` are required. `yeet server init` always sets up TLS");
+    };
+    let cert = std::path::absolute(cert)?;
+    let cert_key = std::path::absolute(cert_key)?;
+    if output.exists() {
+        bail!("{} already exists", output.display());
+    }
+
+    let admin_public_key = match &setup.admin_key {
+        AdminKey::Generate(path) => {
+            key::write_keypair(path, false)?;
+            key::public_key_path(path)
+        }
+        AdminKey::Import(path) => {
+            // make sure the key is usable before it ends up in the instructions
+            get_verify_key(path).attach(path.display().to_string())?;
+            path.clone()
+        }
+    };
+
+    File::create_new(output)
+        .attach(format!("Config file: {}", output.display()))?
+        .write_all(setup.env_file(&cert, &cert_key).as_bytes())?;
+    info!("Server config written to {}", output.display());
+
+    let url = format!("https://<server>:{}", setup.port);
+    section::print_sections(&[
+        section::section!(
+            "Server".bold().underline() => [
+                "Config", std::path::absolute(output)?.display(),
+                "Listen", std::net::SocketAddr::new(setup.host, setup.port),
+                "Admin Key", std::path::absolute(&admin_public_key)?.display(),
+            ]
+        ),
+        section::section!(
+            "systemd".bold().underline() => [
+                "yeetd.service", systemd_unit(&std::path::absolute(output)?),
+            ]
+        ),
+        section::section!(
+            "Next Steps".bold().underline() => [
+                "1", "Start yeetd. State (yeet.db, age.key) is kept in its working directory",
+                "2", format!("Register the first admin: yeet --url {url} user create"),
+                "", format!("Use {} as the key of the admin", admin_public_key.display()),
+            ]
+        ),
+    ]);
+
     Ok(())
 }
+
+fn systemd_unit(env_file: &Path) -> String {
+    format!(
+        "[Unit]
+Description=Yeet Server
+After=network.target
+
+[Service]
+ExecStart=yeetd
+EnvironmentFile={}
+StateDirectory=yeet
+WorkingDirectory=/var/lib/yeet
+
+[Install]
+WantedBy=multi-user.target",
+        env_file.display()
+    )
+}
+
+#[cfg(test)]
+mod test_server_init {
+    use std::path::{Path, PathBuf};
+
+    use super::{AdminKey, ServerSetup};
+
+    #[test]
+    fn env_file() {
+        let setup = ServerSetup {
+            port: 4337,
+            host: "::".parse().unwrap(),
+            cert: None,
+            cert_key: None,
+            admin_key: AdminKey::Generate(PathBuf::from("admin.key")),
+        };
+
+        assert_eq!(
+            setup.env_file(
+                Path::new("/etc/yeet/cert.pem"),
+                Path::new("/etc/yeet/key.pem")
+            ),
+            "# Generated by `yeet server init`
+YEET_PORT=4337
+YEET_HOST=::
+YEET_TLS_CERT=/etc/yeet/cert.pem
+YEET_TLS_KEY=/etc/yeet/key.pem
+"
+        );
+    }
+}
@@ -1,18 +1,47 @@
 #![expect(clippy::multiple_inherent_impl)]
 use std::{
-    os::unix::fs::{PermissionsExt as _, lchown},
-    path::Path,
+    future::Future,
+    net::SocketAddr,
+    os::{
+        fd::{AsFd, BorrowedFd},
+        unix::fs::{PermissionsExt as _, lchown},
+    },
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
 };
 
 use api::AgentAction;
 use httpsig_hyper::prelude::SecretKey;
-use log::info;
+use log::{debug, error, info};
 use nix::unistd::Group;
-use rootcause::{Report, compat::ReportAsError, prelude::ResultExt as _};
+use rootcause::{Report, bail, compat::ReportAsError, prelude::ResultExt as _};
 use serde::{Deserialize, Serialize};
-use tokio::fs::{self, remove_file};
+use tokio::{
+    fs::{self, remove_file},
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch},
+    time::Instant,
+};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        RootCertStore, ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject as _},
+        server::WebPkiClientVerifier,
+    },
+    server::TlsStream,
+};
 use url::Url;
-use zlink::{Connection, ReplyError, connection::socket::FetchPeerCredentials, proxy, unix};
+use zlink::{
+    Connection, ReplyError,
+    connection::socket::{
+        FetchPeerCredentials, ReadHalf, ReadResult, Socket, UnixSocket, WriteHalf,
+    },
+    proxy, unix,
+};
 
 shadow_rs::shadow!(build);
 
@@ -24,6 +53,20 @@ use crate::{
 };
 
 const SOCKET_PATH: &str = "/run/yeet/agent.varlink";
+/// The socket of an agent with `--home-manager-activation`, relative to `$XDG_RUNTIME_DIR`
+const USER_SOCKET: &str = "yeet/agent.varlink";
+
+fn user_socket_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR").map(|dir| Path::new(&dir).join(USER_SOCKET))
+}
+
+/// The agent of the calling user if they run one with `--home-manager-activation`, the one of the
+/// system otherwise
+fn socket_path() -> PathBuf {
+    user_socket_path()
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(SOCKET_PATH))
+}
 
 #[proxy("ch.yeetme.yeet")]
 pub trait YeetProxy {
@@ -34,11 +77,14 @@ pub trait YeetProxy {
         version: api::StorePath,
     ) -> zlink::Result<Result<(), YeetDaemonError>>;
     async fn attach(&mut self) -> zlink::Result<Result<(), YeetDaemonError>>;
+    async fn gc(&mut self, older_than: String) -> zlink::Result<Result<String, YeetDaemonError>>;
+    async fn invalidate_key_cache(&mut self) -> zlink::Result<Result<(), YeetDaemonError>>;
 }
 
 pub async fn client() -> Result<Connection<zlink::unix::Stream>, VarlinkError> {
-    log::debug!("Connecting to {SOCKET_PATH}");
-    Ok(unix::connect(SOCKET_PATH)
+    let path = socket_path();
+    log::debug!("Connecting to {}", path.display());
+    Ok(unix::connect(&path)
         .await
         .context("Trying to set up the varlink connection\nMake sure you are in the `yeet` group and the daemon is running")
         .map_err(ReportAsError::from)?)
@@ -84,6 +130,26 @@ pub async fn attach() -> Result<(), VarlinkError> {
         .map_err(VarlinkError::DaemonError)
 }
 
+pub async fn gc(older_than: String) -> Result<String, VarlinkError> {
+    let mut client = client().await?;
+    client
+        .gc(older_than)
+        .await
+        .context("Could not communicate with the varlink daemon. Are you running the same version?")
+        .map_err(ReportAsError::from)?
+        .map_err(VarlinkError::DaemonError)
+}
+
+pub async fn invalidate_key_cache() -> Result<(), VarlinkError> {
+    let mut client = client().await?;
+    client
+        .invalidate_key_cache()
+        .await
+        .context("Could not communicate with the varlink daemon. Are you running the same version?")
+        .map_err(ReportAsError::from)?
+        .map_err(VarlinkError::DaemonError)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum VarlinkError {
     #[error(transparent)]
@@ -107,6 +173,15 @@ pub enum YeetDaemonError {
     PolkitError {
         error: String,
     },
+    GarbageCollectionFailed {
+        error: String,
+    },
+    /// `older_than` of a garbage collection is not a number of days like `7d`
+    InvalidGcAge {
+        error: String,
+    },
+    /// Neither the global nor the host specific detach permission is set
+    DetachNotAllowed,
 }
 
 impl From<std::io::Error> for YeetDaemonError {
@@ -134,7 +209,9 @@ impl From<api::ResponseError> for YeetDaemonError {
 
 struct YeetVarlinkService {
     pub config: cli_args::AgentConfig,
+    pub server: api::Server,
     pub key: SecretKey,
+    pub soaking: watch::Receiver<Option<api::Soak>>,
 }
 
 #[zlink::service]
@@ -145,52 +222,13 @@ where
     #[zlink(interface = "ch.yeetme.yeet")]
     pub async fn status(&self) -> Result<DaemonStatus, YeetDaemonError> {
         log::debug!("Varlink: Daemon status requested");
-
-        //TODO unwrap
-        let verified = match api::is_host_verified(&self.config.server, &self.key).await {
-            Ok(verified) => Some(verified.is_success()),
-            Err(_) => None,
-        };
-
-        let system_check = {
-            let Ok(store_path) = version::get_active_version() else {
-                return Err(YeetDaemonError::NoCurrentSystem);
-            };
-
-            api::check_system(
-                &self.config.server,
-                &self.key,
-                api::VersionRequest { store_path },
-            )
-            .await
-        };
-
-        let up_to_date = match system_check {
-            Ok(AgentAction::Nothing) => UpToDate::Yes,
-            Ok(AgentAction::Detach) => UpToDate::Detached,
-            Ok(AgentAction::SwitchTo(_)) | Err(_) => UpToDate::No,
-        };
-
-        let mode = 'b: {
-            if let Some(verified) = verified
-                && !verified
-            {
-                break 'b DaemonMode::Unverified;
-            }
-
-            match system_check {
-                Ok(AgentAction::Nothing | AgentAction::SwitchTo(_)) => DaemonMode::Provisioned,
-                Ok(AgentAction::Detach) => DaemonMode::Detached,
-                Err(_) => DaemonMode::NetworkError,
-            }
-        };
-
-        Ok(DaemonStatus {
-            up_to_date,
-            server: self.config.server.clone(),
-            mode,
-            version: String::from(build::PKG_VERSION),
-        })
+        daemon_status(
+            &self.config,
+            &self.server,
+            &self.key,
+            self.soaking.borrow().clone(),
+        )
+        .await
     }
 
     #[expect(clippy::unused_async)]
@@ -219,12 +257,19 @@ where
         // Meaning that once the agent gets the action to switch to the next revision this will be reverted
         // Only use force on offline clients
 
+        if !api::detach_permission(&self.server, &self.key)
+            .await?
+            .effective
+        {
+            return Err(YeetDaemonError::DetachNotAllowed);
+        }
+
         // Signal detaching to server
-        let _status = api::detach_self(&self.config.server, &self.key).await?;
+        let _status = api::detach_self(&self.server, &self.key).await?;
         info!("System detached. Switching");
 
         // Switch to version
-        let _err = agent::switch_to(&version);
+        let _err = agent::switch_to(&version, &self.config).await;
 
         info!("Switched to detached version");
 
@@ -232,40 +277,475 @@ where
     }
 
     pub async fn attach(&self) -> Result<(), YeetDaemonError> {
-        let _status = api::attach_self(&self.config.server, &self.key).await?;
+        let _status = api::attach_self(&self.server, &self.key).await?;
         info!("System attached");
+        if let Err(err) = agent::reset_detached(&self.config.secret_base) {
+            error!("Could not remove the detached marker: {err}");
+        }
+
+        Ok(())
+    }
+
+    pub async fn gc(&self, older_than: String) -> Result<String, YeetDaemonError> {
+        // remote callers do not go through the parser of the CLI
+        let older_than = yeet::nix::parse_gc_age(&older_than)
+            .map_err(|error| YeetDaemonError::InvalidGcAge { error })?;
+        info!("Collecting garbage older than {older_than}");
+        let freed = tokio::task::spawn_blocking(move || yeet::nix::collect_garbage(&older_than))
+            .await
+            .map_err(|err| YeetDaemonError::GarbageCollectionFailed {
+                error: err.to_string(),
+            })?
+            .map_err(|err| YeetDaemonError::GarbageCollectionFailed {
+                error: err.to_string(),
+            })?;
+        info!("{freed}");
+        Ok(freed)
+    }
 
+    /// For deployments that rotate the nix trusted public keys without restarting the agent
+    #[expect(clippy::unused_async)]
+    pub async fn invalidate_key_cache(&self) -> Result<(), YeetDaemonError> {
+        agent::invalidate_trusted_public_keys();
         Ok(())
     }
 }
 
-pub async fn start_service(config: cli_args::AgentConfig, key: SecretKey) -> Result<(), Report> {
-    YeetVarlinkService::start(config, key).await
+/// Shared by the unix socket and the TCP socket. `soaking` is passed on so that a status request
+/// does not clear what the agent loop reported
+async fn daemon_status(
+    config: &AgentConfig,
+    server: &api::Server,
+    key: &SecretKey,
+    soaking: Option<api::Soak>,
+) -> Result<DaemonStatus, YeetDaemonError> {
+    //TODO unwrap
+    let verified = match api::is_host_verified(server, key).await {
+        Ok(verified) => Some(verified.is_success()),
+        Err(_) => None,
+    };
+
+    let system_check = {
+        let Ok(version) = version::version_request(config.home_manager_activation, soaking) else {
+            return Err(YeetDaemonError::NoCurrentSystem);
+        };
+
+        api::check_system(server, key, version).await
+    };
+
+    let up_to_date = match system_check {
+        Ok(AgentAction::Nothing | AgentAction::WaitForNextVersion { .. }) => UpToDate::Yes,
+        Ok(AgentAction::Detach) => UpToDate::Detached,
+        Ok(AgentAction::SwitchTo(_)) | Err(_) => UpToDate::No,
+    };
+
+    let mode = 'b: {
+        if let Some(verified) = verified
+            && !verified
+        {
+            break 'b DaemonMode::Unverified;
+        }
+
+        match system_check {
+            Ok(
+                AgentAction::Nothing
+                | AgentAction::SwitchTo(_)
+                | AgentAction::WaitForNextVersion { .. },
+            ) => DaemonMode::Provisioned,
+            Ok(AgentAction::Detach) => DaemonMode::Detached,
+            Err(_) => DaemonMode::NetworkError,
+        }
+    };
+
+    Ok(DaemonStatus {
+        up_to_date,
+        server: config.server.clone(),
+        mode,
+        version: String::from(build::PKG_VERSION),
+    })
+}
+
+/// Where the varlink service is served
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Every method for members of the `yeet` group
+    Unix(PathBuf),
+    /// Every method for the user running an agent with `--home-manager-activation`
+    User(PathBuf),
+    /// Only `Status` and `Config` for clients with a certificate signed by `TcpTls::client_ca`
+    Tcp { addr: SocketAddr, tls: TcpTls },
+}
+
+/// PEM files of the TCP transport
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpTls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: PathBuf,
+}
+
+/// A unix socket is always served. TCP only if it is configured together with its TLS files
+pub fn transports(config: &AgentConfig) -> Result<Vec<Transport>, Report> {
+    let mut transports = vec![local_transport(
+        config.home_manager_activation,
+        user_socket_path(),
+    )?];
+    match (
+        config.varlink_tcp,
+        &config.varlink_tcp_cert,
+        &config.varlink_tcp_key,
+        &config.varlink_tcp_client_ca,
+    ) {
+        (None, None, None, None) => {}
+        (Some(addr), Some(cert), Some(key), Some(client_ca)) => transports.push(Transport::Tcp {
+            addr,
+            tls: TcpTls {
+                cert: cert.clone(),
+                key: key.clone(),
+                client_ca: client_ca.clone(),
+            },
+        }),
+        (Some(addr), ..) => bail!(
+            "Refusing to serve varlink on {addr} without `varlink_tcp_cert`, `varlink_tcp_key` \
+             and `varlink_tcp_client_ca`"
+        ),
+        (None, ..) => bail!("The varlink TLS files are set but `varlink_tcp` is not"),
+    }
+    Ok(transports)
+}
+
+/// An agent without root can neither create `/run/yeet` nor hand the socket to the `yeet` group
+fn local_transport(home_manager: bool, user_socket: Option<PathBuf>) -> Result<Transport, Report> {
+    if !home_manager {
+        return Ok(Transport::Unix(PathBuf::from(SOCKET_PATH)));
+    }
+    Ok(Transport::User(user_socket.ok_or(rootcause::report!(
+        "Home-manager activation needs `XDG_RUNTIME_DIR` for the varlink socket"
+    ))?))
+}
+
+type Service = Pin<Box<dyn Future<Output = Result<(), Report>>>>;
+
+pub async fn start_service(
+    config: cli_args::AgentConfig,
+    server: api::Server,
+    key: SecretKey,
+    soaking: watch::Receiver<Option<api::Soak>>,
+) -> Result<(), Report> {
+    let services = transports(&config)?
+        .into_iter()
+        .map(|transport| -> Service {
+            match transport {
+                Transport::Unix(path) => Box::pin(YeetVarlinkService::start(
+                    path,
+                    Some("yeet"),
+                    config.clone(),
+                    server.clone(),
+                    key.clone(),
+                    soaking.clone(),
+                )),
+                Transport::User(path) => Box::pin(YeetVarlinkService::start(
+                    path,
+                    None,
+                    config.clone(),
+                    server.clone(),
+                    key.clone(),
+                    soaking.clone(),
+                )),
+                Transport::Tcp { addr, tls } => Box::pin(YeetReadOnlyService::start(
+                    addr,
+                    tls,
+                    config.clone(),
+                    server.clone(),
+                    key.clone(),
+                    soaking.clone(),
+                )),
+            }
+        });
+    futures_util::future::try_join_all(services).await?;
+    Ok(())
 }
 
 impl YeetVarlinkService {
-    pub async fn start(config: cli_args::AgentConfig, key: SecretKey) -> Result<(), Report> {
+    /// Without a group only the user running the agent can connect
+    pub async fn start(
+        path: PathBuf,
+        group: Option<&str>,
+        config: cli_args::AgentConfig,
+        server: api::Server,
+        key: SecretKey,
+        soaking: watch::Receiver<Option<api::Soak>>,
+    ) -> Result<(), Report> {
         let listener = {
-            let _err = remove_file(SOCKET_PATH).await;
-            #[expect(clippy::unwrap_used)]
-            fs::create_dir_all(Path::new(SOCKET_PATH).parent().unwrap())
-                .await
-                .context("Ensuring the Socket dir is available")?;
-            let listener =
-                zlink::unix::bind(SOCKET_PATH).attach(format!("SOCKET_PATH: {SOCKET_PATH}"))?;
-
-            setup_socket_permissions(SOCKET_PATH, "yeet").await?;
+            let _err = remove_file(&path).await;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .await
+                    .context("Ensuring the Socket dir is available")?;
+            }
+            let listener = tokio::net::UnixListener::bind(&path)
+                .attach(format!("SOCKET_PATH: {}", path.display()))?;
+
+            match group {
+                Some(group) => setup_socket_permissions(&path, group).await?,
+                None => {
+                    fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+                        .await
+                        .context("Trying to set file permission")?;
+                }
+            }
 
             listener
         };
 
-        log::debug!("Socket created at {SOCKET_PATH}");
-        let server = zlink::Server::new(listener, Self { config, key });
+        log::debug!("Socket created at {}", path.display());
+        let varlink = zlink::Server::new(
+            IdleListener(listener),
+            Self {
+                config,
+                server,
+                key,
+                soaking,
+            },
+        );
         log::info!("Listening for varlink connections");
-        server.run().await.map_err(std::convert::Into::into)
+        varlink.run().await.map_err(std::convert::Into::into)
     }
 }
 
+/// The subset of `YeetVarlinkService` that does not change the host. Served over TCP
+struct YeetReadOnlyService {
+    config: cli_args::AgentConfig,
+    server: api::Server,
+    key: SecretKey,
+    soaking: watch::Receiver<Option<api::Soak>>,
+}
+
+impl YeetReadOnlyService {
+    pub async fn start(
+        addr: SocketAddr,
+        tls: TcpTls,
+        config: cli_args::AgentConfig,
+        server: api::Server,
+        key: SecretKey,
+        soaking: watch::Receiver<Option<api::Soak>>,
+    ) -> Result<(), Report> {
+        let acceptor = tls_acceptor(&tls)?;
+        let listener = TcpListener::bind(addr)
+            .await
+            .attach(format!("Varlink TCP address: {addr}"))?;
+        let (handshaken, connections) = mpsc::channel(MAX_PENDING_CONNECTIONS);
+        let varlink = zlink::Server::new(
+            TlsListener(connections),
+            Self {
+                config,
+                server,
+                key,
+                soaking,
+            },
+        );
+        info!("Serving the read-only varlink methods on {addr}");
+        tokio::try_join!(handshake(listener, acceptor, handshaken), async {
+            varlink.run().await.map_err(Report::from)
+        })?;
+        Ok(())
+    }
+}
+
+#[zlink::service]
+impl YeetReadOnlyService {
+    #[zlink(interface = "ch.yeetme.yeet")]
+    pub async fn status(&self) -> Result<DaemonStatus, YeetDaemonError> {
+        log::debug!("Varlink: Daemon status requested over TCP");
+        daemon_status(
+            &self.config,
+            &self.server,
+            &self.key,
+            self.soaking.borrow().clone(),
+        )
+        .await
+    }
+
+    #[expect(clippy::unused_async)]
+    pub async fn config(&self) -> AgentConfig {
+        self.config.clone()
+    }
+}
+
+/// Connections that send nothing for this long are closed so that no client holds one forever
+const IDLE_TIMEOUT: Duration = Duration::from_mins(1);
+/// Clients that do not finish the TLS handshake in time are disconnected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// TLS connections that finished the handshake but were not picked up by the server yet
+const MAX_PENDING_CONNECTIONS: usize = 16;
+
+/// Only clients with a certificate signed by `tls.client_ca` finish the handshake
+fn tls_acceptor(tls: &TcpTls) -> Result<TlsAcceptor, Report> {
+    let certs = CertificateDer::pem_file_iter(&tls.cert)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .attach(format!("Varlink TLS certificate: {}", tls.cert.display()))?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key)
+        .attach(format!("Varlink TLS key: {}", tls.key.display()))?;
+    let mut client_ca = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(&tls.client_ca)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .attach(format!("Varlink client CA: {}", tls.client_ca.display()))?
+    {
+        client_ca.add(cert)?;
+    }
+    let verifier = WebPkiClientVerifier::builder(Arc::new(client_ca)).build()?;
+    let config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Handshakes run in their own tasks so that a slow client does not hold up the others
+async fn handshake(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    handshaken: mpsc::Sender<TlsStream<TcpStream>>,
+) -> Result<(), Report> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let handshaken = handshaken.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _closed = handshaken.send(stream).await;
+                }
+                Ok(Err(err)) => debug!("Varlink TLS handshake with {peer} failed: {err}"),
+                Err(_elapsed) => debug!("Varlink TLS handshake with {peer} timed out"),
+            }
+        });
+    }
+}
+
+type UnixReadHalf = <unix::Stream as Socket>::ReadHalf;
+type UnixWriteHalf = <unix::Stream as Socket>::WriteHalf;
+
+/// Accepts unix socket connections that are closed after `IDLE_TIMEOUT`
+#[derive(Debug)]
+struct IdleListener(tokio::net::UnixListener);
+
+impl zlink::Listener for IdleListener {
+    type Socket = IdleStream;
+
+    async fn accept(&mut self) -> zlink::Result<Connection<IdleStream>> {
+        let (stream, _) = self.0.accept().await?;
+        Ok(Connection::new(IdleStream(unix::Stream::from(stream))))
+    }
+}
+
+#[derive(Debug)]
+struct IdleStream(unix::Stream);
+
+impl Socket for IdleStream {
+    type ReadHalf = IdleReadHalf<UnixReadHalf>;
+    type WriteHalf = UnixWriteHalf;
+
+    const CAN_TRANSFER_FDS: bool = true;
+
+    fn split(self) -> (IdleReadHalf<UnixReadHalf>, UnixWriteHalf) {
+        let (read, write) = self.0.split();
+        (IdleReadHalf::new(read), write)
+    }
+}
+
+/// Hands out the connections that finished the TLS handshake. Receiving is cancel safe, which
+/// the server relies on
+#[derive(Debug)]
+struct TlsListener(mpsc::Receiver<TlsStream<TcpStream>>);
+
+impl zlink::Listener for TlsListener {
+    type Socket = TlsSocket;
+
+    async fn accept(&mut self) -> zlink::Result<Connection<TlsSocket>> {
+        let stream = self
+            .0
+            .recv()
+            .await
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(Connection::new(TlsSocket(stream)))
+    }
+}
+
+#[derive(Debug)]
+struct TlsSocket(TlsStream<TcpStream>);
+
+impl Socket for TlsSocket {
+    type ReadHalf = IdleReadHalf<TlsReadHalf>;
+    type WriteHalf = TlsWriteHalf;
+
+    fn split(self) -> (IdleReadHalf<TlsReadHalf>, TlsWriteHalf) {
+        let (read, write) = tokio::io::split(self.0);
+        (IdleReadHalf::new(TlsReadHalf(read)), TlsWriteHalf(write))
+    }
+}
+
+#[derive(Debug)]
+struct TlsReadHalf(tokio::io::ReadHalf<TlsStream<TcpStream>>);
+
+impl ReadHalf for TlsReadHalf {
+    async fn read(&mut self, buf: &mut [u8]) -> zlink::Result<ReadResult> {
+        Ok((self.0.read(buf).await?, Vec::new()))
+    }
+}
+
+#[derive(Debug)]
+struct TlsWriteHalf(tokio::io::WriteHalf<TlsStream<TcpStream>>);
+
+impl WriteHalf for TlsWriteHalf {
+    async fn write(&mut self, buf: &[u8], fds: &[impl AsFd]) -> zlink::Result<()> {
+        if !fds.is_empty() {
+            return Err(std::io::Error::other("File descriptors can not be sent over TCP").into());
+        }
+        self.0.write_all(buf).await?;
+        self.0.flush().await?;
+        Ok(())
+    }
+}
+
+/// The deadline outlives the read futures. The server recreates them after every event
+#[derive(Debug)]
+struct IdleReadHalf<R> {
+    inner: R,
+    deadline: Instant,
+}
+
+impl<R> IdleReadHalf<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            deadline: idle_deadline(),
+        }
+    }
+}
+
+fn idle_deadline() -> Instant {
+    let now = Instant::now();
+    now.checked_add(IDLE_TIMEOUT).unwrap_or(now)
+}
+
+impl<R: ReadHalf> ReadHalf for IdleReadHalf<R> {
+    async fn read(&mut self, buf: &mut [u8]) -> zlink::Result<ReadResult> {
+        let read = tokio::time::timeout_at(self.deadline, self.inner.read(buf))
+            .await
+            .map_err(|_elapsed| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        self.deadline = idle_deadline();
+        Ok(read)
+    }
+}
+
+impl AsFd for IdleReadHalf<UnixReadHalf> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+impl UnixSocket for IdleReadHalf<UnixReadHalf> {}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DaemonStatus {
     pub up_to_date: UpToDate,
@@ -289,10 +769,14 @@ pub enum DaemonMode {
     NetworkError,
 }
 
-async fn setup_socket_permissions(path: &str, group_name: &str) -> Result<(), Report> {
+async fn setup_socket_permissions(path: &Path, group_name: &str) -> Result<(), Report> {
     let group = Group::from_name(group_name)
-        .context("Error while trying to look up `yeet` group on the system")?
-        .ok_or(rootcause::report!("`yeet` Group does not exist on system"))?;
+        .context(format!(
+            "Error while trying to look up `{group_name}` group on the system"
+        ))?
+        .ok_or(rootcause::report!(
+            "`{group_name}` Group does not exist on system"
+        ))?;
 
     lchown(path, None, Some(group.gid.as_raw()))
         .context("Trying to set varlink socket connection")?;
@@ -306,6 +790,195 @@ async fn setup_socket_permissions(path: &str, group_name: &str) -> Result<(), Re
         .await
         .context("Trying to set file permission")?;
 
-    log::debug!("Socket permissions set: Group '{group_name}' can now access {path}");
+    log::debug!(
+        "Socket permissions set: Group '{group_name}' can now access {}",
+        path.display()
+    );
     Ok(())
 }
+
+#[cfg(test)]
+mod test_varlink {
+    use std::{path::PathBuf, sync::Arc};
+
+    use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+    use tokio_rustls::{
+        TlsConnector,
+        rustls::{
+            ClientConfig, RootCertStore,
+            pki_types::{CertificateDer, PrivateKeyDer, ServerName, pem::PemObject as _},
+        },
+    };
+
+    use super::{TcpTls, Transport};
+    use crate::cli_args::AgentConfig;
+
+    const TLS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls");
+
+    fn config(extra: &str) -> AgentConfig {
+        toml::from_str(&format!(
+            "server = \"https://yeet.example.com\"\nsleep = 30\nfacter = false\nkey = \"/etc/ssh/key\"\n{extra}"
+        ))
+        .unwrap()
+    }
+
+    fn key() -> httpsig_hyper::prelude::SecretKey {
+        httpsig_hyper::prelude::SecretKey::from_bytes(
+            &httpsig_hyper::prelude::AlgorithmName::Ed25519,
+            &[1; 32],
+        )
+        .unwrap()
+    }
+
+    fn tls() -> TcpTls {
+        TcpTls {
+            cert: PathBuf::from(TLS).join("server.pem"),
+            key: PathBuf::from(TLS).join("server.key"),
+            client_ca: PathBuf::from(TLS).join("ca.pem"),
+        }
+    }
+
+    fn connector(client: &str) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(format!("{TLS}/ca.pem")).unwrap())
+            .unwrap();
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                vec![CertificateDer::from_pem_file(format!("{TLS}/{client}.pem")).unwrap()],
+                PrivateKeyDer::from_pem_file(format!("{TLS}/{client}.key")).unwrap(),
+            )
+            .unwrap();
+        TlsConnector::from(Arc::new(config))
+    }
+
+    async fn roundtrip<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        message: &str,
+    ) -> std::io::Result<serde_json::Value> {
+        stream.write_all(message.as_bytes()).await?;
+        stream.write_all(&[0]).await?;
+        stream.flush().await?;
+        let mut reply = Vec::new();
+        while reply.last() != Some(&0) {
+            let mut buf = [0; 1024];
+            let read = stream.read(&mut buf).await?;
+            if read == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            reply.extend_from_slice(buf.get(..read).unwrap());
+        }
+        reply.pop();
+        Ok(serde_json::from_slice(&reply)?)
+    }
+
+    #[test]
+    fn local_only_by_default() {
+        assert_eq!(
+            super::transports(&config("")).unwrap(),
+            vec![Transport::Unix(PathBuf::from(super::SOCKET_PATH))]
+        );
+    }
+
+    #[test]
+    fn user_socket_with_home_manager() {
+        let socket = PathBuf::from("/run/user/1000/yeet/agent.varlink");
+        assert_eq!(
+            super::local_transport(true, Some(socket.clone())).unwrap(),
+            Transport::User(socket.clone())
+        );
+        assert_eq!(
+            super::local_transport(false, Some(socket)).unwrap(),
+            Transport::Unix(PathBuf::from(super::SOCKET_PATH))
+        );
+        super::local_transport(true, None).unwrap_err();
+    }
+
+    #[test]
+    fn tcp_with_tls() {
+        assert_eq!(
+            super::transports(&config(
+                "varlink_tcp = \"127.0.0.1:7070\"\nvarlink_tcp_cert = \"/run/cert.pem\"\nvarlink_tcp_key = \"/run/key.pem\"\nvarlink_tcp_client_ca = \"/run/ca.pem\""
+            ))
+            .unwrap(),
+            vec![
+                Transport::Unix(PathBuf::from(super::SOCKET_PATH)),
+                Transport::Tcp {
+                    addr: "127.0.0.1:7070".parse().unwrap(),
+                    tls: TcpTls {
+                        cert: PathBuf::from("/run/cert.pem"),
+                        key: PathBuf::from("/run/key.pem"),
+                        client_ca: PathBuf::from("/run/ca.pem"),
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tcp_without_tls() {
+        super::transports(&config("varlink_tcp = \"127.0.0.1:7070\"")).unwrap_err();
+        super::transports(&config("varlink_tcp_cert = \"/run/cert.pem\"")).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn tcp_readonly() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        // the server future is not `Send` so it gets a runtime of its own
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let config = config("");
+            let server = api::Server::new(config.server.clone(), config.timeouts()).unwrap();
+            runtime.block_on(super::YeetReadOnlyService::start(
+                addr,
+                tls(),
+                config,
+                server,
+                key(),
+                tokio::sync::watch::channel(None).1,
+            ))
+        });
+
+        let connect = async |client: &str| loop {
+            if let Ok(stream) = tokio::net::TcpStream::connect(addr).await {
+                return connector(client)
+                    .connect(ServerName::try_from("localhost").unwrap(), stream)
+                    .await;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+
+        let mut stream = connect("client").await.unwrap();
+        let reply = roundtrip(&mut stream, r#"{"method": "ch.yeetme.yeet.Config"}"#)
+            .await
+            .unwrap();
+        assert_eq!(
+            reply.pointer("/parameters/server"),
+            Some(&serde_json::json!("https://yeet.example.com/"))
+        );
+
+        // nothing that changes the host
+        let reply = roundtrip(&mut stream, r#"{"method": "ch.yeetme.yeet.Attach"}"#)
+            .await
+            .unwrap();
+        assert!(
+            reply.get("error").is_some_and(serde_json::Value::is_string),
+            "{reply}"
+        );
+
+        // the server only learns about a bad client certificate after the client sent it
+        let rejected = match connect("other").await {
+            Ok(mut stream) => {
+                roundtrip(&mut stream, r#"{"method": "ch.yeetme.yeet.Config"}"#).await
+            }
+            Err(err) => Err(err),
+        };
+        assert!(rejected.is_err(), "{rejected:?}");
+    }
+}
@@ -1,10 +1,108 @@
-use std::fs::read_link;
+use std::{
+    fs::{canonicalize, read_link},
+    io,
+    path::{Path, PathBuf},
+};
 
-use rootcause::{Report, prelude::ResultExt as _};
+use rootcause::{Report, report};
 
-pub fn get_active_version() -> Result<String, Report> {
-    Ok(read_link("/run/current-system")
-        .context("Current system has no `/run/current-system`")?
-        .to_string_lossy()
-        .to_string())
+/// Links to the system the host is running
+const CURRENT_SYSTEM: &str = "/run/current-system";
+/// Profile of a standalone home-manager, relative to the home directory
+const HOME_MANAGER_PROFILE: &str = ".local/state/nix/profiles/home-manager";
+
+/// With `home_manager` the active home-manager generation of the user instead of the system.
+/// `api::NOT_ACTIVATED` if nothing was activated yet so that a fresh install can still enroll
+pub fn get_active_version(home_manager: bool) -> Result<String, Report> {
+    if home_manager {
+        return home_manager_version(&home_manager_profile()?);
+    }
+    active_version(Path::new(CURRENT_SYSTEM))
+}
+
+/// Reads the active system once so that a check-in reports a single consistent snapshot
+pub fn version_request(
+    home_manager: bool,
+    soaking: Option<api::Soak>,
+) -> Result<api::VersionRequest, Report> {
+    Ok(api::VersionRequest {
+        store_path: get_active_version(home_manager)?,
+        soaking,
+    })
+}
+
+pub fn home_manager_profile() -> Result<PathBuf, Report> {
+    Ok(std::env::home_dir()
+        .ok_or(report!("Home-manager activation needs a home directory"))?
+        .join(HOME_MANAGER_PROFILE))
+}
+
+/// The profile links to numbered generations which link to the store
+fn home_manager_version(profile: &Path) -> Result<String, Report> {
+    match canonicalize(profile) {
+        Ok(generation) => Ok(generation.to_string_lossy().to_string()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(api::NOT_ACTIVATED.to_owned()),
+        Err(err) => Err(report!(err)
+            .context("Could not resolve the home-manager profile")
+            .attach(profile.display().to_string())
+            .into_dynamic()),
+    }
+}
+
+fn active_version(current_system: &Path) -> Result<String, Report> {
+    match read_link(current_system) {
+        Ok(system) => Ok(system.to_string_lossy().to_string()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(api::NOT_ACTIVATED.to_owned()),
+        Err(err) => Err(report!(err)
+            .context("`/run/current-system` is not a link")
+            .attach(current_system.display().to_string())
+            .into_dynamic()),
+    }
+}
+
+#[cfg(test)]
+mod test_version {
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn active_version() {
+        let base = tempfile::tempdir().unwrap();
+        let current_system = base.path().join("current-system");
+
+        // first boot
+        assert_eq!(
+            super::active_version(&current_system).unwrap(),
+            api::NOT_ACTIVATED
+        );
+
+        std::fs::write(&current_system, "").unwrap();
+        super::active_version(&current_system).unwrap_err();
+        std::fs::remove_file(&current_system).unwrap();
+
+        symlink("/nix/store/abc-nixos-system", &current_system).unwrap();
+        assert_eq!(
+            super::active_version(&current_system).unwrap(),
+            "/nix/store/abc-nixos-system"
+        );
+    }
+
+    #[test]
+    fn home_manager_version() {
+        let base = tempfile::tempdir().unwrap();
+        let generation = base.path().join("abc-home-manager-generation");
+        std::fs::create_dir_all(&generation).unwrap();
+        symlink(&generation, base.path().join("home-manager-2-link")).unwrap();
+        let profile = base.path().join("home-manager");
+
+        assert_eq!(
+            super::home_manager_version(&profile).unwrap(),
+            api::NOT_ACTIVATED
+        );
+
+        symlink("home-manager-2-link", &profile).unwrap();
+        assert_eq!(
+            super::home_manager_version(&profile).unwrap(),
+            generation.canonicalize().unwrap().to_string_lossy()
+        );
+    }
 }
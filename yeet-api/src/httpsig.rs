@@ -1,4 +1,4 @@
-use std::sync::LazyLock;
+use std::{sync::LazyLock, time::Duration};
 
 use http::StatusCode;
 use httpsig_hyper::{
@@ -68,11 +68,54 @@ pub fn sig_param<K: SigningKey + Sync>(key: &K) -> HttpSigResult<HttpSignaturePa
     Ok(signature_params)
 }
 
+/// Response header with the unix time of the server in seconds. Sent with every response so a
+/// client can tell a rejected signature apart from a wrong clock
+pub const SERVER_TIME_HEADER: &str = "X-Yeet-Server-Time";
+
+/// Clocks further apart are reported by `clock_skew`
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_mins(5);
+
+/// Reads `SERVER_TIME_HEADER`
+#[must_use]
+pub fn server_time(headers: &http::HeaderMap) -> Option<jiff::Timestamp> {
+    let secs = headers
+        .get(SERVER_TIME_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    jiff::Timestamp::from_second(secs).ok()
+}
+
+/// How far `local` is behind `server` (negative if ahead) if that exceeds `MAX_CLOCK_SKEW`
+#[must_use]
+pub fn clock_skew(server: jiff::Timestamp, local: jiff::Timestamp) -> Option<jiff::SignedDuration> {
+    let skew = server.duration_since(local);
+    (skew.unsigned_abs() > MAX_CLOCK_SKEW).then_some(skew)
+}
+
+/// Renders ` (server time: ..)` if the server sent its time and nothing otherwise
+struct ServerTimeSuffix(Option<jiff::Timestamp>);
+
+impl core::fmt::Display for ServerTimeSuffix {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(time) => write!(f, " (server time: {time})"),
+            None => Ok(()),
+        }
+    }
+}
+
 error_set::error_set! {
     #[expect(clippy::exhaustive_enums)]
     ResponseError := {
-        #[display("The server responded with a non success code: {code}: {error}")]
-        ServerError{code: StatusCode, error: String},
+        #[display("The server responded with a non success code: {code}: {error}{}", ServerTimeSuffix(*server_time))]
+        ServerError{
+            code: StatusCode,
+            error: String,
+            server_time: Option<jiff::Timestamp>,
+        },
         ReqwestError(reqwest::Error),
         #[display("The url was invalid: {0}")]
         URLParseError(url::ParseError),
@@ -85,6 +128,26 @@ error_set::error_set! {
     }
 }
 
+impl ResponseError {
+    /// `clock_skew` to the server if it rejected the request. A wrong clock makes the server
+    /// reject every signed request
+    #[must_use]
+    pub fn clock_skew(&self, local: jiff::Timestamp) -> Option<jiff::SignedDuration> {
+        let Self::ServerError {
+            code,
+            server_time: Some(server_time),
+            ..
+        } = self
+        else {
+            return None;
+        };
+        if !code.is_client_error() {
+            return None;
+        }
+        clock_skew(*server_time, local)
+    }
+}
+
 #[expect(async_fn_in_trait)]
 pub trait ErrorForJson {
     async fn error_for_json<T: DeserializeOwned>(self) -> Result<T, ResponseError>;
@@ -98,6 +161,7 @@ impl ErrorForJson for reqwest::Response {
         } else {
             Err(ResponseError::ServerError {
                 code: self.status(),
+                server_time: server_time(self.headers()),
                 error: self.text().await?,
             })
         }
@@ -109,6 +173,7 @@ impl ErrorForJson for reqwest::Response {
         } else {
             Err(ResponseError::ServerError {
                 code: self.status(),
+                server_time: server_time(self.headers()),
                 error: self.text().await?,
             })
         }
@@ -158,3 +223,87 @@ MC4CAQAwBQYDK2VwBCIEIDx2kNPzVZ7AmTCEY99KU4gw3DoCc9Unq+YCmVLAychJ
         assert!(req.headers().contains_key("content-digest"));
     }
 }
+
+#[cfg(test)]
+mod test_clock_skew {
+    use http::{HeaderMap, HeaderValue, StatusCode};
+
+    use super::{ResponseError, SERVER_TIME_HEADER};
+
+    const SERVER: i64 = 1_790_000_000;
+
+    fn server_error(code: StatusCode) -> ResponseError {
+        ResponseError::ServerError {
+            code,
+            error: "Signature verification failed".to_owned(),
+            server_time: Some(jiff::Timestamp::from_second(SERVER).unwrap()),
+        }
+    }
+
+    fn local(offset: i64) -> jiff::Timestamp {
+        jiff::Timestamp::from_second(SERVER.checked_add(offset).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn server_time_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(super::server_time(&headers), None);
+
+        headers.insert(SERVER_TIME_HEADER, HeaderValue::from_static("yesterday"));
+        assert_eq!(super::server_time(&headers), None);
+
+        headers.insert(SERVER_TIME_HEADER, HeaderValue::from(SERVER));
+        assert_eq!(super::server_time(&headers), Some(local(0)));
+    }
+
+    #[test]
+    fn detect_skew() {
+        let rejected = server_error(StatusCode::BAD_REQUEST);
+        assert_eq!(rejected.clock_skew(local(0)), None);
+        assert_eq!(rejected.clock_skew(local(300)), None);
+
+        // the host boots in the past, e.g. without a RTC
+        assert_eq!(
+            rejected.clock_skew(local(-3600)),
+            Some(jiff::SignedDuration::from_hours(1))
+        );
+        // or in the future
+        assert_eq!(
+            rejected.clock_skew(local(301)),
+            Some(jiff::SignedDuration::from_secs(-301))
+        );
+    }
+
+    #[test]
+    fn only_rejections_are_skew() {
+        assert_eq!(
+            server_error(StatusCode::INTERNAL_SERVER_ERROR).clock_skew(local(-3600)),
+            None
+        );
+
+        let without_time = ResponseError::ServerError {
+            code: StatusCode::BAD_REQUEST,
+            error: String::new(),
+            server_time: None,
+        };
+        assert_eq!(without_time.clock_skew(local(-3600)), None);
+    }
+
+    #[test]
+    fn display_server_time() {
+        assert_eq!(
+            server_error(StatusCode::BAD_REQUEST).to_string(),
+            "The server responded with a non success code: 400 Bad Request: Signature verification failed (server time: 2026-09-21T14:13:20Z)"
+        );
+
+        let without_time = ResponseError::ServerError {
+            code: StatusCode::BAD_REQUEST,
+            error: "Signature verification failed".to_owned(),
+            server_time: None,
+        };
+        assert_eq!(
+            without_time.to_string(),
+            "The server responded with a non success code: 400 Bad Request: Signature verification failed"
+        );
+    }
+}
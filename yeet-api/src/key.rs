@@ -5,7 +5,11 @@ use ed25519_dalek::{
     pkcs8::{DecodePrivateKey as _, DecodePublicKey as _},
 };
 use httpsig_hyper::prelude::{AlgorithmName, SecretKey};
-use ssh_key::{PrivateKey, PublicKey};
+use ssh_key::{
+    Fingerprint, HashAlg, PrivateKey, PublicKey,
+    public::Ed25519PublicKey,
+    sha2::{Digest as _, Sha256},
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -32,14 +36,34 @@ pub enum KeyError {
 /// # Errors
 /// will throw an `KeyNotSupported` if it could not find a way to get the key material
 pub fn get_verify_key<P: AsRef<Path>>(path: P) -> Result<VerifyingKey, KeyError> {
-    let key = read_to_string(path)?;
-    verifying_from_private_ssh(&key)
-        .or_else(|_| verifying_from_pub_ssh(key.as_str()))
-        .or_else(|_| SigningKey::from_pkcs8_pem(key.as_str()).map(|key| key.verifying_key()))
-        .or_else(|_| VerifyingKey::from_public_key_pem(key.as_str()))
+    parse_verify_key(&read_to_string(path)?)
+}
+
+/// Same as `get_verify_key` but for key material that is already in memory
+/// # Errors
+/// will throw an `KeyNotSupported` if it could not find a way to get the key material
+pub fn parse_verify_key(key: &str) -> Result<VerifyingKey, KeyError> {
+    verifying_from_private_ssh(key)
+        .or_else(|_| verifying_from_pub_ssh(key))
+        .or_else(|_| SigningKey::from_pkcs8_pem(key).map(|key| key.verifying_key()))
+        .or_else(|_| VerifyingKey::from_public_key_pem(key))
         .map_err(|_err| KeyError::KeyNotSupported)
 }
 
+/// SSH style fingerprint e.g. `SHA256:<base64>` as printed by `ssh-keygen -l`
+#[must_use]
+pub fn key_fingerprint(key: &VerifyingKey) -> String {
+    PublicKey::from(Ed25519PublicKey(key.to_bytes()))
+        .fingerprint(HashAlg::Sha256)
+        .to_string()
+}
+
+/// Fingerprint of an age recipient in the style of `key_fingerprint`
+#[must_use]
+pub fn recipient_fingerprint(recipient: &str) -> String {
+    Fingerprint::Sha256(Sha256::digest(recipient.as_bytes()).into()).to_string()
+}
+
 /// Get a verifying key from either
 /// - a private ssh key
 /// - a private pkcs8 pem
@@ -86,3 +110,27 @@ fn public_ssh_bytes(key: &str) -> Result<[u8; 32], KeyError> {
     let key = PublicKey::from_openssh(key)?;
     Ok(key.key_data().ed25519().ok_or(KeyError::NotED25519)?.0)
 }
+
+#[cfg(test)]
+mod test_key {
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn fingerprint_matches_ssh_keygen() {
+        let key = SigningKey::from_bytes(&[1; 32]).verifying_key();
+        assert_eq!(
+            super::key_fingerprint(&key),
+            "SHA256:fe85JkIjo8VPe+XqXJGH5Mau1EMFdK1OdKvJUFicyA8"
+        );
+    }
+
+    #[test]
+    fn recipient_fingerprint() {
+        assert_eq!(
+            super::recipient_fingerprint(
+                "age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p"
+            ),
+            "SHA256:z35WgjExUGhb8W5CxOpI4WjhboIGxcStN8ctATy2pfQ"
+        );
+    }
+}
@@ -5,11 +5,16 @@ mod key;
 mod secret;
 
 mod routes {
+    pub mod approval;
+    pub mod audit;
+    pub mod auth;
     pub mod health;
     pub mod host;
     pub mod key;
+    pub mod maintenance;
     pub mod osquery;
     pub mod secret;
+    pub mod status;
     pub mod system;
     pub mod tag;
     pub mod user;
@@ -19,12 +24,71 @@ mod routes {
 pub use httpsig::*;
 pub use key::*;
 pub use routes::{
-    health::*, host::*, key::*, osquery::*, secret::*, system::*, tag, user::*, verify::*,
+    approval::*, audit::*, auth, health::*, host::*, key::*, maintenance::*, osquery::*, secret::*,
+    status::*, system::*, tag, user::*, verify::*,
 };
 pub use secret::*;
 
 pub type StorePath = String;
 
+/// Timeouts of the client of a `Server`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Until the connection to the server is established
+    pub connect: std::time::Duration,
+    /// The whole request including reading the response
+    pub request: std::time::Duration,
+}
+
+/// Where the requests of this crate are sent to and with which client
+pub trait Endpoint: Sync {
+    fn url(&self) -> &url::Url;
+
+    fn client(&self) -> &reqwest::Client;
+}
+
+/// Without timeouts. Fine for the cli, long running processes should use a `Server`
+impl Endpoint for url::Url {
+    fn url(&self) -> &url::Url {
+        self
+    }
+
+    fn client(&self) -> &reqwest::Client {
+        static CLIENT: std::sync::LazyLock<reqwest::Client> =
+            std::sync::LazyLock::new(reqwest::Client::new);
+        &CLIENT
+    }
+}
+
+/// A server and the client used for every request to it
+#[derive(Clone, Debug)]
+pub struct Server {
+    url: url::Url,
+    client: reqwest::Client,
+}
+
+impl Server {
+    /// Without timeouts a server that accepts the connection but never answers blocks a request
+    /// forever
+    pub fn new(url: url::Url, timeouts: Timeouts) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.request)
+            .build()?;
+        Ok(Self { url, client })
+    }
+}
+
+impl Endpoint for Server {
+    fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+}
+
 #[inline]
 pub fn hash(value: impl std::hash::Hash) -> u64 {
     ahash::RandomState::with_seeds(1, 2, 3, 4).hash_one(value)
@@ -35,6 +99,27 @@ pub fn hash_hex(value: impl std::hash::Hash) -> String {
     format!("{:x}", hash(value))
 }
 
+/// Human readable size e.g. `2.3 GiB`
+#[must_use]
+#[expect(
+    clippy::cast_precision_loss,
+    clippy::float_arithmetic,
+    reason = "Only shown with one decimal"
+)]
+pub fn byte_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    for unit in ["KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return format!("{size:.1} {unit}");
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1} TiB")
+}
+
 /// # Panics
 /// idk maybe
 #[must_use]
@@ -110,13 +195,13 @@ macro_rules! request {
         body: $body:expr
     ) => {
         pub async fn $fn_name<K: httpsig_hyper::prelude::SigningKey + Sync>(
-            url: &url::Url,
+            url: &(impl crate::Endpoint + ?Sized),
             key: &K,
             $($param: $param_ty),*
         ) -> Result<http::StatusCode, crate::ResponseError> {
             use crate::httpsig::{ErrorForJson as _, ReqwestSig as _, sig_param};
-            reqwest::Client::new()
-                .$method(url.join(&format!($path))?)
+            url.client()
+                .$method(url.url().join(&format!($path))?)
                 .json($body)
                 .sign(&sig_param(key)?, key)
                 .await?
@@ -133,13 +218,13 @@ macro_rules! request {
         $method:ident($path:expr) -> StatusCode
     ) => {
         pub async fn $fn_name<K: httpsig_hyper::prelude::SigningKey + Sync>(
-            url: &url::Url,
+            url: &(impl crate::Endpoint + ?Sized),
             key: &K,
             $($param: $param_ty),*
         ) -> Result<http::StatusCode, crate::ResponseError> {
             use crate::httpsig::{ErrorForJson as _, ReqwestSig as _, sig_param};
-            reqwest::Client::new()
-                .$method(url.join(&format!($path))?)
+            url.client()
+                .$method(url.url().join(&format!($path))?)
                 .sign(&sig_param(key)?, key)
                 .await?
                 .send()
@@ -156,13 +241,13 @@ macro_rules! request {
         body: $body:expr
     ) => {
         pub async fn $fn_name<K: httpsig_hyper::prelude::SigningKey + Sync>(
-            url: &url::Url,
+            url: &(impl crate::Endpoint + ?Sized),
             key: &K,
             $($param: $param_ty),*
         ) -> Result<$ret, crate::ResponseError> {
             use crate::httpsig::{ErrorForJson as _, ReqwestSig as _, sig_param};
-            reqwest::Client::new()
-                .$method(url.join(&format!($path))?)
+            url.client()
+                .$method(url.url().join(&format!($path))?)
                 .json($body)
                 .sign(&sig_param(key)?, key)
                 .await?
@@ -179,13 +264,13 @@ macro_rules! request {
         $method:ident($path:expr) -> $ret:ty
     ) => {
         pub async fn $fn_name<K: httpsig_hyper::prelude::SigningKey + Sync>(
-            url: &url::Url,
+            url: &(impl crate::Endpoint + ?Sized),
             key: &K,
             $($param: $param_ty),*
         ) -> Result<$ret, crate::ResponseError> {
             use crate::httpsig::{ErrorForJson as _, ReqwestSig as _, sig_param};
-            reqwest::Client::new()
-                .$method(url.join(&format!($path))?)
+            url.client()
+                .$method(url.url().join(&format!($path))?)
                 .sign(&sig_param(key)?, key)
                 .await?
                 .send()
@@ -196,3 +281,45 @@ macro_rules! request {
     };
 }
 pub(crate) use request;
+
+#[cfg(test)]
+mod test_lib {
+    use std::time::Duration;
+
+    use super::byte_size;
+
+    #[test]
+    fn human_byte_size() {
+        assert_eq!(byte_size(0), "0 B");
+        assert_eq!(byte_size(1023), "1023 B");
+        assert_eq!(byte_size(1024), "1.0 KiB");
+        assert_eq!(byte_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(byte_size(2_469_606_195), "2.3 GiB");
+        assert_eq!(byte_size(3 * 1024 * 1024 * 1024 * 1024), "3.0 TiB");
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        // accepts connections through the backlog but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = url::Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+
+        let server = super::Server::new(
+            url,
+            super::Timeouts {
+                connect: Duration::from_secs(1),
+                request: Duration::from_millis(200),
+            },
+        )
+        .unwrap();
+        let err = tokio::time::timeout(Duration::from_secs(5), super::server_info(&server))
+            .await
+            .expect("the request timeout did not apply")
+            .unwrap_err();
+        assert!(
+            matches!(&err, super::ResponseError::ReqwestError(err) if err.is_timeout()),
+            "{err}"
+        );
+        drop(listener);
+    }
+}
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{HostID, SecretID, UserID, request};
+
+crate::db_id!(ApprovalID);
+
+/// Destructive actions a second admin has to confirm when the server enforces the two-person rule
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApprovalAction {
+    /// What `delete_key` does with the key of a host
+    RemoveHost(HostID),
+    /// What `delete_secret` does
+    DeleteSecret(SecretID),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingApproval {
+    pub id: ApprovalID,
+    pub action: ApprovalAction,
+    /// Can not confirm the action itself
+    pub proposer: UserID,
+    pub proposed: jiff::Timestamp,
+}
+
+request! (
+    propose_approval(action: ApprovalAction),
+    post("/approval/propose") -> ApprovalID,
+    body: &action
+);
+
+// Executes the action. Only an admin other than the proposer can confirm it
+request! (
+    confirm_approval(id: ApprovalID),
+    post("/approval/confirm") -> StatusCode,
+    body: &id
+);
+
+// Only the approvals the caller could confirm
+request! (
+    list_approvals(),
+    get("/approval") -> Vec<PendingApproval>
+);
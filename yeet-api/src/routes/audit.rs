@@ -0,0 +1,29 @@
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+
+use crate::request;
+
+/// One admin mutation. `hash` is the sha256 over `prev_hash`, `actor`, `time`, `action` and `detail`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub prev_hash: Vec<u8>,
+    pub hash: Vec<u8>,
+    /// Key the mutation was signed with
+    pub actor: VerifyingKey,
+    /// Same names as `yeet whoami` e.g. `Secret::Rename`
+    pub action: String,
+    pub detail: String,
+    pub time: jiff::Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditLog {
+    pub entries: Vec<AuditEntry>,
+    /// Index of the first entry whose hash does not match. `None` if the chain is intact
+    pub broken_at: Option<usize>,
+}
+
+request! (
+    audit_mutations(),
+    get("/audit/mutations") -> AuditLog
+);
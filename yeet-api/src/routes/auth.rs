@@ -0,0 +1,76 @@
+use std::fmt::Display;
+
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+
+use crate::{HostInfo, User, request};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExplainRequest {
+    /// `None` explains the key the request is signed with.
+    /// Explaining any other key requires an admin with `all_tag`
+    pub key: Option<VerifyingKey>,
+}
+
+/// Who a key belongs to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum KeyOwner {
+    User(Box<User>),
+    Host(HostInfo),
+    /// Neither a user nor a host
+    Unknown,
+}
+
+/// Which resources an action applies to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// Every resource
+    All,
+    /// Only resources sharing a tag with the user
+    Tagged,
+    /// Only the host itself
+    Own,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scope = match self {
+            Scope::All => "all",
+            Scope::Tagged => "tagged",
+            Scope::Own => "own",
+        };
+        write!(f, "{scope}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Permission {
+    /// Named like the actions in the route table e.g. `Secret::View`
+    pub action: String,
+    pub scope: Scope,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthExplanation {
+    pub key: VerifyingKey,
+    pub owner: KeyOwner,
+    pub permissions: Vec<Permission>,
+}
+
+impl AuthExplanation {
+    /// Scope of `action` or `None` if the key may not perform it
+    #[must_use]
+    pub fn scope(&self, action: &str) -> Option<Scope> {
+        self.permissions
+            .iter()
+            .find(|permission| permission.action == action)
+            .map(|permission| permission.scope)
+    }
+}
+
+// Debugging view over the permission checks of the server
+request! (
+    explain(request: ExplainRequest),
+    post("/auth/explain") -> AuthExplanation,
+    body: &request
+);
@@ -1,11 +1,61 @@
-pub async fn is_healthy(url: &url::Url) -> bool {
-    let Ok(url) = url.join("/health") else {
+use serde::{Deserialize, Serialize};
+
+use crate::{ErrorForJson as _, ResponseError};
+
+pub async fn is_healthy(server: &(impl crate::Endpoint + ?Sized)) -> bool {
+    let Ok(url) = server.url().join("/health") else {
         return false;
     };
 
-    let Ok(response) = reqwest::Client::new().get(url).send().await else {
+    let Ok(response) = server.client().get(url).send().await else {
         return false;
     };
 
     response.status().is_success()
 }
+
+/// Capabilities a server advertises in `ServerInfo::features`
+pub mod feature {
+    pub const ACTIVATION_REPORTS: &str = "activation_reports";
+    pub const APPROVALS: &str = "approvals";
+    pub const AUDIT_LOG: &str = "audit_log";
+    pub const DOWNLOAD_STATS: &str = "download_stats";
+    pub const HOST_GROUPS: &str = "host_groups";
+    pub const HOST_IMPORT: &str = "host_import";
+    pub const SEALED_SECRETS: &str = "sealed_secrets";
+    pub const SECRET_ACL_BATCH: &str = "secret_acl_batch";
+    pub const SECRET_ALIASES: &str = "secret_aliases";
+    pub const SECRET_AS_HOST: &str = "secret_as_host";
+    pub const SECRET_LIST_PAGE: &str = "secret_list_page";
+    pub const SECRET_PUT_MODES: &str = "secret_put_modes";
+    pub const SECRET_ROTATION: &str = "secret_rotation";
+    pub const SERVER_RECIPIENT_ETAG: &str = "server_recipient_etag";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub version: String,
+    /// `SHA256:<base64>` of the age recipient secrets are encrypted for. See `recipient_fingerprint`
+    pub server_public_key_fingerprint: String,
+    /// See `feature`. Older servers do not know about newer features
+    pub features: Vec<String>,
+}
+
+impl ServerInfo {
+    #[must_use]
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|supported| supported == feature)
+    }
+}
+
+/// Public. Servers older than this endpoint answer with 404
+pub async fn server_info(
+    url: &(impl crate::Endpoint + ?Sized),
+) -> Result<ServerInfo, ResponseError> {
+    url.client()
+        .get(url.url().join("/server/info")?)
+        .send()
+        .await?
+        .error_for_json()
+        .await
+}
@@ -4,7 +4,7 @@ use colored::Colorize as _;
 use ed25519_dalek::VerifyingKey;
 use serde::{Deserialize, Serialize};
 
-use crate::{StorePath, request, tag};
+use crate::{Soak, StorePath, request, tag};
 
 crate::db_id!(HostID);
 
@@ -52,9 +52,47 @@ pub struct Host {
     pub hostname: String,
     pub state: ProvisionState,
     pub last_ping: jiff::Timestamp,
+    /// Store path the agent last reported as active
     pub version: Option<StorePath>,
+    /// Store path of the last update requested for the host e.g. with `update_hosts`
     pub latest_update: Option<StorePath>,
+    /// Closure size in bytes of the last update the host downloaded
+    pub last_download_size: Option<u64>,
+    /// When the host last reported its nixos-facter output
+    pub last_facter: Option<jiff::Timestamp>,
+    /// The version the agent held back at its last check
+    #[serde(default)]
+    pub soaking: Option<Soak>,
     pub tags: Vec<tag::Tag>,
+    /// When the host was accepted or imported
+    pub enrolled_at: jiff::Timestamp,
+    /// When the host last reported a successful activation
+    pub last_updated_at: Option<jiff::Timestamp>,
+    /// The last activations of the host, newest first. At most `DEPLOY_HISTORY` entries
+    #[serde(default)]
+    pub history: Vec<Deployment>,
+}
+
+/// How many activations `Host::history` carries
+pub const DEPLOY_HISTORY: usize = 5;
+
+/// An activation the agent reported for the host
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Deployment {
+    pub store_path: StorePath,
+    pub success: bool,
+    pub error: Option<String>,
+    pub time: jiff::Timestamp,
+}
+
+impl Host {
+    /// `latest_update` if the host has not activated it yet
+    #[must_use]
+    pub fn pending_update(&self) -> Option<&StorePath> {
+        self.latest_update
+            .as_ref()
+            .filter(|update| self.version.as_ref() != Some(*update))
+    }
 }
 
 impl Display for Host {
@@ -109,11 +147,49 @@ request! (
     get("/host") -> Vec<Host>
 );
 
+/// The latest nixos-facter output of a host
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FacterReport {
+    pub nixos_facter: String,
+    pub report_time: jiff::Timestamp,
+}
+
+request! (
+    host_facter(host: HostID),
+    get("/host/{host}/facter") -> Option<FacterReport>
+);
+
 request! (
     rename_host(host: HostID, new_name: &str),
     put("/host/{host}/rename/{new_name}") -> StatusCode
 );
 
+/// A host registered ahead of its first check-in. It skips the verification code flow
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostImport {
+    pub hostname: String,
+    /// Public key of the agent in OpenSSH or PKCS#8 PEM format
+    pub key: String,
+    /// Age recipient the host gets its secrets encrypted for.
+    /// Without it the recipient of the first secret request of the host is pinned
+    #[serde(default)]
+    pub recipient: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostImportResult {
+    pub hostname: String,
+    /// The id of the new host or why it was not imported
+    pub result: Result<HostID, String>,
+}
+
+// Every host is imported on its own. One invalid host does not abort the import
+request! (
+    import_hosts(hosts: Vec<HostImport>),
+    post("/host/import") -> Vec<HostImportResult>,
+    body: &hosts
+);
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 /// Represents a Host Update Request
 /// The Agent uses the substitutor to fetch the update via nix
@@ -132,3 +208,40 @@ request! (
     post("/host/update") -> StatusCode,
     body: &update
 );
+
+/// A named set of hosts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostGroup {
+    pub name: String,
+}
+
+request! (
+    create_host_group(group: HostGroup),
+    post("/host/group/create") -> StatusCode,
+    body: &group
+);
+
+/// A host in a group. Both are identified by their name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostGroupMember {
+    pub group: String,
+    pub host: String,
+}
+
+request! (
+    add_to_host_group(member: HostGroupMember),
+    post("/host/group/add") -> StatusCode,
+    body: &member
+);
+
+request! (
+    remove_from_host_group(member: HostGroupMember),
+    post("/host/group/remove") -> StatusCode,
+    body: &member
+);
+
+// Groups map to the names of their hosts
+request! (
+    list_host_groups(),
+    get("/host/groups") -> HashMap<String, Vec<String>>
+);
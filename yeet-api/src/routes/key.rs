@@ -1,9 +1,24 @@
 use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
 
-use crate::request;
+use crate::{auth::KeyOwner, request};
+
+/// An enrolled key together with who it belongs to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyEntry {
+    pub key: VerifyingKey,
+    /// See `key_fingerprint`
+    pub fingerprint: String,
+    pub owner: KeyOwner,
+}
 
 request! (
     delete_key(delete_key: VerifyingKey),
     delete("/key/delete") -> StatusCode,
     body: &delete_key
 );
+
+request! (
+    list_keys(),
+    get("/key/list") -> Vec<KeyEntry>
+);
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{HostID, SecretID, request, tag};
+
+/// An acl entry whose secret or host no longer exists
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrphanedAcl {
+    pub secret: SecretID,
+    pub host: HostID,
+}
+
+/// A tag that is still attached to a resource that no longer exists
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrphanedTag {
+    pub resource: tag::Resource,
+    pub tag: tag::TagID,
+}
+
+/// Everything `prune` removed
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    pub acl: Vec<OrphanedAcl>,
+    pub tags: Vec<OrphanedTag>,
+}
+
+impl PruneReport {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.acl.is_empty() && self.tags.is_empty()
+    }
+}
+
+// Entries only end up orphaned if the database was changed out-of-band
+request! (
+    prune(),
+    post("/maintenance/prune") -> PruneReport
+);
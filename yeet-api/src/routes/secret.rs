@@ -1,6 +1,5 @@
 use httpsig_hyper::prelude::*;
 use serde::{Deserialize, Serialize};
-use url::Url;
 
 use crate::{
     HostID,
@@ -16,6 +15,9 @@ pub struct SecretName {
     pub name: String,
     pub tags: Vec<tag::Tag>,
     pub hosts: Vec<HostID>,
+    /// Label like `pem`, `env` or `json`. Aliases have the content type of their target
+    #[serde(default)]
+    pub content_type: Option<String>,
 }
 
 impl std::fmt::Display for SecretName {
@@ -30,12 +32,170 @@ pub struct GetSecretRequest {
     pub secret: String,
 }
 
+/// Answer of `/secret` for the calling host
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretLookup {
+    /// No secret with that name exists
+    NotFound,
+    /// The secret exists but the host is not in its ACL
+    NoAccess,
+    /// `content` is encrypted for the recipient of the host on the wire. `get_secret` decrypts it
+    Found {
+        content: Vec<u8>,
+        #[serde(default)]
+        content_type: Option<String>,
+    },
+}
+
+impl SecretLookup {
+    #[must_use]
+    pub fn found(self) -> Option<Vec<u8>> {
+        match self {
+            Self::Found { content, .. } => Some(content),
+            Self::NotFound | Self::NoAccess => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecretAccessQuery {
+    pub host: String,
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecretAclQuery {
+    pub secret: String,
+}
+
+/// Whether a host could fetch a secret. `reason` explains a denial
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecretAccess {
+    pub allowed: bool,
+    pub reason: Option<String>,
+}
+
+impl SecretAccess {
+    #[must_use]
+    pub fn allowed() -> Self {
+        Self {
+            allowed: true,
+            reason: None,
+        }
+    }
+
+    #[must_use]
+    pub fn denied(reason: &str) -> Self {
+        Self {
+            allowed: false,
+            reason: Some(reason.to_owned()),
+        }
+    }
+}
+
+/// What `/secret/put` does with the name of an existing secret
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AddSecretMode {
+    /// Conflict if the name exists. Like `/secret/add`
+    CreateOnly,
+    /// Not found unless the name exists. Like `/secret/{id}/rotate`
+    UpdateOnly,
+    /// Create the secret or replace its content
+    #[default]
+    Upsert,
+}
+
+/// `secret` has to be encrypted for the server recipient. Replacing the content keeps the acl and
+/// tags. `content_type` is only changed if it is set
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AddSecretRequest {
+    pub name: String,
+    pub secret: Vec<u8>,
+    #[serde(default)]
+    pub mode: AddSecretMode,
+    #[serde(default)]
+    pub content_type: Option<String>,
+}
+
+/// `created` is false if the content of an existing secret was replaced
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddedSecret {
+    pub id: SecretID,
+    pub created: bool,
+}
+
+/// Query of `/secret/list/page`. `prefix` filters the names before `offset` and `limit` apply.
+/// Without `limit` every secret after `offset` is returned
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListSecretsQuery {
+    pub prefix: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// Secrets sorted by name. `total` counts all secrets matching the prefix, not only this page
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecretPage {
+    pub secrets: Vec<SecretName>,
+    pub total: usize,
+}
+
+/// Grants (`allow`) or revokes access of `host` to `secret`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclSecretRequest {
+    pub secret: SecretID,
+    pub host: HostID,
+    pub allow: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BatchAclSecretRequest {
+    pub operations: Vec<AclSecretRequest>,
+}
+
+/// Operations are identified by their index in `BatchAclSecretRequest::operations`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchAclResult {
+    pub succeeded: Vec<usize>,
+    pub failed: Vec<(usize, String)>,
+}
+
 request! (
     create_secret(name: &str, secret: &[u8]),
     post("/secret/add/{name}") -> SecretName,
     body: secret
 );
 
+// Labels the secret with `content_type` e.g. `pem`. The agent picks a file extension from it
+request! (
+    create_typed_secret(name: &str, content_type: &str, secret: &[u8]),
+    post("/secret/add/{name}/type/{content_type}") -> SecretName,
+    body: secret
+);
+
+// `secret` has to be encrypted for the recipient `host` enrolled with. The server stores it as is
+// and only `host` can fetch it
+request! (
+    create_sealed_secret(name: &str, host: HostID, secret: &[u8]),
+    post("/secret/add/{name}/sealed/{host}") -> SecretName,
+    body: secret
+);
+
+// Creates or replaces a secret depending on `AddSecretRequest::mode`
+request! (
+    put_secret(request: AddSecretRequest),
+    post("/secret/put") -> AddedSecret,
+    body: &request
+);
+
+request! (
+    rotate_secret(id: SecretID, secret: &[u8]),
+    put("/secret/{id}/rotate") -> StatusCode,
+    body: secret
+);
+
 request! (
     rename_secret(id: SecretID, new_name: &str),
     put("/secret/{id}/rename/{new_name}") -> StatusCode
@@ -46,6 +206,17 @@ request! (
     delete("/secret/{id}/delete") -> StatusCode
 );
 
+// The alias shares the content of `target` but has its own acl and tags
+request! (
+    create_alias(target: SecretID, name: &str),
+    post("/secret/{target}/alias/{name}") -> SecretName
+);
+
+request! (
+    delete_alias(alias: SecretID),
+    delete("/secret/{alias}/alias") -> StatusCode
+);
+
 request! (
     allow_host(secret: SecretID, host: HostID),
     put("/secret/{secret}/allow/{host}") -> StatusCode
@@ -56,41 +227,174 @@ request! (
     put("/secret/{secret}/block/{host}") -> StatusCode
 );
 
+// Applies all operations in one transaction. A failed operation does not undo the others
+request! (
+    batch_acl(request: BatchAclSecretRequest),
+    post("/secret/acl/batch") -> BatchAclResult,
+    body: &request
+);
+
 request! (
     list_secrets(),
     get("/secret/list") -> Vec<SecretName>
 );
 
+// `Ok` if the server can still decrypt the secret named `name`
+request! (
+    check_secret(name: &str),
+    post("/secret/check") -> StatusCode,
+    body: name
+);
+
 request! (
     server_age_key(),
     get("/secret/server_key") -> String
 );
 
+/// The recipient of `server_age_key` together with the `ETag` the server sent for it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ServerRecipient {
+    pub recipient: String,
+    /// Empty if the server did not send an `ETag`
+    pub version: String,
+}
+
+/// `ETag` of `/secret/server_key`. Changes whenever the recipient changes
+#[must_use]
+pub fn recipient_version(recipient: &str) -> String {
+    format!("\"{}\"", crate::hash_hex(recipient))
+}
+
+/// Like `server_age_key` but revalidates `cached` with `If-None-Match`.
+/// Returns `cached` if the server answered `304 Not Modified`
+pub async fn server_recipient<K: SigningKey + Sync>(
+    url: &(impl crate::Endpoint + ?Sized),
+    key: &K,
+    cached: Option<&ServerRecipient>,
+) -> Result<ServerRecipient, ResponseError> {
+    let mut request = url.client().get(url.url().join("/secret/server_key")?);
+    if let Some(cached) = cached {
+        request = request.header(http::header::IF_NONE_MATCH, &cached.version);
+    }
+    let response = request.sign(&sig_param(key)?, key).await?.send().await?;
+
+    if let Some(cached) = cached
+        && response.status() == http::StatusCode::NOT_MODIFIED
+    {
+        return Ok(cached.clone());
+    }
+    let version = response
+        .headers()
+        .get(http::header::ETAG)
+        .and_then(|version| version.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    Ok(ServerRecipient {
+        recipient: response.error_for_json().await?,
+        version,
+    })
+}
+
+/// Sorted and paginated `list_secrets` for stores with many secrets
+/// Implemented manually because the query is sent as query parameters
+pub async fn list_secrets_page<K: SigningKey + Sync>(
+    url: &(impl crate::Endpoint + ?Sized),
+    key: &K,
+    query: &ListSecretsQuery,
+) -> Result<SecretPage, ResponseError> {
+    url.client()
+        .get(url.url().join("/secret/list/page")?)
+        .query(query)
+        .sign(&sig_param(key)?, key)
+        .await?
+        .send()
+        .await?
+        .error_for_json()
+        .await
+}
+
+/// Check if `host` could fetch `secret` without decrypting anything
+/// Implemented manually because the names are sent as query parameters
+pub async fn check_secret_access<K: SigningKey + Sync>(
+    url: &(impl crate::Endpoint + ?Sized),
+    key: &K,
+    host: &str,
+    secret: &str,
+) -> Result<SecretAccess, ResponseError> {
+    url.client()
+        .get(url.url().join("/secret/check-access")?)
+        .query(&SecretAccessQuery {
+            host: host.to_owned(),
+            secret: secret.to_owned(),
+        })
+        .sign(&sig_param(key)?, key)
+        .await?
+        .send()
+        .await?
+        .error_for_json()
+        .await
+}
+
+// What `host` would receive for `secret` but decrypted. `SecretLookup::Found::content` is the
+// plaintext. Needs an admin with `all_tag` and a server started with `YEET_SECRET_PEEK`
+request! (
+    secret_as_host(query: SecretAccessQuery),
+    post("/secret/as_host") -> SecretLookup,
+    body: &query
+);
+
+/// Hosts that may fetch `secret`. Hosts the user has no access to are left out
+pub async fn acl_by_secret<K: SigningKey + Sync>(
+    url: &(impl crate::Endpoint + ?Sized),
+    key: &K,
+    secret: &str,
+) -> Result<Vec<HostID>, ResponseError> {
+    url.client()
+        .get(url.url().join("/secret/acl/by-secret")?)
+        .query(&SecretAclQuery {
+            secret: secret.to_owned(),
+        })
+        .sign(&sig_param(key)?, key)
+        .await?
+        .send()
+        .await?
+        .error_for_json()
+        .await
+}
+
 /// This has to do more that a normal fetch so we implement i manually
+/// `identity` has to be the identity whose recipient the host enrolled with.
+/// Host recipients are always x25519, the server rejects plugin recipients on enrollment
 pub async fn get_secret<K: SigningKey + Sync>(
-    url: &Url,
+    url: &(impl crate::Endpoint + ?Sized),
     key: &K,
+    identity: &age::x25519::Identity,
     name: String,
-) -> Result<Option<Vec<u8>>, ResponseError> {
-    let identity = age::x25519::Identity::generate();
+) -> Result<SecretLookup, ResponseError> {
     let request = GetSecretRequest {
         recipient: identity.to_public().to_string(),
         secret: name,
     };
 
-    let response = reqwest::Client::new()
-        .post(url.join("/secret")?)
+    let response = url
+        .client()
+        .post(url.url().join("/secret")?)
         .json(&request)
         .sign(&sig_param(key)?, key)
         .await?
         .send()
         .await?
-        .error_for_json::<Option<Vec<u8>>>()
+        .error_for_json::<SecretLookup>()
         .await?;
 
-    if let Some(ciphertext) = response {
-        Ok(Some(age::decrypt(&identity, &ciphertext)?))
-    } else {
-        Ok(None)
+    match response {
+        SecretLookup::Found {
+            content,
+            content_type,
+        } => Ok(SecretLookup::Found {
+            content: age::decrypt(identity, &content)?,
+            content_type,
+        }),
+        lookup @ (SecretLookup::NotFound | SecretLookup::NoAccess) => Ok(lookup),
     }
 }
@@ -0,0 +1,26 @@
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+
+use crate::{HostID, request};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HostByKeyRequest {
+    /// Has to be the key the request is signed with
+    pub key: VerifyingKey,
+}
+
+/// What the server knows a host as
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HostInfo {
+    pub id: HostID,
+    /// Assigned by the admin when accepting the verification attempt
+    pub hostname: String,
+}
+
+// Lets a freshly enrolled agent learn the hostname the server assigned to it.
+// Returns `None` while the key is not enrolled as a host
+request! (
+    host_by_key(request: HostByKeyRequest),
+    post("/status/host_by_key") -> Option<HostInfo>,
+    body: &request
+);
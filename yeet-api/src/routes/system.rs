@@ -1,6 +1,28 @@
+use std::time::Duration;
+
+use httpsig_hyper::prelude::SigningKey;
 use serde::{Deserialize, Serialize};
 
-use crate::{StorePath, request};
+use crate::{
+    HostID, StorePath,
+    httpsig::{ErrorForJson as _, ReqwestSig as _, ResponseError, sig_param},
+    request,
+};
+
+/// Response header of `/system/check` with the seconds the agent waits before it checks again.
+/// Overrides the configured sleep for the next iteration only
+pub const POLL_AFTER_HEADER: &str = "X-Yeet-Poll-After";
+
+/// Response header of `/system/check` set to `true` if the secrets the host can fetch changed since
+/// its last check e.g. because an admin changed the acl
+pub const SECRETS_UPDATED_HEADER: &str = "X-Yeet-Secrets-Updated";
+
+/// `VersionRequest::store_path` of a host that has not activated a system yet e.g. on the first
+/// boot of a fresh install. The server does not record it as the version of the host
+pub const NOT_ACTIVATED: &str = "none";
+
+/// Upper bound for `POLL_AFTER_HEADER` so a wrong value can not lock a host out for days
+pub const MAX_POLL_AFTER: Duration = Duration::from_hours(1);
 
 // Action the server want the client to take
 
@@ -9,6 +31,11 @@ pub enum AgentAction {
     Nothing,
     Detach,
     SwitchTo(RemoteStorePath),
+    /// The current version is correct but a new one is expected soon.
+    /// The agent polls again after `suggest_poll_in_secs` instead of its configured sleep
+    WaitForNextVersion {
+        suggest_poll_in_secs: u64,
+    },
 }
 
 impl Default for AgentAction {
@@ -21,6 +48,16 @@ impl Default for AgentAction {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct VersionRequest {
     pub store_path: StorePath,
+    /// Set while the agent holds back a version the server switched it to
+    #[serde(default)]
+    pub soaking: Option<Soak>,
+}
+
+/// A version the agent holds back until `until` because of its `soak_minutes`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Soak {
+    pub store_path: StorePath,
+    pub until: jiff::Timestamp,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -35,6 +72,65 @@ pub struct RemoteStorePath {
     pub substitutor: String,
 }
 
+/// Sent by the agent after it tried to switch to `store_path`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ActivationReport {
+    pub store_path: StorePath,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// Sizes in bytes of a store path the agent downloaded. Reported before activating it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DownloadStats {
+    pub store_path: StorePath,
+    pub nar_size: u64,
+    pub closure_size: u64,
+}
+
+/// Whether the calling host may detach itself. Read in one go so that the global and the host
+/// specific flag can not change in between
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DetachPermission {
+    pub global: bool,
+    /// `None` if the host has no permission of its own
+    pub host_specific: Option<bool>,
+    /// `global || host_specific.unwrap_or(false)`
+    pub effective: bool,
+}
+
+impl DetachPermission {
+    #[must_use]
+    pub fn new(global: bool, host_specific: Option<bool>) -> Self {
+        Self {
+            global,
+            host_specific,
+            effective: global || host_specific.unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum SetDetachPermission {
+    Global(bool),
+    /// `None` removes the permission of the host so that only the global one applies
+    Host {
+        host: HostID,
+        allowed: Option<bool>,
+    },
+}
+
+request! (
+    detach_permission(),
+    get("/system/detach/effective") -> DetachPermission
+);
+
+request! (
+    set_detach_permission(permission: SetDetachPermission),
+    put("/system/detach/permission") -> StatusCode,
+    body: &permission
+);
+
 request! (
     detach_self(),
     put("/system/self/detach") -> StatusCode
@@ -50,3 +146,121 @@ request! (
     post("/system/check") -> AgentAction,
     body: &version
 );
+
+/// The answer of `/system/check` together with what the server sent in headers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SystemCheck {
+    pub action: AgentAction,
+    /// `None` if the server did not send `POLL_AFTER_HEADER`
+    pub poll_after: Option<Duration>,
+    /// From `SECRETS_UPDATED_HEADER`
+    pub secrets_updated: bool,
+}
+
+/// Reads `POLL_AFTER_HEADER` capped at `MAX_POLL_AFTER`. Values that are not whole seconds are
+/// ignored
+#[must_use]
+pub fn poll_after(headers: &http::HeaderMap) -> Option<Duration> {
+    let secs = headers
+        .get(POLL_AFTER_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs).min(MAX_POLL_AFTER))
+}
+
+/// Whether `SECRETS_UPDATED_HEADER` is `true`
+#[must_use]
+pub fn secrets_updated(headers: &http::HeaderMap) -> bool {
+    headers
+        .get(SECRETS_UPDATED_HEADER)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"true"))
+}
+
+/// Like `check_system` but also returns what the server sent in headers
+pub async fn check_system_polled<K: SigningKey + Sync>(
+    url: &(impl crate::Endpoint + ?Sized),
+    key: &K,
+    version: VersionRequest,
+) -> Result<SystemCheck, ResponseError> {
+    let response = url
+        .client()
+        .post(url.url().join("/system/check")?)
+        .json(&version)
+        .sign(&sig_param(key)?, key)
+        .await?
+        .send()
+        .await?;
+    let poll_after = poll_after(response.headers());
+    let secrets_updated = secrets_updated(response.headers());
+    Ok(SystemCheck {
+        action: response.error_for_json().await?,
+        poll_after,
+        secrets_updated,
+    })
+}
+
+request! (
+    report_activation(report: ActivationReport),
+    post("/system/report") -> StatusCode,
+    body: &report
+);
+
+request! (
+    report_download_stats(stats: DownloadStats),
+    post("/system/check/download-stats") -> StatusCode,
+    body: &stats
+);
+
+// Replaces the nixos-facter output the host sent when it enrolled
+request! (
+    report_facter(nixos_facter: String),
+    post("/system/facter") -> StatusCode,
+    body: &nixos_facter
+);
+
+#[cfg(test)]
+mod test_system {
+    use std::time::Duration;
+
+    use http::{HeaderMap, HeaderValue};
+
+    fn headers(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(super::POLL_AFTER_HEADER, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn poll_after() {
+        assert_eq!(super::poll_after(&HeaderMap::new()), None);
+        assert_eq!(
+            super::poll_after(&headers("90")),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            super::poll_after(&headers("86400")),
+            Some(super::MAX_POLL_AFTER)
+        );
+        assert_eq!(super::poll_after(&headers("-5")), None);
+        assert_eq!(super::poll_after(&headers("soon")), None);
+    }
+
+    #[test]
+    fn secrets_updated() {
+        let mut headers = HeaderMap::new();
+        assert!(!super::secrets_updated(&headers));
+        headers.insert(
+            super::SECRETS_UPDATED_HEADER,
+            HeaderValue::from_static("true"),
+        );
+        assert!(super::secrets_updated(&headers));
+        headers.insert(
+            super::SECRETS_UPDATED_HEADER,
+            HeaderValue::from_static("false"),
+        );
+        assert!(!super::secrets_updated(&headers));
+    }
+}
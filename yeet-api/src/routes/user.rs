@@ -47,7 +47,12 @@ pub struct User {
 }
 impl Display for User {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} ({}): ", self.username, crate::hash_hex(self.key))?;
+        write!(
+            f,
+            "{} ({}): ",
+            self.username,
+            crate::key_fingerprint(&self.key)
+        )?;
         write!(f, "{}", self.level)?;
 
         if self.all_tag {
@@ -9,6 +9,8 @@ use crate::{ReqwestSig as _, ResponseError, request, sig_param};
 pub struct VerificationAttempt {
     pub key: VerifyingKey,
     pub nixos_facter: Option<String>,
+    /// age recipient the host fetches its secrets with. Hosts without one can not fetch secrets
+    pub recipient: Option<String>,
 }
 
 request! (
@@ -24,11 +26,12 @@ request! (
 );
 
 pub async fn is_host_verified<K: SigningKey + Sync>(
-    url: &url::Url,
+    url: &(impl crate::Endpoint + ?Sized),
     key: &K,
 ) -> Result<StatusCode, ResponseError> {
-    Ok(reqwest::Client::new()
-        .get(url.join("/verification/check")?)
+    Ok(url
+        .client()
+        .get(url.url().join("/verification/check")?)
         .sign(&sig_param(key)?, key)
         .await?
         .send()
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr as _};
 
 use serde::{Deserialize, Serialize};
 
@@ -17,13 +17,93 @@ pub struct Secret {
     /// Permissions mode of the decrypted secret in a format understood by chmod.
     pub mode: String,
 
-    /// User of the decrypted secret.
+    /// User of the decrypted secret. A username or a numeric uid
     pub owner: String,
 
-    /// Group of the decrypted secret.
+    /// Group of the decrypted secret. A group name or a numeric gid
     pub group: String,
 
     /// symlinking secrets to their destination
     /// Else they get copied to their destination
     pub symlink: bool,
+
+    /// Expected size of the decrypted secret in bytes
+    #[serde(default)]
+    pub size: Option<u64>,
+}
+
+error_set::error_set! {
+    RecipientError := {
+        #[display("Unsupported recipient: {recipient}")]
+        Unsupported { recipient: String },
+        #[cfg(feature = "age-plugin")]
+        #[display("Could not use the age plugin: {0}")]
+        Plugin(age::EncryptError),
+    }
+}
+
+/// Parse the recipient of the server store key (see `server_age_key`)
+/// Besides x25519 recipients this supports age plugin recipients with the `age-plugin` feature.
+/// The plugin binary (`age-plugin-<name>`) has to be in `$PATH`
+pub fn parse_recipient(recipient: &str) -> Result<Box<dyn age::Recipient + Send>, RecipientError> {
+    if let Ok(recipient) = age::x25519::Recipient::from_str(recipient) {
+        return Ok(Box::new(recipient));
+    }
+
+    #[cfg(feature = "age-plugin")]
+    if let Ok(recipient) = age::plugin::Recipient::from_str(recipient) {
+        return Ok(Box::new(age::plugin::RecipientPluginV1::new(
+            recipient.plugin(),
+            std::slice::from_ref(&recipient),
+            &[],
+            age::NoCallbacks,
+        )?));
+    }
+
+    Err(RecipientError::Unsupported {
+        recipient: recipient.to_owned(),
+    })
+}
+
+/// Encrypt `plaintext` for a recipient returned by `parse_recipient`
+pub fn encrypt_for(
+    recipient: &dyn age::Recipient,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, age::EncryptError> {
+    encrypt_for_all(std::iter::once(recipient), plaintext)
+}
+
+/// Encrypt `plaintext` so that any of the `recipients` can decrypt it
+pub fn encrypt_for_all<'recipient>(
+    recipients: impl Iterator<Item = &'recipient dyn age::Recipient>,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, age::EncryptError> {
+    use std::io::Write as _;
+
+    let encryptor = age::Encryptor::with_recipients(recipients)?;
+    let mut ciphertext = Vec::with_capacity(plaintext.len());
+    let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+    Ok(ciphertext)
+}
+
+#[cfg(test)]
+mod test_secret {
+    #[test]
+    fn parse_x25519_recipient() {
+        let identity = age::x25519::Identity::generate();
+        let recipient = super::parse_recipient(&identity.to_public().to_string()).unwrap();
+
+        let encrypted = super::encrypt_for(&*recipient, b"secret").unwrap();
+        assert_eq!(age::decrypt(&identity, &encrypted).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn parse_unsupported_recipient() {
+        assert!(matches!(
+            super::parse_recipient("not-a-recipient"),
+            Err(super::RecipientError::Unsupported { .. })
+        ));
+    }
 }
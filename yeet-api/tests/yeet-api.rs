@@ -1,25 +1,53 @@
 use std::{collections::HashMap, str::FromStr as _};
 
 use ed25519_dalek::SigningKey;
-use httpsig_hyper::prelude::{AlgorithmName, SecretKey};
-use yeet_api::{self as api};
+use httpsig_hyper::prelude::{AlgorithmName, SecretKey, SigningKey as _};
+use yeet_api::{self as api, ErrorForJson as _, ReqwestSig as _};
+
+/// A `GetSecretRequest` that additionally tries to name the host it wants the secret for
+#[derive(serde::Serialize)]
+struct SpoofedSecretRequest {
+    secret: String,
+    recipient: String,
+    host: api::HostID,
+    hostname: String,
+}
 
 #[sqlx::test]
 fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
+    let age_key = age::x25519::Identity::generate();
     let _handle = yeetd::launch(
         4337,
-        std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST),
+        [std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
         pool,
-        age::x25519::Identity::generate(),
+        age_key.clone(),
         None,
         None,
         None,
         None,
+        yeetd::BodyLimits::default(),
+        yeetd::Lockout::default(),
+        false,
+        yeetd::hostname::HostnameRules::default(),
+        None,
+        yeetd::webhook::Webhooks::default(),
+        false,
+        false,
     )
     .await;
 
     let url = url::Url::from_str("http://localhost:4337").unwrap();
 
+    // anyone can ask the server what it supports
+    let info = api::server_info(&url).await.unwrap();
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(
+        info.server_public_key_fingerprint,
+        api::recipient_fingerprint(&age_key.to_public().to_string())
+    );
+    assert!(info.supports(api::feature::AUDIT_LOG));
+    assert!(!info.supports("time_travel"));
+
     // first we need to add our admin credentials.
     // The api will allow us to add it when no credentials are specified yet
 
@@ -42,6 +70,8 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
     // The first thing a new host does is to create a verification attempt
     let new_host = SigningKey::from_bytes(&[3; 32]);
     let client_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[3; 32]).unwrap();
+    // secrets are only ever encrypted for the recipient the host enrolled with
+    let client_identity = age::x25519::Identity::generate();
 
     let code = api::add_verification_attempt(
         &url,
@@ -49,6 +79,7 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
         api::VerificationAttempt {
             key: new_host.verifying_key(),
             nixos_facter: Some("Just some facts about a host".into()),
+            recipient: Some(client_identity.to_public().to_string()),
         },
     )
     .await
@@ -57,12 +88,112 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
     assert!((100_000..=999_999).contains(&code));
 
     // The next thing is for an admin to approve this request
+    let before_accept = jiff::Timestamp::now();
     let facter = api::accept_attempt(&url, &key, code as u32, "mysuperhostname")
         .await
         .unwrap();
 
     assert_eq!(facter, Some("Just some facts about a host".into()));
 
+    // The host can now learn the name it was enrolled as
+    let info = api::host_by_key(
+        &url,
+        &client_key,
+        api::HostByKeyRequest {
+            key: new_host.verifying_key(),
+        },
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(info.hostname, "mysuperhostname");
+
+    // The admin key is not a host
+    let info = api::host_by_key(
+        &url,
+        &key,
+        api::HostByKeyRequest {
+            key: admin_key.verifying_key(),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(info, None);
+
+    // Looking up any other key is forbidden
+    let err = api::host_by_key(
+        &url,
+        &key,
+        api::HostByKeyRequest {
+            key: new_host.verifying_key(),
+        },
+    )
+    .await;
+    assert!(matches!(
+        err,
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::FORBIDDEN,
+            ..
+        })
+    ));
+
+    // The admin can do everything on every resource
+    let explanation = api::auth::explain(&url, &key, api::auth::ExplainRequest::default())
+        .await
+        .unwrap();
+    assert_eq!(explanation.key, admin_key.verifying_key());
+    assert!(matches!(
+        explanation.owner,
+        api::auth::KeyOwner::User(ref user)
+            if user.username == "mysuperadmin" && user.level == api::AuthLevel::Admin
+    ));
+    assert_eq!(
+        explanation.scope("Secret::Create"),
+        Some(api::auth::Scope::All)
+    );
+    assert_eq!(explanation.scope("System::Check"), None);
+
+    // The host may only act on itself
+    let explanation = api::auth::explain(&url, &client_key, api::auth::ExplainRequest::default())
+        .await
+        .unwrap();
+    assert!(matches!(
+        explanation.owner,
+        api::auth::KeyOwner::Host(ref host) if host.hostname == "mysuperhostname"
+    ));
+    assert_eq!(
+        explanation.scope("System::Check"),
+        Some(api::auth::Scope::Own)
+    );
+    assert_eq!(explanation.scope("Secret::View"), None);
+
+    // The admin can explain the host key but not the other way around
+    let explanation = api::auth::explain(
+        &url,
+        &key,
+        api::auth::ExplainRequest {
+            key: Some(new_host.verifying_key()),
+        },
+    )
+    .await
+    .unwrap();
+    assert!(matches!(explanation.owner, api::auth::KeyOwner::Host(_)));
+    let err = api::auth::explain(
+        &url,
+        &client_key,
+        api::auth::ExplainRequest {
+            key: Some(admin_key.verifying_key()),
+        },
+    )
+    .await;
+    assert!(matches!(
+        err,
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::FORBIDDEN,
+            ..
+        })
+    ));
+
     // Now that we have a host we may want to list it
     let hosts = api::list_hosts(&url, &key).await.unwrap();
     assert_eq!(
@@ -72,6 +203,10 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
     assert_eq!(hosts.first().unwrap().state, api::ProvisionState::NotSet);
     assert_eq!(hosts.first().unwrap().version, None);
     assert_eq!(hosts.first().unwrap().latest_update, None);
+    // enrolled with the accept and never updated yet
+    assert!(hosts.first().unwrap().enrolled_at >= before_accept);
+    assert!(hosts.first().unwrap().enrolled_at <= jiff::Timestamp::now());
+    assert_eq!(hosts.first().unwrap().last_updated_at, None);
 
     // lets push an update to the host
     api::update_hosts(
@@ -98,6 +233,22 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
         Some("mysuperversion".into())
     );
 
+    // A fresh install without an active system is told to switch as well
+    let action = api::check_system(
+        &url,
+        &client_key,
+        api::VersionRequest {
+            store_path: api::NOT_ACTIVATED.into(),
+            soaking: None,
+        },
+    )
+    .await
+    .unwrap();
+    assert!(matches!(action, api::AgentAction::SwitchTo(_)));
+    // but it is not recorded as the version of the host
+    let hosts = api::list_hosts(&url, &key).await.unwrap();
+    assert_eq!(hosts.first().unwrap().version, None);
+
     // We simulate that the hosts now has pinged the system but provided an old version
     // Yeet now tasks the agent to update
     let action = api::check_system(
@@ -105,6 +256,7 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
         &client_key,
         api::VersionRequest {
             store_path: "myoldversion".into(),
+            soaking: None,
         },
     )
     .await
@@ -122,6 +274,25 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
     // If we do not udpate yet but look at the host we see that he has now an latest version
     let hosts = api::list_hosts(&url, &key).await.unwrap();
     assert_eq!(hosts.first().unwrap().version, Some("myoldversion".into()));
+    assert_eq!(hosts.first().unwrap().soaking, None);
+
+    // An agent with `soak_minutes` holds the update back and tells the server until when
+    let soak = api::Soak {
+        store_path: "mysuperversion".into(),
+        until: "2026-10-17T12:10:00Z".parse().unwrap(),
+    };
+    api::check_system(
+        &url,
+        &client_key,
+        api::VersionRequest {
+            store_path: "myoldversion".into(),
+            soaking: Some(soak.clone()),
+        },
+    )
+    .await
+    .unwrap();
+    let hosts = api::list_hosts(&url, &key).await.unwrap();
+    assert_eq!(hosts.first().unwrap().soaking, Some(soak));
 
     // The agent now signals the server that he has done the update
     let action = api::check_system(
@@ -129,6 +300,7 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
         &client_key,
         api::VersionRequest {
             store_path: "mysuperversion".into(),
+            soaking: None,
         },
     )
     .await
@@ -142,6 +314,8 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
         hosts.first().unwrap().version,
         Some("mysuperversion".into())
     );
+    // and the soak is over
+    assert_eq!(hosts.first().unwrap().soaking, None);
 
     // Kinda bored of `mysuperhostname` lets rename it
     api::rename_host(&url, &key, hosts.first().unwrap().id, "mynewname")
@@ -151,6 +325,40 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
     let hosts = api::list_hosts(&url, &key).await.unwrap();
     assert_eq!(hosts.first().unwrap().hostname, "mynewname".to_owned());
 
+    // An admin can forbid detaching for every host
+    api::set_detach_permission(&url, &key, api::SetDetachPermission::Global(false))
+        .await
+        .unwrap();
+    let permission = api::detach_permission(&url, &client_key).await.unwrap();
+    assert_eq!(permission, api::DetachPermission::new(false, None));
+    let err = api::detach_self(&url, &client_key).await;
+    assert!(matches!(
+        err,
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::FORBIDDEN,
+            ..
+        })
+    ));
+
+    // and grant it to a single host
+    api::set_detach_permission(
+        &url,
+        &key,
+        api::SetDetachPermission::Host {
+            host: hosts.first().unwrap().id,
+            allowed: Some(true),
+        },
+    )
+    .await
+    .unwrap();
+    let permission = api::detach_permission(&url, &client_key).await.unwrap();
+    assert!(permission.effective);
+
+    // hosts can not change their own permission
+    api::set_detach_permission(&url, &client_key, api::SetDetachPermission::Global(true))
+        .await
+        .unwrap_err();
+
     // The agent now decide he no longer wants to listen to the server and detaches
 
     api::detach_self(&url, &client_key).await.unwrap();
@@ -165,6 +373,7 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
         &client_key,
         api::VersionRequest {
             store_path: "mydetachedversion".into(),
+            soaking: None,
         },
     )
     .await
@@ -210,6 +419,7 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
         &client_key,
         api::VersionRequest {
             store_path: "mydetachedversion".into(),
+            soaking: None,
         },
     )
     .await
@@ -229,6 +439,7 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
         &client_key,
         api::VersionRequest {
             store_path: "mynewversion".into(),
+            soaking: None,
         },
     )
     .await
@@ -239,9 +450,135 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
     let hosts = api::list_hosts(&url, &key).await.unwrap();
     assert_eq!(hosts.first().unwrap().version, Some("mynewversion".into()));
 
+    // The agent reports the size of what it downloaded
+    assert_eq!(hosts.first().unwrap().last_download_size, None);
+    api::report_download_stats(
+        &url,
+        &client_key,
+        api::DownloadStats {
+            store_path: "mynewversion".into(),
+            nar_size: 1024,
+            closure_size: 2_469_606_195,
+        },
+    )
+    .await
+    .unwrap();
+    let hosts = api::list_hosts(&url, &key).await.unwrap();
+    assert_eq!(
+        hosts.first().unwrap().last_download_size,
+        Some(2_469_606_195)
+    );
+
+    // The enrollment facts are kept until the host reports newer ones
+    let host = hosts.first().unwrap().id;
+    let enrolled = api::host_facter(&url, &key, host).await.unwrap().unwrap();
+    assert_eq!(enrolled.nixos_facter, "Just some facts about a host");
+    assert_eq!(
+        hosts.first().unwrap().last_facter,
+        Some(enrolled.report_time)
+    );
+    api::report_facter(&url, &client_key, "A new disk appeared".into())
+        .await
+        .unwrap();
+    let updated = api::host_facter(&url, &key, host).await.unwrap().unwrap();
+    assert_eq!(updated.nixos_facter, "A new disk appeared");
+    assert!(updated.report_time >= enrolled.report_time);
+    // hosts can not read the facts
+    api::host_facter(&url, &client_key, host).await.unwrap_err();
+
+    // A failed activation is reported but the host keeps its version
+    api::report_activation(
+        &url,
+        &client_key,
+        api::ActivationReport {
+            store_path: "mybrokenversion".into(),
+            success: false,
+            error: Some("switch-to-configuration failed".into()),
+        },
+    )
+    .await
+    .unwrap();
+    let hosts = api::list_hosts(&url, &key).await.unwrap();
+    assert_eq!(hosts.first().unwrap().version, Some("mynewversion".into()));
+    assert_eq!(hosts.first().unwrap().last_updated_at, None);
+
+    // A successful one moves the host to the reported version
+    let before_update = jiff::Timestamp::now();
+    api::report_activation(
+        &url,
+        &client_key,
+        api::ActivationReport {
+            store_path: "myreportedversion".into(),
+            success: true,
+            error: None,
+        },
+    )
+    .await
+    .unwrap();
+    let hosts = api::list_hosts(&url, &key).await.unwrap();
+    assert_eq!(
+        hosts.first().unwrap().version,
+        Some("myreportedversion".into())
+    );
+    let updated_at = hosts.first().unwrap().last_updated_at.unwrap();
+    assert!(updated_at >= before_update);
+    assert!(updated_at >= hosts.first().unwrap().enrolled_at);
+
+    // Only hosts report activations
+    api::report_activation(
+        &url,
+        &key,
+        api::ActivationReport {
+            store_path: "myreportedversion".into(),
+            success: true,
+            error: None,
+        },
+    )
+    .await
+    .unwrap_err();
+
+    // Back to the version the server wants
+    api::report_activation(
+        &url,
+        &client_key,
+        api::ActivationReport {
+            store_path: "mynewversion".into(),
+            success: true,
+            error: None,
+        },
+    )
+    .await
+    .unwrap();
+
     // Ok now maybe we want to create a secret for the host
     // first we have to get the encryption key of the server
     let server_key = api::server_age_key(&url, &key).await.unwrap();
+
+    // the recipient can be cached and revalidated by its version
+    let fresh = api::server_recipient(&url, &key, None).await.unwrap();
+    assert_eq!(fresh.recipient, server_key);
+    assert_eq!(fresh.version, api::recipient_version(&server_key));
+    let cached = api::ServerRecipient {
+        recipient: "cached".to_owned(),
+        version: fresh.version.clone(),
+    };
+    assert_eq!(
+        api::server_recipient(&url, &key, Some(&cached))
+            .await
+            .unwrap(),
+        cached
+    );
+    let stale = api::ServerRecipient {
+        recipient: "stale".to_owned(),
+        version: api::recipient_version("stale"),
+    };
+    assert_eq!(
+        api::server_recipient(&url, &key, Some(&stale))
+            .await
+            .unwrap(),
+        fresh
+    );
+
     let server_key = age::x25519::Recipient::from_str(&server_key).unwrap();
     let _ups = api::create_secret(
         &url,
@@ -255,10 +592,16 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
     // the client tries to get the secret but fails because he is not authorized
     // but first the client needs to generate a recipient key
 
-    let secret = api::get_secret(&url, &client_key, "mysecret".into())
+    let secret = api::get_secret(&url, &client_key, &client_identity, "mysecret".into())
+        .await
+        .unwrap();
+    assert_eq!(secret, api::SecretLookup::NoAccess);
+
+    // which is different from asking for a secret that does not exist
+    let secret = api::get_secret(&url, &client_key, &client_identity, "nosecret".into())
         .await
         .unwrap();
-    assert!(secret.is_none());
+    assert_eq!(secret, api::SecretLookup::NotFound);
 
     // so lets give the client permission. but oh wait i forgor the note the secretid and hostid
     // lets list all secrets
@@ -271,103 +614,513 @@ fn api_e2e_with_credentials(pool: sqlx::SqlitePool) {
     let host = hosts.first().unwrap();
     assert_eq!(host.hostname, "mynewname".to_owned());
 
+    // the server explains why the host can not get the secret
+    let access = api::check_secret_access(&url, &key, "mynewname", "mysecret")
+        .await
+        .unwrap();
+    assert_eq!(access, api::SecretAccess::denied("host not in ACL"));
+    let access = api::check_secret_access(&url, &key, "mynewname", "nosecret")
+        .await
+        .unwrap();
+    assert_eq!(access, api::SecretAccess::denied("secret does not exist"));
+    let access = api::check_secret_access(&url, &key, "nohost", "mysecret")
+        .await
+        .unwrap();
+    assert_eq!(access, api::SecretAccess::denied("host not enrolled"));
+
     // now allow the host
     api::allow_host(&url, &key, secret.id, host.id)
         .await
         .unwrap();
-
-    // the client can now get the secret
-    let secret = api::get_secret(&url, &client_key, "mysecret".into())
+    let access = api::check_secret_access(&url, &key, "mynewname", "mysecret")
         .await
         .unwrap();
-    assert_eq!(secret, Some(b"secretstuff".to_vec()));
-}
-
-#[sqlx::test]
-fn api_e2e_with_non_superuser(pool: sqlx::SqlitePool) {
-    let _handle = yeetd::launch(
-        4338,
-        std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST),
-        pool,
-        age::x25519::Identity::generate(),
-        None,
-        None,
-        None,
-        None,
+    assert_eq!(access, api::SecretAccess::allowed());
+    // reading the secret as the host is disabled by default
+    api::secret_as_host(
+        &url,
+        &key,
+        api::SecretAccessQuery {
+            host: "mynewname".to_owned(),
+            secret: "mysecret".to_owned(),
+        },
     )
-    .await;
-
-    let url = url::Url::from_str("http://localhost:4338").unwrap();
-
-    // first we need to add our admin credentials.
-    // The api will allow us to add it when no credentials are specified yet
-
-    let admin_signing_key = SigningKey::from_bytes(&[4; 32]);
-    let admin_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[4; 32]).unwrap();
+    .await
+    .unwrap_err();
+    // the host learns about the new secret with its next check, only once
+    for secrets_updated in [true, false] {
+        let check = api::check_system_polled(
+            &url,
+            &client_key,
+            api::VersionRequest {
+                store_path: "mynewversion".into(),
+                soaking: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(check.action, api::AgentAction::Nothing);
+        assert_eq!(check.secrets_updated, secrets_updated);
+    }
 
-    api::create_user(
+    // the same in one request. A failed operation does not undo the others
+    let result = api::batch_acl(
         &url,
-        &admin_key,
-        api::CreateUser {
-            key: admin_signing_key.verifying_key(),
-            level: api::AuthLevel::Admin,
-            username: "mysuperadmin".into(),
-            all_tag: true,
+        &key,
+        api::BatchAclSecretRequest {
+            operations: vec![
+                api::AclSecretRequest {
+                    secret: secret.id,
+                    host: host.id,
+                    allow: false,
+                },
+                api::AclSecretRequest {
+                    secret: api::SecretID::new(999),
+                    host: host.id,
+                    allow: true,
+                },
+                api::AclSecretRequest {
+                    secret: secret.id,
+                    host: host.id,
+                    allow: true,
+                },
+            ],
         },
     )
     .await
     .unwrap();
+    assert_eq!(result.succeeded, [0, 2]);
+    assert_eq!(
+        result
+            .failed
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>(),
+        [1]
+    );
+    let access = api::check_secret_access(&url, &key, "mynewname", "mysecret")
+        .await
+        .unwrap();
+    assert_eq!(access, api::SecretAccess::allowed());
 
-    // To test the scoping of tags we want to create a new user that does not see all tags
-    let signing_key = SigningKey::from_bytes(&[5; 32]);
-    let key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[5; 32]).unwrap();
+    // the client can now get the secret
+    let secret = api::get_secret(&url, &client_key, &client_identity, "mysecret".into())
+        .await
+        .unwrap();
+    assert_eq!(secret.found().unwrap(), b"secretstuff");
 
-    let normal_admin = api::create_user(
+    // a sealed secret is encrypted for the host directly and never readable by the server
+    api::create_sealed_secret(
         &url,
-        &admin_key, // the admin user thas to create the key
-        api::CreateUser {
-            key: signing_key.verifying_key(),
-            level: api::AuthLevel::Admin, // he es still an admin e.g. can modify stuff - just not an suepr admin
-            username: "mynormaladmin".into(),
-            all_tag: false,
-        },
+        &key,
+        "mysealed",
+        host.id,
+        &age::encrypt(&client_identity.to_public(), b"onlyforyou").unwrap(),
     )
     .await
     .unwrap();
+    let secret = api::get_secret(&url, &client_key, &client_identity, "mysealed".into())
+        .await
+        .unwrap();
+    assert_eq!(secret.found().unwrap(), b"onlyforyou");
 
-    // The first thing a new host does is to create a verification attempt
-    let new_host = SigningKey::from_bytes(&[3; 32]);
-
-    let code = api::add_verification_attempt(
+    // the content type is handed to the host together with the content
+    let typed = api::create_typed_secret(
         &url,
         &key,
-        api::VerificationAttempt {
-            key: new_host.verifying_key(),
-            nixos_facter: Some("Just some facts about a host".into()),
-        },
+        "mytyped",
+        "pem",
+        &age::encrypt(&server_key, b"certificate").unwrap(),
     )
     .await
     .unwrap();
-
-    assert!((100_000..=999_999).contains(&code));
-
-    // A normal admin is not allowed to accept verify requests
-    let _err = api::accept_attempt(&url, &key, code as u32, "mysuperhostname")
+    assert_eq!(typed.content_type.as_deref(), Some("pem"));
+    api::allow_host(&url, &key, typed.id, host.id)
+        .await
+        .unwrap();
+    let secret = api::get_secret(&url, &client_key, &client_identity, "mytyped".into())
+        .await
+        .unwrap();
+    assert_eq!(
+        secret,
+        api::SecretLookup::Found {
+            content: b"certificate".to_vec(),
+            content_type: Some("pem".to_owned())
+        }
+    );
+    api::create_typed_secret(&url, &key, "badtype", "PEM", b"")
         .await
         .unwrap_err();
 
-    // but our admin can
-    let facter = api::accept_attempt(&url, &admin_key, code as u32, "mysuperhostname")
+    // an alias shares the content but has its own acl
+    let alias = api::create_alias(&url, &key, secrets.first().unwrap().id, "myalias")
         .await
         .unwrap();
+    let secret = api::get_secret(&url, &client_key, &client_identity, "myalias".into())
+        .await
+        .unwrap();
+    assert_eq!(secret, api::SecretLookup::NoAccess);
+    api::allow_host(&url, &key, alias.id, host.id)
+        .await
+        .unwrap();
+    let secret = api::get_secret(&url, &client_key, &client_identity, "myalias".into())
+        .await
+        .unwrap();
+    assert_eq!(secret.found().unwrap(), b"secretstuff");
+    api::delete_alias(&url, &key, alias.id).await.unwrap();
+    let secret = api::get_secret(&url, &client_key, &client_identity, "myalias".into())
+        .await
+        .unwrap();
+    assert_eq!(secret, api::SecretLookup::NotFound);
 
-    assert_eq!(facter, Some("Just some facts about a host".into()));
-
-    // our admin can see the host
-    let hosts = api::list_hosts(&url, &admin_key).await.unwrap();
-    assert!(hosts.len() == 1);
+    // but only for the recipient it enrolled with
+    let err = api::get_secret(
+        &url,
+        &client_key,
+        &age::x25519::Identity::generate(),
+        "mysecret".into(),
+    )
+    .await;
+    assert!(matches!(
+        err,
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::FORBIDDEN,
+            ..
+        })
+    ));
 
-    // our normal admin can't
+    // another host must not be able to get the secret by claiming to be `mynewname`
+    let other_host = SigningKey::from_bytes(&[5; 32]);
+    let other_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[5; 32]).unwrap();
+    let other_identity = age::x25519::Identity::generate();
+    let code = api::add_verification_attempt(
+        &url,
+        &other_key,
+        api::VerificationAttempt {
+            key: other_host.verifying_key(),
+            nixos_facter: None,
+            recipient: Some(other_identity.to_public().to_string()),
+        },
+    )
+    .await
+    .unwrap();
+    api::accept_attempt(&url, &key, code as u32, "otherhost")
+        .await
+        .unwrap();
+
+    let spoofed = SpoofedSecretRequest {
+        secret: "mysecret".into(),
+        recipient: other_identity.to_public().to_string(),
+        host: host.id,
+        hostname: "mynewname".into(),
+    };
+    let secret = reqwest::Client::new()
+        .post(url.join("/secret").unwrap())
+        .json(&spoofed)
+        .sign(&api::sig_param(&other_key).unwrap(), &other_key)
+        .await
+        .unwrap()
+        .send()
+        .await
+        .unwrap()
+        .error_for_json::<api::SecretLookup>()
+        .await
+        .unwrap();
+    assert_eq!(secret, api::SecretLookup::NoAccess);
+
+    // and it can not take over the name either
+    let hosts = api::list_hosts(&url, &key).await.unwrap();
+    let other = hosts
+        .iter()
+        .find(|host| host.hostname == "otherhost")
+        .unwrap();
+    let err = api::rename_host(&url, &key, other.id, "mynewname").await;
+    assert!(matches!(
+        err,
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::CONFLICT,
+            ..
+        })
+    ));
+    // nor a name that is not a DNS name
+    let err = api::rename_host(&url, &key, other.id, "my new name").await;
+    assert!(matches!(
+        err,
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::BAD_REQUEST,
+            ..
+        })
+    ));
+    let mut hostnames: Vec<_> = api::list_hosts(&url, &key)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|host| host.hostname)
+        .collect();
+    hostnames.sort();
+    assert_eq!(hostnames, vec!["mynewname", "otherhost"]);
+
+    // renaming a host to its own name is fine
+    api::rename_host(&url, &key, other.id, "otherhost")
+        .await
+        .unwrap();
+
+    // bootstrapping a fleet imports hosts without the verification code flow
+    let imported_host = SigningKey::from_bytes(&[7; 32]);
+    let imported_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[7; 32]).unwrap();
+    let openssh = |key: &SigningKey| {
+        ssh_key::PublicKey::from(ssh_key::public::Ed25519PublicKey(
+            key.verifying_key().to_bytes(),
+        ))
+        .to_openssh()
+        .unwrap()
+    };
+    let import = |hostname: &str, key: String, recipient: Option<String>| api::HostImport {
+        hostname: hostname.to_owned(),
+        key,
+        recipient,
+    };
+    let results = api::import_hosts(
+        &url,
+        &key,
+        vec![
+            import(
+                "imported",
+                openssh(&imported_host),
+                Some(age::x25519::Identity::generate().to_public().to_string()),
+            ),
+            import("garbage", "not a key".to_owned(), None),
+            import(
+                "bad-recipient",
+                openssh(&SigningKey::from_bytes(&[8; 32])),
+                Some("not a recipient".to_owned()),
+            ),
+            import(
+                "mynewname",
+                openssh(&SigningKey::from_bytes(&[9; 32])),
+                None,
+            ),
+            import("duplicate", openssh(&new_host), None),
+        ],
+    )
+    .await
+    .unwrap();
+    let hostnames: Vec<_> = results
+        .iter()
+        .map(|result| result.hostname.as_str())
+        .collect();
+    assert_eq!(
+        hostnames,
+        vec![
+            "imported",
+            "garbage",
+            "bad-recipient",
+            "mynewname",
+            "duplicate"
+        ]
+    );
+    let imported = results.first().unwrap().result.clone().unwrap();
+    assert!(results.iter().skip(1).all(|result| result.result.is_err()));
+
+    // the imported host can check in right away
+    api::check_system(
+        &url,
+        &imported_key,
+        api::VersionRequest {
+            store_path: "myversion".into(),
+            soaking: None,
+        },
+    )
+    .await
+    .unwrap();
+    let hosts = api::list_hosts(&url, &key).await.unwrap();
+    assert_eq!(hosts.len(), 3);
+    assert!(
+        hosts
+            .iter()
+            .any(|host| host.id == imported && host.hostname == "imported")
+    );
+
+    // hosts can be grouped by name
+    let member = api::HostGroupMember {
+        group: "prod".to_owned(),
+        host: "imported".to_owned(),
+    };
+    api::add_to_host_group(&url, &key, member.clone())
+        .await
+        .unwrap_err();
+    api::create_host_group(
+        &url,
+        &key,
+        api::HostGroup {
+            name: "prod".to_owned(),
+        },
+    )
+    .await
+    .unwrap();
+    api::add_to_host_group(&url, &key, member.clone())
+        .await
+        .unwrap();
+    api::add_to_host_group(
+        &url,
+        &key,
+        api::HostGroupMember {
+            group: "prod".to_owned(),
+            host: "unknown".to_owned(),
+        },
+    )
+    .await
+    .unwrap_err();
+    let groups = api::list_host_groups(&url, &key).await.unwrap();
+    assert_eq!(
+        groups,
+        HashMap::from([("prod".to_owned(), vec!["imported".to_owned()])])
+    );
+    api::remove_from_host_group(&url, &key, member)
+        .await
+        .unwrap();
+    let groups = api::list_host_groups(&url, &key).await.unwrap();
+    assert_eq!(groups, HashMap::from([("prod".to_owned(), vec![])]));
+
+    // every admin mutation ended up in the audit log
+    let audit = api::audit_mutations(&url, &key).await.unwrap();
+    assert_eq!(audit.broken_at, None);
+    let actions: Vec<_> = audit
+        .entries
+        .iter()
+        .map(|entry| entry.action.as_str())
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            "User::Create",
+            "Verification::Accept",
+            "Host::Update",
+            "Host::Rename",
+            "Host::DetachPermission",
+            "Host::DetachPermission",
+            "Host::Update",
+            "Secret::Create",
+            "Secret::Allow",
+            "Secret::Block",
+            "Secret::Allow",
+            "Secret::Create",
+            "Secret::Create",
+            "Secret::Allow",
+            "Secret::Alias",
+            "Secret::Allow",
+            "Secret::RemoveAlias",
+            "Verification::Accept",
+            "Host::Rename",
+            "Host::Import",
+            "HostGroup::Create",
+            "HostGroup::Edit",
+            "HostGroup::Edit"
+        ]
+    );
+
+    // hosts can not read it
+    api::audit_mutations(&url, &client_key).await.unwrap_err();
+
+    // nothing was removed out-of-band
+    assert!(api::prune(&url, &key).await.unwrap().is_empty());
+    api::prune(&url, &client_key).await.unwrap_err();
+}
+
+#[sqlx::test]
+fn api_e2e_with_non_superuser(pool: sqlx::SqlitePool) {
+    let _handle = yeetd::launch(
+        4338,
+        [std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
+        pool,
+        age::x25519::Identity::generate(),
+        None,
+        None,
+        None,
+        None,
+        yeetd::BodyLimits::default(),
+        yeetd::Lockout::default(),
+        false,
+        yeetd::hostname::HostnameRules::default(),
+        None,
+        yeetd::webhook::Webhooks::default(),
+        false,
+        false,
+    )
+    .await;
+
+    let url = url::Url::from_str("http://localhost:4338").unwrap();
+
+    // first we need to add our admin credentials.
+    // The api will allow us to add it when no credentials are specified yet
+
+    let admin_signing_key = SigningKey::from_bytes(&[4; 32]);
+    let admin_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[4; 32]).unwrap();
+
+    api::create_user(
+        &url,
+        &admin_key,
+        api::CreateUser {
+            key: admin_signing_key.verifying_key(),
+            level: api::AuthLevel::Admin,
+            username: "mysuperadmin".into(),
+            all_tag: true,
+        },
+    )
+    .await
+    .unwrap();
+
+    // To test the scoping of tags we want to create a new user that does not see all tags
+    let signing_key = SigningKey::from_bytes(&[5; 32]);
+    let key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[5; 32]).unwrap();
+
+    let normal_admin = api::create_user(
+        &url,
+        &admin_key, // the admin user thas to create the key
+        api::CreateUser {
+            key: signing_key.verifying_key(),
+            level: api::AuthLevel::Admin, // he es still an admin e.g. can modify stuff - just not an suepr admin
+            username: "mynormaladmin".into(),
+            all_tag: false,
+        },
+    )
+    .await
+    .unwrap();
+
+    // The first thing a new host does is to create a verification attempt
+    let new_host = SigningKey::from_bytes(&[3; 32]);
+
+    let code = api::add_verification_attempt(
+        &url,
+        &key,
+        api::VerificationAttempt {
+            key: new_host.verifying_key(),
+            nixos_facter: Some("Just some facts about a host".into()),
+            recipient: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    assert!((100_000..=999_999).contains(&code));
+
+    // A normal admin is not allowed to accept verify requests
+    let _err = api::accept_attempt(&url, &key, code as u32, "mysuperhostname")
+        .await
+        .unwrap_err();
+
+    // but our admin can
+    let facter = api::accept_attempt(&url, &admin_key, code as u32, "mysuperhostname")
+        .await
+        .unwrap();
+
+    assert_eq!(facter, Some("Just some facts about a host".into()));
+
+    // our admin can see the host
+    let hosts = api::list_hosts(&url, &admin_key).await.unwrap();
+    assert!(hosts.len() == 1);
+
+    // our normal admin can't
     let hosts = api::list_hosts(&url, &key).await.unwrap();
     assert!(hosts.len() == 0);
 
@@ -587,6 +1340,18 @@ fn api_e2e_with_non_superuser(pool: sqlx::SqlitePool) {
     let hosts = api::list_hosts(&url, &key).await.unwrap();
     assert!(hosts.len() == 0);
 
+    // only admins with `all_tag` see every enrolled key
+    api::list_keys(&url, &key).await.unwrap_err();
+    let keys = api::list_keys(&url, &admin_key).await.unwrap();
+    let entry = keys
+        .iter()
+        .find(|entry| entry.key == signing_key.verifying_key())
+        .unwrap();
+    assert_eq!(
+        entry.fingerprint,
+        api::key_fingerprint(&signing_key.verifying_key())
+    );
+
     // lets delete the admin but het tries to delete us
     api::delete_key(&url, &key, admin_signing_key.verifying_key())
         .await
@@ -605,13 +1370,21 @@ fn api_e2e_with_non_superuser(pool: sqlx::SqlitePool) {
 fn api_secrets_with_tags(pool: sqlx::SqlitePool) {
     let _handle = yeetd::launch(
         4339,
-        std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST),
+        [std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
         pool,
         age::x25519::Identity::generate(),
         None,
         None,
         None,
         None,
+        yeetd::BodyLimits::default(),
+        yeetd::Lockout::default(),
+        false,
+        yeetd::hostname::HostnameRules::default(),
+        None,
+        yeetd::webhook::Webhooks::default(),
+        false,
+        false,
     )
     .await;
 
@@ -662,6 +1435,7 @@ fn api_secrets_with_tags(pool: sqlx::SqlitePool) {
         api::VerificationAttempt {
             key: new_host.verifying_key(),
             nixos_facter: Some("Just some facts about a host".into()),
+            recipient: None,
         },
     )
     .await
@@ -764,6 +1538,19 @@ fn api_secrets_with_tags(pool: sqlx::SqlitePool) {
 
     let secrets = api::list_secrets(&url, &key).await.unwrap();
     assert!(secrets.first().unwrap().hosts.is_empty());
+    // the same goes for the acl of a single secret
+    assert!(
+        api::acl_by_secret(&url, &key, "supersecret")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        api::acl_by_secret(&url, &admin_key, "supersecret")
+            .await
+            .unwrap(),
+        vec![host.id]
+    );
 
     // but we can give the host the same `mytag` which normal_admin has access to
     assert!(api::list_hosts(&url, &key).await.unwrap().is_empty());
@@ -792,6 +1579,10 @@ fn api_secrets_with_tags(pool: sqlx::SqlitePool) {
     // now he can see the host
     let secrets = api::list_secrets(&url, &key).await.unwrap();
     assert!(secrets.first().unwrap().hosts.len() == 1);
+    assert_eq!(
+        api::acl_by_secret(&url, &key, "supersecret").await.unwrap(),
+        vec![host.id]
+    );
 
     assert!(api::list_hosts(&url, &key).await.unwrap().len() == 1);
 
@@ -811,9 +1602,592 @@ fn api_secrets_with_tags(pool: sqlx::SqlitePool) {
     let secrets = api::list_secrets(&url, &key).await.unwrap();
     assert!(secrets.first().unwrap().hosts.len() == 1);
 
+    // he can not probe for secrets or hosts that do not exist
+    for (host, secret) in [
+        ("mysuperhostname", "nosuchsecret"),
+        ("nosuchhost", "supersecret"),
+    ] {
+        let err = api::check_secret_access(&url, &key, host, secret).await;
+        assert!(matches!(
+            err,
+            Err(api::ResponseError::ServerError {
+                code: http::StatusCode::FORBIDDEN,
+                ..
+            })
+        ));
+    }
+
+    // an alias he is tagged on does not let him rotate a target he is not tagged on
+    let hidden = api::create_secret(&url, &admin_key, "hiddensecret", &encrypted)
+        .await
+        .unwrap();
+    let alias = api::create_alias(&url, &admin_key, hidden.id, "hiddenalias")
+        .await
+        .unwrap();
+    api::tag::tag_resource(
+        &url,
+        &admin_key,
+        api::tag::ResourceTag {
+            resource: alias.id.into(),
+            tag: mytag,
+        },
+    )
+    .await
+    .unwrap();
+    let rotated = age::encrypt(&server_key, b"rotated").unwrap();
+    let err = api::rotate_secret(&url, &key, alias.id, &rotated).await;
+    assert!(matches!(
+        err,
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::FORBIDDEN,
+            ..
+        })
+    ));
+
+    // with the tag on the target as well he can
+    api::tag::tag_resource(
+        &url,
+        &admin_key,
+        api::tag::ResourceTag {
+            resource: hidden.id.into(),
+            tag: mytag,
+        },
+    )
+    .await
+    .unwrap();
+    api::rotate_secret(&url, &key, alias.id, &rotated)
+        .await
+        .unwrap();
+
     // he can even rename or delete the secret
     api::rename_secret(&url, &key, secret.id, "new_secret_name")
         .await
         .unwrap();
     api::delete_secret(&url, &key, secret.id).await.unwrap();
 }
+
+#[sqlx::test]
+fn api_lockout_after_failed_verifications(pool: sqlx::SqlitePool) {
+    let _handle = yeetd::launch(
+        4340,
+        [std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
+        pool,
+        age::x25519::Identity::generate(),
+        None,
+        None,
+        None,
+        None,
+        yeetd::BodyLimits::default(),
+        yeetd::Lockout {
+            threshold: 3,
+            window: std::time::Duration::from_secs(2),
+        },
+        false,
+        yeetd::hostname::HostnameRules::default(),
+        None,
+        yeetd::webhook::Webhooks::default(),
+        false,
+        false,
+    )
+    .await;
+
+    let url = url::Url::from_str("http://localhost:4340").unwrap();
+
+    let admin_signing_key = SigningKey::from_bytes(&[4; 32]);
+    let admin_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[4; 32]).unwrap();
+
+    api::create_user(
+        &url,
+        &admin_key,
+        api::CreateUser {
+            key: admin_signing_key.verifying_key(),
+            level: api::AuthLevel::Admin,
+            username: "mysuperadmin".into(),
+            all_tag: true,
+        },
+    )
+    .await
+    .unwrap();
+
+    // unknown keys e.g. of agents waiting for their verification are not counted
+    let unknown_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[9; 32]).unwrap();
+    for _ in 0..4 {
+        let err = api::auth::explain(&url, &unknown_key, api::auth::ExplainRequest::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            api::ResponseError::ServerError {
+                code: http::StatusCode::BAD_REQUEST,
+                ..
+            }
+        ));
+    }
+
+    // signatures that do not verify for the claimed keyid are
+    let forged = || async {
+        let mut params = api::sig_param(&unknown_key).unwrap();
+        params.set_keyid(&admin_key.key_id());
+        reqwest::Client::new()
+            .post(url.join("/auth/explain").unwrap())
+            .json(&api::auth::ExplainRequest::default())
+            .sign(&params, &unknown_key)
+            .await
+            .unwrap()
+            .send()
+            .await
+            .unwrap()
+            .status()
+    };
+    for _ in 0..3 {
+        assert_eq!(forged().await, http::StatusCode::BAD_REQUEST);
+    }
+
+    // now the keyid is locked out from this source, even with a valid signature
+    let err = api::auth::explain(&url, &admin_key, api::auth::ExplainRequest::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        api::ResponseError::ServerError {
+            code: http::StatusCode::TOO_MANY_REQUESTS,
+            ..
+        }
+    ));
+
+    let metrics = reqwest::get(url.join("metrics").unwrap())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains("yeet_signature_lockouts_total 1"));
+
+    // the lockout ends after the window
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+    api::auth::explain(&url, &admin_key, api::auth::ExplainRequest::default())
+        .await
+        .unwrap();
+}
+
+#[sqlx::test]
+fn api_two_person_rule(pool: sqlx::SqlitePool) {
+    let _handle = yeetd::launch(
+        4341,
+        [std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
+        pool,
+        age::x25519::Identity::generate(),
+        None,
+        None,
+        None,
+        None,
+        yeetd::BodyLimits::default(),
+        yeetd::Lockout::default(),
+        false,
+        yeetd::hostname::HostnameRules::default(),
+        None,
+        yeetd::webhook::Webhooks::default(),
+        true,
+        false,
+    )
+    .await;
+
+    let url = url::Url::from_str("http://localhost:4341").unwrap();
+
+    let admin_signing_key = SigningKey::from_bytes(&[4; 32]);
+    let admin_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[4; 32]).unwrap();
+    api::create_user(
+        &url,
+        &admin_key,
+        api::CreateUser {
+            key: admin_signing_key.verifying_key(),
+            level: api::AuthLevel::Admin,
+            username: "firstadmin".into(),
+            all_tag: true,
+        },
+    )
+    .await
+    .unwrap();
+
+    let second_signing_key = SigningKey::from_bytes(&[5; 32]);
+    let second_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[5; 32]).unwrap();
+    api::create_user(
+        &url,
+        &admin_key,
+        api::CreateUser {
+            key: second_signing_key.verifying_key(),
+            level: api::AuthLevel::Admin,
+            username: "secondadmin".into(),
+            all_tag: true,
+        },
+    )
+    .await
+    .unwrap();
+
+    let server_key = api::server_age_key(&url, &admin_key).await.unwrap();
+    let server_key = age::x25519::Recipient::from_str(&server_key).unwrap();
+    let encrypted = age::encrypt(&server_key, b"secret").unwrap();
+    let secret = api::create_secret(&url, &admin_key, "supersecret", &encrypted)
+        .await
+        .unwrap();
+
+    // deleting only proposes the deletion
+    let status = api::delete_secret(&url, &admin_key, secret.id)
+        .await
+        .unwrap();
+    assert_eq!(status, http::StatusCode::ACCEPTED);
+    assert_eq!(api::list_secrets(&url, &admin_key).await.unwrap().len(), 1);
+
+    // proposing it twice is a conflict
+    api::delete_secret(&url, &second_key, secret.id)
+        .await
+        .unwrap_err();
+
+    let pending = api::list_approvals(&url, &second_key).await.unwrap();
+    let approval = pending.first().unwrap();
+    assert_eq!(
+        approval.action,
+        api::ApprovalAction::DeleteSecret(secret.id)
+    );
+
+    // the proposer can't confirm on their own
+    api::confirm_approval(&url, &admin_key, approval.id)
+        .await
+        .unwrap_err();
+    assert_eq!(api::list_secrets(&url, &admin_key).await.unwrap().len(), 1);
+
+    api::confirm_approval(&url, &second_key, approval.id)
+        .await
+        .unwrap();
+    assert!(
+        api::list_secrets(&url, &admin_key)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        api::list_approvals(&url, &admin_key)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[sqlx::test]
+fn api_secret_add_modes(pool: sqlx::SqlitePool) {
+    let _handle = yeetd::launch(
+        4342,
+        [std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
+        pool,
+        age::x25519::Identity::generate(),
+        None,
+        None,
+        None,
+        None,
+        yeetd::BodyLimits::default(),
+        yeetd::Lockout::default(),
+        false,
+        yeetd::hostname::HostnameRules::default(),
+        None,
+        yeetd::webhook::Webhooks::default(),
+        false,
+        false,
+    )
+    .await;
+
+    let url = url::Url::from_str("http://localhost:4342").unwrap();
+
+    let admin_signing_key = SigningKey::from_bytes(&[4; 32]);
+    let admin_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[4; 32]).unwrap();
+    api::create_user(
+        &url,
+        &admin_key,
+        api::CreateUser {
+            key: admin_signing_key.verifying_key(),
+            level: api::AuthLevel::Admin,
+            username: "admin".into(),
+            all_tag: true,
+        },
+    )
+    .await
+    .unwrap();
+
+    let server_key = api::server_age_key(&url, &admin_key).await.unwrap();
+    let server_key = age::x25519::Recipient::from_str(&server_key).unwrap();
+    let request = |mode, content_type: Option<&str>| api::AddSecretRequest {
+        name: "supersecret".into(),
+        secret: age::encrypt(&server_key, b"secret").unwrap(),
+        mode,
+        content_type: content_type.map(ToOwned::to_owned),
+    };
+    let status = |result: Result<api::AddedSecret, api::ResponseError>| match result {
+        Err(api::ResponseError::ServerError { code, .. }) => code,
+        other => panic!("expected a server error, got {other:?}"),
+    };
+
+    // nothing to update yet
+    assert_eq!(
+        status(
+            api::put_secret(
+                &url,
+                &admin_key,
+                request(api::AddSecretMode::UpdateOnly, None)
+            )
+            .await
+        ),
+        http::StatusCode::NOT_FOUND
+    );
+    assert!(
+        api::list_secrets(&url, &admin_key)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let created = api::put_secret(
+        &url,
+        &admin_key,
+        request(api::AddSecretMode::CreateOnly, None),
+    )
+    .await
+    .unwrap();
+    assert!(created.created);
+
+    // the name is taken now
+    assert_eq!(
+        status(
+            api::put_secret(
+                &url,
+                &admin_key,
+                request(api::AddSecretMode::CreateOnly, None)
+            )
+            .await
+        ),
+        http::StatusCode::CONFLICT
+    );
+    // the old route does not replace secrets either
+    let err = api::create_secret(
+        &url,
+        &admin_key,
+        "supersecret",
+        &age::encrypt(&server_key, b"secret").unwrap(),
+    )
+    .await;
+    assert!(matches!(
+        err,
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::CONFLICT,
+            ..
+        })
+    ));
+
+    // updating replaces the content of the same secret
+    let updated = api::put_secret(
+        &url,
+        &admin_key,
+        request(api::AddSecretMode::UpdateOnly, Some("pem")),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        updated,
+        api::AddedSecret {
+            id: created.id,
+            created: false
+        }
+    );
+
+    let upserted = api::put_secret(&url, &admin_key, request(api::AddSecretMode::Upsert, None))
+        .await
+        .unwrap();
+    assert_eq!(upserted.id, created.id);
+    assert!(!upserted.created);
+
+    // only one secret and it kept the content type
+    let secrets = api::list_secrets(&url, &admin_key).await.unwrap();
+    assert_eq!(secrets.len(), 1);
+    assert_eq!(secrets.first().unwrap().content_type, Some("pem".into()));
+    assert_eq!(
+        api::check_secret(&url, &admin_key, "supersecret")
+            .await
+            .unwrap(),
+        http::StatusCode::OK
+    );
+
+    // upserting a new name creates it
+    let upserted = api::put_secret(
+        &url,
+        &admin_key,
+        api::AddSecretRequest {
+            name: "othersecret".into(),
+            ..request(api::AddSecretMode::Upsert, None)
+        },
+    )
+    .await
+    .unwrap();
+    assert!(upserted.created);
+    assert_eq!(api::list_secrets(&url, &admin_key).await.unwrap().len(), 2);
+}
+
+#[sqlx::test]
+fn api_secret_as_host(pool: sqlx::SqlitePool) {
+    let _handle = yeetd::launch(
+        4343,
+        [std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
+        pool,
+        age::x25519::Identity::generate(),
+        None,
+        None,
+        None,
+        None,
+        yeetd::BodyLimits::default(),
+        yeetd::Lockout::default(),
+        false,
+        yeetd::hostname::HostnameRules::default(),
+        None,
+        yeetd::webhook::Webhooks::default(),
+        false,
+        true,
+    )
+    .await;
+
+    let url = url::Url::from_str("http://localhost:4343").unwrap();
+
+    let admin_signing_key = SigningKey::from_bytes(&[4; 32]);
+    let admin_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[4; 32]).unwrap();
+    api::create_user(
+        &url,
+        &admin_key,
+        api::CreateUser {
+            key: admin_signing_key.verifying_key(),
+            level: api::AuthLevel::Admin,
+            username: "admin".into(),
+            all_tag: true,
+        },
+    )
+    .await
+    .unwrap();
+
+    let tagged_signing_key = SigningKey::from_bytes(&[5; 32]);
+    let tagged_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[5; 32]).unwrap();
+    api::create_user(
+        &url,
+        &admin_key,
+        api::CreateUser {
+            key: tagged_signing_key.verifying_key(),
+            level: api::AuthLevel::Admin,
+            username: "tagged".into(),
+            all_tag: false,
+        },
+    )
+    .await
+    .unwrap();
+
+    let host_key = SecretKey::from_bytes(&AlgorithmName::Ed25519, &[3; 32]).unwrap();
+    let code = api::add_verification_attempt(
+        &url,
+        &host_key,
+        api::VerificationAttempt {
+            key: SigningKey::from_bytes(&[3; 32]).verifying_key(),
+            nixos_facter: None,
+            recipient: Some(age::x25519::Identity::generate().to_public().to_string()),
+        },
+    )
+    .await
+    .unwrap();
+    api::accept_attempt(&url, &admin_key, code as u32, "peekhost")
+        .await
+        .unwrap();
+    let host = api::list_hosts(&url, &admin_key)
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|host| host.hostname == "peekhost")
+        .unwrap();
+
+    let server_key = api::server_age_key(&url, &admin_key).await.unwrap();
+    let server_key = age::x25519::Recipient::from_str(&server_key).unwrap();
+    let secret = api::create_secret(
+        &url,
+        &admin_key,
+        "peeked",
+        &age::encrypt(&server_key, b"plaintext").unwrap(),
+    )
+    .await
+    .unwrap();
+
+    let query = |secret: &str| api::SecretAccessQuery {
+        host: "peekhost".to_owned(),
+        secret: secret.to_owned(),
+    };
+
+    // the acl of the host applies
+    assert_eq!(
+        api::secret_as_host(&url, &admin_key, query("peeked"))
+            .await
+            .unwrap(),
+        api::SecretLookup::NoAccess
+    );
+    assert_eq!(
+        api::secret_as_host(&url, &admin_key, query("missing"))
+            .await
+            .unwrap(),
+        api::SecretLookup::NotFound
+    );
+
+    api::allow_host(&url, &admin_key, secret.id, host.id)
+        .await
+        .unwrap();
+    assert_eq!(
+        api::secret_as_host(&url, &admin_key, query("peeked"))
+            .await
+            .unwrap(),
+        api::SecretLookup::Found {
+            content: b"plaintext".to_vec(),
+            content_type: None,
+        }
+    );
+
+    // the server can not open a sealed secret so nothing is revealed
+    api::create_sealed_secret(
+        &url,
+        &admin_key,
+        "sealedpeek",
+        host.id,
+        &age::encrypt(&age::x25519::Identity::generate().to_public(), b"sealed").unwrap(),
+    )
+    .await
+    .unwrap();
+    let err = api::secret_as_host(&url, &admin_key, query("sealedpeek")).await;
+    assert!(matches!(
+        err,
+        Err(api::ResponseError::ServerError {
+            code: http::StatusCode::CONFLICT,
+            ..
+        })
+    ));
+
+    // admins without `all_tag` can not peek
+    api::secret_as_host(&url, &tagged_key, query("peeked"))
+        .await
+        .unwrap_err();
+
+    // every attempt is audited
+    let peeks = api::audit_mutations(&url, &admin_key)
+        .await
+        .unwrap()
+        .entries
+        .into_iter()
+        .filter(|entry| entry.action == "Secret::Peek")
+        .map(|entry| entry.detail)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        peeks,
+        [
+            "PLAINTEXT secret peeked as host peekhost: no access",
+            "PLAINTEXT secret missing as host peekhost: not found",
+            "PLAINTEXT secret peeked as host peekhost: revealed",
+            "PLAINTEXT secret sealedpeek as host peekhost: not revealed (409 Conflict)",
+        ]
+    );
+}
@@ -0,0 +1,126 @@
+//! The files the server keeps: `yeet.db`, the `age.key` of the store and the `state.json`
+//! imported on the first start. `YEET_DATA_DIR` roots all of them
+
+use std::{
+    fs::{DirBuilder, File, OpenOptions},
+    io,
+    os::unix::fs::{DirBuilderExt as _, OpenOptionsExt as _},
+    path::{Path, PathBuf},
+};
+
+const DATABASE: &str = "yeet.db";
+const AGE_KEY: &str = "age.key";
+const STATE: &str = "state.json";
+
+#[derive(Clone, Debug)]
+pub struct DataDir {
+    root: PathBuf,
+}
+
+impl DataDir {
+    /// A missing directory is created and only readable by the server.
+    /// The permissions of an existing directory are kept
+    pub fn open(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        if !root.exists() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&root)?;
+        }
+        Ok(Self { root })
+    }
+
+    /// Like `open` but nothing is created. For checks that must not change the data directory
+    #[must_use]
+    pub fn existing(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Relative paths end up in the data directory. Absolute paths are kept
+    #[must_use]
+    pub fn resolve(&self, file: impl AsRef<Path>) -> PathBuf {
+        self.root.join(file)
+    }
+
+    #[must_use]
+    pub fn database(&self) -> PathBuf {
+        self.resolve(DATABASE)
+    }
+
+    #[must_use]
+    pub fn age_key(&self) -> PathBuf {
+        self.resolve(AGE_KEY)
+    }
+
+    /// A new `age.key` that only the server can read. Fails if one exists already
+    pub fn create_age_key(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(self.age_key())
+    }
+
+    /// `state` overrides the default `state.json` e.g. with `YEET_STATE`
+    #[must_use]
+    pub fn state(&self, state: Option<&Path>) -> PathBuf {
+        self.resolve(state.unwrap_or(Path::new(STATE)))
+    }
+}
+
+#[cfg(test)]
+mod test_data_dir {
+    use std::{fs, os::unix::fs::PermissionsExt as _, path::Path};
+
+    use super::DataDir;
+
+    #[test]
+    fn resolve() {
+        let data_dir = DataDir {
+            root: "/var/lib/yeet".into(),
+        };
+        assert_eq!(data_dir.database(), Path::new("/var/lib/yeet/yeet.db"));
+        assert_eq!(data_dir.age_key(), Path::new("/var/lib/yeet/age.key"));
+        assert_eq!(data_dir.state(None), Path::new("/var/lib/yeet/state.json"));
+        assert_eq!(
+            data_dir.state(Some(Path::new("import/state.json"))),
+            Path::new("/var/lib/yeet/import/state.json")
+        );
+        assert_eq!(
+            data_dir.state(Some(Path::new("/etc/yeet/state.json"))),
+            Path::new("/etc/yeet/state.json")
+        );
+    }
+
+    #[test]
+    fn permissions() {
+        let base = tempfile::tempdir().unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let root = base.path().join("data/yeet");
+        DataDir::open(&root).unwrap();
+        assert_eq!(mode(&root), 0o700);
+        assert_eq!(mode(&base.path().join("data")), 0o700);
+
+        // existing directories are not touched
+        let existing = base.path().join("existing");
+        fs::create_dir_all(&existing).unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o750)).unwrap();
+        DataDir::open(&existing).unwrap();
+        assert_eq!(mode(&existing), 0o750);
+
+        let missing = base.path().join("missing");
+        assert!(!DataDir::existing(&missing).database().exists());
+        assert!(!missing.exists());
+
+        let data_dir = DataDir::open(&root).unwrap();
+        data_dir.create_age_key().unwrap();
+        assert_eq!(mode(&data_dir.age_key()), 0o600);
+        // an existing key is never replaced
+        assert_eq!(
+            data_dir.create_age_key().unwrap_err().kind(),
+            std::io::ErrorKind::AlreadyExists
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use jiff_sqlx::ToSqlx as _;
+use sqlx::types::Json;
+
+error_set::error_set! {
+    ApprovalError := {
+        #[display("The action is already waiting for a second admin")]
+        AlreadyProposed,
+        #[display("No pending approval with this id")]
+        NotFound,
+        SQLXError(sqlx::Error),
+    }
+}
+
+pub async fn propose(
+    conn: &mut sqlx::SqliteConnection,
+    proposer: api::UserID,
+    action: api::ApprovalAction,
+) -> Result<api::ApprovalID, ApprovalError> {
+    let action = Json(action);
+    let now = jiff::Timestamp::now().to_sqlx();
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO pending_approvals (action, proposer, proposed)
+        VALUES ($1, $2, $3)
+        RETURNING id AS "id: api::ApprovalID""#,
+        action,
+        proposer,
+        now
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(|err| {
+        if let sqlx::Error::Database(db_err) = &err
+            && db_err.is_unique_violation()
+        {
+            ApprovalError::AlreadyProposed
+        } else {
+            ApprovalError::SQLXError(err)
+        }
+    })
+}
+
+/// Removes the approval and returns it. Only one of concurrent callers gets it
+pub async fn take(
+    conn: &mut sqlx::SqliteConnection,
+    id: api::ApprovalID,
+) -> Result<api::PendingApproval, ApprovalError> {
+    sqlx::query!(
+        r#"
+        DELETE FROM pending_approvals
+        WHERE id = $1
+        RETURNING
+            id AS "id: api::ApprovalID",
+            action AS "action: Json<api::ApprovalAction>",
+            proposer AS "proposer: api::UserID",
+            proposed AS "proposed: jiff_sqlx::Timestamp""#,
+        id
+    )
+    .map(|row| api::PendingApproval {
+        id: row.id,
+        action: row.action.0,
+        proposer: row.proposer,
+        proposed: row.proposed.to_jiff(),
+    })
+    .fetch_optional(conn)
+    .await?
+    .ok_or(ApprovalError::NotFound)
+}
+
+/// Oldest first
+pub async fn list(
+    conn: &mut sqlx::SqliteConnection,
+) -> Result<Vec<api::PendingApproval>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT
+            id AS "id: api::ApprovalID",
+            action AS "action: Json<api::ApprovalAction>",
+            proposer AS "proposer: api::UserID",
+            proposed AS "proposed: jiff_sqlx::Timestamp"
+        FROM pending_approvals
+        ORDER BY id"#
+    )
+    .map(|row| api::PendingApproval {
+        id: row.id,
+        action: row.action.0,
+        proposer: row.proposer,
+        proposed: row.proposed.to_jiff(),
+    })
+    .fetch_all(conn)
+    .await
+}
+
+#[cfg(test)]
+mod test_approvals {
+    use ed25519_dalek::SigningKey;
+
+    use crate::db::{self, approvals::ApprovalError};
+
+    #[sqlx::test]
+    async fn propose_and_take(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let admin = db::user::create_user(
+            &mut conn,
+            "adminkey".to_owned(),
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "admin".to_owned(),
+            api::AuthLevel::Admin,
+            true,
+        )
+        .await
+        .unwrap();
+        let action = api::ApprovalAction::DeleteSecret(api::SecretID::new(1));
+
+        let id = db::approvals::propose(&mut conn, admin, action)
+            .await
+            .unwrap();
+        assert!(matches!(
+            db::approvals::propose(&mut conn, admin, action).await,
+            Err(ApprovalError::AlreadyProposed)
+        ));
+
+        let pending = db::approvals::list(&mut conn).await.unwrap();
+        let [pending] = pending.as_slice() else {
+            panic!("Expected exactly one approval: {pending:?}");
+        };
+        assert_eq!(pending.id, id);
+        assert_eq!(pending.action, action);
+        assert_eq!(pending.proposer, admin);
+
+        assert_eq!(&db::approvals::take(&mut conn, id).await.unwrap(), pending);
+        assert!(db::approvals::list(&mut conn).await.unwrap().is_empty());
+        assert!(matches!(
+            db::approvals::take(&mut conn, id).await,
+            Err(ApprovalError::NotFound)
+        ));
+        // the action can be proposed again
+        db::approvals::propose(&mut conn, admin, action)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use ed25519_dalek::VerifyingKey;
+use jiff_sqlx::ToSqlx as _;
+use sha2::{Digest as _, Sha256};
+use sqlx::Acquire as _;
+
+/// `prev_hash` of the first entry
+const GENESIS: [u8; 32] = [0; 32];
+
+#[expect(
+    clippy::big_endian_bytes,
+    reason = "Length prefixes keep the fields apart. The byte order only has to be fixed"
+)]
+fn entry_hash(
+    prev_hash: &[u8],
+    actor: &VerifyingKey,
+    time: jiff::Timestamp,
+    action: &str,
+    detail: &str,
+) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash);
+    hasher.update(actor.as_bytes());
+    for field in [time.to_string().as_str(), action, detail] {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Append a mutation `user` performed to the end of the chain
+pub async fn append(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    action: &str,
+    detail: &str,
+) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+
+    let actor = sqlx::query_scalar!(
+        r#"
+        SELECT keys.verifying_key FROM users
+        JOIN keys ON users.key_id = keys.id
+        WHERE users.id = $1"#,
+        user
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let actor = VerifyingKey::from_bytes(&actor.try_into().expect("We only store valid keys"))
+        .expect("We only store valid keys");
+
+    let prev_hash = sqlx::query_scalar!(r#"SELECT hash FROM audit_log ORDER BY id DESC LIMIT 1"#)
+        .fetch_optional(&mut *tx)
+        .await?
+        .unwrap_or_else(|| GENESIS.to_vec());
+
+    let time = jiff::Timestamp::now();
+    let hash = entry_hash(&prev_hash, &actor, time, action, detail);
+    let actor = &actor.as_bytes()[..];
+    let time = time.to_sqlx();
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (prev_hash, hash, actor, action, detail, time)
+        VALUES ($1,$2,$3,$4,$5,$6)"#,
+        prev_hash,
+        hash,
+        actor,
+        action,
+        detail,
+        time
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+pub async fn list(conn: &mut sqlx::SqliteConnection) -> Result<Vec<api::AuditEntry>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT prev_hash, hash, actor, action, detail, time AS "time: jiff_sqlx::Timestamp"
+        FROM audit_log
+        ORDER BY id"#
+    )
+    .map(|row| api::AuditEntry {
+        prev_hash: row.prev_hash,
+        hash: row.hash,
+        actor: VerifyingKey::from_bytes(&row.actor.try_into().expect("We only store valid keys"))
+            .expect("We only store valid keys"),
+        action: row.action,
+        detail: row.detail,
+        time: row.time.to_jiff(),
+    })
+    .fetch_all(conn)
+    .await
+}
+
+/// Index of the first entry that does not link to its predecessor or whose content changed
+pub fn verify_chain(entries: &[api::AuditEntry]) -> Option<usize> {
+    let mut prev_hash = GENESIS.as_slice();
+    for (index, entry) in entries.iter().enumerate() {
+        let hash = entry_hash(
+            &entry.prev_hash,
+            &entry.actor,
+            entry.time,
+            &entry.action,
+            &entry.detail,
+        );
+        if entry.prev_hash != prev_hash || entry.hash != hash {
+            return Some(index);
+        }
+        prev_hash = &entry.hash;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test_audit {
+    use ed25519_dalek::SigningKey;
+
+    use crate::db;
+
+    #[sqlx::test]
+    async fn tampering_breaks_chain(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let user = db::user::create_user(
+            &mut conn,
+            "adminkey".to_owned(),
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "admin".to_owned(),
+            api::AuthLevel::Admin,
+            true,
+        )
+        .await
+        .unwrap();
+
+        for (action, detail) in [
+            ("Secret::Create", "wifi"),
+            ("Secret::Allow", "secret 1 host 1"),
+            ("Secret::Delete", "secret 1"),
+        ] {
+            db::audit::append(&mut conn, user, action, detail)
+                .await
+                .unwrap();
+        }
+
+        let entries = db::audit::list(&mut conn).await.unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(db::audit::verify_chain(&entries), None);
+
+        // rewriting the middle entry is detected there
+        sqlx::query!("UPDATE audit_log SET detail = 'secret 1 host 2' WHERE id = 2")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        let mut entries = db::audit::list(&mut conn).await.unwrap();
+        assert_eq!(db::audit::verify_chain(&entries), Some(1));
+
+        // recomputing its hash only moves the break to the next entry
+        let middle = entries.get_mut(1).expect("three entries");
+        middle.hash = super::entry_hash(
+            &middle.prev_hash,
+            &middle.actor,
+            middle.time,
+            &middle.action,
+            &middle.detail,
+        );
+        assert_eq!(db::audit::verify_chain(&entries), Some(2));
+
+        // as does dropping it
+        entries.remove(1);
+        assert_eq!(db::audit::verify_chain(&entries), Some(1));
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+error_set::error_set! {
+    GroupError := {
+        #[display("Host group already exists")]
+        GroupExists,
+        #[display("Host group does not exist")]
+        GroupNotFound,
+        SQLXError(sqlx::Error),
+    }
+}
+
+pub async fn create_group(conn: &mut sqlx::SqliteConnection, name: &str) -> Result<(), GroupError> {
+    sqlx::query!(r#"INSERT INTO host_groups (name) VALUES ($1)"#, name)
+        .execute(conn)
+        .await
+        .map_err(|err| {
+            if let sqlx::Error::Database(db_err) = &err
+                && db_err.is_unique_violation()
+            {
+                GroupError::GroupExists
+            } else {
+                GroupError::SQLXError(err)
+            }
+        })?;
+    Ok(())
+}
+
+async fn group_id(conn: &mut sqlx::SqliteConnection, name: &str) -> Result<i64, GroupError> {
+    sqlx::query_scalar!(r#"SELECT id FROM host_groups WHERE name = $1"#, name)
+        .fetch_optional(conn)
+        .await?
+        .ok_or(GroupError::GroupNotFound)
+}
+
+/// Adding a host twice keeps a single membership
+pub async fn add_member(
+    conn: &mut sqlx::SqliteConnection,
+    group: &str,
+    host: api::HostID,
+) -> Result<(), GroupError> {
+    let group = group_id(conn, group).await?;
+    sqlx::query!(
+        r#"INSERT OR IGNORE INTO host_group_members (group_id, host_id) VALUES ($1, $2)"#,
+        group,
+        host
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn remove_member(
+    conn: &mut sqlx::SqliteConnection,
+    group: &str,
+    host: api::HostID,
+) -> Result<(), GroupError> {
+    let group = group_id(conn, group).await?;
+    sqlx::query!(
+        r#"DELETE FROM host_group_members WHERE group_id = $1 AND host_id = $2"#,
+        group,
+        host
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Every group with the ids and names of its hosts. Empty groups are included
+pub async fn list_groups(
+    conn: &mut sqlx::SqliteConnection,
+) -> Result<HashMap<String, Vec<(api::HostID, String)>>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            g.name AS "group!",
+            h.id AS "host_id?: api::HostID",
+            h.hostname AS "hostname?: String"
+        FROM host_groups g
+        LEFT JOIN host_group_members m ON m.group_id = g.id
+        LEFT JOIN hosts h ON h.id = m.host_id
+        ORDER BY g.name, h.hostname"#
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut groups: HashMap<String, Vec<(api::HostID, String)>> = HashMap::new();
+    for row in rows {
+        let hosts = groups.entry(row.group).or_default();
+        if let (Some(host), Some(hostname)) = (row.host_id, row.hostname) {
+            hosts.push((host, hostname));
+        }
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod test_groups {
+    use std::collections::HashMap;
+
+    use ed25519_dalek::SigningKey;
+
+    use crate::db::{self, groups::GroupError};
+
+    #[sqlx::test]
+    async fn membership(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let web = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "web".to_owned(),
+        )
+        .await
+        .unwrap();
+        let db_host = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[2; 32]).verifying_key(),
+            "db".to_owned(),
+        )
+        .await
+        .unwrap();
+
+        db::groups::create_group(&mut conn, "prod").await.unwrap();
+        db::groups::create_group(&mut conn, "staging")
+            .await
+            .unwrap();
+        assert!(matches!(
+            db::groups::create_group(&mut conn, "prod").await,
+            Err(GroupError::GroupExists)
+        ));
+        assert!(matches!(
+            db::groups::add_member(&mut conn, "nope", web).await,
+            Err(GroupError::GroupNotFound)
+        ));
+
+        db::groups::add_member(&mut conn, "prod", web)
+            .await
+            .unwrap();
+        db::groups::add_member(&mut conn, "prod", web)
+            .await
+            .unwrap();
+        db::groups::add_member(&mut conn, "prod", db_host)
+            .await
+            .unwrap();
+        let groups = db::groups::list_groups(&mut conn).await.unwrap();
+        assert_eq!(
+            groups,
+            HashMap::from([
+                (
+                    "prod".to_owned(),
+                    vec![(db_host, "db".to_owned()), (web, "web".to_owned())]
+                ),
+                ("staging".to_owned(), vec![])
+            ])
+        );
+
+        db::groups::remove_member(&mut conn, "prod", db_host)
+            .await
+            .unwrap();
+        // removing a host removes it from every group
+        db::groups::add_member(&mut conn, "staging", web)
+            .await
+            .unwrap();
+        db::hosts::remove_host(&mut conn, web).await.unwrap();
+        let groups = db::groups::list_groups(&mut conn).await.unwrap();
+        assert_eq!(
+            groups,
+            HashMap::from([("prod".to_owned(), vec![]), ("staging".to_owned(), vec![])])
+        );
+    }
+}
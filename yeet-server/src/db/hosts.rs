@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use api::ProvisionState;
 use ed25519_dalek::VerifyingKey;
 use httpsig_hyper::prelude::{AlgorithmName, PublicKey, VerifyingKey as _};
@@ -58,7 +60,7 @@ pub async fn fetch_current_version(
 }
 
 /// Fetch the latest update ignoring if the host has already applied it
-async fn fetch_latest_update(
+pub async fn fetch_latest_update(
     conn: &mut sqlx::SqliteConnection,
     host: api::HostID,
 ) -> Result<Option<api::RemoteStorePath>, sqlx::Error> {
@@ -103,6 +105,138 @@ pub async fn update_current_version(
     Ok(())
 }
 
+pub async fn add_activation_report(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+    report: api::ActivationReport,
+) -> Result<(), sqlx::Error> {
+    let now = jiff::Timestamp::now().to_sqlx();
+    sqlx::query!(
+        r#"
+        INSERT INTO activation_reports (host_id, store_path, success, error, report_time)
+        VALUES ($1,$2,$3,$4,$5)"#,
+        host,
+        report.store_path,
+        report.success,
+        report.error,
+        now
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn add_download_stats(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+    stats: api::DownloadStats,
+) -> Result<(), sqlx::Error> {
+    let now = jiff::Timestamp::now().to_sqlx();
+    let nar_size = i64::try_from(stats.nar_size).unwrap_or(i64::MAX);
+    let closure_size = i64::try_from(stats.closure_size).unwrap_or(i64::MAX);
+    sqlx::query!(
+        r#"
+        INSERT INTO download_stats (host_id, store_path, nar_size, closure_size, report_time)
+        VALUES ($1,$2,$3,$4,$5)"#,
+        host,
+        stats.store_path,
+        nar_size,
+        closure_size,
+        now
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Only the latest report is kept
+pub async fn set_facter(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+    facter: &str,
+) -> Result<(), sqlx::Error> {
+    let now = jiff::Timestamp::now().to_sqlx();
+    sqlx::query!(
+        r#"
+        INSERT INTO host_facter (host_id, facter, report_time)
+        VALUES ($1,$2,$3)
+        ON CONFLICT(host_id) DO UPDATE SET facter = excluded.facter, report_time = excluded.report_time"#,
+        host,
+        facter,
+        now
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn fetch_facter(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+) -> Result<Option<api::FacterReport>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT facter, report_time AS "report_time: jiff_sqlx::Timestamp"
+        FROM host_facter
+        WHERE host_id = $1"#,
+        host
+    )
+    .map(|row| api::FacterReport {
+        nixos_facter: row.facter,
+        report_time: row.report_time.to_jiff(),
+    })
+    .fetch_optional(conn)
+    .await
+}
+
+/// Reads the global and the host specific permission in a single query
+pub async fn detach_permission(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+) -> Result<api::DetachPermission, sqlx::Error> {
+    let permission = sqlx::query!(
+        r#"
+        SELECT settings.global_allowed AS "global: bool", hosts.detach_allowed AS "host_specific?: bool"
+        FROM hosts, detach_settings AS settings
+        WHERE hosts.id = $1"#,
+        host
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(api::DetachPermission::new(
+        permission.global,
+        permission.host_specific,
+    ))
+}
+
+pub async fn set_global_detach(
+    conn: &mut sqlx::SqliteConnection,
+    allowed: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE detach_settings SET global_allowed = $1 WHERE id = 1"#,
+        allowed
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_host_detach(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+    allowed: Option<bool>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE hosts SET detach_allowed = $1 WHERE id = $2"#,
+        allowed,
+        host
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
 /// Returns an update only if the host is not currently on the update
 /// Does not check if the host is detached
 pub async fn fetch_available_update(
@@ -183,6 +317,14 @@ pub async fn update(
     Ok(())
 }
 
+/// The columns `set_soaking` writes
+fn soak(store_path: Option<String>, until: Option<jiff_sqlx::Timestamp>) -> Option<api::Soak> {
+    store_path.zip(until).map(|(store_path, until)| api::Soak {
+        store_path,
+        until: until.to_jiff(),
+    })
+}
+
 pub async fn list_hosts(
     conn: &mut sqlx::SqliteConnection,
     user: api::UserID,
@@ -203,6 +345,17 @@ pub async fn list_hosts(
             SELECT host_id, store_path, update_time,
                    ROW_NUMBER() OVER(PARTITION BY host_id ORDER BY update_time DESC) as rn
             FROM update_request_history
+        ),
+        latest_download AS (
+            SELECT host_id, closure_size,
+                   ROW_NUMBER() OVER(PARTITION BY host_id ORDER BY id DESC) as rn
+            FROM download_stats
+        ),
+        last_activation AS (
+            SELECT host_id, MAX(report_time) AS report_time
+            FROM activation_reports
+            WHERE success = 1
+            GROUP BY host_id
         )
         SELECT
             h.id AS "id!",
@@ -212,6 +365,12 @@ pub async fn list_hosts(
             ls.state AS "state: Option<api::ProvisionState>",
             lv.store_path AS "current_version: Option<String>",
             lur.store_path AS "latest_update: Option<String>",
+            ld.closure_size AS "last_download_size: Option<i64>",
+            hf.report_time AS "last_facter: Option<jiff_sqlx::Timestamp>",
+            h.soaking_store_path,
+            h.soaking_until AS "soaking_until: jiff_sqlx::Timestamp",
+            h.enrolled_at AS "enrolled_at!: jiff_sqlx::Timestamp",
+            la.report_time AS "last_updated_at: jiff_sqlx::Timestamp",
             json_group_array(
                 json_object('id', t.id, 'name', t.name)
             ) FILTER (WHERE t.id IS NOT NULL) as "tags!: Json<Vec<api::tag::Tag>>"
@@ -220,6 +379,9 @@ pub async fn list_hosts(
         LEFT JOIN current_state ls ON ls.host_id = h.id AND ls.rn = 1
         LEFT JOIN current_version lv ON lv.host_id = h.id AND lv.rn = 1
         LEFT JOIN latest_update_request lur ON lur.host_id = h.id AND lur.rn = 1
+        LEFT JOIN latest_download ld ON ld.host_id = h.id AND ld.rn = 1
+        LEFT JOIN host_facter hf ON hf.host_id = h.id
+        LEFT JOIN last_activation la ON la.host_id = h.id
 
         JOIN access a_s
             ON h.id = a_s.resource_id
@@ -246,20 +408,79 @@ pub async fn list_hosts(
         last_ping: row.last_ping.to_jiff(),
         version: row.current_version,
         latest_update: row.latest_update,
+        last_download_size: row.last_download_size.map(|size| size as u64),
+        last_facter: row.last_facter.map(jiff_sqlx::Timestamp::to_jiff),
+        soaking: soak(row.soaking_store_path, row.soaking_until),
         tags: row.tags.0,
+        enrolled_at: row.enrolled_at.to_jiff(),
+        last_updated_at: row.last_updated_at.map(jiff_sqlx::Timestamp::to_jiff),
+        history: Vec::new(),
     })
     .fetch_all(&mut *conn)
     .await?;
 
-    Ok(hosts)
+    let mut histories = deploy_histories(&mut *conn).await?;
+    Ok(hosts
+        .into_iter()
+        .map(|host| api::Host {
+            history: histories.remove(&host.id).unwrap_or_default(),
+            ..host
+        })
+        .collect())
+}
+
+/// The last `api::DEPLOY_HISTORY` activation reports of every host, newest first
+pub async fn deploy_histories(
+    conn: &mut sqlx::SqliteConnection,
+) -> Result<HashMap<api::HostID, Vec<api::Deployment>>, sqlx::Error> {
+    let limit = i64::try_from(api::DEPLOY_HISTORY).unwrap_or(i64::MAX);
+    let reports = sqlx::query!(
+        r#"
+        SELECT host_id AS "host_id: api::HostID", store_path, success AS "success: bool", error,
+               report_time AS "report_time: jiff_sqlx::Timestamp"
+        FROM (
+            SELECT *, ROW_NUMBER() OVER(PARTITION BY host_id ORDER BY id DESC) AS rn
+            FROM activation_reports
+        )
+        WHERE rn <= $1
+        ORDER BY host_id, id DESC"#,
+        limit
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut histories: HashMap<_, Vec<_>> = HashMap::new();
+    for report in reports {
+        histories
+            .entry(report.host_id)
+            .or_default()
+            .push(api::Deployment {
+                store_path: report.store_path,
+                success: report.success,
+                error: report.error,
+                time: report.report_time.to_jiff(),
+            });
+    }
+    Ok(histories)
+}
+
+error_set::error_set! {
+    RenameError := {
+        #[display("Another host is already called {hostname}")]
+        HostnameTaken {
+            hostname: String,
+        },
+        SQLXError(sqlx::Error),
+    }
 }
 
+/// Hostnames identify hosts for secrets and updates. Two hosts must never share one
 pub async fn rename(
     conn: &mut sqlx::SqliteConnection,
     id: api::HostID,
     new: String,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<(), RenameError> {
+    let renamed = sqlx::query!(
         r#"
         UPDATE hosts
         SET hostname = $1
@@ -268,8 +489,14 @@ pub async fn rename(
         id
     )
     .execute(conn)
-    .await?;
-    Ok(())
+    .await;
+    match renamed {
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            Err(RenameError::HostnameTaken { hostname: new })
+        }
+        Err(err) => Err(err.into()),
+        Ok(_) => Ok(()),
+    }
 }
 
 pub async fn ping(conn: &mut sqlx::SqliteConnection, id: api::HostID) -> Result<(), sqlx::Error> {
@@ -287,6 +514,28 @@ pub async fn ping(conn: &mut sqlx::SqliteConnection, id: api::HostID) -> Result<
     Ok(())
 }
 
+/// Replaces what the host reported to be soaking. `None` once it stopped
+pub async fn set_soaking(
+    conn: &mut sqlx::SqliteConnection,
+    id: api::HostID,
+    soaking: Option<&api::Soak>,
+) -> Result<(), sqlx::Error> {
+    let store_path = soaking.map(|soak| soak.store_path.as_str());
+    let until = soaking.map(|soak| soak.until.to_sqlx());
+    sqlx::query!(
+        r#"
+        UPDATE hosts
+        SET soaking_store_path = $1, soaking_until = $2
+        WHERE id = $3"#,
+        store_path,
+        until,
+        id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
 pub async fn host_by_verify_key(
     conn: &mut sqlx::SqliteConnection,
     key: VerifyingKey,
@@ -303,6 +552,49 @@ pub async fn host_by_verify_key(
     .await
 }
 
+pub async fn host_info_by_key(
+    conn: &mut sqlx::SqliteConnection,
+    key: VerifyingKey,
+) -> Result<Option<api::HostInfo>, sqlx::Error> {
+    let key = &key.as_bytes()[..];
+    sqlx::query_as!(
+        api::HostInfo,
+        r#"
+        SELECT hosts.id as "id: api::HostID", hostname FROM hosts
+        JOIN keys on hosts.key_id = keys.id
+        WHERE verifying_key = $1"#,
+        key
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+pub async fn fetch_hostname(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(r#"SELECT hostname FROM hosts WHERE id = $1"#, host)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Id, hostname and last ping of every host
+pub async fn last_pings(
+    conn: &mut sqlx::SqliteConnection,
+) -> Result<Vec<(api::HostID, String, jiff::Timestamp)>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT
+            id AS "id: api::HostID",
+            hostname,
+            last_ping AS "last_ping: jiff_sqlx::Timestamp"
+        FROM hosts"#
+    )
+    .map(|row| (row.id, row.hostname, row.last_ping.to_jiff()))
+    .fetch_all(conn)
+    .await
+}
+
 pub async fn host_by_hostname(
     conn: &mut sqlx::SqliteConnection,
     hostname: &str,
@@ -317,6 +609,78 @@ pub async fn host_by_hostname(
     .await
 }
 
+error_set::error_set! {
+    RecipientError := {
+        #[display("This key is not enrolled as a host")]
+        NotEnrolled,
+        #[display("The recipient does not match the recipient this host enrolled with")]
+        Mismatch,
+        SQLXError(sqlx::Error),
+    }
+}
+
+/// Pin the age recipient of a host. Secrets are only ever encrypted for this recipient
+pub async fn set_recipient(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+    recipient: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE hosts SET recipient = $1 WHERE id = $2"#,
+        recipient,
+        host
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Make sure that `recipient` is the recipient the host enrolled with.
+/// Hosts enrolled before recipients were pinned or imported without one have none yet.
+/// The request is signed by the host so the first recipient it sends is pinned.
+/// Pinned hosts are only read, the write lock is only taken to pin
+pub async fn check_recipient(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+    recipient: &age::x25519::Recipient,
+) -> Result<(), RecipientError> {
+    let recipient = recipient.to_string();
+    if let Some(enrolled) = enrolled_recipient(conn, host).await? {
+        return if enrolled == recipient {
+            Ok(())
+        } else {
+            Err(RecipientError::Mismatch)
+        };
+    }
+
+    let pinned = sqlx::query!(
+        r#"UPDATE hosts SET recipient = $1 WHERE id = $2 AND recipient IS NULL"#,
+        recipient,
+        host
+    )
+    .execute(&mut *conn)
+    .await?;
+    if pinned.rows_affected() == 1 {
+        return Ok(());
+    }
+    // another request pinned a recipient in between
+    match enrolled_recipient(conn, host).await? {
+        Some(enrolled) if enrolled == recipient => Ok(()),
+        _ => Err(RecipientError::Mismatch),
+    }
+}
+
+/// `NotEnrolled` if the host does not exist, `None` if it has no recipient yet
+async fn enrolled_recipient(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+) -> Result<Option<String>, RecipientError> {
+    sqlx::query_scalar!(r#"SELECT recipient FROM hosts WHERE id = $1"#, host)
+        .fetch_optional(conn)
+        .await?
+        .ok_or(RecipientError::NotEnrolled)
+}
+
 pub async fn add_host(
     conn: &mut sqlx::SqliteConnection,
     key: VerifyingKey,
@@ -332,8 +696,8 @@ pub async fn add_host(
 
     let host = sqlx::query!(
         r#"
-        INSERT INTO hosts (hostname, last_ping, key_id)
-        VALUES ($1, $2, $3)"#,
+        INSERT INTO hosts (hostname, last_ping, key_id, enrolled_at)
+        VALUES ($1, $2, $3, $2)"#,
         hostname,
         now,
         key
@@ -345,9 +709,398 @@ pub async fn add_host(
     Ok(api::HostID::new(host.last_insert_rowid()))
 }
 
+/// Remove a host and everything referencing it.
+/// Tags are not bound by a foreign key. Without removing them a new host reusing the id
+/// would inherit the tags and with them the access of restricted users
+pub async fn remove_host(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let resource_type = api::tag::ResourceType::Host;
+
+    sqlx::query!(
+        r#"DELETE FROM resource_tags WHERE resource_type = $1 AND resource_id = $2"#,
+        resource_type,
+        host
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // deleting the key propagates to the host, its secret acl and its history
+    sqlx::query!(
+        r#"DELETE FROM keys WHERE id = (SELECT key_id FROM hosts WHERE id = $1)"#,
+        host
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
 // pub async fn add_version(conn: &mut sqlx::SqliteConnection, host: HostID,store_path) -> Result<()> {
 //     sqlx::query!(r#"DELETE FROM hosts WHERE id = $1"#, host)
 //         .execute(conn)
 //         .await?;
 //     Ok(())
 // }
+
+#[cfg(test)]
+mod test_hosts {
+    use ed25519_dalek::SigningKey;
+
+    use crate::db::{self, hosts::RenameError};
+
+    #[sqlx::test]
+    async fn detach_permission(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let host = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "host".to_owned(),
+        )
+        .await
+        .unwrap();
+
+        // every host may detach unless an admin says otherwise
+        assert_eq!(
+            db::hosts::detach_permission(&mut conn, host).await.unwrap(),
+            api::DetachPermission {
+                global: true,
+                host_specific: None,
+                effective: true
+            }
+        );
+
+        for (global, host_specific, effective) in [
+            (false, Some(false), false),
+            (false, Some(true), true),
+            (true, Some(false), true),
+            (true, Some(true), true),
+            (false, None, false),
+        ] {
+            db::hosts::set_global_detach(&mut conn, global)
+                .await
+                .unwrap();
+            db::hosts::set_host_detach(&mut conn, host, host_specific)
+                .await
+                .unwrap();
+            assert_eq!(
+                db::hosts::detach_permission(&mut conn, host).await.unwrap(),
+                api::DetachPermission {
+                    global,
+                    host_specific,
+                    effective
+                }
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn rename_to_taken_hostname(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let first = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "first".to_owned(),
+        )
+        .await
+        .unwrap();
+        let second = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[2; 32]).verifying_key(),
+            "second".to_owned(),
+        )
+        .await
+        .unwrap();
+
+        let err = db::hosts::rename(&mut conn, second, "first".to_owned()).await;
+        assert!(matches!(err, Err(RenameError::HostnameTaken { hostname }) if hostname == "first"));
+        assert_eq!(
+            db::hosts::host_by_hostname(&mut conn, "first")
+                .await
+                .unwrap(),
+            Some(first)
+        );
+        assert_eq!(
+            db::hosts::host_by_hostname(&mut conn, "second")
+                .await
+                .unwrap(),
+            Some(second)
+        );
+
+        db::hosts::rename(&mut conn, second, "third".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(
+            db::hosts::host_by_hostname(&mut conn, "third")
+                .await
+                .unwrap(),
+            Some(second)
+        );
+    }
+
+    #[sqlx::test]
+    async fn rename_keeps_tags(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let host = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "host-a".to_owned(),
+        )
+        .await
+        .unwrap();
+        let tag = db::tag::create_tag(&mut conn, "mytag".to_owned())
+            .await
+            .unwrap();
+        db::tag::add_resource_to_tag(&mut conn, host.into(), tag)
+            .await
+            .unwrap();
+        let user = db::user::create_user(
+            &mut conn,
+            "userkey".to_owned(),
+            SigningKey::from_bytes(&[3; 32]).verifying_key(),
+            "restricted".to_owned(),
+            api::AuthLevel::Admin,
+            false,
+        )
+        .await
+        .unwrap();
+        db::tag::allow_user_on_tag(&mut conn, user, tag)
+            .await
+            .unwrap();
+
+        db::hosts::rename(&mut conn, host, "host-b".to_owned())
+            .await
+            .unwrap();
+
+        // tags reference the host id, the hostname is not part of the policy
+        db::tag::auth_tag(&mut conn, user, host.into())
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn remove_host(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let encrypted = age::encrypt(&store_key.to_public(), b"my-secret").unwrap();
+        let secret = db::secrets::add_secret(&mut conn, "my-secret", encrypted, &store_key, false)
+            .await
+            .unwrap();
+        let host_key = SigningKey::from_bytes(&[1; 32]).verifying_key();
+        let host = db::hosts::add_host(&mut conn, host_key, "myhost".to_owned())
+            .await
+            .unwrap();
+        let tag = db::tag::create_tag(&mut conn, "mytag".to_owned())
+            .await
+            .unwrap();
+        db::tag::add_resource_to_tag(&mut conn, host.into(), tag)
+            .await
+            .unwrap();
+        db::secrets::add_access_for(&mut conn, secret.id, host)
+            .await
+            .unwrap();
+        // only sees hosts tagged with `mytag`
+        let user = db::user::create_user(
+            &mut conn,
+            "userkey".to_owned(),
+            SigningKey::from_bytes(&[3; 32]).verifying_key(),
+            "restricted".to_owned(),
+            api::AuthLevel::Admin,
+            false,
+        )
+        .await
+        .unwrap();
+        db::tag::allow_user_on_tag(&mut conn, user, tag)
+            .await
+            .unwrap();
+
+        db::hosts::remove_host(&mut conn, host).await.unwrap();
+
+        assert_eq!(
+            db::hosts::host_by_verify_key(&mut conn, host_key)
+                .await
+                .unwrap(),
+            None
+        );
+        let tagged = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) FROM resource_tags WHERE resource_type = 'Host'"#
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        assert_eq!(tagged, 0);
+        let acl = sqlx::query_scalar!(r#"SELECT COUNT(*) FROM secrets_acl"#)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(acl, 0);
+
+        // a new host reusing the id starts without tags
+        let new_host = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[2; 32]).verifying_key(),
+            "newhost".to_owned(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(new_host, host);
+        db::tag::auth_tag(&mut conn, user, new_host.into())
+            .await
+            .unwrap_err();
+    }
+
+    #[sqlx::test]
+    async fn activation_reports(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let host = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "host-a".to_owned(),
+        )
+        .await
+        .unwrap();
+
+        db::hosts::add_activation_report(
+            &mut conn,
+            host,
+            api::ActivationReport {
+                store_path: "/nix/store/good".to_owned(),
+                success: true,
+                error: None,
+            },
+        )
+        .await
+        .unwrap();
+        db::hosts::add_activation_report(
+            &mut conn,
+            host,
+            api::ActivationReport {
+                store_path: "/nix/store/bad".to_owned(),
+                success: false,
+                error: Some("activation failed".to_owned()),
+            },
+        )
+        .await
+        .unwrap();
+
+        let reports = sqlx::query!(
+            r#"
+            SELECT store_path, success AS "success: bool", error
+            FROM activation_reports
+            WHERE host_id = $1
+            ORDER BY id"#,
+            host
+        )
+        .map(|row| api::ActivationReport {
+            store_path: row.store_path,
+            success: row.success,
+            error: row.error,
+        })
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+        assert_eq!(
+            reports,
+            vec![
+                api::ActivationReport {
+                    store_path: "/nix/store/good".to_owned(),
+                    success: true,
+                    error: None,
+                },
+                api::ActivationReport {
+                    store_path: "/nix/store/bad".to_owned(),
+                    success: false,
+                    error: Some("activation failed".to_owned()),
+                },
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn deploy_history_is_bounded(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let host = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "host-a".to_owned(),
+        )
+        .await
+        .unwrap();
+        let quiet = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[2; 32]).verifying_key(),
+            "host-b".to_owned(),
+        )
+        .await
+        .unwrap();
+
+        for generation in 0..8 {
+            db::hosts::add_activation_report(
+                &mut conn,
+                host,
+                api::ActivationReport {
+                    store_path: format!("/nix/store/{generation}"),
+                    success: generation != 6,
+                    error: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut histories = db::hosts::deploy_histories(&mut conn).await.unwrap();
+        assert!(!histories.contains_key(&quiet));
+        let history = histories.remove(&host).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|deployment| deployment.store_path.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "/nix/store/7",
+                "/nix/store/6",
+                "/nix/store/5",
+                "/nix/store/4",
+                "/nix/store/3"
+            ]
+        );
+        assert_eq!(history.len(), api::DEPLOY_HISTORY);
+    }
+
+    #[sqlx::test]
+    async fn facter_keeps_latest(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let host = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "host-a".to_owned(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            db::hosts::fetch_facter(&mut conn, host).await.unwrap(),
+            None
+        );
+
+        db::hosts::set_facter(&mut conn, host, "enrolled")
+            .await
+            .unwrap();
+        db::hosts::set_facter(&mut conn, host, "new disk")
+            .await
+            .unwrap();
+
+        let facter = db::hosts::fetch_facter(&mut conn, host)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(facter.nixos_facter, "new disk");
+        let reports = sqlx::query_scalar!(r#"SELECT COUNT(*) FROM host_facter"#)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(reports, 1);
+    }
+}
@@ -2,7 +2,7 @@ use ed25519_dalek::VerifyingKey;
 
 pub async fn fetch_by_keyid(
     conn: &mut sqlx::SqliteConnection,
-    keyid: &String,
+    keyid: &str,
 ) -> Result<Option<VerifyingKey>, sqlx::Error> {
     let key = sqlx::query_scalar!(
         r#"
@@ -0,0 +1,165 @@
+//! Repairs for a database that was changed out-of-band e.g. with foreign keys disabled
+
+use sqlx::Acquire as _;
+
+/// Removes acl entries whose secret or host no longer exists and tags of removed resources.
+/// Known hosts and secrets are the ones in the database
+pub async fn prune_orphans(
+    conn: &mut sqlx::SqliteConnection,
+) -> Result<api::PruneReport, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+
+    let acl = sqlx::query!(
+        r#"
+        DELETE FROM secrets_acl
+        WHERE secret_id NOT IN (SELECT id FROM secrets)
+           OR host_id NOT IN (SELECT id FROM hosts)
+        RETURNING secret_id AS "secret: api::SecretID", host_id AS "host: api::HostID""#
+    )
+    .map(|row| api::OrphanedAcl {
+        secret: row.secret,
+        host: row.host,
+    })
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let host = api::tag::ResourceType::Host;
+    let secret = api::tag::ResourceType::Secret;
+    let tags = sqlx::query!(
+        r#"
+        DELETE FROM resource_tags
+        WHERE (resource_type = $1 AND resource_id NOT IN (SELECT id FROM hosts))
+           OR (resource_type = $2 AND resource_id NOT IN (SELECT id FROM secrets))
+        RETURNING
+            resource_id,
+            resource_type AS "resource_type: api::tag::ResourceType",
+            tag_id AS "tag: api::tag::TagID""#,
+        host,
+        secret
+    )
+    .map(|row| api::OrphanedTag {
+        resource: row.resource_type.with_id(row.resource_id),
+        tag: row.tag,
+    })
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(api::PruneReport { acl, tags })
+}
+
+#[cfg(test)]
+mod test_maintenance {
+    use ed25519_dalek::SigningKey;
+
+    use crate::db;
+
+    #[sqlx::test]
+    async fn prune_inconsistent_store(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let secret = |name: &'static str| {
+            let encrypted = age::encrypt(&store_key.to_public(), name.as_bytes()).unwrap();
+            (name, encrypted)
+        };
+        let (kept_name, kept_secret) = secret("kept");
+        let (removed_name, removed_secret) = secret("removed");
+        let kept = db::secrets::add_secret(&mut conn, kept_name, kept_secret, &store_key, false)
+            .await
+            .unwrap()
+            .id;
+        let removed =
+            db::secrets::add_secret(&mut conn, removed_name, removed_secret, &store_key, false)
+                .await
+                .unwrap()
+                .id;
+        let kept_host = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "kept".to_owned(),
+        )
+        .await
+        .unwrap();
+        let removed_host = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[2; 32]).verifying_key(),
+            "removed".to_owned(),
+        )
+        .await
+        .unwrap();
+        let tag = db::tag::create_tag(&mut conn, "prod".to_owned())
+            .await
+            .unwrap();
+        for secret in [kept, removed] {
+            for host in [kept_host, removed_host] {
+                db::secrets::add_access_for(&mut conn, secret, host)
+                    .await
+                    .unwrap();
+            }
+            db::tag::add_resource_to_tag(&mut conn, secret.into(), tag)
+                .await
+                .unwrap();
+        }
+
+        // nothing to prune in a consistent store
+        let report = db::maintenance::prune_orphans(&mut conn).await.unwrap();
+        assert!(report.is_empty());
+
+        // removed out-of-band without the cascades
+        sqlx::query("PRAGMA foreign_keys = OFF")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query!(r#"DELETE FROM secrets WHERE id = $1"#, removed)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query!(r#"DELETE FROM hosts WHERE id = $1"#, removed_host)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query("PRAGMA foreign_keys = ON")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+
+        let mut report = db::maintenance::prune_orphans(&mut conn).await.unwrap();
+        report.acl.sort_by_key(|acl| (acl.secret, acl.host));
+        assert_eq!(
+            report,
+            api::PruneReport {
+                acl: vec![
+                    api::OrphanedAcl {
+                        secret: kept,
+                        host: removed_host
+                    },
+                    api::OrphanedAcl {
+                        secret: removed,
+                        host: kept_host
+                    },
+                    api::OrphanedAcl {
+                        secret: removed,
+                        host: removed_host
+                    },
+                ],
+                tags: vec![api::OrphanedTag {
+                    resource: removed.into(),
+                    tag
+                }],
+            }
+        );
+
+        // the consistent part is untouched
+        let acl = sqlx::query_scalar!(r#"SELECT COUNT(*) FROM secrets_acl"#)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(acl, 1);
+        assert!(
+            db::maintenance::prune_orphans(&mut conn)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
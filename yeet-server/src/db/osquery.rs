@@ -6,13 +6,15 @@ use osquery_tls::LogType;
 use sqlx::Acquire as _;
 use uuid::Uuid;
 
+use crate::{db, store_key::StoreKey};
+
 error_set::error_set! {
     EnrollError := {
         #[display("Enroll secret not set or does not match")]
         SecretMismatch,
         #[display("Enroll secret is not yet set")]
         SecretNotSet,
-        Decrypt(age::DecryptError),
+        Open(db::secrets::OpenSecretError),
         SQLXE(sqlx::Error),
     }
 }
@@ -103,21 +105,26 @@ pub async fn create_query(
 
 /// The node needs to provide the same content as the `osquery-enroll` secret
 /// As a response the ode receives an unique `UUIDv7` this is the nodes `node_key`
-pub async fn enroll_node<I: age::Identity>(
+pub async fn enroll_node<K: StoreKey + ?Sized>(
     conn: &mut sqlx::SqliteConnection,
-    store_key: &I,
+    store_key: &K,
     enroll_request: osquery_tls::EnrollmentRequest,
 ) -> Result<Uuid, EnrollError> {
-    // we hardcode the name of the enroll secret
-    let Some(enroll_secret) =
-        sqlx::query_scalar!(r#"SELECT secret FROM secrets WHERE name = "osquery-enroll""#)
-            .fetch_optional(&mut *conn)
-            .await?
-    else {
+    // we hardcode the name of the enroll secret. It may be an alias
+    let Some(id) = db::secrets::secret_by_name(conn, "osquery-enroll").await? else {
         return Err(EnrollError::SecretNotSet);
     };
+    let enroll_secret = sqlx::query!(
+        r#"
+        SELECT secret, compressed AS "compressed: bool" FROM secrets
+        WHERE id = COALESCE((SELECT target_id FROM secret_aliases WHERE secret_id = $1), $1)"#,
+        id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
 
-    let enroll_secret = age::decrypt(store_key, &enroll_secret)?;
+    let enroll_secret =
+        db::secrets::open_secret(store_key, &enroll_secret.secret, enroll_secret.compressed)?;
 
     if Some(String::from_utf8_lossy(&enroll_secret).to_string()) != enroll_request.enroll_secret {
         return Err(EnrollError::SecretMismatch);
@@ -389,6 +396,20 @@ mod test {
 
     use crate::db;
 
+    fn enrollment(enroll_secret: &str) -> osquery_tls::EnrollmentRequest {
+        osquery_tls::EnrollmentRequest {
+            enroll_secret: Some(enroll_secret.to_owned()),
+            host_identifier: "unique-host".into(),
+            host_details: osquery_tls::EnrollmentHostDetails {
+                os_version: HashMap::new(),
+                osquery_info: HashMap::new(),
+                system_info: HashMap::new(),
+                platform_info: HashMap::new(),
+            },
+            platform_type: "9".into(),
+        }
+    }
+
     #[sqlx::test]
     async fn enroll_new_node(pool: sqlx::SqlitePool) {
         let mut conn = crate::sql_conn(pool).await;
@@ -398,26 +419,32 @@ mod test {
         let encrypted = age::encrypt(&store_key.to_public(), b"my-secret-enroll-secret").unwrap();
 
         let _enroll_secret =
-            db::secrets::add_secret(&mut conn, "osquery-enroll", encrypted, &store_key)
+            db::secrets::add_secret(&mut conn, "osquery-enroll", encrypted, &store_key, false)
                 .await
                 .unwrap();
 
-        db::osquery::enroll_node(
-            &mut conn,
-            &store_key,
-            osquery_tls::EnrollmentRequest {
-                enroll_secret: Some("my-secret-enroll-secret".to_owned()),
-                host_identifier: "unique-host".into(),
-                host_details: osquery_tls::EnrollmentHostDetails {
-                    os_version: HashMap::new(),
-                    osquery_info: HashMap::new(),
-                    system_info: HashMap::new(),
-                    platform_info: HashMap::new(),
-                },
-                platform_type: "9".into(),
-            },
-        )
-        .await
-        .unwrap();
+        db::osquery::enroll_node(&mut conn, &store_key, enrollment("my-secret-enroll-secret"))
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn enroll_through_alias(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+
+        let store_key = age::x25519::Identity::generate();
+
+        let encrypted = age::encrypt(&store_key.to_public(), b"my-secret-enroll-secret").unwrap();
+        let target =
+            db::secrets::add_secret(&mut conn, "fleet-enroll", encrypted, &store_key, false)
+                .await
+                .unwrap();
+        db::secrets::add_alias(&mut conn, "osquery-enroll".to_owned(), target.id)
+            .await
+            .unwrap();
+
+        db::osquery::enroll_node(&mut conn, &store_key, enrollment("my-secret-enroll-secret"))
+            .await
+            .unwrap();
     }
 }
@@ -4,6 +4,11 @@
 //! test if the host is allowed to access the secret and if true will decrypt the
 //! secret and re-encrypt it for the host. This ensures encryption at rest and
 //! handles ACLs
+//! With replicas (see `store_key::StoreKeys`) secrets are encrypted for the store key of each replica
+//! An alias (see `add_alias`) is a secret with its own name, tags and acl that shares the content
+//! of its target. Reading or rotating the content of an alias uses the target
+//! A sealed secret (see `add_sealed_secret`) is encrypted for a single host by the client. It is
+//! stored opaquely and handed out verbatim, so the server can never read it
 //!
 //! A possible hardening method would to instead use a single server key to encrypt the secrets
 //! encrypt them with all the hosts that have currently access. The contra is that
@@ -11,33 +16,137 @@
 //! because an attack would need to also obtain the identity key of a hosts that
 //! has access to the secrets
 
-use sqlx::types::Json;
+use sqlx::{Acquire as _, types::Json};
+
+use crate::store_key::StoreKey;
 
 error_set::error_set! {
-    AddSecretError := {
+    SealSecretError := {
+        #[display("Could not compress the secret: {0}")]
+        Compress(std::io::Error),
+        #[display("Could not encrypt the secret for the store recipients: {0}")]
+        Recipient(api::RecipientError),
+        Encrypt(age::EncryptError),
+    }
+    AddSecretError := SealSecretError || {
         #[display("Secret is not encrytped")]
         UnencryptedSecretError(age::DecryptError),
         SQLXError(sqlx::Error),
     }
+    AddSealedSecretError := {
+        #[display("Secret is not an age file: {0}")]
+        NotAgeFile(age::DecryptError),
+        SQLXError(sqlx::Error),
+    }
+    RotateSecretError := AddSecretError || {
+        #[display("Secret does not exist")]
+        SecretNotFound,
+        #[display("A sealed secret can not be rotated. Remove and add it again")]
+        Sealed,
+    }
+    OpenSecretError := {
+        #[display("Secret can not be decrypted with the store key: {0}")]
+        Decrypt(age::DecryptError),
+        #[display("Could not decompress the secret: {0}")]
+        Decompress(std::io::Error),
+    }
+    GetSecretError := OpenSecretError || {
+        #[display("Could not encrypt the secret for the target: {0}")]
+        Encrypt(age::EncryptError),
+        SQLX(sqlx::Error),
+    }
+    CheckSecretError := OpenSecretError || {
+        #[display("Secret does not exist")]
+        SecretNotFound,
+        SQLXError(sqlx::Error),
+    }
+    ContentTypeError := {
+        #[display("Secret does not exist")]
+        SecretNotFound,
+        #[display("Invalid content type `{content_type}`. Use a short label like `pem`, `env` or `json`")]
+        Invalid { content_type: String },
+        SQLXError(sqlx::Error),
+    }
+    AliasError := {
+        #[display("Secret does not exist")]
+        SecretNotFound,
+        #[display("Secret is not an alias")]
+        NotAnAlias,
+        SQLXError(sqlx::Error),
+    }
+}
+
+/// Prepares a secret the client encrypted for the store key to be stored.
+/// It is encrypted again if it gets compressed or if other replicas have to be able to read it.
+/// Returns the ciphertext to store and whether it is compressed
+fn seal_secret<K: StoreKey + ?Sized>(
+    store_key: &K,
+    ciphertext: Vec<u8>,
+    plaintext: &[u8],
+    compress: bool,
+) -> Result<(Vec<u8>, bool), SealSecretError> {
+    let recipients = store_key.recipients();
+    let encrypt = |content: &[u8]| -> Result<Vec<u8>, SealSecretError> {
+        let recipients = recipients
+            .iter()
+            .map(|recipient| api::parse_recipient(recipient))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(api::encrypt_for_all(
+            recipients
+                .iter()
+                .map(|recipient| &**recipient as &dyn age::Recipient),
+            content,
+        )?)
+    };
+
+    if compress {
+        let compressed = zstd::encode_all(plaintext, 0)?;
+        // only worth it if compressing makes the secret smaller
+        if compressed.len() < plaintext.len() {
+            return Ok((encrypt(&compressed)?, true));
+        }
+    }
+    if recipients.len() > 1 {
+        return Ok((encrypt(plaintext)?, false));
+    }
+    Ok((ciphertext, false))
+}
+
+/// Decrypts a stored secret and undoes the compression
+pub fn open_secret<K: StoreKey + ?Sized>(
+    store_key: &K,
+    secret: &[u8],
+    compressed: bool,
+) -> Result<Vec<u8>, OpenSecretError> {
+    let plaintext = store_key.decrypt(secret)?;
+    if compressed {
+        Ok(zstd::decode_all(plaintext.as_slice())?)
+    } else {
+        Ok(plaintext)
+    }
 }
 
 /// The secrets needs to be encrypted with the servers identity key
 /// retrieve it with GET `/secret/server_key`
 /// Add a new secret - `store_key` required to test if it is an actual encrypted secret and not bogus
-pub async fn add_secret<I: age::Identity, S: Into<String>, V: Into<Vec<u8>>>(
+/// With `compress` the plaintext is stored zstd compressed if that makes it smaller
+pub async fn add_secret<K: StoreKey + ?Sized, S: Into<String>, V: Into<Vec<u8>>>(
     conn: &mut sqlx::SqliteConnection,
     name: S,
     secret: V,
-    store_key: &I,
+    store_key: &K,
+    compress: bool,
 ) -> Result<api::SecretName, AddSecretError> {
     let secret = secret.into();
     let name = name.into();
     // test if secret is decryptable
-    let _: Vec<u8> = age::decrypt(store_key, &secret)?;
+    let plaintext = store_key.decrypt(&secret)?;
+    let (secret, compressed) = seal_secret(store_key, secret, &plaintext, compress)?;
     let row = sqlx::query!(
-        r#"INSERT INTO secrets (name, secret) VALUES ($1, $2)"#,
+        r#"INSERT INTO secrets (name, secret, compressed) VALUES ($1, $2, $3)"#,
         name,
-        secret
+        secret,
+        compressed
     )
     .execute(conn)
     .await?;
@@ -46,31 +155,150 @@ pub async fn add_secret<I: age::Identity, S: Into<String>, V: Into<Vec<u8>>>(
         name,
         tags: Vec::new(),
         hosts: Vec::new(),
+        content_type: None,
     })
 }
 
-error_set::error_set! {
-    GetSecretError := {
-        #[display("Could not encrypt the secret for the target: {0}")]
-        Encrypt(age::EncryptError),
-        Decrypt(age::DecryptError),
-        SQLX(sqlx::Error),
+/// Add a secret the client encrypted for the recipient `host` enrolled with.
+/// It is stored as is and only `host` can fetch it, so it is added to the acl right away
+pub async fn add_sealed_secret<S: Into<String>, V: Into<Vec<u8>>>(
+    conn: &mut sqlx::SqliteConnection,
+    name: S,
+    secret: V,
+    host: api::HostID,
+) -> Result<api::SecretName, AddSealedSecretError> {
+    let secret = secret.into();
+    let name = name.into();
+    // the server can not decrypt it. Only test that it is not bogus
+    age::Decryptor::new(secret.as_slice())?;
+
+    let mut tx = conn.begin().await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO secrets (name, secret, sealed_to) VALUES ($1, $2, $3)
+        RETURNING id AS "id: api::SecretID""#,
+        name,
+        secret,
+        host
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"INSERT INTO secrets_acl (secret_id, host_id) VALUES ($1,$2)"#,
+        id,
+        host
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(api::SecretName {
+        id,
+        name,
+        tags: Vec::new(),
+        hosts: vec![host],
+        content_type: None,
+    })
+}
+
+/// Content types are labels like `pem` or `env`. Anything else is rejected so they are safe to
+/// show and to put into a url path
+#[must_use]
+pub fn valid_content_type(content_type: &str) -> bool {
+    (1..=32).contains(&content_type.len())
+        && content_type.bytes().all(|byte| {
+            byte.is_ascii_lowercase() || byte.is_ascii_digit() || b"+-.".contains(&byte)
+        })
+}
+
+/// Sets or with `None` removes the content type of `secret`. Setting it on an alias is allowed
+/// but has no effect since aliases have the content type of their target
+pub async fn set_content_type(
+    conn: &mut sqlx::SqliteConnection,
+    secret: api::SecretID,
+    content_type: Option<&str>,
+) -> Result<(), ContentTypeError> {
+    if let Some(content_type) = content_type
+        && !valid_content_type(content_type)
+    {
+        return Err(ContentTypeError::Invalid {
+            content_type: content_type.to_owned(),
+        });
+    }
+    let row = sqlx::query!(
+        r#"UPDATE secrets SET content_type = $1 WHERE id = $2"#,
+        content_type,
+        secret
+    )
+    .execute(conn)
+    .await?;
+    if row.rows_affected() == 0 {
+        return Err(ContentTypeError::SecretNotFound);
+    }
+    Ok(())
+}
+
+/// The secret `alias` points to. `None` if it is not an alias
+pub async fn alias_target(
+    conn: &mut sqlx::SqliteConnection,
+    alias: api::SecretID,
+) -> Result<Option<api::SecretID>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT target_id AS "target_id: api::SecretID" FROM secret_aliases WHERE secret_id = $1"#,
+        alias
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+/// Replace the content of a secret. Name, tags and acl are kept
+/// Rotating an alias replaces the content of its target. Sealed secrets can not be rotated
+/// Security: The caller has to be authorized for the target (see `alias_target`)
+/// `store_key` required to test if it is an actual encrypted secret and not bogus
+pub async fn rotate_secret<K: StoreKey + ?Sized, V: Into<Vec<u8>>>(
+    conn: &mut sqlx::SqliteConnection,
+    id: api::SecretID,
+    secret: V,
+    store_key: &K,
+    compress: bool,
+) -> Result<(), RotateSecretError> {
+    let secret = secret.into();
+    // test if secret is decryptable
+    let plaintext = store_key.decrypt(&secret)?;
+    let (secret, compressed) = seal_secret(store_key, secret, &plaintext, compress)?;
+    let row = sqlx::query!(
+        r#"
+        UPDATE secrets SET secret = $1, compressed = $2
+        WHERE id = COALESCE((SELECT target_id FROM secret_aliases WHERE secret_id = $3), $3)
+            AND sealed_to IS NULL"#,
+        secret,
+        compressed,
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+    if row.rows_affected() == 0 {
+        return Err(if secret_exists(conn, id).await? {
+            RotateSecretError::Sealed
+        } else {
+            RotateSecretError::SecretNotFound
+        });
     }
+    Ok(())
 }
 
-/// Security: the caller is responsible to make sure that `recipient` equals `host`
-///     If this identity is not verified a malicious actor could insert his identity
-///     and retrieve a secret that is checked by another acl
-///     This is because each identity is thought to be ephemeral
+/// Security: `host` has to be derived from the verified key of the caller (see `httpsig::Host`)
+///     and never from the request itself. `recipient` has to be the recipient the host
+///     enrolled with (see `db::hosts::check_recipient`)
 /// Prepares a secret for a host by decrypting and the encrypting it
-/// Returns `Ok(None)` if the host is not allowed to access the secret or if the secret does not exist
-pub async fn get_secret_for<R: age::Recipient, I: age::Identity>(
+/// Tells apart secrets that do not exist from secrets the host is not allowed to access
+pub async fn get_secret_for<R: age::Recipient, K: StoreKey + ?Sized>(
     conn: &mut sqlx::SqliteConnection,
     secret: &str,
-    store_key: &I,
+    store_key: &K,
     host: api::HostID,
     recipient: &R,
-) -> Result<Option<Vec<u8>>, GetSecretError> {
+) -> Result<api::SecretLookup, GetSecretError> {
     // TODO transaction so that no TOCTOU can occur
 
     let Some(secret) = sqlx::query_scalar!(
@@ -80,21 +308,79 @@ pub async fn get_secret_for<R: age::Recipient, I: age::Identity>(
     .fetch_optional(&mut *conn)
     .await?
     else {
-        return Ok(None);
+        return Ok(api::SecretLookup::NotFound);
     };
 
     // return if the host has no access
     if !check_acl(conn, secret, host).await? {
-        return Ok(None);
+        return Ok(api::SecretLookup::NoAccess);
     }
 
     // since we checked the acl this means that the secret has to exist
-    let secret = sqlx::query_scalar!(r#"SELECT secret FROM secrets WHERE id = $1"#, secret)
-        .fetch_one(conn)
-        .await?;
+    // and removing a target also removes its aliases
+    let secret = sqlx::query!(
+        r#"
+        SELECT
+            secret,
+            compressed AS "compressed: bool",
+            sealed_to AS "sealed_to: api::HostID",
+            content_type
+        FROM secrets
+        WHERE id = COALESCE((SELECT target_id FROM secret_aliases WHERE secret_id = $1), $1)"#,
+        secret
+    )
+    .fetch_one(conn)
+    .await?;
 
-    let decrypted = age::decrypt(store_key, &secret)?;
-    Ok(Some(age::encrypt(recipient, &decrypted)?))
+    // sealed secrets are already encrypted for their host. An alias does not widen that
+    match secret.sealed_to {
+        Some(sealed_to) if sealed_to == host => {
+            return Ok(api::SecretLookup::Found {
+                content: secret.secret,
+                content_type: secret.content_type,
+            });
+        }
+        Some(_) => return Ok(api::SecretLookup::NoAccess),
+        None => {}
+    }
+
+    let decrypted = open_secret(store_key, &secret.secret, secret.compressed)?;
+    Ok(api::SecretLookup::Found {
+        content: age::encrypt(recipient, &decrypted)?,
+        content_type: secret.content_type,
+    })
+}
+
+/// Whether the secrets `host` can fetch changed since the last call e.g. because of an acl change.
+/// The first call for a host only remembers them
+pub async fn secrets_changed(
+    conn: &mut sqlx::SqliteConnection,
+    host: api::HostID,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let seen = sqlx::query_scalar!(r#"SELECT secrets_seen FROM hosts WHERE id = $1"#, host)
+        .fetch_one(&mut *tx)
+        .await?;
+    let current = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(group_concat(secret_id, ','), '') AS "ids!: String"
+        FROM (SELECT secret_id FROM secrets_acl WHERE host_id = $1 ORDER BY secret_id)"#,
+        host
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if seen.as_ref() == Some(&current) {
+        return Ok(false);
+    }
+    sqlx::query!(
+        r#"UPDATE hosts SET secrets_seen = $1 WHERE id = $2"#,
+        current,
+        host
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(seen.is_some())
 }
 
 async fn check_acl(
@@ -111,15 +397,28 @@ async fn check_acl(
     .await
 }
 
+error_set::error_set! {
+    AddAccessError := {
+        #[display("Secret does not exist")]
+        SecretNotFound,
+        SQLXError(sqlx::Error),
+    }
+}
+
 /// Adds the specified host to the acl of a secret
-/// Can fail if the secret or host do not exist
+/// Granting access to a host that already has access is a no-op
+/// Fails if the secret does not exist. Can also fail if the host does not exist
 pub async fn add_access_for(
     conn: &mut sqlx::SqliteConnection,
     secret: api::SecretID,
     host: api::HostID,
-) -> Result<(), sqlx::Error> {
+) -> Result<(), AddAccessError> {
+    if !secret_exists(conn, secret).await? {
+        return Err(AddAccessError::SecretNotFound);
+    }
+
     sqlx::query!(
-        r#"INSERT INTO secrets_acl (secret_id, host_id) VALUES ($1,$2)"#,
+        r#"INSERT INTO secrets_acl (secret_id, host_id) VALUES ($1,$2) ON CONFLICT DO NOTHING"#,
         secret,
         host
     )
@@ -128,6 +427,18 @@ pub async fn add_access_for(
     Ok(())
 }
 
+async fn secret_exists(
+    conn: &mut sqlx::SqliteConnection,
+    secret: api::SecretID,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM secrets WHERE id = $1) AS 'exists!: bool'"#,
+        secret
+    )
+    .fetch_one(conn)
+    .await
+}
+
 /// Removes the specified host to the acl of a secret
 pub async fn remove_access_for(
     conn: &mut sqlx::SqliteConnection,
@@ -144,20 +455,139 @@ pub async fn remove_access_for(
     Ok(())
 }
 
-/// Removes the specified host to the acl of a secret
+/// Removes a secret together with its aliases
 pub async fn remove_secret(
     conn: &mut sqlx::SqliteConnection,
     secret: api::SecretID,
 ) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    sqlx::query!(
+        r#"DELETE FROM secrets WHERE id IN (SELECT secret_id FROM secret_aliases WHERE target_id = $1)"#,
+        secret
+    )
+    .execute(&mut *tx)
+    .await?;
     sqlx::query!(r#"DELETE FROM secrets WHERE id = $1"#, secret)
-        .execute(conn)
+        .execute(&mut *tx)
         .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Adds a secret named `name` that serves the content of `target` with its own acl and tags
+/// An alias of an alias points at the final target
+pub async fn add_alias(
+    conn: &mut sqlx::SqliteConnection,
+    name: String,
+    target: api::SecretID,
+) -> Result<api::SecretName, AliasError> {
+    let mut tx = conn.begin().await?;
+    let Some(target) = sqlx::query!(
+        r#"
+        SELECT COALESCE(a.target_id, s.id) AS "id!: api::SecretID", t.content_type
+        FROM secrets s
+        LEFT JOIN secret_aliases a ON a.secret_id = s.id
+        JOIN secrets t ON t.id = COALESCE(a.target_id, s.id)
+        WHERE s.id = $1"#,
+        target
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(AliasError::SecretNotFound);
+    };
+
+    // the content is never read. `get_secret_for` reads the target instead
+    let row = sqlx::query!(
+        r#"INSERT INTO secrets (name, secret) VALUES ($1, x'')"#,
+        name
+    )
+    .execute(&mut *tx)
+    .await?;
+    let id = api::SecretID::new(row.last_insert_rowid());
+    sqlx::query!(
+        r#"INSERT INTO secret_aliases (secret_id, target_id) VALUES ($1, $2)"#,
+        id,
+        target.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(api::SecretName {
+        id,
+        name,
+        tags: Vec::new(),
+        hosts: Vec::new(),
+        content_type: target.content_type,
+    })
+}
+
+/// Removes an alias including its acl. The target is kept
+pub async fn remove_alias(
+    conn: &mut sqlx::SqliteConnection,
+    alias: api::SecretID,
+) -> Result<(), AliasError> {
+    let row = sqlx::query!(
+        r#"DELETE FROM secrets WHERE id = $1 AND id IN (SELECT secret_id FROM secret_aliases)"#,
+        alias
+    )
+    .execute(conn)
+    .await?;
+    if row.rows_affected() == 0 {
+        return Err(AliasError::NotAnAlias);
+    }
     Ok(())
 }
 
 pub async fn list_secrets(
     conn: &mut sqlx::SqliteConnection,
     user: api::UserID,
+) -> Result<Vec<api::SecretName>, sqlx::Error> {
+    query_secrets(conn, user, None, -1, 0).await
+}
+
+/// Secrets of `user` sorted by name, reduced to the page described by `query`
+pub async fn page_secrets(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    query: &api::ListSecretsQuery,
+) -> Result<api::SecretPage, sqlx::Error> {
+    let prefix = query.prefix.as_deref();
+    // a negative limit is no limit in sqlite
+    let limit = query
+        .limit
+        .map_or(-1, |limit| i64::try_from(limit).unwrap_or(i64::MAX));
+    let offset = i64::try_from(query.offset.unwrap_or_default()).unwrap_or(i64::MAX);
+
+    let mut tx = conn.begin().await?;
+    let secrets = query_secrets(&mut tx, user, prefix, limit, offset).await?;
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "total!: i64" FROM secrets s
+        WHERE s.id IN (SELECT resource_id FROM access WHERE resource_type = $2 AND user_id = $1)
+        AND ($3 IS NULL OR substr(s.name, 1, length($3)) = $3)"#,
+        user,
+        api::tag::ResourceType::Secret,
+        prefix
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(api::SecretPage {
+        secrets,
+        total: usize::try_from(total).unwrap_or_default(),
+    })
+}
+
+/// Secrets of `user` whose name starts with `prefix`, sorted by name
+async fn query_secrets(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    prefix: Option<&str>,
+    limit: i64,
+    offset: i64,
 ) -> Result<Vec<api::SecretName>, sqlx::Error> {
     let secrets = sqlx::query!(
         r#"
@@ -171,8 +601,12 @@ pub async fn list_secrets(
             -- Gather hosts authorized for this User+Host via the View
             json_group_array(sacl.host_id)
                 FILTER (WHERE a_h.resource_id IS NOT NULL)
-                as "hosts!: Json<Vec<api::HostID>>"
+                as "hosts!: Json<Vec<api::HostID>>",
+            COALESCE(target.content_type, s.content_type) AS "content_type: String"
         FROM secrets s
+        -- Aliases have the content type of their target
+        LEFT JOIN secret_aliases alias ON alias.secret_id = s.id
+        LEFT JOIN secrets target ON target.id = alias.target_id
 
         -- Join View to find authorized Secrets
         JOIN access a_s
@@ -190,17 +624,24 @@ pub async fn list_secrets(
             ON sacl.host_id = a_h.resource_id
             AND a_h.resource_type = $3
             AND a_h.user_id = $1
+        WHERE $4 IS NULL OR substr(s.name, 1, length($4)) = $4
         GROUP BY s.id, s.name
+        ORDER BY s.name
+        LIMIT $5 OFFSET $6
         "#,
         user,
         api::tag::ResourceType::Secret,
-        api::tag::ResourceType::Host
+        api::tag::ResourceType::Host,
+        prefix,
+        limit,
+        offset
     )
     .map(|row| api::SecretName {
         id: row.id,
         name: row.name,
         tags: row.tags.0,
         hosts: row.hosts.0,
+        content_type: row.content_type,
     })
     .fetch_all(conn)
     .await?;
@@ -208,6 +649,32 @@ pub async fn list_secrets(
     Ok(secrets)
 }
 
+/// Hosts in the acl of `secret`. Only hosts the user has access to are returned
+pub async fn acl_by_secret(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    secret: api::SecretID,
+) -> Result<Vec<api::HostID>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT sacl.host_id as "host_id!: api::HostID"
+        FROM secrets_acl sacl
+        JOIN access a_h
+            ON sacl.host_id = a_h.resource_id
+            AND a_h.resource_type = $3
+            AND a_h.user_id = $1
+        WHERE sacl.secret_id = $2
+        GROUP BY sacl.host_id
+        ORDER BY sacl.host_id
+        "#,
+        user,
+        secret,
+        api::tag::ResourceType::Host
+    )
+    .fetch_all(conn)
+    .await
+}
+
 /// Rename a secret including its acl
 pub async fn rename_secret(
     conn: &mut sqlx::SqliteConnection,
@@ -226,3 +693,1010 @@ pub async fn rename_secret(
     .await?;
     Ok(())
 }
+
+/// Look up a secret by its unique name
+pub async fn secret_by_name(
+    conn: &mut sqlx::SqliteConnection,
+    name: &str,
+) -> Result<Option<api::SecretID>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT id as "id: api::SecretID" FROM secrets WHERE name = $1"#,
+        name
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+/// Explain if `host` could fetch `secret`. Mirrors the checks of `get_secret_for`
+/// without decrypting anything. A host without a recipient pins one on its first fetch
+pub async fn access_for(
+    conn: &mut sqlx::SqliteConnection,
+    secret: api::SecretID,
+    host: api::HostID,
+) -> Result<api::SecretAccess, sqlx::Error> {
+    if !check_acl(conn, secret, host).await? {
+        return Ok(api::SecretAccess::denied("host not in ACL"));
+    }
+    let sealed_to = sqlx::query_scalar!(
+        r#"
+        SELECT sealed_to AS "sealed_to: api::HostID" FROM secrets
+        WHERE id = COALESCE((SELECT target_id FROM secret_aliases WHERE secret_id = $1), $1)"#,
+        secret
+    )
+    .fetch_optional(conn)
+    .await?
+    .flatten();
+    if sealed_to.is_some_and(|sealed_to| sealed_to != host) {
+        return Ok(api::SecretAccess::denied("sealed to another host"));
+    }
+    Ok(api::SecretAccess::allowed())
+}
+
+/// Test if the stored ciphertext can still be decrypted (and decompressed) with `store_key`
+/// The plaintext is discarded immediately. Sealed secrets always pass
+pub async fn check_secret<K: StoreKey + ?Sized>(
+    conn: &mut sqlx::SqliteConnection,
+    secret: api::SecretID,
+    store_key: &K,
+) -> Result<(), CheckSecretError> {
+    let Some(secret) = sqlx::query!(
+        r#"
+        SELECT secret, compressed AS "compressed: bool", sealed_to AS "sealed_to: api::HostID"
+        FROM secrets
+        WHERE id = COALESCE((SELECT target_id FROM secret_aliases WHERE secret_id = $1), $1)"#,
+        secret
+    )
+    .fetch_optional(conn)
+    .await?
+    else {
+        return Err(CheckSecretError::SecretNotFound);
+    };
+    // never encrypted for the store key so changing it does not affect sealed secrets
+    if secret.sealed_to.is_some() {
+        return Ok(());
+    }
+
+    let _: Vec<u8> = open_secret(store_key, &secret.secret, secret.compressed)?;
+    Ok(())
+}
+
+/// Every stored secret including aliases, sorted by name
+pub async fn all_secrets(
+    conn: &mut sqlx::SqliteConnection,
+) -> Result<Vec<(api::SecretID, String)>, sqlx::Error> {
+    Ok(
+        sqlx::query!(r#"SELECT id AS "id: api::SecretID", name FROM secrets ORDER BY name"#)
+            .fetch_all(conn)
+            .await?
+            .into_iter()
+            .map(|row| (row.id, row.name))
+            .collect(),
+    )
+}
+
+/// ACL rows whose secret or host is gone. Foreign keys prevent them unless they were disabled
+/// while the database was changed e.g. by hand
+pub async fn dangling_acl(
+    conn: &mut sqlx::SqliteConnection,
+) -> Result<Vec<(api::SecretID, api::HostID)>, sqlx::Error> {
+    Ok(sqlx::query!(
+        r#"
+        SELECT secret_id AS "secret_id: api::SecretID", host_id AS "host_id: api::HostID"
+        FROM secrets_acl
+        WHERE secret_id NOT IN (SELECT id FROM secrets) OR host_id NOT IN (SELECT id FROM hosts)
+        ORDER BY secret_id, host_id"#
+    )
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .map(|row| (row.secret_id, row.host_id))
+    .collect())
+}
+
+/// Aliases whose own secret or target is gone
+pub async fn dangling_aliases(
+    conn: &mut sqlx::SqliteConnection,
+) -> Result<Vec<(api::SecretID, api::SecretID)>, sqlx::Error> {
+    Ok(sqlx::query!(
+        r#"
+        SELECT secret_id AS "secret_id: api::SecretID", target_id AS "target_id: api::SecretID"
+        FROM secret_aliases
+        WHERE secret_id NOT IN (SELECT id FROM secrets) OR target_id NOT IN (SELECT id FROM secrets)
+        ORDER BY secret_id"#
+    )
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .map(|row| (row.secret_id, row.target_id))
+    .collect())
+}
+
+#[cfg(test)]
+mod test_secrets {
+    use ed25519_dalek::{SigningKey, VerifyingKey};
+
+    use crate::{
+        db::{self, secrets::AddAccessError},
+        store_key::{StoreKey, StoreKeys},
+    };
+
+    /// Stand-in for a store key that lives outside of the server (e.g. an age plugin)
+    struct StubStoreKey(age::x25519::Identity);
+
+    impl StoreKey for StubStoreKey {
+        fn recipient(&self) -> String {
+            self.0.to_public().to_string()
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, age::DecryptError> {
+            age::decrypt(&self.0, ciphertext)
+        }
+    }
+
+    async fn secret_and_host(conn: &mut sqlx::SqliteConnection) -> (api::SecretID, api::HostID) {
+        let store_key = age::x25519::Identity::generate();
+        let encrypted = age::encrypt(&store_key.to_public(), b"my-secret").unwrap();
+        let secret = db::secrets::add_secret(conn, "my-secret", encrypted, &store_key, false)
+            .await
+            .unwrap();
+        let host = db::hosts::add_host(conn, VerifyingKey::default(), "myhost".to_owned())
+            .await
+            .unwrap();
+        (secret.id, host)
+    }
+
+    async fn acl_count(conn: &mut sqlx::SqliteConnection, secret: api::SecretID) -> i64 {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) FROM secrets_acl WHERE secret_id = $1"#,
+            secret
+        )
+        .fetch_one(conn)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn add_access_twice(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let (secret, host) = secret_and_host(&mut conn).await;
+
+        db::secrets::add_access_for(&mut conn, secret, host)
+            .await
+            .unwrap();
+        db::secrets::add_access_for(&mut conn, secret, host)
+            .await
+            .unwrap();
+
+        assert_eq!(acl_count(&mut conn, secret).await, 1);
+    }
+
+    #[sqlx::test]
+    async fn add_access_missing_secret(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let (secret, host) = secret_and_host(&mut conn).await;
+        db::secrets::remove_secret(&mut conn, secret).await.unwrap();
+
+        let err = db::secrets::add_access_for(&mut conn, secret, host).await;
+        assert!(matches!(err, Err(AddAccessError::SecretNotFound)));
+        assert_eq!(acl_count(&mut conn, secret).await, 0);
+    }
+
+    #[sqlx::test]
+    async fn content_type(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let host_key = age::x25519::Identity::generate();
+        let encrypted = age::encrypt(&store_key.to_public(), b"my-secret").unwrap();
+        let secret = db::secrets::add_secret(&mut conn, "my-secret", encrypted, &store_key, false)
+            .await
+            .unwrap();
+        let host = db::hosts::add_host(&mut conn, VerifyingKey::default(), "myhost".to_owned())
+            .await
+            .unwrap();
+        db::secrets::add_access_for(&mut conn, secret.id, host)
+            .await
+            .unwrap();
+        let admin = db::user::create_user(
+            &mut conn,
+            "adminkey".to_owned(),
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "admin".to_owned(),
+            api::AuthLevel::Admin,
+            true,
+        )
+        .await
+        .unwrap();
+        let listed = async |conn: &mut sqlx::SqliteConnection, name: &str| {
+            db::secrets::list_secrets(conn, admin)
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|secret| secret.name == name)
+                .unwrap()
+                .content_type
+        };
+
+        // secrets have none by default
+        assert_eq!(secret.content_type, None);
+        assert_eq!(listed(&mut conn, "my-secret").await, None);
+
+        db::secrets::set_content_type(&mut conn, secret.id, Some("pem"))
+            .await
+            .unwrap();
+        assert_eq!(listed(&mut conn, "my-secret").await.as_deref(), Some("pem"));
+
+        // the host gets it together with the content
+        let lookup = db::secrets::get_secret_for(
+            &mut conn,
+            "my-secret",
+            &store_key,
+            host,
+            &host_key.to_public(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            lookup,
+            api::SecretLookup::Found { content_type: Some(content_type), .. } if content_type == "pem"
+        ));
+
+        // aliases follow their target
+        let alias = db::secrets::add_alias(&mut conn, "alias".to_owned(), secret.id)
+            .await
+            .unwrap();
+        assert_eq!(alias.content_type.as_deref(), Some("pem"));
+        assert_eq!(listed(&mut conn, "alias").await.as_deref(), Some("pem"));
+
+        // only short labels are accepted
+        for invalid in ["", "application/json", "PEM", "../pem"] {
+            assert!(matches!(
+                db::secrets::set_content_type(&mut conn, secret.id, Some(invalid)).await,
+                Err(db::secrets::ContentTypeError::Invalid { .. })
+            ));
+        }
+
+        db::secrets::set_content_type(&mut conn, secret.id, None)
+            .await
+            .unwrap();
+        assert_eq!(listed(&mut conn, "my-secret").await, None);
+
+        db::secrets::remove_secret(&mut conn, secret.id)
+            .await
+            .unwrap();
+        assert!(matches!(
+            db::secrets::set_content_type(&mut conn, secret.id, Some("pem")).await,
+            Err(db::secrets::ContentTypeError::SecretNotFound)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn secrets_changed(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let (secret, host) = secret_and_host(&mut conn).await;
+
+        // the first check only remembers the secrets
+        assert!(!db::secrets::secrets_changed(&mut conn, host).await.unwrap());
+        assert!(!db::secrets::secrets_changed(&mut conn, host).await.unwrap());
+
+        // granted and revoked access is reported once
+        db::secrets::add_access_for(&mut conn, secret, host)
+            .await
+            .unwrap();
+        assert!(db::secrets::secrets_changed(&mut conn, host).await.unwrap());
+        assert!(!db::secrets::secrets_changed(&mut conn, host).await.unwrap());
+        db::secrets::remove_access_for(&mut conn, secret, host)
+            .await
+            .unwrap();
+        assert!(db::secrets::secrets_changed(&mut conn, host).await.unwrap());
+        assert!(!db::secrets::secrets_changed(&mut conn, host).await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn secret_for_other_host(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let encrypted = age::encrypt(&store_key.to_public(), b"my-secret").unwrap();
+        let secret = db::secrets::add_secret(&mut conn, "my-secret", encrypted, &store_key, false)
+            .await
+            .unwrap();
+        let allowed = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "allowed".to_owned(),
+        )
+        .await
+        .unwrap();
+        let other = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[2; 32]).verifying_key(),
+            "other".to_owned(),
+        )
+        .await
+        .unwrap();
+        db::secrets::add_access_for(&mut conn, secret.id, allowed)
+            .await
+            .unwrap();
+
+        let host_key = age::x25519::Identity::generate();
+        let for_other = db::secrets::get_secret_for(
+            &mut conn,
+            "my-secret",
+            &store_key,
+            other,
+            &host_key.to_public(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(for_other, api::SecretLookup::NoAccess);
+
+        let missing = db::secrets::get_secret_for(
+            &mut conn,
+            "missing-secret",
+            &store_key,
+            allowed,
+            &host_key.to_public(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(missing, api::SecretLookup::NotFound);
+    }
+
+    #[sqlx::test]
+    async fn stub_store_key(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key: Box<dyn StoreKey> =
+            Box::new(StubStoreKey(age::x25519::Identity::generate()));
+
+        // this is what the cli does with the key from `/secret/server_key`
+        let recipient = api::parse_recipient(&store_key.recipient()).unwrap();
+        let encrypted = api::encrypt_for(&*recipient, b"my-secret").unwrap();
+
+        let secret = db::secrets::add_secret(&mut conn, "my-secret", encrypted, &*store_key, false)
+            .await
+            .unwrap();
+        let host = db::hosts::add_host(&mut conn, VerifyingKey::default(), "myhost".to_owned())
+            .await
+            .unwrap();
+        db::secrets::add_access_for(&mut conn, secret.id, host)
+            .await
+            .unwrap();
+
+        let host_key = age::x25519::Identity::generate();
+        let for_host = db::secrets::get_secret_for(
+            &mut conn,
+            "my-secret",
+            &*store_key,
+            host,
+            &host_key.to_public(),
+        )
+        .await
+        .unwrap()
+        .found()
+        .unwrap();
+        assert_eq!(age::decrypt(&host_key, &for_host).unwrap(), b"my-secret");
+    }
+
+    #[sqlx::test]
+    async fn reject_secret_for_other_store_key(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = StubStoreKey(age::x25519::Identity::generate());
+
+        let encrypted =
+            age::encrypt(&age::x25519::Identity::generate().to_public(), b"my-secret").unwrap();
+
+        let err =
+            db::secrets::add_secret(&mut conn, "my-secret", encrypted, &store_key, false).await;
+        assert!(matches!(
+            err,
+            Err(db::secrets::AddSecretError::UnencryptedSecretError(_))
+        ));
+    }
+
+    #[sqlx::test]
+    async fn check_secret(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let encrypted = age::encrypt(&store_key.to_public(), b"my-secret").unwrap();
+        let secret = db::secrets::add_secret(&mut conn, "my-secret", encrypted, &store_key, false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            db::secrets::secret_by_name(&mut conn, "my-secret")
+                .await
+                .unwrap(),
+            Some(secret.id)
+        );
+        db::secrets::check_secret(&mut conn, secret.id, &store_key)
+            .await
+            .unwrap();
+
+        // e.g. the store key got rotated without re-encrypting the secrets
+        let rotated = age::x25519::Identity::generate();
+        let err = db::secrets::check_secret(&mut conn, secret.id, &rotated).await;
+        assert!(matches!(
+            err,
+            Err(db::secrets::CheckSecretError::Decrypt(_))
+        ));
+
+        db::secrets::remove_secret(&mut conn, secret.id)
+            .await
+            .unwrap();
+        let err = db::secrets::check_secret(&mut conn, secret.id, &store_key).await;
+        assert!(matches!(
+            err,
+            Err(db::secrets::CheckSecretError::SecretNotFound)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn access_for(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let (secret, host) = secret_and_host(&mut conn).await;
+
+        assert_eq!(
+            db::secrets::access_for(&mut conn, secret, host)
+                .await
+                .unwrap(),
+            api::SecretAccess::denied("host not in ACL")
+        );
+
+        db::secrets::add_access_for(&mut conn, secret, host)
+            .await
+            .unwrap();
+        assert_eq!(
+            db::secrets::access_for(&mut conn, secret, host)
+                .await
+                .unwrap(),
+            api::SecretAccess::allowed()
+        );
+    }
+
+    #[sqlx::test]
+    async fn rotate_secret(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let encrypted = age::encrypt(&store_key.to_public(), b"old").unwrap();
+        let secret = db::secrets::add_secret(&mut conn, "my-secret", encrypted, &store_key, false)
+            .await
+            .unwrap();
+        let host = db::hosts::add_host(&mut conn, VerifyingKey::default(), "myhost".to_owned())
+            .await
+            .unwrap();
+        db::secrets::add_access_for(&mut conn, secret.id, host)
+            .await
+            .unwrap();
+
+        let rotated = age::encrypt(&store_key.to_public(), b"new").unwrap();
+        db::secrets::rotate_secret(&mut conn, secret.id, rotated, &store_key, false)
+            .await
+            .unwrap();
+
+        // the host keeps its access and gets the new content
+        let host_key = age::x25519::Identity::generate();
+        let for_host = db::secrets::get_secret_for(
+            &mut conn,
+            "my-secret",
+            &store_key,
+            host,
+            &host_key.to_public(),
+        )
+        .await
+        .unwrap()
+        .found()
+        .unwrap();
+        assert_eq!(age::decrypt(&host_key, &for_host).unwrap(), b"new");
+
+        let bogus = age::encrypt(&age::x25519::Identity::generate().to_public(), b"new").unwrap();
+        let err = db::secrets::rotate_secret(&mut conn, secret.id, bogus, &store_key, false).await;
+        assert!(matches!(
+            err,
+            Err(db::secrets::RotateSecretError::UnencryptedSecretError(_))
+        ));
+
+        db::secrets::remove_secret(&mut conn, secret.id)
+            .await
+            .unwrap();
+        let rotated = age::encrypt(&store_key.to_public(), b"new").unwrap();
+        let err =
+            db::secrets::rotate_secret(&mut conn, secret.id, rotated, &store_key, false).await;
+        assert!(matches!(
+            err,
+            Err(db::secrets::RotateSecretError::SecretNotFound)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn acl_by_secret(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let user = db::user::create_user(
+            &mut conn,
+            "admin".to_owned(),
+            SigningKey::from_bytes(&[9; 32]).verifying_key(),
+            "admin".to_owned(),
+            api::AuthLevel::Admin,
+            true,
+        )
+        .await
+        .unwrap();
+
+        let mut acl = Vec::new();
+        for (index, name) in ["first", "second"].into_iter().enumerate() {
+            let encrypted = age::encrypt(&store_key.to_public(), b"my-secret").unwrap();
+            let secret = db::secrets::add_secret(&mut conn, name, encrypted, &store_key, false)
+                .await
+                .unwrap();
+            let seed = u8::try_from(index).unwrap().saturating_add(1);
+            let host = db::hosts::add_host(
+                &mut conn,
+                SigningKey::from_bytes(&[seed; 32]).verifying_key(),
+                name.to_owned(),
+            )
+            .await
+            .unwrap();
+            db::secrets::add_access_for(&mut conn, secret.id, host)
+                .await
+                .unwrap();
+            acl.push((secret.id, host));
+        }
+
+        // only the hosts of the requested secret are returned
+        for (secret, host) in acl {
+            assert_eq!(
+                db::secrets::acl_by_secret(&mut conn, user, secret)
+                    .await
+                    .unwrap(),
+                vec![host]
+            );
+        }
+    }
+
+    async fn is_compressed(conn: &mut sqlx::SqliteConnection, secret: api::SecretID) -> bool {
+        sqlx::query_scalar!(
+            r#"SELECT compressed AS "compressed: bool" FROM secrets WHERE id = $1"#,
+            secret
+        )
+        .fetch_one(conn)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn compressed_round_trip(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let host = db::hosts::add_host(&mut conn, VerifyingKey::default(), "myhost".to_owned())
+            .await
+            .unwrap();
+        let host_key = age::x25519::Identity::generate();
+        let bundle = "-----BEGIN CERTIFICATE-----\nMIIBszCCAVmgAwIBAgIUW\n".repeat(64);
+
+        // secrets that do not shrink are stored as they are
+        for (name, content, compress, compressed) in [
+            ("plain", bundle.as_str(), false, false),
+            ("compressed", bundle.as_str(), true, true),
+            ("tiny", "x", true, false),
+        ] {
+            let encrypted = age::encrypt(&store_key.to_public(), content.as_bytes()).unwrap();
+            let secret = db::secrets::add_secret(&mut conn, name, encrypted, &store_key, compress)
+                .await
+                .unwrap();
+            db::secrets::add_access_for(&mut conn, secret.id, host)
+                .await
+                .unwrap();
+            assert_eq!(is_compressed(&mut conn, secret.id).await, compressed);
+            db::secrets::check_secret(&mut conn, secret.id, &store_key)
+                .await
+                .unwrap();
+
+            let for_host = db::secrets::get_secret_for(
+                &mut conn,
+                name,
+                &store_key,
+                host,
+                &host_key.to_public(),
+            )
+            .await
+            .unwrap()
+            .found()
+            .unwrap();
+            assert_eq!(
+                age::decrypt(&host_key, &for_host).unwrap(),
+                content.as_bytes()
+            );
+        }
+
+        // rotating without compression stores the new content uncompressed
+        let secret = db::secrets::secret_by_name(&mut conn, "compressed")
+            .await
+            .unwrap()
+            .unwrap();
+        let rotated = age::encrypt(&store_key.to_public(), bundle.as_bytes()).unwrap();
+        db::secrets::rotate_secret(&mut conn, secret, rotated, &store_key, false)
+            .await
+            .unwrap();
+        assert!(!is_compressed(&mut conn, secret).await);
+    }
+
+    #[sqlx::test]
+    async fn replicas_share_secrets(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let key_a = age::x25519::Identity::generate();
+        let key_b = age::x25519::Identity::generate();
+        let replica_a =
+            StoreKeys::new(Box::new(key_a.clone())).with_replica(key_b.to_public().to_string());
+        let replica_b =
+            StoreKeys::new(Box::new(key_b.clone())).with_replica(key_a.to_public().to_string());
+        let host = db::hosts::add_host(&mut conn, VerifyingKey::default(), "myhost".to_owned())
+            .await
+            .unwrap();
+        let host_key = age::x25519::Identity::generate();
+
+        // added on replica a with its server key. rotated on replica b with its server key
+        let encrypted = age::encrypt(&key_a.to_public(), b"my-secret").unwrap();
+        let secret = db::secrets::add_secret(&mut conn, "my-secret", encrypted, &replica_a, false)
+            .await
+            .unwrap();
+        db::secrets::add_access_for(&mut conn, secret.id, host)
+            .await
+            .unwrap();
+        for replica in [&replica_a, &replica_b] {
+            let for_host = db::secrets::get_secret_for(
+                &mut conn,
+                "my-secret",
+                replica,
+                host,
+                &host_key.to_public(),
+            )
+            .await
+            .unwrap()
+            .found()
+            .unwrap();
+            assert_eq!(age::decrypt(&host_key, &for_host).unwrap(), b"my-secret");
+        }
+
+        let rotated = age::encrypt(&key_b.to_public(), b"rotated").unwrap();
+        db::secrets::rotate_secret(&mut conn, secret.id, rotated, &replica_b, true)
+            .await
+            .unwrap();
+        db::secrets::check_secret(&mut conn, secret.id, &replica_a)
+            .await
+            .unwrap();
+        db::secrets::check_secret(&mut conn, secret.id, &key_a)
+            .await
+            .unwrap();
+
+        // replica b without replica a configured only seals for itself
+        let encrypted = age::encrypt(&key_b.to_public(), b"alone").unwrap();
+        let alone = db::secrets::add_secret(&mut conn, "alone", encrypted, &key_b, true)
+            .await
+            .unwrap();
+        db::secrets::check_secret(&mut conn, alone.id, &key_a)
+            .await
+            .unwrap_err();
+    }
+
+    /// An `all_tag` admin that sees the secrets `names`
+    async fn with_secrets(conn: &mut sqlx::SqliteConnection, names: &[&str]) -> api::UserID {
+        let store_key = age::x25519::Identity::generate();
+        for name in names {
+            let encrypted = age::encrypt(&store_key.to_public(), b"content").unwrap();
+            db::secrets::add_secret(&mut *conn, *name, encrypted, &store_key, false)
+                .await
+                .unwrap();
+        }
+        db::user::create_user(
+            conn,
+            "adminkey".to_owned(),
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "admin".to_owned(),
+            api::AuthLevel::Admin,
+            true,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn page(
+        conn: &mut sqlx::SqliteConnection,
+        user: api::UserID,
+        query: &api::ListSecretsQuery,
+    ) -> (Vec<String>, usize) {
+        let page = db::secrets::page_secrets(conn, user, query).await.unwrap();
+        (
+            page.secrets.into_iter().map(|secret| secret.name).collect(),
+            page.total,
+        )
+    }
+
+    #[sqlx::test]
+    async fn page_secrets_sorted(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let user = with_secrets(&mut conn, &["b", "c", "a"]).await;
+        assert_eq!(
+            page(&mut conn, user, &api::ListSecretsQuery::default()).await,
+            (vec!["a".to_owned(), "b".to_owned(), "c".to_owned()], 3)
+        );
+    }
+
+    #[sqlx::test]
+    async fn page_secrets_prefix(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let user = with_secrets(&mut conn, &["db/b", "web/a", "db/a", "db", "db_"]).await;
+        let query = api::ListSecretsQuery {
+            prefix: Some("db/".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            page(&mut conn, user, &query).await,
+            (vec!["db/a".to_owned(), "db/b".to_owned()], 2)
+        );
+        // no pattern matching in the prefix
+        let query = api::ListSecretsQuery {
+            prefix: Some("db_".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            page(&mut conn, user, &query).await,
+            (vec!["db_".to_owned()], 1)
+        );
+    }
+
+    #[sqlx::test]
+    async fn page_secrets_boundaries(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let user = with_secrets(&mut conn, &["a", "b", "c", "d", "e"]).await;
+        let query = |offset, limit| api::ListSecretsQuery {
+            prefix: None,
+            limit,
+            offset,
+        };
+
+        assert_eq!(
+            page(&mut conn, user, &query(Some(0), Some(2))).await,
+            (vec!["a".to_owned(), "b".to_owned()], 5)
+        );
+        // the last page is shorter
+        assert_eq!(
+            page(&mut conn, user, &query(Some(4), Some(2))).await,
+            (vec!["e".to_owned()], 5)
+        );
+        // past the end
+        assert_eq!(
+            page(&mut conn, user, &query(Some(5), Some(2))).await,
+            (Vec::new(), 5)
+        );
+        assert_eq!(
+            page(&mut conn, user, &query(Some(9), None)).await,
+            (Vec::new(), 5)
+        );
+        assert_eq!(
+            page(&mut conn, user, &query(None, Some(0))).await,
+            (Vec::new(), 5)
+        );
+        // without a limit everything after the offset
+        assert_eq!(
+            page(&mut conn, user, &query(Some(3), None)).await,
+            (vec!["d".to_owned(), "e".to_owned()], 5)
+        );
+    }
+
+    #[sqlx::test]
+    async fn page_secrets_prefix_and_offset(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let user = with_secrets(&mut conn, &["db/c", "db/a", "web/b", "db/b"]).await;
+        let query = api::ListSecretsQuery {
+            prefix: Some("db/".to_owned()),
+            limit: Some(1),
+            offset: Some(1),
+        };
+        assert_eq!(
+            page(&mut conn, user, &query).await,
+            (vec!["db/b".to_owned()], 3)
+        );
+    }
+
+    #[test]
+    fn store_keys_try_every_identity() {
+        let old = age::x25519::Identity::generate();
+        let new = age::x25519::Identity::generate();
+        let keys = StoreKeys::new(Box::new(new.clone())).with_identity(Box::new(old.clone()));
+
+        assert_eq!(keys.recipient(), new.to_public().to_string());
+        // the retired key only decrypts
+        assert_eq!(keys.recipients(), vec![new.to_public().to_string()]);
+        let encrypted = age::encrypt(&old.to_public(), b"before rotation").unwrap();
+        assert_eq!(keys.decrypt(&encrypted).unwrap(), b"before rotation");
+
+        let other = age::x25519::Identity::generate();
+        let encrypted = age::encrypt(&other.to_public(), b"foreign").unwrap();
+        keys.decrypt(&encrypted).unwrap_err();
+    }
+
+    #[sqlx::test]
+    async fn alias_serves_target(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let host_key = age::x25519::Identity::generate();
+        let encrypted = age::encrypt(&store_key.to_public(), b"shared").unwrap();
+        let target = db::secrets::add_secret(&mut conn, "target", encrypted, &store_key, false)
+            .await
+            .unwrap()
+            .id;
+        let host = db::hosts::add_host(&mut conn, VerifyingKey::default(), "myhost".to_owned())
+            .await
+            .unwrap();
+
+        let alias = db::secrets::add_alias(&mut conn, "alias".to_owned(), target)
+            .await
+            .unwrap()
+            .id;
+        // an alias of an alias points at the target
+        let nested = db::secrets::add_alias(&mut conn, "nested".to_owned(), alias)
+            .await
+            .unwrap()
+            .id;
+        db::secrets::add_access_for(&mut conn, alias, host)
+            .await
+            .unwrap();
+        db::secrets::add_access_for(&mut conn, nested, host)
+            .await
+            .unwrap();
+
+        let fetch = async |conn: &mut sqlx::SqliteConnection, name: &str| {
+            db::secrets::get_secret_for(conn, name, &store_key, host, &host_key.to_public())
+                .await
+                .unwrap()
+                .found()
+                .map(|content| age::decrypt(&host_key, &content).unwrap())
+        };
+
+        // the acl of the alias applies, not the one of the target
+        assert_eq!(fetch(&mut conn, "alias").await.unwrap(), b"shared");
+        assert_eq!(fetch(&mut conn, "nested").await.unwrap(), b"shared");
+        assert_eq!(fetch(&mut conn, "target").await, None);
+        db::secrets::check_secret(&mut conn, alias, &store_key)
+            .await
+            .unwrap();
+
+        // renaming and rotating the target is seen through the alias
+        db::secrets::rename_secret(&mut conn, target, "renamed".to_owned())
+            .await
+            .unwrap();
+        let rotated = age::encrypt(&store_key.to_public(), b"rotated").unwrap();
+        db::secrets::rotate_secret(&mut conn, alias, rotated, &store_key, false)
+            .await
+            .unwrap();
+        assert_eq!(fetch(&mut conn, "alias").await.unwrap(), b"rotated");
+
+        // removing an alias keeps the target
+        assert!(matches!(
+            db::secrets::remove_alias(&mut conn, target).await,
+            Err(db::secrets::AliasError::NotAnAlias)
+        ));
+        db::secrets::remove_alias(&mut conn, nested).await.unwrap();
+        assert_eq!(
+            db::secrets::secret_by_name(&mut conn, "nested")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            db::secrets::secret_by_name(&mut conn, "renamed")
+                .await
+                .unwrap(),
+            Some(target)
+        );
+    }
+
+    #[sqlx::test]
+    async fn removed_target_removes_aliases(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let (target, host) = secret_and_host(&mut conn).await;
+        let alias = db::secrets::add_alias(&mut conn, "alias".to_owned(), target)
+            .await
+            .unwrap()
+            .id;
+        db::secrets::add_access_for(&mut conn, alias, host)
+            .await
+            .unwrap();
+
+        db::secrets::remove_secret(&mut conn, target).await.unwrap();
+
+        assert_eq!(
+            db::secrets::secret_by_name(&mut conn, "alias")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(acl_count(&mut conn, alias).await, 0);
+        assert!(matches!(
+            db::secrets::add_alias(&mut conn, "again".to_owned(), target).await,
+            Err(db::secrets::AliasError::SecretNotFound)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn sealed_secret(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let sealed_to = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "sealed".to_owned(),
+        )
+        .await
+        .unwrap();
+        let other = db::hosts::add_host(
+            &mut conn,
+            SigningKey::from_bytes(&[2; 32]).verifying_key(),
+            "other".to_owned(),
+        )
+        .await
+        .unwrap();
+        let host_key = age::x25519::Identity::generate();
+        let encrypted = age::encrypt(&host_key.to_public(), b"my-secret").unwrap();
+
+        assert!(matches!(
+            db::secrets::add_sealed_secret(&mut conn, "bogus", b"not age".to_vec(), sealed_to)
+                .await,
+            Err(db::secrets::AddSealedSecretError::NotAgeFile(_))
+        ));
+        let secret =
+            db::secrets::add_sealed_secret(&mut conn, "sealed", encrypted.clone(), sealed_to)
+                .await
+                .unwrap();
+        assert_eq!(secret.hosts, [sealed_to]);
+
+        // handed out verbatim without the store key being involved
+        let for_host = db::secrets::get_secret_for(
+            &mut conn,
+            "sealed",
+            &store_key,
+            sealed_to,
+            &host_key.to_public(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            for_host,
+            api::SecretLookup::Found {
+                content: encrypted,
+                content_type: None
+            }
+        );
+
+        // neither the acl nor an alias hands it to another host
+        db::secrets::add_access_for(&mut conn, secret.id, other)
+            .await
+            .unwrap();
+        let alias = db::secrets::add_alias(&mut conn, "alias".to_owned(), secret.id)
+            .await
+            .unwrap();
+        db::secrets::add_access_for(&mut conn, alias.id, other)
+            .await
+            .unwrap();
+        for name in ["sealed", "alias"] {
+            let for_other = db::secrets::get_secret_for(
+                &mut conn,
+                name,
+                &store_key,
+                other,
+                &host_key.to_public(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(for_other, api::SecretLookup::NoAccess);
+        }
+
+        db::secrets::check_secret(&mut conn, secret.id, &store_key)
+            .await
+            .unwrap();
+        let rotated = age::encrypt(&store_key.to_public(), b"new-secret").unwrap();
+        assert!(matches!(
+            db::secrets::rotate_secret(&mut conn, secret.id, rotated, &store_key, false).await,
+            Err(db::secrets::RotateSecretError::Sealed)
+        ));
+    }
+}
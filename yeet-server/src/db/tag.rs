@@ -224,7 +224,7 @@ pub async fn auth_level(
         WHERE id = $1"#,
         user
     )
-    .fetch_optional(conn)
+    .fetch_optional(&mut *conn)
     .await
     .internal_server()?;
 
@@ -235,13 +235,87 @@ pub async fn auth_level(
             } else {
                 Err((
                     StatusCode::FORBIDDEN,
-                    "Key is registered but you have not the required permissions".to_owned(),
+                    format!(
+                        "Key {} is registered but you have not the required permissions",
+                        user_fingerprint(conn, user).await?
+                    ),
                 ))
             }
         }
         None => Err((
             StatusCode::FORBIDDEN,
-            "Key is registered but has no AuthLevel associated".to_owned(),
+            format!(
+                "Key {} is registered but has no AuthLevel associated",
+                user_fingerprint(conn, user).await?
+            ),
         )),
     }
 }
+
+/// Fingerprint of the key `user` signs with so rejections can be matched to an enrolled key
+async fn user_fingerprint(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+) -> Result<String, (StatusCode, String)> {
+    Ok(crate::db::user::fetch_key(conn, user)
+        .await
+        .internal_server()?
+        .map_or_else(|| "<unknown>".to_owned(), |key| api::key_fingerprint(&key)))
+}
+
+#[cfg(test)]
+mod test_tag {
+    use ed25519_dalek::SigningKey;
+
+    use crate::db;
+
+    #[sqlx::test]
+    async fn admin_passes_every_level(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let admin = db::user::create_user(
+            &mut conn,
+            "adminkey".to_owned(),
+            SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            "admin".to_owned(),
+            api::AuthLevel::Admin,
+            false,
+        )
+        .await
+        .unwrap();
+        let build = db::user::create_user(
+            &mut conn,
+            "buildkey".to_owned(),
+            SigningKey::from_bytes(&[2; 32]).verifying_key(),
+            "build".to_owned(),
+            api::AuthLevel::Build,
+            false,
+        )
+        .await
+        .unwrap();
+
+        db::tag::auth_build(&mut conn, admin).await.unwrap();
+        db::tag::auth_osquery(&mut conn, admin).await.unwrap();
+        db::tag::auth_build(&mut conn, build).await.unwrap();
+        db::tag::auth_admin(&mut conn, build).await.unwrap_err();
+    }
+
+    #[sqlx::test]
+    async fn auth_level_error_names_fingerprint(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let key = SigningKey::from_bytes(&[2; 32]).verifying_key();
+        let build = db::user::create_user(
+            &mut conn,
+            "buildkey".to_owned(),
+            key,
+            "build".to_owned(),
+            api::AuthLevel::Build,
+            false,
+        )
+        .await
+        .unwrap();
+
+        let (status, message) = db::tag::auth_admin(&mut conn, build).await.unwrap_err();
+        assert_eq!(status, axum::http::StatusCode::FORBIDDEN);
+        assert!(message.contains(&api::key_fingerprint(&key)));
+    }
+}
@@ -21,6 +21,27 @@ pub async fn fetch_by_key(
     Ok(user.map(api::UserID::new))
 }
 
+/// The key `user` signs with
+pub async fn fetch_key(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+) -> Result<Option<VerifyingKey>, sqlx::Error> {
+    let key = sqlx::query_scalar!(
+        r#"
+        SELECT keys.verifying_key from users
+        JOIN keys on keys.id = users.key_id
+        WHERE users.id = $1"#,
+        user
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(key.map(|key| {
+        VerifyingKey::from_bytes(&key.try_into().expect("we only store valid keys"))
+            .expect("we only store valid keys")
+    }))
+}
+
 // TODO
 // pub async fn allow_all_tag(
 //     conn: &mut sqlx::SqliteConnection,
@@ -1,9 +1,15 @@
+use std::ops::RangeInclusive;
+
 use ed25519_dalek::VerifyingKey;
 use jiff_sqlx::ToSqlx as _;
 use rand::RngExt as _;
+use sqlx::Acquire as _;
 
 use crate::db;
 
+/// Verification codes are six digit numbers
+const CODES: RangeInclusive<i64> = 100_000..=999_999;
+
 error_set::error_set! {
     AddVerificationError := {
         #[display("Key already in an verification attempt")]
@@ -29,10 +35,28 @@ error_set::error_set! {
 /// This is the only method that is done without any form of authentication.
 /// It may be advised to but this behind a firewall
 /// However no `DDoS` can come from this because the attempt count is hard limited at 10
+///
+/// `recipient` is the age recipient the host wants its secrets encrypted for.
+/// Once approved the host can not fetch secrets for any other recipient
 pub async fn add_verification_attempt(
     conn: &mut sqlx::SqliteConnection,
     key: VerifyingKey,
     nixos_facter: Option<String>,
+    recipient: Option<String>,
+) -> Result<i64, AddVerificationError> {
+    add_attempt_with(conn, key, nixos_facter, recipient, || {
+        rand::rng().random_range(CODES)
+    })
+    .await
+}
+
+/// `add_verification_attempt` with the codes drawn from `new_code`
+async fn add_attempt_with(
+    conn: &mut sqlx::SqliteConnection,
+    key: VerifyingKey,
+    nixos_facter: Option<String>,
+    recipient: Option<String>,
+    new_code: impl FnMut() -> i64 + Send,
 ) -> Result<i64, AddVerificationError> {
     // delete old attemps to give room for new ones
     delete_old_attempts(conn).await?;
@@ -52,19 +76,20 @@ pub async fn add_verification_attempt(
         return Err(AddVerificationError::TooManyAttempts);
     }
 
-    let id = rand::rng().random_range(100_000..=999_999);
+    let id = unused_code(conn, new_code).await?;
 
     let now = jiff::Timestamp::now().to_sqlx();
     let key = &key.as_bytes()[..];
     let row_id = sqlx::query!(
         r#"
-        INSERT INTO verification_attempts (id, verifying_key, timestamp, nixos_facter)
-        VALUES ( $1, $2, $3, $4)
+        INSERT INTO verification_attempts (id, verifying_key, timestamp, nixos_facter, recipient)
+        VALUES ( $1, $2, $3, $4, $5)
         "#,
         id,
         key,
         now,
-        nixos_facter
+        nixos_facter,
+        recipient
     )
     .execute(conn)
     .await?
@@ -90,7 +115,7 @@ pub async fn accept_attempt(
 
     // TODO: what happens if you approve a key that does not exist
     let approved = sqlx::query!(
-        r#"DELETE FROM verification_attempts WHERE id = $1 RETURNING nixos_facter,verifying_key"#,
+        r#"DELETE FROM verification_attempts WHERE id = $1 RETURNING nixos_facter,verifying_key,recipient"#,
         code,
     )
     .fetch_one(&mut *conn)
@@ -104,11 +129,50 @@ pub async fn accept_attempt(
     )
     .expect("We never store anything else than verifying keys");
 
-    db::hosts::add_host(conn, key, hostname).await?;
+    let host = register_host(conn, key, hostname, approved.recipient.as_deref()).await?;
+    if let Some(nixos_facter) = &approved.nixos_facter {
+        db::hosts::set_facter(conn, host, nixos_facter).await?;
+    }
 
     Ok(approved.nixos_facter)
 }
 
+/// Register a host whose key an admin vouched for.
+/// Shared by accepted verification attempts and bulk imports
+pub async fn register_host(
+    conn: &mut sqlx::SqliteConnection,
+    key: VerifyingKey,
+    hostname: String,
+    recipient: Option<&str>,
+) -> Result<api::HostID, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let host = db::hosts::add_host(&mut tx, key, hostname).await?;
+    if let Some(recipient) = recipient {
+        db::hosts::set_recipient(&mut tx, host, recipient).await?;
+    }
+    tx.commit().await?;
+    Ok(host)
+}
+
+/// The admin approves an attempt by its code. Draws until the code is not pending already
+async fn unused_code(
+    conn: &mut sqlx::SqliteConnection,
+    mut new_code: impl FnMut() -> i64 + Send,
+) -> Result<i64, sqlx::Error> {
+    loop {
+        let code = new_code();
+        let pending = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM verification_attempts WHERE id = $1) AS 'exists!: bool'"#,
+            code
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        if !pending {
+            return Ok(code);
+        }
+    }
+}
+
 async fn count_attempts(conn: &mut sqlx::SqliteConnection) -> Result<i64, sqlx::Error> {
     sqlx::query_scalar!(r#"SELECT COUNT(*) FROM verification_attempts"#)
         .fetch_one(conn)
@@ -157,19 +221,49 @@ mod test_verification {
     async fn add_verification(pool: sqlx::SqlitePool) {
         let mut conn = crate::sql_conn(pool).await;
 
-        db::verification::add_verification_attempt(&mut conn, VerifyingKey::default(), None)
+        db::verification::add_verification_attempt(&mut conn, VerifyingKey::default(), None, None)
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn codes_are_unique(pool: sqlx::SqlitePool) {
+        use rand::SeedableRng as _;
+
+        let mut conn = crate::sql_conn(pool).await;
+
+        // the same seed draws the same code first
+        let mut codes = Vec::new();
+        for _ in 0..2 {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+            let code = super::add_attempt_with(
+                &mut conn,
+                SigningKey::from_bytes(&rand::random()).verifying_key(),
+                None,
+                None,
+                || rng.random_range(super::CODES),
+            )
             .await
             .unwrap();
+            codes.push(code);
+        }
+
+        assert_ne!(codes.first(), codes.last());
+        assert_eq!(super::count_attempts(&mut conn).await.unwrap(), 2);
     }
 
     #[sqlx::test]
     async fn add_verification_and_accept(pool: sqlx::SqlitePool) {
         let mut conn = crate::sql_conn(pool).await;
 
-        let code =
-            db::verification::add_verification_attempt(&mut conn, VerifyingKey::default(), None)
-                .await
-                .unwrap();
+        let code = db::verification::add_verification_attempt(
+            &mut conn,
+            VerifyingKey::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
 
         db::verification::accept_attempt(&mut conn, code, "somehost".to_owned())
             .await
@@ -188,13 +282,17 @@ mod test_verification {
     async fn key_already_requestd(pool: sqlx::SqlitePool) {
         let mut conn = crate::sql_conn(pool).await;
 
-        db::verification::add_verification_attempt(&mut conn, VerifyingKey::default(), None)
+        db::verification::add_verification_attempt(&mut conn, VerifyingKey::default(), None, None)
             .await
             .unwrap();
 
-        let err =
-            db::verification::add_verification_attempt(&mut conn, VerifyingKey::default(), None)
-                .await;
+        let err = db::verification::add_verification_attempt(
+            &mut conn,
+            VerifyingKey::default(),
+            None,
+            None,
+        )
+        .await;
         match err {
             Err(AddVerificationError::KeyPendingVerification) => {}
             _ => panic!(),
@@ -209,9 +307,13 @@ mod test_verification {
             .await
             .unwrap();
 
-        let err =
-            db::verification::add_verification_attempt(&mut conn, VerifyingKey::default(), None)
-                .await;
+        let err = db::verification::add_verification_attempt(
+            &mut conn,
+            VerifyingKey::default(),
+            None,
+            None,
+        )
+        .await;
 
         match err {
             Err(AddVerificationError::KeyAlreadyInUse) => {}
@@ -228,14 +330,19 @@ mod test_verification {
                 &mut conn,
                 SigningKey::from_bytes(&rand::random()).verifying_key(),
                 None,
+                None,
             )
             .await
             .unwrap();
         }
 
-        let err =
-            db::verification::add_verification_attempt(&mut conn, VerifyingKey::default(), None)
-                .await;
+        let err = db::verification::add_verification_attempt(
+            &mut conn,
+            VerifyingKey::default(),
+            None,
+            None,
+        )
+        .await;
 
         match err {
             Err(AddVerificationError::TooManyAttempts) => {}
@@ -270,6 +377,7 @@ mod test_verification {
             &mut conn,
             SigningKey::from_bytes(&rand::random()).verifying_key(),
             None,
+            None,
         )
         .await
         .unwrap();
@@ -279,4 +387,109 @@ mod test_verification {
             1 // two were added but only one is still valid
         )
     }
+
+    #[sqlx::test]
+    async fn rerequest_expired_attempt(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+
+        let key = VerifyingKey::default();
+        let code = db::verification::add_verification_attempt(&mut conn, key, None, None)
+            .await
+            .unwrap();
+
+        // let the attempt expire
+        let before = jiff::Timestamp::now()
+            .checked_sub(jiff::Span::new().minutes(3))
+            .unwrap()
+            .to_sqlx();
+        sqlx::query!(
+            "UPDATE verification_attempts SET timestamp = $1 WHERE id = $2",
+            before,
+            code
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+        let new_code = db::verification::add_verification_attempt(&mut conn, key, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            db::verification::count_attempts(&mut conn).await.unwrap(),
+            1
+        );
+        let old = db::verification::accept_attempt(&mut conn, code, "somehost".to_owned()).await;
+        assert!(matches!(old, Err(sqlx::Error::RowNotFound)));
+        db::verification::accept_attempt(&mut conn, new_code, "somehost".to_owned())
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn accept_pins_recipient(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+
+        let key = VerifyingKey::default();
+        let identity = age::x25519::Identity::generate();
+        let code = db::verification::add_verification_attempt(
+            &mut conn,
+            key,
+            None,
+            Some(identity.to_public().to_string()),
+        )
+        .await
+        .unwrap();
+        db::verification::accept_attempt(&mut conn, code, "somehost".to_owned())
+            .await
+            .unwrap();
+        let host = db::hosts::host_by_verify_key(&mut conn, key)
+            .await
+            .unwrap()
+            .unwrap();
+
+        db::hosts::check_recipient(&mut conn, host, &identity.to_public())
+            .await
+            .unwrap();
+        let mismatch = db::hosts::check_recipient(
+            &mut conn,
+            host,
+            &age::x25519::Identity::generate().to_public(),
+        )
+        .await;
+        assert!(matches!(mismatch, Err(db::hosts::RecipientError::Mismatch)));
+    }
+
+    #[sqlx::test]
+    async fn accept_without_recipient(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+
+        let key = VerifyingKey::default();
+        let code = db::verification::add_verification_attempt(&mut conn, key, None, None)
+            .await
+            .unwrap();
+        db::verification::accept_attempt(&mut conn, code, "somehost".to_owned())
+            .await
+            .unwrap();
+        let host = db::hosts::host_by_verify_key(&mut conn, key)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // the first recipient the host sends is pinned
+        let recipient = age::x25519::Identity::generate().to_public();
+        db::hosts::check_recipient(&mut conn, host, &recipient)
+            .await
+            .unwrap();
+        db::hosts::check_recipient(&mut conn, host, &recipient)
+            .await
+            .unwrap();
+        let mismatch = db::hosts::check_recipient(
+            &mut conn,
+            host,
+            &age::x25519::Identity::generate().to_public(),
+        )
+        .await;
+        assert!(matches!(mismatch, Err(db::hosts::RecipientError::Mismatch)));
+    }
 }
@@ -0,0 +1,143 @@
+//! Hostnames end up in ACLs, audit entries and paths on the hosts. Every hostname that is
+//! assigned by enrolling, importing or renaming a host is checked against `HostnameRules`
+
+use regex::Regex;
+
+error_set::error_set! {
+    HostnameError := {
+        #[display("Invalid hostname `{hostname}`: {reason}")]
+        Invalid { hostname: String, reason: &'static str },
+        #[display("Hostname `{hostname}` does not match `{pattern}`")]
+        NoMatch { hostname: String, pattern: String },
+    }
+}
+
+/// By default hostnames have to be DNS names. A pattern replaces the DNS rules but names with
+/// path separators, whitespace or control characters are always rejected
+#[derive(Clone, Debug, Default)]
+pub struct HostnameRules {
+    pattern: Option<Regex>,
+}
+
+impl HostnameRules {
+    /// The whole hostname has to match `pattern`
+    pub fn with_pattern(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: Some(Regex::new(&format!("^(?:{pattern})$"))?),
+        })
+    }
+
+    pub fn check(&self, hostname: &str) -> Result<(), HostnameError> {
+        let invalid = |reason| {
+            Err(HostnameError::Invalid {
+                hostname: hostname.to_owned(),
+                reason,
+            })
+        };
+
+        if hostname.is_empty() {
+            return invalid("must not be empty");
+        }
+        if hostname == "." || hostname == ".." {
+            return invalid("must not be a relative path");
+        }
+        if hostname.contains(['/', '\\']) {
+            return invalid("must not contain path separators");
+        }
+        if hostname
+            .chars()
+            .any(|char| char.is_whitespace() || char.is_control())
+        {
+            return invalid("must not contain whitespace or control characters");
+        }
+
+        match &self.pattern {
+            Some(pattern) if !pattern.is_match(hostname) => Err(HostnameError::NoMatch {
+                hostname: hostname.to_owned(),
+                pattern: pattern.as_str().to_owned(),
+            }),
+            Some(_) => Ok(()),
+            None => dns_name(hostname).or_else(invalid),
+        }
+    }
+}
+
+/// Labels of 1 to 63 letters, digits, hyphens and underscores separated by dots. Labels do not
+/// start or end with a hyphen. Underscores are not valid in DNS hostnames but common in existing
+/// fleets
+fn dns_name(hostname: &str) -> Result<(), &'static str> {
+    if hostname.len() > 253 {
+        return Err("must not be longer than 253 characters");
+    }
+    for label in hostname.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err("every label has to be 1 to 63 characters long");
+        }
+        if !label
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_')
+        {
+            return Err("may only contain letters, digits, hyphens, underscores and dots");
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err("labels must not start or end with a hyphen");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_hostname {
+    use super::{HostnameError, HostnameRules};
+
+    #[test]
+    fn dns_names() {
+        let rules = HostnameRules::default();
+        for valid in ["myhost", "host-b", "web01.example.com", "A1", "host_name"] {
+            rules.check(valid).unwrap();
+        }
+        for invalid in [
+            "",
+            ".",
+            "..",
+            "../etc",
+            "host/name",
+            "host\\name",
+            "my host",
+            "host\n",
+            "host\0",
+            "-host",
+            "host-",
+            "host..example",
+            "h\u{f6}st",
+            &"a".repeat(64),
+        ] {
+            assert!(
+                matches!(rules.check(invalid), Err(HostnameError::Invalid { .. })),
+                "{invalid:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn pattern() {
+        let rules = HostnameRules::with_pattern("[a-z_]+|prod-[0-9]+").unwrap();
+        rules.check("host_name").unwrap();
+        rules.check("prod-1").unwrap();
+        // the whole hostname has to match
+        assert!(matches!(
+            rules.check("prod-1x"),
+            Err(HostnameError::NoMatch { .. })
+        ));
+        // a pattern can not allow unsafe names
+        let rules = HostnameRules::with_pattern(".*").unwrap();
+        assert!(matches!(
+            rules.check("../etc"),
+            Err(HostnameError::Invalid { .. })
+        ));
+        assert!(matches!(
+            rules.check("my host"),
+            Err(HostnameError::Invalid { .. })
+        ));
+    }
+}
@@ -1,6 +1,8 @@
+use std::{net::SocketAddr, time::Instant};
+
 use axum::{
     Json,
-    extract::{FromRequest, FromRequestParts, Request},
+    extract::{ConnectInfo, FromRequest, FromRequestParts, Request},
     http::{self, HeaderMap, StatusCode, header},
 };
 use ed25519_dalek::VerifyingKey;
@@ -13,6 +15,7 @@ use serde::de::DeserializeOwned;
 use crate::{
     YeetState, db,
     error::{InternalError as _, WithStatusCode as _},
+    lockout,
 };
 
 pub struct HttpSig(pub VerifyingKey);
@@ -51,7 +54,10 @@ impl FromRequestParts<YeetState> for User {
         else {
             return Err((
                 StatusCode::FORBIDDEN,
-                "Key is registered but caller is not an user".to_owned(),
+                format!(
+                    "Key {} is registered but caller is not an user",
+                    api::key_fingerprint(&user_key)
+                ),
             ));
         };
 
@@ -59,12 +65,94 @@ impl FromRequestParts<YeetState> for User {
     }
 }
 
+/// A registered host. The host is always derived from the verified signature key
+/// so a host can never act on behalf of another host
+pub struct Host(pub api::HostID);
+
+impl FromRequestParts<YeetState> for Host {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &YeetState,
+    ) -> Result<Self, Self::Rejection> {
+        let host_key = extract_key(parts, state).await?;
+
+        let mut conn = state
+            .pool
+            .acquire()
+            .await
+            .with_code(StatusCode::INTERNAL_SERVER_ERROR)?;
+        let Some(host_id) = db::hosts::host_by_verify_key(&mut conn, host_key)
+            .await
+            .internal_server()?
+        else {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Unknown keyid. You are not a registered host".to_owned(),
+            ));
+        };
+
+        Ok(Host(host_id))
+    }
+}
+
+/// Sources that failed too often are refused before their signature is even looked at.
+/// Only signatures that do not verify count as failure. Unknown keyids are expected from agents
+/// waiting for their verification
 async fn extract_key(
     parts: &mut axum::http::request::Parts,
     state: &YeetState,
 ) -> Result<VerifyingKey, (StatusCode, String)> {
     let req = http::Request::from_parts(parts.clone(), String::new());
+    let keyid = signature_keyid(&req)?;
+
+    let source = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| lockout::Source {
+            addr: addr.ip(),
+            keyid: keyid.clone(),
+        });
+
+    if let Some(source) = &source
+        && let Some(remaining) = state
+            .failed_verifications
+            .locked_out(source, Instant::now())
+    {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            format!(
+                "Too many failed signature verifications. Retry in {}s",
+                remaining.as_secs().max(1)
+            ),
+        ));
+    }
+
+    let Some(verifying_key) = registered_key(state, &keyid).await? else {
+        // the db does not have any users so we allow to add the first admin
+        return Ok(VerifyingKey::default());
+    };
+
+    let pub_key = PublicKey::from_bytes(&AlgorithmName::Ed25519, verifying_key.as_bytes())
+        .with_code(StatusCode::BAD_REQUEST)?;
 
+    let verified = req
+        .verify_message_signature(&pub_key, Some(&keyid))
+        .await
+        .with_code(StatusCode::BAD_REQUEST);
+    if let Some(source) = &source {
+        match verified {
+            Ok(_) => state.failed_verifications.success(source),
+            Err(_) => state.failed_verifications.failure(source, Instant::now()),
+        }
+    }
+    verified?;
+    Ok(verifying_key)
+}
+
+/// The one Ed25519 keyid the request claims to be signed with
+fn signature_keyid(req: &http::Request<String>) -> Result<String, (StatusCode, String)> {
     let keyids = req.get_alg_key_ids().with_code(StatusCode::BAD_REQUEST)?;
     if keyids.len() != 1 {
         return Err((
@@ -85,46 +173,44 @@ async fn extract_key(
         ));
     }
 
-    #[expect(clippy::pattern_type_mismatch)] // I am to dumb for this one
-    let Some(keyid) = keyid else {
-        return Err((
+    keyid.clone().ok_or_else(|| {
+        (
             StatusCode::BAD_REQUEST,
             "Key signature included but no keyid found".to_owned(),
-        ));
-    };
-    // TODO maybe acquire a connection only once instead of here and in the handler
+        )
+    })
+}
 
+/// The key registered for `keyid`. `None` while no admin exists so the first one can be added
+async fn registered_key(
+    state: &YeetState,
+    keyid: &str,
+) -> Result<Option<VerifyingKey>, (StatusCode, String)> {
+    // TODO maybe acquire a connection only once instead of here and in the handler
     let mut conn = state
         .pool
         .acquire()
         .await
         .with_code(StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    let Some(verifying_key) = db::keys::fetch_by_keyid(&mut conn, keyid)
+    if let Some(verifying_key) = db::keys::fetch_by_keyid(&mut conn, keyid)
         .await
         .with_code(StatusCode::INTERNAL_SERVER_ERROR)?
-    else {
-        // the db does not have any users so we allow to add the first admin
-        if !db::keys::has_any_admin(&mut conn)
-            .await
-            .with_code(StatusCode::INTERNAL_SERVER_ERROR)?
-        {
-            return Ok(VerifyingKey::default());
-        }
-
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "The KeyID is not registered".to_owned(),
-        ));
-    };
-
-    let pub_key = PublicKey::from_bytes(&AlgorithmName::Ed25519, verifying_key.as_bytes())
-        .with_code(StatusCode::BAD_REQUEST)?;
+    {
+        return Ok(Some(verifying_key));
+    }
 
-    req.verify_message_signature(&pub_key, Some(keyid))
+    if !db::keys::has_any_admin(&mut conn)
         .await
-        .with_code(StatusCode::BAD_REQUEST)?;
-    Ok(verifying_key)
+        .with_code(StatusCode::INTERNAL_SERVER_ERROR)?
+    {
+        return Ok(None);
+    }
+
+    Err((
+        StatusCode::BAD_REQUEST,
+        "The KeyID is not registered".to_owned(),
+    ))
 }
 
 pub struct VerifiedJson<T>(pub T);
@@ -3,7 +3,7 @@ use std::{
     env,
     fs::File,
     io::{self},
-    net::SocketAddr,
+    net::{AddrParseError, IpAddr, SocketAddr},
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
@@ -12,42 +12,69 @@ use std::{
 use axum::routing::{delete, get, post, put};
 
 mod routes {
+    pub mod approval;
+    pub mod audit;
+    pub mod auth;
     pub mod health;
     pub mod host;
     pub mod key;
+    pub mod maintenance;
     pub mod osquery;
     pub mod secret;
+    pub mod status;
     pub mod system;
     pub mod tag;
     pub mod user;
     pub mod verify;
 }
 mod db {
+    pub mod approvals;
+    pub mod audit;
+    pub mod groups;
     pub mod hosts;
     pub mod keys;
+    pub mod maintenance;
     pub mod osquery;
     pub mod secrets;
     pub mod tag;
     pub mod user;
     pub mod verification;
 }
+pub mod data_dir;
 pub mod defectdojo;
 mod error;
+pub mod hostname;
 mod httpsig;
+mod lockout;
 mod splunk_sender;
+pub mod store_key;
+pub mod webhook;
 
 use axum_server::tls_rustls::RustlsConfig;
 use ed25519_dalek::VerifyingKey;
 use indexmap::IndexMap;
-pub(crate) use routes::{health, host, key, secret, system, verify};
+pub(crate) use routes::{
+    approval, audit, auth, health, host, key, maintenance, secret, status, system, verify,
+};
+use store_key::StoreKey;
+use tower_http::limit::RequestBodyLimitLayer;
 
 #[derive(Clone)]
 struct YeetState {
     pub pool: sqlx::SqlitePool,
-    pub age_key: Arc<age::x25519::Identity>,
+    pub age_key: Arc<dyn StoreKey>,
     pub splunk_sender: Option<tokio::sync::mpsc::Sender<()>>,
     pub defectdojo_sender: Option<tokio::sync::mpsc::Sender<defectdojo::Action>>,
+    pub webhook_sender: Option<tokio::sync::mpsc::Sender<webhook::Event>>,
     pub osquery_packs: IndexMap<String, serde_json::Value>,
+    pub failed_verifications: Arc<lockout::FailedVerifications>,
+    /// Store new and rotated secrets zstd compressed
+    pub compress_secrets: bool,
+    pub hostnames: hostname::HostnameRules,
+    /// Removing hosts and secrets needs a second admin
+    pub two_person_rule: bool,
+    /// Admins may read the plaintext a host would receive with `/secret/as_host`
+    pub secret_peek: bool,
 }
 
 use serde::{Deserialize, Serialize};
@@ -55,6 +82,40 @@ use serde_json_any_key::any_key_map;
 
 use crate::routes::{osquery, tag, user};
 
+/// Upper bounds for request bodies in bytes. Larger requests are rejected with 413
+#[derive(Clone, Copy, Debug)]
+pub struct BodyLimits {
+    /// Applies to every route not listed in `large`
+    pub default: usize,
+    /// Routes that carry secrets, nixos-facter reports or osquery logs
+    pub large: usize,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            default: 1024 * 1024,
+            large: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Sources with `threshold` failed signature verifications within `window` get 429 for `window`
+#[derive(Clone, Copy, Debug)]
+pub struct Lockout {
+    pub threshold: u32,
+    pub window: Duration,
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Self {
+            threshold: 10,
+            window: Duration::from_mins(5),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Default)]
 pub struct AppState {
     #[serde(with = "any_key_map")]
@@ -62,18 +123,132 @@ pub struct AppState {
     keyids: HashMap<String, VerifyingKey>,
 }
 
+impl AppState {
+    /// Only hosts whose key is also listed in `keyids` are imported
+    fn trusted_hosts(&self) -> impl Iterator<Item = (&VerifyingKey, &String)> {
+        let valid_keys = self.keyids.values().collect::<Vec<_>>();
+        self.host_by_key
+            .iter()
+            .filter(move |(key, _)| valid_keys.contains(key))
+    }
+}
+
+error_set::error_set! {
+    StateProblem := {
+        #[display("Host {hostname} is skipped because its key is not listed in `keyids`")]
+        UntrustedKey { hostname: String },
+        #[display("Hostname {hostname} is used by more than one key")]
+        DuplicateHostname { hostname: String },
+        #[display("Secret {name} can not be opened with the store key: {reason}")]
+        UndecryptableSecret { name: String, reason: String },
+        #[display("ACL entry of secret {secret} for host {host} references a missing secret or host")]
+        DanglingAcl { secret: api::SecretID, host: api::HostID },
+        #[display("Alias {alias} of secret {target} references a missing secret")]
+        DanglingAlias { alias: api::SecretID, target: api::SecretID },
+    }
+}
+
+/// What `launch` would import from a `state.json`
+#[derive(Debug)]
+pub struct StateReport {
+    /// Hosts that would be imported
+    pub hosts: usize,
+    pub problems: Vec<StateProblem>,
+}
+
+/// Validates a `state.json` without touching the database. Errors if it does not match the schema.
+/// Secrets and their ACLs are not part of the `state.json`. They only live in `yeet.db`
+pub fn check_state(state: impl io::Read) -> Result<StateReport, serde_json::Error> {
+    let state: AppState = serde_json::from_reader(state)?;
+
+    let mut untrusted = state
+        .host_by_key
+        .iter()
+        .filter(|(key, _)| !state.keyids.values().any(|valid| valid == *key))
+        .map(|(_, hostname)| hostname.clone())
+        .collect::<Vec<_>>();
+    untrusted.sort();
+
+    let mut hostnames = state
+        .trusted_hosts()
+        .map(|(_, hostname)| hostname)
+        .collect::<Vec<_>>();
+    hostnames.sort();
+    let mut duplicates = hostnames
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [first, second] if first == second => Some((*first).clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    duplicates.dedup();
+
+    Ok(StateReport {
+        hosts: hostnames.len(),
+        problems: untrusted
+            .into_iter()
+            .map(|hostname| StateProblem::UntrustedKey { hostname })
+            .chain(
+                duplicates
+                    .into_iter()
+                    .map(|hostname| StateProblem::DuplicateHostname { hostname }),
+            )
+            .collect(),
+    })
+}
+
+/// Checks the secrets in `yeet.db` against `store_key`: every secret has to decrypt and every ACL
+/// and alias row has to reference existing secrets and hosts. Nothing is written
+pub async fn check_store<K: StoreKey + ?Sized>(
+    conn: &mut sqlx::SqliteConnection,
+    store_key: &K,
+) -> Result<Vec<StateProblem>, sqlx::Error> {
+    let mut problems = Vec::new();
+    for (id, name) in db::secrets::all_secrets(conn).await? {
+        match db::secrets::check_secret(conn, id, store_key).await {
+            Ok(()) => {}
+            Err(db::secrets::CheckSecretError::SQLXError(err)) => return Err(err),
+            Err(err) => problems.push(StateProblem::UndecryptableSecret {
+                name,
+                reason: err.to_string(),
+            }),
+        }
+    }
+    problems.extend(
+        db::secrets::dangling_acl(conn)
+            .await?
+            .into_iter()
+            .map(|(secret, host)| StateProblem::DanglingAcl { secret, host }),
+    );
+    problems.extend(
+        db::secrets::dangling_aliases(conn)
+            .await?
+            .into_iter()
+            .map(|(alias, target)| StateProblem::DanglingAlias { alias, target }),
+    );
+    Ok(problems)
+}
+
 // TODO: too_many_arguments
 #[expect(clippy::too_many_arguments)]
 #[expect(clippy::missing_panics_doc)]
-pub async fn launch<I: Into<std::net::IpAddr>>(
+pub async fn launch<H: IntoIterator<Item = IpAddr>>(
     port: u16,
-    host: I,
+    hosts: H,
     pool: sqlx::SqlitePool,
-    age_key: age::x25519::Identity,
+    age_key: impl StoreKey + 'static,
     tls: Option<RustlsConfig>,
     splunk: Option<splunk_hec::SplunkConfig>,
     osquery_packs: Option<PathBuf>,
     defectdojo: Option<defectdojo::Config>,
+    body_limits: BodyLimits,
+    lockout: Lockout,
+    compress_secrets: bool,
+    hostnames: hostname::HostnameRules,
+    state: Option<PathBuf>,
+    webhooks: webhook::Webhooks,
+    two_person_rule: bool,
+    secret_peek: bool,
 ) -> tokio::task::JoinHandle<()> {
     #[expect(clippy::unwrap_used)]
     {
@@ -83,23 +258,25 @@ pub async fn launch<I: Into<std::net::IpAddr>>(
             .await
             .unwrap();
         // add hosts from state.json
-        let state = std::fs::File::open("state.json");
-        if let Ok(state) = state
+        if let Some(state) = state
+            && let Ok(state) = std::fs::File::open(state)
             && !db::keys::has_any_admin(&mut conn).await.unwrap()
         {
             let state: AppState = serde_json::from_reader(state).unwrap();
-            let valid_keys = state.keyids.values().collect::<Vec<_>>();
-            for (key, hostname) in state.host_by_key {
-                if valid_keys.contains(&&key) {
-                    db::hosts::add_host(&mut conn, key, hostname).await.unwrap();
-                }
+            for (key, hostname) in state.trusted_hosts() {
+                db::hosts::add_host(&mut conn, *key, hostname.clone())
+                    .await
+                    .unwrap();
             }
         }
     }
 
-    let addr = SocketAddr::from((host, port));
+    let addrs = hosts
+        .into_iter()
+        .map(|host| SocketAddr::from((host, port)))
+        .collect::<Vec<_>>();
 
-    let age_key = Arc::new(age_key);
+    let age_key: Arc<dyn StoreKey> = Arc::new(age_key);
 
     let splunk_sender = if let Some(splunk) = splunk {
         let (tx, rx) = tokio::sync::mpsc::channel(5);
@@ -119,6 +296,15 @@ pub async fn launch<I: Into<std::net::IpAddr>>(
         None
     };
 
+    let webhook_sender = if webhooks.hooks.is_empty() {
+        None
+    } else {
+        let (tx, rx) = tokio::sync::mpsc::channel(32);
+        let pool = pool.clone();
+        let _detached = tokio::spawn(async move { webhook::run(webhooks, rx, pool).await });
+        Some(tx)
+    };
+
     let osquery_packs = osquery_packs
         .map(|path| get_osquery_packs(&path).expect("Could not retrive packs"))
         .unwrap_or_default();
@@ -128,31 +314,97 @@ pub async fn launch<I: Into<std::net::IpAddr>>(
         age_key,
         splunk_sender,
         defectdojo_sender,
+        webhook_sender,
         osquery_packs,
+        failed_verifications: Arc::new(lockout::FailedVerifications::new(lockout)),
+        compress_secrets,
+        hostnames,
+        two_person_rule,
+        secret_peek,
     };
 
     // wake the splunk sender immediately so that he can send all logs
     wake_splunk(state.splunk_sender.as_ref()).await;
 
-    tokio::spawn(async move {
-        if let Some(tls) = tls {
-            axum_server::bind_rustls(addr, tls)
-                .serve(routes(state).into_make_service())
-                .await
-                .expect("Could not start axum");
+    let app = routes(state, body_limits).into_make_service_with_connect_info::<SocketAddr>();
+    let mut servers = tokio::task::JoinSet::new();
+    for addr in addrs {
+        let app = app.clone();
+        // bound before returning so that the server accepts connections once `launch` is done
+        let listener = listen(addr).expect("Could not bind the listen address");
+        if let Some(tls) = tls.clone() {
+            servers.spawn(async move {
+                axum_server::from_tcp_rustls(listener, tls)?
+                    .serve(app)
+                    .await
+            });
         } else {
-            axum_server::bind(addr)
-                .serve(routes(state).into_make_service())
-                .await
+            servers.spawn(async move { axum_server::from_tcp(listener)?.serve(app).await });
+        }
+    }
+
+    // the first address that fails takes the whole server down
+    tokio::spawn(async move {
+        while let Some(server) = servers.join_next().await {
+            server
+                .expect("axum panicked")
                 .expect("Could not start axum");
         }
     })
 }
 
-fn routes(state: YeetState) -> axum::Router {
-    axum::Router::new()
+/// IPv6 sockets only take IPv6 connections. Otherwise `::` would also claim the IPv4 port on
+/// Linux and `::,0.0.0.0` would fail with `EADDRINUSE`
+fn listen(addr: SocketAddr) -> io::Result<std::net::TcpListener> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+/// Comma separated list of addresses like `::1,127.0.0.1`
+pub fn parse_hosts(hosts: &str) -> Result<Vec<IpAddr>, AddrParseError> {
+    hosts.split(',').map(|host| host.trim().parse()).collect()
+}
+
+fn routes(state: YeetState, body_limits: BodyLimits) -> axum::Router {
+    // Routes that legitimately carry larger payloads
+    let large_payloads = axum::Router::new()
         // Public
         .route("/verification/add", post(verify::add_verification_attempt))
+        // `api::auth::Secret::Create`
+        .route("/secret/add/{name}", post(secret::add_secret))
+        // `api::auth::Secret::Create`
+        .route(
+            "/secret/add/{name}/type/{content_type}",
+            post(secret::add_typed_secret),
+        )
+        // `api::auth::Secret::Create`
+        .route(
+            "/secret/add/{name}/sealed/{host}",
+            post(secret::add_sealed_secret),
+        )
+        // `api::auth::Secret::Create`
+        .route("/secret/put", post(secret::put_secret))
+        // `api::auth::Secret::Create`
+        .route("/secret/{id}/rotate", put(secret::rotate_secret))
+        // Host
+        .route("/system/facter", post(system::facter))
+        // === Osquery - Node
+        .route("/osquery/query/write", post(osquery::query_write))
+        .route("/osquery/log", post(osquery::log))
+        .layer(RequestBodyLimitLayer::new(body_limits.large));
+
+    axum::Router::new()
         // `api::auth::Host::Accept`
         .route("/verification/{id}/accept", put(verify::accept_attempt))
         // Public
@@ -160,8 +412,6 @@ fn routes(state: YeetState) -> axum::Router {
         // Public / legacy path binding
         .route("/system/verify", get(verify::is_host_verified))
         // === Secrets
-        // `api::auth::Secret::Create`
-        .route("/secret/add/{name}", post(secret::add_secret))
         // `api::auth::Secret::Allow`
         .route(
             "/secret/{secret_id}/allow/{host_id}",
@@ -172,18 +422,35 @@ fn routes(state: YeetState) -> axum::Router {
             "/secret/{secret_id}/block/{host_id}",
             put(secret::block_host),
         )
+        // `api::auth::Secret::Allow` and `api::auth::Secret::Block`
+        .route("/secret/acl/batch", post(secret::batch_acl))
         // `api::auth::Secret::Rename`
         .route("/secret/{id}/rename/{name}", put(secret::rename_secret))
         // `api::auth::Secret::Delete`
         .route("/secret/{id}/delete", delete(secret::delete_secret))
+        // `api::auth::Secret::Alias`
+        .route("/secret/{id}/alias/{name}", post(secret::add_alias))
+        // `api::auth::Secret::RemoveAlias`
+        .route("/secret/{id}/alias", delete(secret::delete_alias))
         // `api::auth::Secret::View`
         .route("/secret/list", get(secret::list_secrets))
+        .route("/secret/list/page", get(secret::list_secrets_page))
+        // `api::auth::Secret::View`
+        .route("/secret/acl/by-secret", get(secret::get_acl_by_secret))
+        // `api::auth::Secret::View`
+        .route("/secret/check", post(secret::check_secret))
+        // `api::auth::Secret::View`
+        .route("/secret/check-access", get(secret::check_access))
+        // `api::auth::Secret::Peek`. Only if enabled with `YEET_SECRET_PEEK`
+        .route("/secret/as_host", post(secret::secret_as_host))
         // Public
         .route("/secret/server_key", get(secret::get_server_age_key)) // locked
         // Public
         .route("/secret", post(secret::get_secret)) // locked
         // === Keys
         .route("/key/delete", delete(key::delete_key))
+        // `api::auth::Key::View`
+        .route("/key/list", get(key::list_keys))
         // === User
         .route("/user", get(user::list_users))
         .route("/user/create", post(user::create_user))
@@ -202,27 +469,73 @@ fn routes(state: YeetState) -> axum::Router {
         .route("/host", get(host::list_hosts))
         // `api::auth::Host::Rename`
         .route("/host/{id}/rename/{name}", put(host::rename_host))
+        // `api::auth::Host::View`
+        .route("/host/{id}/facter", get(host::facter))
         // `api::auth::Host::Update`
         .route("/host/update", post(host::update_hosts)) // TODO: use put and make it non batch
+        // `api::auth::Host::Accept`
+        .route("/host/import", post(host::import_hosts))
+        // `api::auth::HostGroup::Create`
+        .route("/host/group/create", post(host::create_group))
+        // `api::auth::HostGroup::Edit`
+        .route("/host/group/add", post(host::add_to_group))
+        // `api::auth::HostGroup::Edit`
+        .route("/host/group/remove", post(host::remove_from_group))
+        // `api::auth::HostGroup::View`
+        .route("/host/groups", get(host::list_groups))
         // === System - Public
         .route("/system/self/detach", put(system::detach))
+        .route("/system/detach/effective", get(system::detach_permission))
+        // `api::auth::Host::Detach`
+        .route(
+            "/system/detach/permission",
+            put(system::set_detach_permission),
+        )
         .route("/system/self/attach", put(system::attach))
         .route("/system/check", post(system::system_check)) // locked
+        .route("/system/report", post(system::report))
+        .route("/system/check/download-stats", post(system::download_stats))
+        .route("/status/host_by_key", post(status::hosts_by_key))
         // === Osquery - Node
         .route("/osquery/enroll", post(osquery::enroll))
         .route("/osquery/query/read", post(osquery::query_read))
-        .route("/osquery/query/write", post(osquery::query_write))
         .route("/osquery/config", post(osquery::config))
-        .route("/osquery/log", post(osquery::log))
         // === Osquery
         .route("/osquery/nodes", get(osquery::list_nodes))
         .route("/osquery/query/create", post(osquery::create_query))
+        // === Approval
+        // `api::auth::Approval::Propose`
+        .route("/approval/propose", post(approval::propose))
+        // `api::auth::Approval::Confirm`
+        .route("/approval/confirm", post(approval::confirm))
+        // `api::auth::Approval::View`
+        .route("/approval", get(approval::list))
+        // === Auth
+        .route("/auth/explain", post(auth::explain))
+        // === Audit
+        .route("/audit/mutations", get(audit::mutations))
+        // === Maintenance
+        .route("/maintenance/prune", post(maintenance::prune))
         // === health endpoint
         .route("/health", get(health::health))
+        .route("/metrics", get(health::metrics))
+        .route("/server/info", get(health::info))
+        .layer(RequestBodyLimitLayer::new(body_limits.default))
+        .merge(large_payloads)
+        .layer(axum::middleware::map_response(server_time))
         .layer(tower_http::trace::TraceLayer::new_for_http())
         .with_state(state)
 }
 
+/// Sets `api::SERVER_TIME_HEADER` so agents with a wrong clock can tell why they are rejected
+async fn server_time(mut response: axum::response::Response) -> axum::response::Response {
+    response.headers_mut().insert(
+        api::SERVER_TIME_HEADER,
+        axum::http::HeaderValue::from(jiff::Timestamp::now().as_second()),
+    );
+    response
+}
+
 pub(crate) async fn wake_splunk(sender: Option<&tokio::sync::mpsc::Sender<()>>) {
     if let Some(sender) = sender {
         // TODO: log if we could not notify
@@ -240,6 +553,16 @@ pub(crate) async fn wake_defectdojo(
     }
 }
 
+pub(crate) async fn notify_webhooks(
+    sender: Option<&tokio::sync::mpsc::Sender<webhook::Event>>,
+    event: webhook::Event,
+) {
+    if let Some(sender) = sender {
+        // TODO: log if we could not notify
+        let _ignore = sender.send_timeout(event, Duration::from_secs(1)).await;
+    }
+}
+
 /// Read all files in a directory to json
 #[expect(clippy::indexing_slicing)]
 fn get_osquery_packs(path: &Path) -> Result<IndexMap<String, serde_json::Value>, io::Error> {
@@ -308,3 +631,245 @@ async fn sql_conn(pool: sqlx::SqlitePool) -> sqlx::pool::PoolConnection<sqlx::Sq
         .unwrap();
     conn
 }
+
+#[cfg(test)]
+mod test_bind {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use super::{listen, parse_hosts};
+
+    #[test]
+    fn single_address() {
+        assert_eq!(
+            parse_hosts("::1"),
+            Ok(vec![IpAddr::V6(Ipv6Addr::LOCALHOST)])
+        );
+    }
+
+    #[test]
+    fn dual_stack() {
+        assert_eq!(
+            parse_hosts("::, 0.0.0.0"),
+            Ok(vec![
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_address() {
+        parse_hosts("::1,localhost").unwrap_err();
+        parse_hosts("").unwrap_err();
+    }
+
+    #[test]
+    fn dual_stack_same_port() {
+        let v6 = listen(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))).unwrap();
+        let port = v6.local_addr().unwrap().port();
+        listen(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_body_limit {
+    use std::sync::Arc;
+
+    use axum::http::{StatusCode, header};
+    use axum_test::TestServer;
+
+    use crate::{BodyLimits, YeetState};
+
+    fn server(pool: sqlx::SqlitePool) -> TestServer {
+        let state = YeetState {
+            pool,
+            age_key: Arc::new(age::x25519::Identity::generate()),
+            splunk_sender: None,
+            defectdojo_sender: None,
+            webhook_sender: None,
+            osquery_packs: indexmap::IndexMap::new(),
+            failed_verifications: Arc::new(crate::lockout::FailedVerifications::new(
+                crate::Lockout::default(),
+            )),
+            compress_secrets: false,
+            hostnames: crate::hostname::HostnameRules::default(),
+            two_person_rule: false,
+            secret_peek: false,
+        };
+        TestServer::new(super::routes(
+            state,
+            BodyLimits {
+                default: 16,
+                large: 64,
+            },
+        ))
+    }
+
+    // Clients like reqwest send a `Content-Length` which lets the server reject the request
+    // before the body is read
+    #[sqlx::test]
+    async fn over_limit(pool: sqlx::SqlitePool) {
+        let server = server(pool);
+
+        let response = server
+            .post("/user/create")
+            .bytes(vec![b'a'; 32].into())
+            .add_header(header::CONTENT_LENGTH, "32")
+            .expect_failure()
+            .await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = server
+            .post("/secret/add/my-secret")
+            .bytes(vec![b'a'; 128].into())
+            .add_header(header::CONTENT_LENGTH, "128")
+            .expect_failure()
+            .await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[sqlx::test]
+    async fn server_time_header(pool: sqlx::SqlitePool) {
+        let server = server(pool);
+
+        let before = jiff::Timestamp::now().as_second();
+        let response = server.get("/health").await;
+        let server_time = api::server_time(response.headers()).unwrap().as_second();
+        assert!((before..=jiff::Timestamp::now().as_second()).contains(&server_time));
+
+        // rejected requests carry it as well
+        let response = server.get("/user").expect_failure().await;
+        assert!(api::server_time(response.headers()).is_some());
+    }
+
+    #[sqlx::test]
+    async fn large_payload_route(pool: sqlx::SqlitePool) {
+        let server = server(pool);
+
+        // over the default limit but within the limit for large payloads
+        let response = server
+            .post("/secret/add/my-secret")
+            .bytes(vec![b'a'; 32].into())
+            .add_header(header::CONTENT_LENGTH, "32")
+            .expect_failure()
+            .await;
+        assert_ne!(response.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
+
+#[cfg(test)]
+mod test_check_state {
+    use ed25519_dalek::SigningKey;
+
+    use super::{AppState, StateProblem, check_state};
+
+    fn state(hosts: &[(u8, &str)], trusted: &[u8]) -> Vec<u8> {
+        let key = |seed: u8| SigningKey::from_bytes(&[seed; 32]).verifying_key();
+        serde_json::to_vec(&AppState {
+            host_by_key: hosts
+                .iter()
+                .map(|(seed, hostname)| (key(*seed), (*hostname).to_owned()))
+                .collect(),
+            keyids: trusted
+                .iter()
+                .map(|seed| (format!("key-{seed}"), key(*seed)))
+                .collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn clean_state() {
+        let report = check_state(state(&[(1, "web"), (2, "db")], &[1, 2]).as_slice()).unwrap();
+        assert_eq!(report.hosts, 2);
+        assert!(report.problems.is_empty());
+    }
+
+    #[test]
+    fn broken_state() {
+        let report =
+            check_state(state(&[(1, "web"), (2, "web"), (3, "db")], &[1, 2]).as_slice()).unwrap();
+        assert_eq!(report.hosts, 2);
+        assert!(matches!(
+            report.problems.as_slice(),
+            [
+                StateProblem::UntrustedKey { hostname: untrusted },
+                StateProblem::DuplicateHostname { hostname: duplicate },
+            ] if untrusted == "db" && duplicate == "web"
+        ));
+    }
+
+    #[test]
+    fn not_a_state() {
+        check_state(br#"{"hosts": []}"#.as_slice()).unwrap_err();
+    }
+
+    #[sqlx::test]
+    async fn broken_store(pool: sqlx::SqlitePool) {
+        let mut conn = crate::sql_conn(pool).await;
+        let store_key = age::x25519::Identity::generate();
+        let add = async |conn: &mut sqlx::SqliteConnection, name: &str| {
+            let encrypted = age::encrypt(&store_key.to_public(), name.as_bytes()).unwrap();
+            crate::db::secrets::add_secret(conn, name, encrypted, &store_key, false)
+                .await
+                .unwrap()
+                .id
+        };
+        add(&mut conn, "fine").await;
+        let gone = add(&mut conn, "gone").await;
+        let target = add(&mut conn, "target").await;
+        let alias = add(&mut conn, "alias").await;
+        sqlx::query("INSERT INTO secret_aliases (secret_id, target_id) VALUES ($1, $2)")
+            .bind(alias)
+            .bind(target)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        assert!(
+            super::check_store(&mut conn, &store_key)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        // rows left behind by changes made without foreign keys
+        sqlx::query("PRAGMA foreign_keys = OFF")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO secrets_acl (secret_id, host_id) VALUES ($1, 999)")
+            .bind(gone)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM secrets WHERE id IN ($1, $2)")
+            .bind(gone)
+            .bind(target)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        // encrypted for another store key
+        let foreign =
+            age::encrypt(&age::x25519::Identity::generate().to_public(), b"other").unwrap();
+        sqlx::query("UPDATE secrets SET secret = $1 WHERE name = 'fine'")
+            .bind(foreign)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+
+        let problems = super::check_store(&mut conn, &store_key).await.unwrap();
+        assert!(
+            matches!(
+                problems.as_slice(),
+                [
+                    StateProblem::UndecryptableSecret { name: alias_name, .. },
+                    StateProblem::UndecryptableSecret { name: fine, .. },
+                    StateProblem::DanglingAcl { secret, .. },
+                    StateProblem::DanglingAlias { alias: dangling, target: missing },
+                ] if alias_name == "alias" && fine == "fine" && *secret == gone
+                    && *dangling == alias && *missing == target
+            ),
+            "{problems:?}"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Counts failed signature verifications per source address and keyid. Sources with too many
+//! failures are refused until `Lockout::window` passed
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{
+        PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::Lockout;
+
+/// The address of a client and the keyid it signed with. Clients sharing an address behind a
+/// NAT do not lock each other out
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Source {
+    pub addr: IpAddr,
+    pub keyid: String,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.addr, self.keyid)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Failures {
+    count: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    /// Failures are forgotten one window after the first one or once the lockout is over
+    fn expired(&self, now: Instant, window: Duration) -> bool {
+        match self.locked_until {
+            Some(until) => now >= until,
+            None => now.saturating_duration_since(self.since) >= window,
+        }
+    }
+}
+
+/// Every signed request checks and clears its source. A `RwLock` keeps those lookups concurrent,
+/// only failures and clearing an existing entry take the write lock
+pub struct FailedVerifications {
+    config: Lockout,
+    sources: RwLock<HashMap<Source, Failures>>,
+    /// Lockouts since the server started
+    lockouts: AtomicU64,
+}
+
+impl FailedVerifications {
+    pub fn new(config: Lockout) -> Self {
+        Self {
+            config,
+            sources: RwLock::new(HashMap::new()),
+            lockouts: AtomicU64::new(0),
+        }
+    }
+
+    fn sources(&self) -> RwLockReadGuard<'_, HashMap<Source, Failures>> {
+        self.sources.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn sources_mut(&self) -> RwLockWriteGuard<'_, HashMap<Source, Failures>> {
+        self.sources.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// How long `source` is still locked out
+    pub fn locked_out(&self, source: &Source, now: Instant) -> Option<Duration> {
+        let until = self.sources().get(source)?.locked_until?;
+        (now < until).then(|| until.saturating_duration_since(now))
+    }
+
+    pub fn failure(&self, source: &Source, now: Instant) {
+        let mut sources = self.sources_mut();
+        sources.retain(|_, failures| !failures.expired(now, self.config.window));
+
+        let failures = sources.entry(source.clone()).or_insert(Failures {
+            count: 0,
+            since: now,
+            locked_until: None,
+        });
+        failures.count = failures.count.saturating_add(1);
+        if failures.count >= self.config.threshold && failures.locked_until.is_none() {
+            failures.locked_until = now.checked_add(self.config.window);
+            let lockouts = self
+                .lockouts
+                .fetch_add(1, Ordering::Relaxed)
+                .saturating_add(1);
+            log::warn!(
+                "Locked out {source} for {:?} after {} failed signature verifications. {lockouts} lockouts in total",
+                self.config.window,
+                failures.count
+            );
+        }
+    }
+
+    pub fn success(&self, source: &Source) {
+        // most sources never failed. Do not serialize them on the write lock
+        if self.sources().contains_key(source) {
+            self.sources_mut().remove(source);
+        }
+    }
+
+    pub fn lockouts(&self) -> u64 {
+        self.lockouts.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test_lockout {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use super::{FailedVerifications, Source};
+    use crate::Lockout;
+
+    const ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn source(addr: IpAddr, keyid: &str) -> Source {
+        Source {
+            addr,
+            keyid: keyid.to_owned(),
+        }
+    }
+
+    fn tracker() -> FailedVerifications {
+        FailedVerifications::new(Lockout {
+            threshold: 3,
+            window: Duration::from_mins(1),
+        })
+    }
+
+    #[test]
+    fn lockout_after_threshold() {
+        let tracker = tracker();
+        // another address or another key behind the same address
+        let others = [
+            source(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)), "host-a"),
+            source(ADDR, "host-b"),
+        ];
+        let source = source(ADDR, "host-a");
+        let start = Instant::now();
+
+        tracker.failure(&source, start);
+        tracker.failure(&source, start);
+        assert_eq!(tracker.locked_out(&source, start), None);
+
+        tracker.failure(&source, start);
+        assert_eq!(
+            tracker.locked_out(&source, start),
+            Some(Duration::from_mins(1))
+        );
+        for other in &others {
+            assert_eq!(tracker.locked_out(other, start), None);
+        }
+        assert_eq!(tracker.lockouts(), 1);
+
+        // further failures do not extend the lockout
+        tracker.failure(&source, start + Duration::from_secs(30));
+        assert_eq!(
+            tracker.locked_out(&source, start + Duration::from_secs(30)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(tracker.lockouts(), 1);
+
+        assert_eq!(
+            tracker.locked_out(&source, start + Duration::from_mins(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn success_resets() {
+        let tracker = tracker();
+        let source = source(ADDR, "host-a");
+        let start = Instant::now();
+
+        tracker.failure(&source, start);
+        tracker.failure(&source, start);
+        tracker.success(&source);
+        tracker.failure(&source, start);
+        tracker.failure(&source, start);
+        assert_eq!(tracker.locked_out(&source, start), None);
+    }
+
+    #[test]
+    fn failures_expire() {
+        let tracker = tracker();
+        let source = source(ADDR, "host-a");
+        let start = Instant::now();
+
+        tracker.failure(&source, start);
+        tracker.failure(&source, start);
+        // the first two failures are out of the window
+        let later = start + Duration::from_secs(61);
+        tracker.failure(&source, later);
+        assert_eq!(tracker.locked_out(&source, later), None);
+
+        // a finished lockout starts counting from zero
+        tracker.failure(&source, later);
+        tracker.failure(&source, later);
+        assert!(tracker.locked_out(&source, later).is_some());
+        let after = later + Duration::from_mins(1);
+        tracker.failure(&source, after);
+        assert_eq!(tracker.locked_out(&source, after), None);
+    }
+}
@@ -4,8 +4,10 @@ use std::{
     env,
     fs::{File, read_to_string},
     io::Write as _,
-    path::Path,
+    path::{Path, PathBuf},
+    process::ExitCode,
     str::FromStr as _,
+    time::Duration,
 };
 
 use age::secrecy::ExposeSecret as _;
@@ -18,7 +20,22 @@ use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
     clippy::unwrap_used,
     reason = "allow in server main"
 )]
-async fn main() {
+async fn main() -> ExitCode {
+    // `yeetd --check-state <state.json>` validates a state and the store before they are deployed
+    let mut args = env::args().skip(1);
+    if args.next().as_deref() == Some("--check-state") {
+        let path = args
+            .next()
+            .expect("Usage: yeetd --check-state <state.json>");
+        let state = check_state(Path::new(&path));
+        let store = check_store(&yeetd::data_dir::DataDir::existing(data_dir_root())).await;
+        return if state && store {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        };
+    }
+
     let _tracer = tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
@@ -28,31 +45,17 @@ async fn main() {
         .try_init();
 
     let port = env::var("YEET_PORT").map_or(4337, |port| port.parse().unwrap());
-    let host = env::var("YEET_HOST").map_or(
-        std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST),
-        |host| host.parse().unwrap(),
+    // `YEET_HOST` accepts a comma separated list for dual-stack or multiple interfaces
+    let hosts = env::var("YEET_HOST").map_or(
+        vec![std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
+        |hosts| yeetd::parse_hosts(&hosts).unwrap(),
     );
 
-    let age_key = {
-        if let Ok(content) = read_to_string("age.key") {
-            age::x25519::Identity::from_str(serde_json::from_str(&content).unwrap()).unwrap()
-        } else {
-            let identity = age::x25519::Identity::generate();
-            File::create("age.key")
-                .unwrap()
-                .write_all(
-                    &serde_json::to_vec(&identity.to_string().expose_secret().to_owned()).unwrap(),
-                )
-                .unwrap();
-            identity
-        }
-    };
+    let data_dir = data_dir();
 
-    let tls = {
-        let cert = env::var("YEET_CERT").expect("`YEET_CERT` must be set");
-        let key = env::var("YEET_CERT_KEY").expect("`YEET_CERT_KEY` must be set");
-        RustlsConfig::from_pem_file(cert, key).await.unwrap()
-    };
+    let age_key = replicated_store_key(store_key(&data_dir));
+
+    let tls = tls().await;
 
     let splunk = 'splunk: {
         let Ok(server) = env::var("YEET_SPLUNK_URL").map(|url| url.parse().unwrap()) else {
@@ -95,8 +98,13 @@ async fn main() {
         env.map(|env| Path::new(&env).to_path_buf())
     };
 
+    let body_limits = body_limits();
+    let lockout = lockout();
+    let hostnames = hostname_rules();
+    let webhooks = webhooks();
+
     let options = SqliteConnectOptions::new()
-        .filename("yeet.db")
+        .filename(data_dir.database())
         .create_if_missing(true);
 
     let pool = SqlitePoolOptions::new()
@@ -106,14 +114,286 @@ async fn main() {
 
     let handle = yeetd::launch(
         port,
-        host,
+        hosts,
         pool,
         age_key,
-        Some(tls),
+        tls,
         splunk,
         packs,
         defectdojo,
+        body_limits,
+        lockout,
+        env::var("YEET_COMPRESS_SECRETS").is_ok_and(|compress| compress == "true"),
+        hostnames,
+        Some(data_dir.state(env::var_os("YEET_STATE").map(PathBuf::from).as_deref())),
+        webhooks,
+        env::var("YEET_TWO_PERSON_RULE").is_ok_and(|rule| rule == "true"),
+        env::var("YEET_SECRET_PEEK").is_ok_and(|peek| peek == "true"),
     )
     .await;
     handle.await.expect("axum quit");
+    ExitCode::SUCCESS
+}
+
+/// Prints what `launch` would import. False on any problem. Does not bind a port
+#[expect(
+    clippy::print_stdout,
+    clippy::print_stderr,
+    reason = "report for the operator"
+)]
+fn check_state(path: &Path) -> bool {
+    let report = match File::open(path)
+        .map_err(|err| err.to_string())
+        .and_then(|state| yeetd::check_state(state).map_err(|err| err.to_string()))
+    {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{} is not a valid state: {err}", path.display());
+            return false;
+        }
+    };
+
+    println!("{}: {} hosts to import", path.display(), report.hosts);
+    for problem in &report.problems {
+        println!("  {problem}");
+    }
+    report.problems.is_empty()
+}
+
+/// Prints the secrets of `yeet.db` the store key can not open and ACL or alias rows that reference
+/// missing rows. False on any problem. The database is opened read-only and no store key is created
+#[expect(
+    clippy::print_stdout,
+    clippy::print_stderr,
+    reason = "report for the operator"
+)]
+async fn check_store(data_dir: &yeetd::data_dir::DataDir) -> bool {
+    let database = data_dir.database();
+    if !database.exists() {
+        println!("{}: no database yet, nothing to check", database.display());
+        return true;
+    }
+    let Some(store_key) = existing_store_key(data_dir) else {
+        eprintln!(
+            "{} does not exist. The secrets in {} can not be checked",
+            data_dir.age_key().display(),
+            database.display()
+        );
+        return false;
+    };
+    let store_key = replicated_store_key(store_key);
+
+    let options = SqliteConnectOptions::new()
+        .filename(&database)
+        .read_only(true);
+    let problems = match SqlitePoolOptions::new().connect_with(options).await {
+        Ok(pool) => match pool.acquire().await {
+            Ok(mut conn) => yeetd::check_store(&mut conn, &*store_key).await,
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err),
+    };
+    let problems = match problems {
+        Ok(problems) => problems,
+        Err(err) => {
+            eprintln!("Could not check {}: {err}", database.display());
+            return false;
+        }
+    };
+
+    println!("{}: secrets and their ACLs checked", database.display());
+    for problem in &problems {
+        println!("  {problem}");
+    }
+    problems.is_empty()
+}
+
+/// `YEET_TLS_CERT` and `YEET_TLS_KEY` enable TLS. `YEET_CERT` and `YEET_CERT_KEY` are still read
+/// for older setups. Without them the server speaks plain HTTP behind a reverse proxy
+#[expect(clippy::expect_used, reason = "allow in server main")]
+async fn tls() -> Option<RustlsConfig> {
+    let cert = env::var("YEET_TLS_CERT").or_else(|_| env::var("YEET_CERT"));
+    let key = env::var("YEET_TLS_KEY").or_else(|_| env::var("YEET_CERT_KEY"));
+    if cert.is_err() && key.is_err() {
+        log::warn!("`YEET_TLS_CERT` not set. Serving plain HTTP");
+        return None;
+    }
+    let cert = cert.expect("`YEET_TLS_CERT` must be set with `YEET_TLS_KEY`");
+    let key = key.expect("`YEET_TLS_KEY` must be set with `YEET_TLS_CERT`");
+    Some(
+        RustlsConfig::from_pem_file(cert, key)
+            .await
+            .expect("Could not load `YEET_TLS_CERT` and `YEET_TLS_KEY`"),
+    )
+}
+
+/// `YEET_BODY_LIMIT` and `YEET_LARGE_BODY_LIMIT` in bytes
+fn body_limits() -> yeetd::BodyLimits {
+    let defaults = yeetd::BodyLimits::default();
+    yeetd::BodyLimits {
+        default: env::var("YEET_BODY_LIMIT").map_or(defaults.default, |limit| {
+            limit
+                .parse()
+                .expect("`YEET_BODY_LIMIT` must be a number of bytes")
+        }),
+        large: env::var("YEET_LARGE_BODY_LIMIT").map_or(defaults.large, |limit| {
+            limit
+                .parse()
+                .expect("`YEET_LARGE_BODY_LIMIT` must be a number of bytes")
+        }),
+    }
+}
+
+/// `YEET_LOCKOUT_THRESHOLD` failed verifications within `YEET_LOCKOUT_WINDOW` seconds
+fn lockout() -> yeetd::Lockout {
+    let defaults = yeetd::Lockout::default();
+    yeetd::Lockout {
+        threshold: env::var("YEET_LOCKOUT_THRESHOLD").map_or(defaults.threshold, |threshold| {
+            threshold
+                .parse()
+                .expect("`YEET_LOCKOUT_THRESHOLD` must be a number of failed verifications")
+        }),
+        window: env::var("YEET_LOCKOUT_WINDOW").map_or(defaults.window, |window| {
+            Duration::from_secs(
+                window
+                    .parse()
+                    .expect("`YEET_LOCKOUT_WINDOW` must be a number of seconds"),
+            )
+        }),
+    }
+}
+
+/// `YEET_WEBHOOKS` is a JSON file with a list of webhooks. Each delivery is cancelled after
+/// `YEET_WEBHOOK_TIMEOUT_MS`. Hosts are stale after `YEET_WEBHOOK_STALE_AFTER` seconds without a ping
+#[expect(clippy::expect_used, reason = "allow in server main")]
+fn webhooks() -> yeetd::webhook::Webhooks {
+    let defaults = yeetd::webhook::Webhooks::default();
+    yeetd::webhook::Webhooks {
+        hooks: env::var("YEET_WEBHOOKS").map_or_else(
+            |_| Vec::new(),
+            |path| {
+                let hooks = File::open(path).expect("Could not open `YEET_WEBHOOKS`");
+                serde_json::from_reader(hooks).expect("`YEET_WEBHOOKS` must be a list of webhooks")
+            },
+        ),
+        timeout: env::var("YEET_WEBHOOK_TIMEOUT_MS").map_or(defaults.timeout, |timeout| {
+            Duration::from_millis(
+                timeout
+                    .parse()
+                    .expect("`YEET_WEBHOOK_TIMEOUT_MS` must be a number of milliseconds"),
+            )
+        }),
+        stale_after: env::var("YEET_WEBHOOK_STALE_AFTER").map_or(
+            defaults.stale_after,
+            |stale_after| {
+                Duration::from_secs(
+                    stale_after
+                        .parse()
+                        .expect("`YEET_WEBHOOK_STALE_AFTER` must be a number of seconds"),
+                )
+            },
+        ),
+    }
+}
+
+/// `YEET_DATA_DIR` roots `yeet.db`, `age.key` and a relative `YEET_STATE`.
+/// Defaults to the working directory
+#[expect(clippy::expect_used, reason = "allow in server main")]
+fn data_dir() -> yeetd::data_dir::DataDir {
+    yeetd::data_dir::DataDir::open(data_dir_root()).expect("Could not create `YEET_DATA_DIR`")
+}
+
+fn data_dir_root() -> PathBuf {
+    env::var_os("YEET_DATA_DIR").map_or_else(|| PathBuf::from("."), PathBuf::from)
+}
+
+/// `YEET_HOSTNAME_PATTERN` replaces the DNS rules for hostnames with a regex
+#[expect(clippy::expect_used, reason = "allow in server main")]
+fn hostname_rules() -> yeetd::hostname::HostnameRules {
+    env::var("YEET_HOSTNAME_PATTERN").map_or_else(
+        |_| yeetd::hostname::HostnameRules::default(),
+        |pattern| {
+            yeetd::hostname::HostnameRules::with_pattern(&pattern)
+                .expect("`YEET_HOSTNAME_PATTERN` must be a valid regex")
+        },
+    )
+}
+
+/// `YEET_STORE_IDENTITIES` lists retired age identity files this replica still decrypts with.
+/// `YEET_STORE_RECIPIENTS` lists the store recipients of the other replicas.
+/// Both are comma separated. New secrets are encrypted for the replicas but not the retired keys
+#[expect(clippy::expect_used, reason = "allow in server main")]
+fn replicated_store_key(
+    store_key: Box<dyn yeetd::store_key::StoreKey>,
+) -> Box<dyn yeetd::store_key::StoreKey> {
+    let identities = env::var("YEET_STORE_IDENTITIES").ok();
+    let replicas = env::var("YEET_STORE_RECIPIENTS").ok();
+    if identities.is_none() && replicas.is_none() {
+        return store_key;
+    }
+
+    let mut keys = yeetd::store_key::StoreKeys::new(store_key);
+    for path in identities.iter().flat_map(|paths| paths.split(',')) {
+        let content = read_to_string(path.trim()).expect("Could not read `YEET_STORE_IDENTITIES`");
+        for line in content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            let identity = age::x25519::Identity::from_str(line)
+                .expect("`YEET_STORE_IDENTITIES` must only contain x25519 identities");
+            keys = keys.with_identity(Box::new(identity));
+        }
+    }
+    for recipient in replicas.iter().flat_map(|recipients| recipients.split(',')) {
+        let recipient = recipient.trim();
+        api::parse_recipient(recipient).expect("`YEET_STORE_RECIPIENTS` must be age recipients");
+        keys = keys.with_replica(recipient.to_owned());
+    }
+    Box::new(keys)
+}
+
+/// With the `age-plugin` feature `YEET_AGE_PLUGIN_IDENTITY` and `YEET_AGE_PLUGIN_RECIPIENT`
+/// select a plugin store key. Otherwise the x25519 identity in `age.key` of the data directory is used.
+/// Nothing is written. `None` if there is no `age.key` yet
+#[expect(clippy::unwrap_used, reason = "allow in server main")]
+#[cfg_attr(
+    feature = "age-plugin",
+    expect(clippy::expect_used, reason = "allow in server main")
+)]
+fn existing_store_key(
+    data_dir: &yeetd::data_dir::DataDir,
+) -> Option<Box<dyn yeetd::store_key::StoreKey>> {
+    #[cfg(feature = "age-plugin")]
+    if let Ok(identity) = env::var("YEET_AGE_PLUGIN_IDENTITY") {
+        let recipient =
+            env::var("YEET_AGE_PLUGIN_RECIPIENT").expect("`YEET_AGE_PLUGIN_RECIPIENT` must be set");
+        return Some(Box::new(
+            yeetd::store_key::PluginStoreKey::new(&identity, &recipient)
+                .expect("Could not set up the age plugin store key"),
+        ));
+    }
+
+    let content = read_to_string(data_dir.age_key()).ok()?;
+    Some(Box::new(
+        age::x25519::Identity::from_str(serde_json::from_str(&content).unwrap()).unwrap(),
+    ))
+}
+
+/// `existing_store_key` or a new x25519 identity written to `age.key`
+#[expect(clippy::unwrap_used, reason = "allow in server main")]
+fn store_key(data_dir: &yeetd::data_dir::DataDir) -> Box<dyn yeetd::store_key::StoreKey> {
+    if let Some(store_key) = existing_store_key(data_dir) {
+        store_key
+    } else {
+        let identity = age::x25519::Identity::generate();
+        data_dir
+            .create_age_key()
+            .unwrap()
+            .write_all(
+                &serde_json::to_vec(&identity.to_string().expose_secret().to_owned()).unwrap(),
+            )
+            .unwrap();
+        Box::new(identity)
+    }
 }
@@ -0,0 +1,148 @@
+//! Two-person rule for destructive actions. With `YEET_TWO_PERSON_RULE` the handlers of these
+//! actions only propose them and a second admin has to confirm
+
+use axum::{Json, extract::State, http::StatusCode};
+use sqlx::Acquire as _;
+
+use crate::{
+    YeetState,
+    db::{self, approvals::ApprovalError},
+    error::{BadRequest as _, InternalError as _},
+    httpsig::{User, VerifiedJson},
+};
+
+/// `api::auth::Approval::Propose`
+pub async fn propose(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(action): VerifiedJson<api::ApprovalAction>,
+) -> Result<Json<api::ApprovalID>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    authorize(&mut conn, user, action).await?;
+
+    Ok(Json(propose_action(&mut conn, user, action).await?))
+}
+
+/// `api::auth::Approval::Confirm`. The approval is taken in the transaction that executes it so
+/// concurrent confirmations can not execute it twice
+pub async fn confirm(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(id): VerifiedJson<api::ApprovalID>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    let pending = db::approvals::take(&mut tx, id)
+        .await
+        .map_err(|err| approval_error(&err))?;
+    authorize(&mut tx, user, pending.action).await?;
+    if pending.proposer == user {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "A second admin has to confirm the action".to_owned(),
+        ));
+    }
+
+    execute(&mut tx, user, pending.action).await?;
+    tx.commit().await.internal_server()?;
+
+    Ok(StatusCode::OK)
+}
+
+/// `api::auth::Approval::View`. Only approvals the caller could confirm are listed
+pub async fn list(
+    State(state): State<YeetState>,
+    User(user): User,
+) -> Result<Json<Vec<api::PendingApproval>>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+
+    let mut confirmable = Vec::new();
+    for pending in db::approvals::list(&mut conn).await.internal_server()? {
+        match authorize(&mut conn, user, pending.action).await {
+            Ok(()) => confirmable.push(pending),
+            Err((StatusCode::FORBIDDEN, _)) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Json(confirmable))
+}
+
+/// The checks of the handler that performs `action` directly
+async fn authorize(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    action: api::ApprovalAction,
+) -> Result<(), (StatusCode, String)> {
+    db::tag::auth_admin(conn, user).await?;
+    match action {
+        api::ApprovalAction::RemoveHost(_) => db::tag::auth_all_tag(conn, user).await,
+        api::ApprovalAction::DeleteSecret(secret) => {
+            db::tag::auth_tag(conn, user, secret.into()).await
+        }
+    }
+}
+
+/// Callers have to `authorize` the proposer first
+pub(crate) async fn propose_action(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    action: api::ApprovalAction,
+) -> Result<api::ApprovalID, (StatusCode, String)> {
+    let mut tx = conn.begin().await.internal_server()?;
+    let id = db::approvals::propose(&mut tx, user, action)
+        .await
+        .map_err(|err| approval_error(&err))?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Approval::Propose",
+        &format!("approval {id}: {}", describe(action)),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
+    Ok(id)
+}
+
+/// Audited like the handler that performs `action` directly
+async fn execute(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    action: api::ApprovalAction,
+) -> Result<(), (StatusCode, String)> {
+    match action {
+        api::ApprovalAction::RemoveHost(host) => {
+            db::hosts::remove_host(conn, host).await.internal_server()?;
+            db::audit::append(conn, user, "Key::Delete", &format!("host {host}"))
+                .await
+                .internal_server()
+        }
+        api::ApprovalAction::DeleteSecret(secret) => {
+            db::secrets::remove_secret(conn, secret)
+                .await
+                .bad_request()?;
+            db::audit::append(conn, user, "Secret::Delete", &format!("secret {secret}"))
+                .await
+                .internal_server()
+        }
+    }
+}
+
+fn describe(action: api::ApprovalAction) -> String {
+    match action {
+        api::ApprovalAction::RemoveHost(host) => format!("remove host {host}"),
+        api::ApprovalAction::DeleteSecret(secret) => format!("delete secret {secret}"),
+    }
+}
+
+fn approval_error(err: &ApprovalError) -> (StatusCode, String) {
+    let code = match err {
+        ApprovalError::AlreadyProposed => StatusCode::CONFLICT,
+        ApprovalError::NotFound => StatusCode::NOT_FOUND,
+        ApprovalError::SQLXError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (code, err.to_string())
+}
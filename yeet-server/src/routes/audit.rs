@@ -0,0 +1,17 @@
+use axum::{Json, extract::State, http::StatusCode};
+
+use crate::{YeetState, db, error::InternalError as _, httpsig::User};
+
+/// The whole mutation log and whether its hash chain is intact
+pub async fn mutations(
+    State(state): State<YeetState>,
+    User(user): User,
+) -> Result<Json<api::AuditLog>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+
+    let entries = db::audit::list(&mut conn).await.internal_server()?;
+    let broken_at = db::audit::verify_chain(&entries);
+    Ok(Json(api::AuditLog { entries, broken_at }))
+}
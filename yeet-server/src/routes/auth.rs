@@ -0,0 +1,213 @@
+use axum::{Json, extract::State, http::StatusCode};
+
+use crate::{
+    YeetState, db,
+    error::InternalError as _,
+    httpsig::{HttpSig, VerifiedJson},
+};
+
+/// What a handler checks before performing an action
+#[derive(Clone, Copy)]
+enum Requires {
+    /// The `Host` extractor
+    Host,
+    /// `auth_level` and `auth_all_tag`
+    AllTag(api::AuthLevel),
+    /// `auth_level` and `auth_tag` on every resource the action touches
+    Tagged(api::AuthLevel),
+}
+
+/// Has to be kept in sync with the checks in the handlers
+const ACTIONS: &[(&str, Requires)] = &[
+    ("Secret::Create", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Secret::Rotate", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Secret::Allow", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Secret::Block", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Secret::Rename", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Secret::Delete", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Secret::Alias", Requires::AllTag(api::AuthLevel::Admin)),
+    (
+        "Secret::RemoveAlias",
+        Requires::Tagged(api::AuthLevel::Admin),
+    ),
+    ("Secret::View", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Secret::Peek", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Secret::Fetch", Requires::Host),
+    ("Host::Accept", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Host::View", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Host::Rename", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Host::Update", Requires::Tagged(api::AuthLevel::Build)),
+    ("Host::Detach", Requires::Tagged(api::AuthLevel::Admin)),
+    ("HostGroup::Create", Requires::AllTag(api::AuthLevel::Admin)),
+    ("HostGroup::Edit", Requires::Tagged(api::AuthLevel::Admin)),
+    ("HostGroup::View", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Key::Delete", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Key::View", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Approval::Propose", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Approval::Confirm", Requires::Tagged(api::AuthLevel::Admin)),
+    ("Approval::View", Requires::Tagged(api::AuthLevel::Admin)),
+    ("User::Create", Requires::AllTag(api::AuthLevel::Admin)),
+    ("User::Rename", Requires::AllTag(api::AuthLevel::Admin)),
+    ("User::View", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Tag::Create", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Tag::Rename", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Tag::Delete", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Tag::Allow", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Tag::Remove", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Tag::View", Requires::AllTag(api::AuthLevel::Admin)),
+    ("Audit::View", Requires::AllTag(api::AuthLevel::Admin)),
+    (
+        "Maintenance::Prune",
+        Requires::AllTag(api::AuthLevel::Admin),
+    ),
+    ("Osquery::View", Requires::AllTag(api::AuthLevel::Osquery)),
+    ("Osquery::Query", Requires::AllTag(api::AuthLevel::Osquery)),
+    ("System::Check", Requires::Host),
+    ("System::Detach", Requires::Host),
+    ("System::DetachPermission", Requires::Host),
+    ("System::Attach", Requires::Host),
+    ("System::Report", Requires::Host),
+    ("System::DownloadStats", Requires::Host),
+    ("System::Facter", Requires::Host),
+];
+
+/// Explain who a key belongs to and which actions it may perform.
+/// Any key may explain itself. Explaining other keys requires an admin with `all_tag`
+pub async fn explain(
+    State(state): State<YeetState>,
+    HttpSig(caller): HttpSig,
+    VerifiedJson(api::auth::ExplainRequest { key }): VerifiedJson<api::auth::ExplainRequest>,
+) -> Result<Json<api::auth::AuthExplanation>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+
+    let key = key.unwrap_or(caller);
+    if key != caller {
+        let Some(user) = db::user::fetch_by_key(&mut conn, caller)
+            .await
+            .internal_server()?
+        else {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Only users can explain other keys".to_owned(),
+            ));
+        };
+        db::tag::auth_admin(&mut conn, user).await?;
+        db::tag::auth_all_tag(&mut conn, user).await?;
+    }
+
+    let owner = if let Some(user) = db::user::fetch_by_key(&mut conn, key)
+        .await
+        .internal_server()?
+    {
+        db::user::list_users(&mut conn)
+            .await
+            .internal_server()?
+            .into_iter()
+            .find(|listed| listed.id == user)
+            .map_or(api::auth::KeyOwner::Unknown, |listed| {
+                api::auth::KeyOwner::User(Box::new(listed))
+            })
+    } else if let Some(host) = db::hosts::host_info_by_key(&mut conn, key)
+        .await
+        .internal_server()?
+    {
+        api::auth::KeyOwner::Host(host)
+    } else {
+        api::auth::KeyOwner::Unknown
+    };
+
+    Ok(Json(api::auth::AuthExplanation {
+        key,
+        permissions: permissions(&owner),
+        owner,
+    }))
+}
+
+fn permissions(owner: &api::auth::KeyOwner) -> Vec<api::auth::Permission> {
+    ACTIONS
+        .iter()
+        .filter_map(|&(action, requires)| {
+            let scope = match (owner, requires) {
+                (api::auth::KeyOwner::Host(_), Requires::Host) => api::auth::Scope::Own,
+                (api::auth::KeyOwner::User(user), Requires::AllTag(level))
+                    if user.all_tag && has_level(user.level, level) =>
+                {
+                    api::auth::Scope::All
+                }
+                (api::auth::KeyOwner::User(user), Requires::Tagged(level))
+                    if has_level(user.level, level) =>
+                {
+                    if user.all_tag {
+                        api::auth::Scope::All
+                    } else {
+                        api::auth::Scope::Tagged
+                    }
+                }
+                _ => return None,
+            };
+            Some(api::auth::Permission {
+                action: action.to_owned(),
+                scope,
+            })
+        })
+        .collect()
+}
+
+/// Same rule as `db::tag::auth_level`
+fn has_level(level: api::AuthLevel, required: api::AuthLevel) -> bool {
+    level == required || level == api::AuthLevel::Admin
+}
+
+#[cfg(test)]
+mod test_auth {
+    use ed25519_dalek::SigningKey;
+
+    fn user(level: api::AuthLevel, all_tag: bool) -> api::auth::KeyOwner {
+        api::auth::KeyOwner::User(Box::new(api::User {
+            id: api::UserID::new(1),
+            key: SigningKey::from_bytes(&[1; 32]).verifying_key(),
+            username: "user".to_owned(),
+            level,
+            all_tag,
+            tags: Vec::new(),
+        }))
+    }
+
+    fn scope(owner: &api::auth::KeyOwner, action: &str) -> Option<api::auth::Scope> {
+        super::permissions(owner)
+            .into_iter()
+            .find(|permission| permission.action == action)
+            .map(|permission| permission.scope)
+    }
+
+    #[test]
+    fn tagged_build_user() {
+        let owner = user(api::AuthLevel::Build, false);
+        assert_eq!(
+            scope(&owner, "Host::Update"),
+            Some(api::auth::Scope::Tagged)
+        );
+        assert_eq!(scope(&owner, "Secret::View"), None);
+        assert_eq!(scope(&owner, "System::Check"), None);
+    }
+
+    #[test]
+    fn admin_without_all_tag() {
+        let owner = user(api::AuthLevel::Admin, false);
+        assert_eq!(
+            scope(&owner, "Secret::View"),
+            Some(api::auth::Scope::Tagged)
+        );
+        // admins pass every level check
+        assert_eq!(
+            scope(&owner, "Host::Update"),
+            Some(api::auth::Scope::Tagged)
+        );
+        assert_eq!(scope(&owner, "User::Create"), None);
+    }
+
+    #[test]
+    fn unknown_key() {
+        assert!(super::permissions(&api::auth::KeyOwner::Unknown).is_empty());
+    }
+}
@@ -1,5 +1,43 @@
-use axum::http::StatusCode;
+use axum::{Json, extract::State, http::StatusCode};
+
+use crate::YeetState;
 
 pub async fn health() -> StatusCode {
     StatusCode::OK
 }
+
+/// Prometheus text format
+pub async fn metrics(State(state): State<YeetState>) -> String {
+    format!(
+        "# HELP yeet_signature_lockouts_total Sources locked out after failed signature verifications\n\
+         # TYPE yeet_signature_lockouts_total counter\n\
+         yeet_signature_lockouts_total {}\n",
+        state.failed_verifications.lockouts()
+    )
+}
+
+/// Public so that clients can check the server before they have credentials
+pub async fn info(State(state): State<YeetState>) -> Json<api::ServerInfo> {
+    Json(api::ServerInfo {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        server_public_key_fingerprint: api::recipient_fingerprint(&state.age_key.recipient()),
+        features: [
+            api::feature::ACTIVATION_REPORTS,
+            api::feature::APPROVALS,
+            api::feature::AUDIT_LOG,
+            api::feature::DOWNLOAD_STATS,
+            api::feature::HOST_GROUPS,
+            api::feature::HOST_IMPORT,
+            api::feature::SEALED_SECRETS,
+            api::feature::SECRET_ACL_BATCH,
+            api::feature::SECRET_ALIASES,
+            api::feature::SECRET_AS_HOST,
+            api::feature::SECRET_LIST_PAGE,
+            api::feature::SECRET_PUT_MODES,
+            api::feature::SECRET_ROTATION,
+            api::feature::SERVER_RECIPIENT_ETAG,
+        ]
+        .map(str::to_owned)
+        .to_vec(),
+    })
+}
@@ -1,12 +1,19 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr as _,
+};
+
 use axum::{
     Json,
     extract::{Path, State},
     http::StatusCode,
 };
+use sqlx::Acquire as _;
 
 use crate::{
     YeetState, db,
     error::{BadRequest as _, InternalError as _},
+    hostname::HostnameRules,
     httpsig::{User, VerifiedJson},
 };
 
@@ -58,12 +65,242 @@ pub async fn rename_host(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_tag(&mut conn, user, id.into()).await?;
-    db::hosts::rename(&mut conn, id, name)
+    state.hostnames.check(&name).bad_request()?;
+    let mut tx = conn.begin().await.internal_server()?;
+    let detail = format!("host {id} to {name}");
+    db::hosts::rename(&mut tx, id, name)
+        .await
+        .map_err(|err| match err {
+            db::hosts::RenameError::HostnameTaken { .. } => (StatusCode::CONFLICT, err.to_string()),
+            db::hosts::RenameError::SQLXError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+        })?;
+    db::audit::append(&mut tx, user, "Host::Rename", &detail)
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
+    Ok(StatusCode::OK)
+}
+
+/// The latest nixos-facter output. Either from the enrollment or reported by the host since
+pub async fn facter(
+    State(state): State<YeetState>,
+    Path(id): Path<api::HostID>,
+    User(user): User,
+) -> Result<Json<Option<api::FacterReport>>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_tag(&mut conn, user, id.into()).await?;
+
+    let facter = db::hosts::fetch_facter(&mut conn, id)
+        .await
+        .internal_server()?;
+    Ok(Json(facter))
+}
+
+/// Register hosts ahead of their first check-in e.g. when bootstrapping a fleet.
+/// The admin vouches for the keys so the hosts skip the verification code flow.
+/// Every host is validated and imported on its own and gets its own result
+pub async fn import_hosts(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(hosts): VerifiedJson<Vec<api::HostImport>>,
+) -> Result<Json<Vec<api::HostImportResult>>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+
+    let mut results = Vec::with_capacity(hosts.len());
+    for host in hosts {
+        let result = import_host(&mut conn, user, &state.hostnames, &host).await;
+        results.push(api::HostImportResult {
+            hostname: host.hostname,
+            result,
+        });
+    }
+    Ok(Json(results))
+}
+
+async fn import_host(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    hostnames: &HostnameRules,
+    host: &api::HostImport,
+) -> Result<api::HostID, String> {
+    hostnames
+        .check(&host.hostname)
+        .map_err(|err| err.to_string())?;
+    let key = api::parse_verify_key(&host.key).map_err(|err| format!("Invalid key: {err}"))?;
+    // store the canonical form so that it can be compared when fetching secrets
+    let recipient = host
+        .recipient
+        .as_deref()
+        .map(|recipient| {
+            age::x25519::Recipient::from_str(recipient).map(|parsed| parsed.to_string())
+        })
+        .transpose()
+        .map_err(|err| format!("Invalid age recipient: {err}"))?;
+
+    let mut tx = conn.begin().await.map_err(|err| err.to_string())?;
+    if db::hosts::host_by_hostname(&mut tx, &host.hostname)
+        .await
+        .map_err(|err| err.to_string())?
+        .is_some()
+    {
+        return Err(format!("Another host is already called {}", host.hostname));
+    }
+
+    let id =
+        db::verification::register_host(&mut tx, key, host.hostname.clone(), recipient.as_deref())
+            .await
+            .map_err(|err| {
+                if let sqlx::Error::Database(db_err) = &err
+                    && db_err.is_unique_violation()
+                {
+                    "Key is already registered".to_owned()
+                } else {
+                    err.to_string()
+                }
+            })?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Host::Import",
+        &format!("host {id}: {}", host.hostname),
+    )
+    .await
+    .map_err(|err| err.to_string())?;
+    tx.commit().await.map_err(|err| err.to_string())?;
+    Ok(id)
+}
+
+pub async fn create_group(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(api::HostGroup { name }): VerifiedJson<api::HostGroup>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    db::groups::create_group(&mut tx, &name)
+        .await
+        .map_err(|err| group_error(&err))?;
+    db::audit::append(&mut tx, user, "HostGroup::Create", &name)
         .await
         .internal_server()?;
+    tx.commit().await.internal_server()?;
+    Ok(StatusCode::CREATED)
+}
+
+pub async fn add_to_group(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(api::HostGroupMember { group, host }): VerifiedJson<api::HostGroupMember>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    let id = group_member(&mut conn, user, &host).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    db::groups::add_member(&mut tx, &group, id)
+        .await
+        .map_err(|err| group_error(&err))?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "HostGroup::Edit",
+        &format!("added {host} to {group}"),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn remove_from_group(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(api::HostGroupMember { group, host }): VerifiedJson<api::HostGroupMember>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    let id = group_member(&mut conn, user, &host).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    db::groups::remove_member(&mut tx, &group, id)
+        .await
+        .map_err(|err| group_error(&err))?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "HostGroup::Edit",
+        &format!("removed {host} from {group}"),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
     Ok(StatusCode::OK)
 }
 
+/// Groups with the hosts the user may see. Every group is listed
+pub async fn list_groups(
+    State(state): State<YeetState>,
+    User(user): User,
+) -> Result<Json<HashMap<String, Vec<String>>>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+
+    let visible = db::hosts::list_hosts(&mut conn, user)
+        .await
+        .internal_server()?
+        .into_iter()
+        .map(|host| host.id)
+        .collect::<HashSet<_>>();
+    let groups = db::groups::list_groups(&mut conn)
+        .await
+        .internal_server()?
+        .into_iter()
+        .map(|(group, hosts)| {
+            let hosts = hosts
+                .into_iter()
+                .filter(|(id, _)| visible.contains(id))
+                .map(|(_, hostname)| hostname)
+                .collect();
+            (group, hosts)
+        })
+        .collect();
+    Ok(Json(groups))
+}
+
+/// Only hosts the user has a tag for can be grouped
+async fn group_member(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    hostname: &str,
+) -> Result<api::HostID, (StatusCode, String)> {
+    let host = db::hosts::host_by_hostname(conn, hostname)
+        .await
+        .internal_server()?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            format!("Host `{hostname}` does not exist"),
+        ))?;
+    db::tag::auth_tag(conn, user, host.into()).await?;
+    Ok(host)
+}
+
+fn group_error(err: &db::groups::GroupError) -> (StatusCode, String) {
+    let code = match err {
+        db::groups::GroupError::GroupExists => StatusCode::CONFLICT,
+        db::groups::GroupError::GroupNotFound => StatusCode::NOT_FOUND,
+        db::groups::GroupError::SQLXError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (code, err.to_string())
+}
+
 /// Endpoint to set a new version for a host.
 /// The whole request needs to be signed by a build machine.
 /// The update consist of a simple `key` -> `version` and a `substitutor` which is where the agent should get its update
@@ -81,6 +318,7 @@ pub async fn update_hosts(
     let mut conn = state.pool.acquire().await.internal_server()?;
 
     db::tag::auth_build(&mut conn, user).await?;
+    // only names of existing hosts are accepted. They were checked when they were assigned
     for host in hosts.keys() {
         let Ok(Some(host)) = db::hosts::host_by_hostname(&mut conn, host).await else {
             return Err((
@@ -91,9 +329,20 @@ pub async fn update_hosts(
         db::tag::auth_tag(&mut conn, user, api::tag::Resource::from(host)).await?;
     }
 
-    db::hosts::update(&mut conn, hosts.iter(), public_key, substitutor)
+    let mut detail = hosts
+        .iter()
+        .map(|(host, store_path)| format!("{host} to {store_path}"))
+        .collect::<Vec<_>>();
+    detail.sort();
+
+    let mut tx = conn.begin().await.internal_server()?;
+    db::hosts::update(&mut tx, hosts.iter(), public_key, substitutor)
         .await
         .bad_request()?;
+    db::audit::append(&mut tx, user, "Host::Update", &detail.join(", "))
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::CREATED)
 }
@@ -1,8 +1,9 @@
-use axum::{extract::State, http::StatusCode};
+use axum::{Json, extract::State, http::StatusCode};
 use ed25519_dalek::VerifyingKey;
+use sqlx::Acquire as _;
 
 use crate::{
-    YeetState, db,
+    YeetState, approval, db,
     error::InternalError as _,
     httpsig::{User, VerifiedJson},
 };
@@ -17,10 +18,64 @@ pub async fn delete_key(
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
 
-    // deleting this propagates the user credentials deletion
-    db::keys::delete_key(&mut conn, key)
+    let mut tx = conn.begin().await.internal_server()?;
+    let detail = if let Some(host) = db::hosts::host_by_verify_key(&mut tx, key)
+        .await
+        .internal_server()?
+    {
+        if state.two_person_rule {
+            approval::propose_action(&mut tx, user, api::ApprovalAction::RemoveHost(host)).await?;
+            tx.commit().await.internal_server()?;
+            return Ok(StatusCode::ACCEPTED);
+        }
+        db::hosts::remove_host(&mut tx, host)
+            .await
+            .internal_server()?;
+        format!("host {host}")
+    } else {
+        // deleting this propagates the user credentials deletion
+        db::keys::delete_key(&mut tx, key).await.internal_server()?;
+        format!("key {}", api::key_fingerprint(&key))
+    };
+    db::audit::append(&mut tx, user, "Key::Delete", &detail)
         .await
         .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
+
+/// Every enrolled user and host key with its fingerprint
+pub async fn list_keys(
+    State(state): State<YeetState>,
+    User(user): User,
+) -> Result<Json<Vec<api::KeyEntry>>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+
+    let users = db::user::list_users(&mut conn)
+        .await
+        .internal_server()?
+        .into_iter()
+        .map(|user| api::KeyEntry {
+            key: user.key,
+            fingerprint: api::key_fingerprint(&user.key),
+            owner: api::auth::KeyOwner::User(Box::new(user)),
+        });
+    let hosts = db::hosts::list_hosts(&mut conn, user)
+        .await
+        .internal_server()?
+        .into_iter()
+        .map(|host| api::KeyEntry {
+            key: host.key,
+            fingerprint: api::key_fingerprint(&host.key),
+            owner: api::auth::KeyOwner::Host(api::HostInfo {
+                id: host.id,
+                hostname: host.hostname,
+            }),
+        });
+
+    Ok(Json(users.chain(hosts).collect()))
+}
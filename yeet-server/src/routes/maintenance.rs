@@ -0,0 +1,31 @@
+use axum::{Json, extract::State, http::StatusCode};
+use sqlx::Acquire as _;
+
+use crate::{YeetState, db, error::InternalError as _, httpsig::User};
+
+/// Removes acl entries and tags that point to secrets or hosts that no longer exist
+pub async fn prune(
+    State(state): State<YeetState>,
+    User(user): User,
+) -> Result<Json<api::PruneReport>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    let report = db::maintenance::prune_orphans(&mut tx)
+        .await
+        .internal_server()?;
+    if !report.is_empty() {
+        let detail = format!(
+            "{} acl entries, {} tags",
+            report.acl.len(),
+            report.tags.len()
+        );
+        db::audit::append(&mut tx, user, "Maintenance::Prune", &detail)
+            .await
+            .internal_server()?;
+    }
+    tx.commit().await.internal_server()?;
+    Ok(Json(report))
+}
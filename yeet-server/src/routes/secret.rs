@@ -2,15 +2,18 @@ use std::str::FromStr as _;
 
 use axum::{
     Json,
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse as _, Response},
 };
+use sqlx::Acquire as _;
 
 use crate::{
-    YeetState,
+    YeetState, approval,
     db::{self},
     error::{BadRequest as _, InternalError as _, WithStatusCode as _},
-    httpsig::{HttpSig, User, VerifiedJson},
+    httpsig::{Host, HttpSig, User, VerifiedJson},
+    webhook,
 };
 
 pub async fn add_secret(
@@ -23,12 +26,238 @@ pub async fn add_secret(
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
 
-    let id = db::secrets::add_secret(&mut conn, name, secret, &*state.age_key)
+    let mut tx = conn.begin().await.internal_server()?;
+    ensure_new_name(&mut tx, &name).await?;
+    let id = db::secrets::add_secret(
+        &mut tx,
+        name,
+        secret,
+        &*state.age_key,
+        state.compress_secrets,
+    )
+    .await
+    .bad_request()?;
+    db::audit::append(&mut tx, user, "Secret::Create", &id.name)
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
+    crate::notify_webhooks(
+        state.webhook_sender.as_ref(),
+        webhook::Event::SecretUpdated { secret: id.id },
+    )
+    .await;
+    Ok(Json(id))
+}
+
+/// Like `add_secret` but labels the secret with `content_type` in the same transaction
+pub async fn add_typed_secret(
+    State(state): State<YeetState>,
+    User(user): User,
+    Path((name, content_type)): Path<(String, String)>,
+    VerifiedJson(secret): VerifiedJson<Vec<u8>>,
+) -> Result<Json<api::SecretName>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    ensure_new_name(&mut tx, &name).await?;
+    let mut id = db::secrets::add_secret(
+        &mut tx,
+        name,
+        secret,
+        &*state.age_key,
+        state.compress_secrets,
+    )
+    .await
+    .bad_request()?;
+    db::secrets::set_content_type(&mut tx, id.id, Some(&content_type))
+        .await
+        .bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Secret::Create",
+        &format!("{} as {content_type}", id.name),
+    )
+    .await
+    .internal_server()?;
+    id.content_type = Some(content_type);
+    tx.commit().await.internal_server()?;
+    crate::notify_webhooks(
+        state.webhook_sender.as_ref(),
+        webhook::Event::SecretUpdated { secret: id.id },
+    )
+    .await;
+    Ok(Json(id))
+}
+
+/// `/secret/add` never replaces a secret. Use `/secret/put` for that
+async fn ensure_new_name(
+    conn: &mut sqlx::SqliteConnection,
+    name: &str,
+) -> Result<(), (StatusCode, String)> {
+    if db::secrets::secret_by_name(conn, name)
+        .await
+        .internal_server()?
+        .is_some()
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Secret {name} already exists"),
+        ));
+    }
+    Ok(())
+}
+
+/// Creates a secret or replaces the content of an existing one depending on `mode`.
+/// Replacing keeps the acl and tags like `rotate_secret`
+pub async fn put_secret(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(api::AddSecretRequest {
+        name,
+        secret,
+        mode,
+        content_type,
+    }): VerifiedJson<api::AddSecretRequest>,
+) -> Result<Json<api::AddedSecret>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    let existing = db::secrets::secret_by_name(&mut tx, &name)
+        .await
+        .internal_server()?;
+    let added = match (mode, existing) {
+        (api::AddSecretMode::CreateOnly, Some(_)) => {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Secret {name} already exists"),
+            ));
+        }
+        (api::AddSecretMode::UpdateOnly, None) => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("Secret {name} does not exist"),
+            ));
+        }
+        (api::AddSecretMode::CreateOnly | api::AddSecretMode::Upsert, None) => {
+            let id = db::secrets::add_secret(
+                &mut tx,
+                name.clone(),
+                secret,
+                &*state.age_key,
+                state.compress_secrets,
+            )
+            .await
+            .bad_request()?;
+            db::audit::append(&mut tx, user, "Secret::Create", &name)
+                .await
+                .internal_server()?;
+            api::AddedSecret {
+                id: id.id,
+                created: true,
+            }
+        }
+        (api::AddSecretMode::UpdateOnly | api::AddSecretMode::Upsert, Some(id)) => {
+            db::secrets::rotate_secret(
+                &mut tx,
+                id,
+                secret,
+                &*state.age_key,
+                state.compress_secrets,
+            )
+            .await
+            .bad_request()?;
+            db::audit::append(&mut tx, user, "Secret::Rotate", &format!("secret {id}"))
+                .await
+                .internal_server()?;
+            api::AddedSecret { id, created: false }
+        }
+    };
+    if let Some(content_type) = &content_type {
+        db::secrets::set_content_type(&mut tx, added.id, Some(content_type))
+            .await
+            .bad_request()?;
+    }
+    tx.commit().await.internal_server()?;
+
+    crate::notify_webhooks(
+        state.webhook_sender.as_ref(),
+        webhook::Event::SecretUpdated { secret: added.id },
+    )
+    .await;
+    Ok(Json(added))
+}
+
+/// Store a secret the client encrypted for `host`. The server can not read it
+pub async fn add_sealed_secret(
+    State(state): State<YeetState>,
+    User(user): User,
+    Path((name, host)): Path<(String, api::HostID)>,
+    VerifiedJson(secret): VerifiedJson<Vec<u8>>,
+) -> Result<Json<api::SecretName>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    let id = db::secrets::add_sealed_secret(&mut tx, name, secret, host)
         .await
         .bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Secret::Create",
+        &format!("{} sealed to host {host}", id.name),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
+    crate::notify_webhooks(
+        state.webhook_sender.as_ref(),
+        webhook::Event::SecretUpdated { secret: id.id },
+    )
+    .await;
     Ok(Json(id))
 }
 
+/// Replace the content of a secret. The acl stays untouched
+/// Rotating an alias replaces its target, so the user needs the tags of both
+pub async fn rotate_secret(
+    State(state): State<YeetState>,
+    User(user): User,
+    Path(id): Path<api::SecretID>,
+    VerifiedJson(secret): VerifiedJson<Vec<u8>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_tag(&mut conn, user, id.into()).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    let target = db::secrets::alias_target(&mut tx, id)
+        .await
+        .internal_server()?;
+    if let Some(target) = target {
+        db::tag::auth_tag(&mut tx, user, target.into()).await?;
+    }
+    db::secrets::rotate_secret(&mut tx, id, secret, &*state.age_key, state.compress_secrets)
+        .await
+        .bad_request()?;
+    db::audit::append(&mut tx, user, "Secret::Rotate", &format!("secret {id}"))
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
+    crate::notify_webhooks(
+        state.webhook_sender.as_ref(),
+        webhook::Event::SecretUpdated { secret: id },
+    )
+    .await;
+    Ok(StatusCode::OK)
+}
+
 pub async fn rename_secret(
     State(state): State<YeetState>,
     User(user): User,
@@ -37,9 +266,15 @@ pub async fn rename_secret(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_tag(&mut conn, user, secret_id.into()).await?;
-    db::secrets::rename_secret(&mut conn, secret_id, name)
+    let detail = format!("secret {secret_id} to {name}");
+    let mut tx = conn.begin().await.internal_server()?;
+    db::secrets::rename_secret(&mut tx, secret_id, name)
         .await
         .bad_request()?;
+    db::audit::append(&mut tx, user, "Secret::Rename", &detail)
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
@@ -52,9 +287,69 @@ pub async fn delete_secret(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_tag(&mut conn, user, id.into()).await?;
-    db::secrets::remove_secret(&mut conn, id)
+    if state.two_person_rule {
+        approval::propose_action(&mut conn, user, api::ApprovalAction::DeleteSecret(id)).await?;
+        return Ok(StatusCode::ACCEPTED);
+    }
+    let mut tx = conn.begin().await.internal_server()?;
+    db::secrets::remove_secret(&mut tx, id)
         .await
         .bad_request()?;
+    db::audit::append(&mut tx, user, "Secret::Delete", &format!("secret {id}"))
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Create a secret that serves the content of `target` with its own acl
+pub async fn add_alias(
+    State(state): State<YeetState>,
+    User(user): User,
+    Path((target, name)): Path<(api::SecretID, String)>,
+) -> Result<Json<api::SecretName>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+    db::tag::auth_tag(&mut conn, user, target.into()).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    let alias = db::secrets::add_alias(&mut tx, name, target)
+        .await
+        .bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Secret::Alias",
+        &format!("{} for secret {target}", alias.name),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
+    Ok(Json(alias))
+}
+
+/// Remove an alias. Its target is kept
+pub async fn delete_alias(
+    State(state): State<YeetState>,
+    Path(id): Path<api::SecretID>,
+    User(user): User,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_tag(&mut conn, user, id.into()).await?;
+    let mut tx = conn.begin().await.internal_server()?;
+    db::secrets::remove_alias(&mut tx, id).await.bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Secret::RemoveAlias",
+        &format!("secret {id}"),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
@@ -69,9 +364,19 @@ pub async fn allow_host(
     db::tag::auth_tag(&mut conn, user, secret_id.into()).await?;
     db::tag::auth_tag(&mut conn, user, host_id.into()).await?;
 
-    db::secrets::add_access_for(&mut conn, secret_id, host_id)
+    let mut tx = conn.begin().await.internal_server()?;
+    db::secrets::add_access_for(&mut tx, secret_id, host_id)
         .await
         .bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Secret::Allow",
+        &format!("secret {secret_id} host {host_id}"),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
@@ -86,13 +391,87 @@ pub async fn block_host(
     db::tag::auth_tag(&mut conn, user, secret_id.into()).await?;
     db::tag::auth_tag(&mut conn, user, host_id.into()).await?;
 
-    db::secrets::remove_access_for(&mut conn, secret_id, host_id)
+    let mut tx = conn.begin().await.internal_server()?;
+    db::secrets::remove_access_for(&mut tx, secret_id, host_id)
         .await
         .bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Secret::Block",
+        &format!("secret {secret_id} host {host_id}"),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
 
+/// `allow_host` and `block_host` for many pairs in one transaction.
+/// Each operation is checked and applied on its own so one failure does not undo the others
+pub async fn batch_acl(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(api::BatchAclSecretRequest { operations }): VerifiedJson<
+        api::BatchAclSecretRequest,
+    >,
+) -> Result<Json<api::BatchAclResult>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    let mut result = api::BatchAclResult::default();
+    for (index, operation) in operations.into_iter().enumerate() {
+        let mut savepoint = tx.begin().await.internal_server()?;
+        match apply_acl(&mut savepoint, user, operation).await {
+            Ok(()) => {
+                savepoint.commit().await.internal_server()?;
+                result.succeeded.push(index);
+            }
+            Err(err) => {
+                savepoint.rollback().await.internal_server()?;
+                result.failed.push((index, err));
+            }
+        }
+    }
+    tx.commit().await.internal_server()?;
+
+    Ok(Json(result))
+}
+
+async fn apply_acl(
+    conn: &mut sqlx::SqliteConnection,
+    user: api::UserID,
+    api::AclSecretRequest {
+        secret,
+        host,
+        allow,
+    }: api::AclSecretRequest,
+) -> Result<(), String> {
+    db::tag::auth_tag(conn, user, secret.into())
+        .await
+        .map_err(|(_, err)| err)?;
+    db::tag::auth_tag(conn, user, host.into())
+        .await
+        .map_err(|(_, err)| err)?;
+
+    let action = if allow {
+        db::secrets::add_access_for(conn, secret, host)
+            .await
+            .map_err(|err| err.to_string())?;
+        "Secret::Allow"
+    } else {
+        db::secrets::remove_access_for(conn, secret, host)
+            .await
+            .map_err(|err| err.to_string())?;
+        "Secret::Block"
+    };
+    db::audit::append(conn, user, action, &format!("secret {secret} host {host}"))
+        .await
+        .map_err(|err| err.to_string())
+}
+
 pub async fn list_secrets(
     State(state): State<YeetState>,
     User(user): User,
@@ -107,37 +486,244 @@ pub async fn list_secrets(
     ))
 }
 
+/// `list_secrets` sorted by name. Filtered by `prefix` and cut down to one page
+pub async fn list_secrets_page(
+    State(state): State<YeetState>,
+    User(user): User,
+    Query(query): Query<api::ListSecretsQuery>,
+) -> Result<Json<api::SecretPage>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+
+    let page = db::secrets::page_secrets(&mut conn, user, &query)
+        .await
+        .bad_request()?;
+    Ok(Json(page))
+}
+
+/// Test if the server can still decrypt a secret without revealing its content
+pub async fn check_secret(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(name): VerifiedJson<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+
+    let secret = db::secrets::secret_by_name(&mut conn, &name)
+        .await
+        .internal_server()?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            format!("Secret {name} does not exist"),
+        ))?;
+    db::tag::auth_tag(&mut conn, user, secret.into()).await?;
+
+    db::secrets::check_secret(&mut conn, secret, &*state.age_key)
+        .await
+        .map_err(|err| match err {
+            db::secrets::CheckSecretError::SecretNotFound => {
+                (StatusCode::NOT_FOUND, err.to_string())
+            }
+            db::secrets::CheckSecretError::Decrypt(_)
+            | db::secrets::CheckSecretError::Decompress(_)
+            | db::secrets::CheckSecretError::SQLXError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+        })?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Explain if a host could fetch a secret. Nothing is decrypted
+/// Only admins with `all_tag` learn that a secret or host does not exist. Everyone else gets the
+/// same 403 as for a secret or host they have no tag on
+pub async fn check_access(
+    State(state): State<YeetState>,
+    User(user): User,
+    Query(api::SecretAccessQuery { host, secret }): Query<api::SecretAccessQuery>,
+) -> Result<Json<api::SecretAccess>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+
+    let secret = db::secrets::secret_by_name(&mut conn, &secret)
+        .await
+        .internal_server()?;
+    let host = db::hosts::host_by_hostname(&mut conn, &host)
+        .await
+        .internal_server()?;
+    match secret {
+        Some(secret) => db::tag::auth_tag(&mut conn, user, secret.into()).await?,
+        None => db::tag::auth_all_tag(&mut conn, user).await?,
+    }
+    match host {
+        Some(host) => db::tag::auth_tag(&mut conn, user, host.into()).await?,
+        None => db::tag::auth_all_tag(&mut conn, user).await?,
+    }
+
+    let Some(secret) = secret else {
+        return Ok(Json(api::SecretAccess::denied("secret does not exist")));
+    };
+    let Some(host) = host else {
+        return Ok(Json(api::SecretAccess::denied("host not enrolled")));
+    };
+
+    Ok(Json(
+        db::secrets::access_for(&mut conn, secret, host)
+            .await
+            .internal_server()?,
+    ))
+}
+
+/// Look up a secret like `host` would with `/secret` and return its plaintext.
+/// The acl of the host applies. Only admins with `all_tag` may do this and only if the server
+/// was started with `YEET_SECRET_PEEK`. Every attempt is audited
+pub async fn secret_as_host(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(api::SecretAccessQuery { host, secret }): VerifiedJson<api::SecretAccessQuery>,
+) -> Result<Json<api::SecretLookup>, (StatusCode, String)> {
+    if !state.secret_peek {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Reading secrets as a host is disabled. Start the server with YEET_SECRET_PEEK=true"
+                .to_owned(),
+        ));
+    }
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+    db::tag::auth_all_tag(&mut conn, user).await?;
+
+    let host_id = db::hosts::host_by_hostname(&mut conn, &host)
+        .await
+        .internal_server()?
+        .ok_or((StatusCode::NOT_FOUND, format!("Host {host} does not exist")))?;
+
+    // the host would receive the secret encrypted for its recipient. Use a throwaway one instead
+    let identity = age::x25519::Identity::generate();
+    let lookup = db::secrets::get_secret_for(
+        &mut conn,
+        &secret,
+        &*state.age_key,
+        host_id,
+        &identity.to_public(),
+    )
+    .await
+    .map_err(|err| match err {
+        db::secrets::GetSecretError::SQLX(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+        }
+        db::secrets::GetSecretError::Decrypt(_)
+        | db::secrets::GetSecretError::Decompress(_)
+        | db::secrets::GetSecretError::Encrypt(_) => (StatusCode::BAD_REQUEST, err.to_string()),
+    });
+    let revealed = lookup.and_then(|lookup| match lookup {
+        api::SecretLookup::Found {
+            content,
+            content_type,
+        } => {
+            // sealed secrets are encrypted for the host and the server can not open them
+            let content = age::decrypt(&identity, &content).map_err(|_err| {
+                (
+                    StatusCode::CONFLICT,
+                    format!("Secret {secret} is sealed to {host}. Only the host can decrypt it"),
+                )
+            })?;
+            Ok(api::SecretLookup::Found {
+                content,
+                content_type,
+            })
+        }
+        other @ (api::SecretLookup::NotFound | api::SecretLookup::NoAccess) => Ok(other),
+    });
+
+    // only audited once it is known whether the plaintext leaves the server
+    let outcome = match &revealed {
+        Ok(api::SecretLookup::NotFound) => "not found".to_owned(),
+        Ok(api::SecretLookup::NoAccess) => "no access".to_owned(),
+        Ok(api::SecretLookup::Found { .. }) => "revealed".to_owned(),
+        Err((code, _)) => format!("not revealed ({code})"),
+    };
+    log::warn!("Secret {secret} was read as host {host} by user {user}: {outcome}");
+    db::audit::append(
+        &mut conn,
+        user,
+        "Secret::Peek",
+        &format!("PLAINTEXT secret {secret} as host {host}: {outcome}"),
+    )
+    .await
+    .internal_server()?;
+
+    revealed.map(Json)
+}
+
+/// The acl of a single secret. Hosts the user can not see are left out
+pub async fn get_acl_by_secret(
+    State(state): State<YeetState>,
+    User(user): User,
+    Query(api::SecretAclQuery { secret }): Query<api::SecretAclQuery>,
+) -> Result<Json<Vec<api::HostID>>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+
+    let secret = db::secrets::secret_by_name(&mut conn, &secret)
+        .await
+        .internal_server()?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            format!("Secret {secret} does not exist"),
+        ))?;
+    db::tag::auth_tag(&mut conn, user, secret.into()).await?;
+
+    Ok(Json(
+        db::secrets::acl_by_secret(&mut conn, user, secret)
+            .await
+            .internal_server()?,
+    ))
+}
+
+/// Sends `api::recipient_version` as `ETag` so clients can cache the recipient.
+/// Answers `304 Not Modified` without a body if `If-None-Match` still matches
 pub async fn get_server_age_key(
     State(state): State<YeetState>,
     HttpSig(_key): HttpSig,
-) -> Json<String> {
-    Json(state.age_key.to_public().to_string())
+    headers: HeaderMap,
+) -> Response {
+    let recipient = state.age_key.recipient();
+    let version = api::recipient_version(&recipient);
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|cached| cached.as_bytes() == version.as_bytes())
+    {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, version)]).into_response();
+    }
+    ([(header::ETAG, version)], Json(recipient)).into_response()
 }
 
 pub async fn get_secret(
     State(state): State<YeetState>,
-    // can't use user because these are hosts TODO: maybe add a HOST extractor
-    HttpSig(key): HttpSig,
+    // The host is derived from the signature. The request body can not name another host
+    Host(host): Host,
     VerifiedJson(api::GetSecretRequest { secret, recipient }): VerifiedJson<api::GetSecretRequest>,
-) -> Result<Json<Option<Vec<u8>>>, (StatusCode, String)> {
+) -> Result<Json<api::SecretLookup>, (StatusCode, String)> {
     let mut conn = state
         .pool
         .acquire()
         .await
         .with_code(StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    let Some(host) = db::hosts::host_by_verify_key(&mut conn, key)
-        .await
-        .internal_server()?
-    else {
-        return Err((
-            StatusCode::FORBIDDEN,
-            "Unknown keyid. You are not a registered host".to_owned(),
-        ));
-    };
-
     let recipient =
         age::x25519::Recipient::from_str(&recipient).with_code(StatusCode::BAD_REQUEST)?;
+    db::hosts::check_recipient(&mut conn, host, &recipient)
+        .await
+        .map_err(|err| match err {
+            db::hosts::RecipientError::NotEnrolled | db::hosts::RecipientError::Mismatch => {
+                (StatusCode::FORBIDDEN, err.to_string())
+            }
+            db::hosts::RecipientError::SQLXError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+        })?;
 
     let secret = db::secrets::get_secret_for(&mut conn, &secret, &*state.age_key, host, &recipient)
         .await
@@ -0,0 +1,36 @@
+use axum::{Json, extract::State, http::StatusCode};
+
+use crate::{
+    YeetState, db,
+    error::InternalError as _,
+    httpsig::{HttpSig, VerifiedJson},
+};
+
+/// Resolve the host a verifying key is enrolled as.
+///
+/// A freshly enrolled agent only knows its own key. Calling this with that key tells it the
+/// hostname the admin assigned when accepting the verification attempt, so the agent does not
+/// have to store the name separately.
+///
+/// Only the key the request is signed with can be looked up, otherwise anyone with a
+/// registered key could enumerate hostnames. Returns `None` if the key is not enrolled as a host
+pub async fn hosts_by_key(
+    State(state): State<YeetState>,
+    HttpSig(caller): HttpSig,
+    VerifiedJson(api::HostByKeyRequest { key }): VerifiedJson<api::HostByKeyRequest>,
+) -> Result<Json<Option<api::HostInfo>>, (StatusCode, String)> {
+    if key != caller {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Only the key the request is signed with can be looked up".to_owned(),
+        ));
+    }
+
+    let mut conn = state.pool.acquire().await.internal_server()?;
+
+    Ok(Json(
+        db::hosts::host_info_by_key(&mut conn, key)
+            .await
+            .internal_server()?,
+    ))
+}
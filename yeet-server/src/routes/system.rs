@@ -1,9 +1,15 @@
-use axum::{Json, extract::State, http::StatusCode};
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode},
+};
+use sqlx::Acquire as _;
 
 use crate::{
     YeetState, db,
     error::InternalError as _,
-    httpsig::{HttpSig, VerifiedJson},
+    httpsig::{Host, User, VerifiedJson},
+    webhook,
 };
 
 /// This is the "ping" command every client should send in a specific interval.
@@ -14,6 +20,8 @@ use crate::{
 ///
 /// `host.last_ping` = `Zoned::now`
 ///
+/// `host.soaking` = `soaking`
+///
 /// ====== if `host.provision_state` == Provisioned
 ///
 /// # this is the path when the client did the update
@@ -39,49 +47,69 @@ use crate::{
 ///
 /// ====== if `host.provision_state` == `NotSet`
 /// -> Nothing
+///
+/// A `store_path` of `api::NOT_ACTIVATED` is never stored. The host is treated as behind
+///
+/// `api::SECRETS_UPDATED_HEADER` is set if the secrets the host can fetch changed since its last check
 pub async fn system_check(
     State(state): State<YeetState>,
-    HttpSig(key): HttpSig,
-    VerifiedJson(api::VersionRequest { store_path }): VerifiedJson<api::VersionRequest>,
-) -> Result<Json<api::AgentAction>, (StatusCode, String)> {
+    Host(host): Host,
+    VerifiedJson(api::VersionRequest {
+        store_path,
+        soaking,
+    }): VerifiedJson<api::VersionRequest>,
+) -> Result<(HeaderMap, Json<api::AgentAction>), (StatusCode, String)> {
     let mut conn = state.pool.acquire().await.internal_server()?;
 
-    let Some(host) = db::hosts::host_by_verify_key(&mut conn, key)
+    db::hosts::ping(&mut conn, host).await.internal_server()?;
+    db::hosts::set_soaking(&mut conn, host, soaking.as_ref())
         .await
-        .internal_server()?
-    else {
-        return Err((
-            StatusCode::FORBIDDEN,
-            "Unknown keyid. You are not a registered host".to_owned(),
-        ));
-    };
+        .internal_server()?;
 
-    db::hosts::ping(&mut conn, host).await.internal_server()?;
+    let mut headers = HeaderMap::new();
+    if db::secrets::secrets_changed(&mut conn, host)
+        .await
+        .internal_server()?
+    {
+        headers.insert(
+            api::SECRETS_UPDATED_HEADER,
+            HeaderValue::from_static("true"),
+        );
+    }
 
     let state = db::hosts::fetch_provision_state(&mut conn, host)
         .await
         .internal_server()?;
 
+    // a host that has not activated anything yet has no version to record
+    let store_path = (store_path != api::NOT_ACTIVATED).then_some(store_path);
+
     let action = match state {
         api::ProvisionState::NotSet => api::AgentAction::Nothing,
         // Host is detached -> only updated the latest version
         api::ProvisionState::Detached => {
-            db::hosts::update_current_version(&mut conn, host, store_path)
-                .await
-                .internal_server()?;
+            if let Some(store_path) = store_path {
+                db::hosts::update_current_version(&mut conn, host, store_path)
+                    .await
+                    .internal_server()?;
+            }
             api::AgentAction::Detach
         }
 
         api::ProvisionState::Provisioned => {
             // first update the current version that is stored for the host
-            db::hosts::update_current_version(&mut conn, host, store_path)
-                .await
-                .internal_server()?;
+            let update = if let Some(store_path) = store_path {
+                db::hosts::update_current_version(&mut conn, host, store_path)
+                    .await
+                    .internal_server()?;
 
-            // let see if there is still an update available
-            let update = db::hosts::fetch_available_update(&mut conn, host)
-                .await
-                .internal_server()?;
+                // let see if there is still an update available
+                db::hosts::fetch_available_update(&mut conn, host).await
+            } else {
+                // the recorded version might be from before a reinstall
+                db::hosts::fetch_latest_update(&mut conn, host).await
+            }
+            .internal_server()?;
 
             match update {
                 Some(update) => api::AgentAction::SwitchTo(update),
@@ -90,54 +118,150 @@ pub async fn system_check(
         }
     };
 
-    Ok(Json(action))
+    Ok((headers, Json(action)))
 }
 
-// TODO: currently there are no detach permissions
-// all hosts are allowed to detach
-// /// Inquire if you (current system) are allowed to detach your own system
-// /// If you are an admin and want to see if hosts are allowed to detach, use the hosts api
-// pub async fn is_detach_allowed(
-//     State(state): State<Arc<RwLock<AppState>>>,
-//     HttpSig(key): HttpSig,
-// ) -> Result<Json<bool>, StateError> {
-//     let state = state.read_arc();
-//     Ok(Json(state.is_detach_allowed(&key)?))
-// }
-
-// /// Set the detach permission either Global or PerHost. PerHost will always take over the global setting
-// pub async fn set_detach_permission(
-//     State(state): State<Arc<RwLock<AppState>>>,
-//     HttpSig(key): HttpSig,
-//     VerifiedJson(set_detach): VerifiedJson<api::SetDetachPermission>,
-// ) -> Result<StatusCode, StateError> {
-//     let mut state = state.write_arc();
-//     state.auth_admin(&key)?;
-
-//     match set_detach {
-//         api::SetDetachPermission::Global(allowed) => state.set_global_detach_permission(allowed),
-//         api::SetDetachPermission::PerHost(items) => state.set_detach_permissions(items),
-//     }
-
-//     Ok(StatusCode::OK)
-// }
+/// Outcome of the last activation. A successful one also updates the current version
+pub async fn report(
+    State(state): State<YeetState>,
+    Host(host): Host,
+    VerifiedJson(report): VerifiedJson<api::ActivationReport>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+
+    if report.success {
+        db::hosts::update_current_version(&mut conn, host, report.store_path.clone())
+            .await
+            .internal_server()?;
+    }
+    db::hosts::add_activation_report(&mut conn, host, report.clone())
+        .await
+        .internal_server()?;
+
+    if state.webhook_sender.is_some() {
+        let hostname = db::hosts::fetch_hostname(&mut conn, host)
+            .await
+            .internal_server()?
+            .unwrap_or_default();
+        let event = if report.success {
+            webhook::Event::HostActivated {
+                host,
+                hostname,
+                store_path: report.store_path,
+            }
+        } else {
+            webhook::Event::HostFailed {
+                host,
+                hostname,
+                store_path: report.store_path,
+                error: report.error,
+            }
+        };
+        crate::notify_webhooks(state.webhook_sender.as_ref(), event).await;
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Sizes of the update the host just downloaded
+pub async fn download_stats(
+    State(state): State<YeetState>,
+    Host(host): Host,
+    VerifiedJson(download): VerifiedJson<api::DownloadStats>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+
+    db::hosts::add_download_stats(&mut conn, host, download)
+        .await
+        .internal_server()?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Replaces the nixos-facter output the host enrolled with
+pub async fn facter(
+    State(state): State<YeetState>,
+    Host(host): Host,
+    VerifiedJson(nixos_facter): VerifiedJson<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+
+    db::hosts::set_facter(&mut conn, host, &nixos_facter)
+        .await
+        .internal_server()?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Inquire if you (current system) are allowed to detach your own system
+pub async fn detach_permission(
+    State(state): State<YeetState>,
+    Host(host): Host,
+) -> Result<Json<api::DetachPermission>, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+
+    let permission = db::hosts::detach_permission(&mut conn, host)
+        .await
+        .internal_server()?;
+
+    Ok(Json(permission))
+}
+
+/// Set the detach permission either Global or for a host. A host can only be granted the
+/// permission on top of the global one
+pub async fn set_detach_permission(
+    State(state): State<YeetState>,
+    User(user): User,
+    VerifiedJson(permission): VerifiedJson<api::SetDetachPermission>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.pool.acquire().await.internal_server()?;
+    db::tag::auth_admin(&mut conn, user).await?;
+
+    let mut tx = conn.begin().await.internal_server()?;
+    let detail = match permission {
+        api::SetDetachPermission::Global(allowed) => {
+            db::tag::auth_all_tag(&mut tx, user).await?;
+            db::hosts::set_global_detach(&mut tx, allowed)
+                .await
+                .internal_server()?;
+            format!("global to {allowed}")
+        }
+        api::SetDetachPermission::Host { host, allowed } => {
+            db::tag::auth_tag(&mut tx, user, host.into()).await?;
+            db::hosts::set_host_detach(&mut tx, host, allowed)
+                .await
+                .internal_server()?;
+            match allowed {
+                Some(allowed) => format!("host {host} to {allowed}"),
+                None => format!("host {host} to the global permission"),
+            }
+        }
+    };
+    db::audit::append(&mut tx, user, "Host::DetachPermission", &detail)
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
+
+    Ok(StatusCode::OK)
+}
 
 /// Detach self
 pub async fn detach(
     State(state): State<YeetState>,
-    HttpSig(key): HttpSig,
+    Host(host): Host,
 ) -> Result<StatusCode, (StatusCode, String)> {
     let mut conn = state.pool.acquire().await.internal_server()?;
 
-    let Some(host) = db::hosts::host_by_verify_key(&mut conn, key)
+    let permission = db::hosts::detach_permission(&mut conn, host)
         .await
-        .internal_server()?
-    else {
+        .internal_server()?;
+    if !permission.effective {
         return Err((
             StatusCode::FORBIDDEN,
-            "Unknown keyid. You are not a registered host".to_owned(),
+            "This host is not allowed to detach".to_owned(),
         ));
-    };
+    }
+
     db::hosts::set_provision_state(&mut conn, host, api::ProvisionState::Detached)
         .await
         .internal_server()?;
@@ -147,19 +271,10 @@ pub async fn detach(
 /// Attach self
 pub async fn attach(
     State(state): State<YeetState>,
-    HttpSig(key): HttpSig,
+    Host(host): Host,
 ) -> Result<StatusCode, (StatusCode, String)> {
     let mut conn = state.pool.acquire().await.internal_server()?;
 
-    let Some(host) = db::hosts::host_by_verify_key(&mut conn, key)
-        .await
-        .internal_server()?
-    else {
-        return Err((
-            StatusCode::FORBIDDEN,
-            "Unknown keyid. You are not a registered host".to_owned(),
-        ));
-    };
     db::hosts::set_provision_state(&mut conn, host, api::ProvisionState::Provisioned)
         .await
         .internal_server()?;
@@ -3,6 +3,7 @@ use axum::{
     extract::{Path, State},
     http::StatusCode,
 };
+use sqlx::Acquire as _;
 
 use crate::{
     YeetState, db,
@@ -19,9 +20,15 @@ pub async fn create_tag(
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
 
-    Ok(Json(
-        db::tag::create_tag(&mut conn, name).await.bad_request()?,
-    ))
+    let mut tx = conn.begin().await.internal_server()?;
+    let tag = db::tag::create_tag(&mut tx, name.clone())
+        .await
+        .bad_request()?;
+    db::audit::append(&mut tx, user, "Tag::Create", &format!("tag {tag}: {name}"))
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
+    Ok(Json(tag))
 }
 
 pub async fn rename_tag(
@@ -32,9 +39,15 @@ pub async fn rename_tag(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
-    db::tag::rename_tag(&mut conn, tag, name)
+    let mut tx = conn.begin().await.internal_server()?;
+    let detail = format!("tag {tag} to {name}");
+    db::tag::rename_tag(&mut tx, tag, name)
         .await
         .bad_request()?;
+    db::audit::append(&mut tx, user, "Tag::Rename", &detail)
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
@@ -47,7 +60,12 @@ pub async fn delete_tag(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
-    db::tag::delete_tag(&mut conn, tag).await.bad_request()?;
+    let mut tx = conn.begin().await.internal_server()?;
+    db::tag::delete_tag(&mut tx, tag).await.bad_request()?;
+    db::audit::append(&mut tx, user, "Tag::Delete", &format!("tag {tag}"))
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
@@ -60,9 +78,19 @@ pub async fn allow_user(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
-    db::tag::allow_user_on_tag(&mut conn, user_id, tag)
+    let mut tx = conn.begin().await.internal_server()?;
+    db::tag::allow_user_on_tag(&mut tx, user_id, tag)
         .await
         .bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Tag::Allow",
+        &format!("user {user_id} on tag {tag}"),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
@@ -75,9 +103,19 @@ pub async fn remove_user(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
-    db::tag::remove_user_from_tag(&mut conn, user_id, tag)
+    let mut tx = conn.begin().await.internal_server()?;
+    db::tag::remove_user_from_tag(&mut tx, user_id, tag)
         .await
         .bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Tag::Remove",
+        &format!("user {user_id} from tag {tag}"),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
@@ -90,9 +128,19 @@ pub async fn add_resource_tag(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
-    db::tag::add_resource_to_tag(&mut conn, resource, tag)
+    let mut tx = conn.begin().await.internal_server()?;
+    db::tag::add_resource_to_tag(&mut tx, resource, tag)
         .await
         .bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Tag::Edit",
+        &format!("added {} to tag {tag}", describe(resource)),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
@@ -105,13 +153,30 @@ pub async fn delete_resource_tag(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
-    db::tag::delete_resource_from_tag(&mut conn, resource, tag)
+    let mut tx = conn.begin().await.internal_server()?;
+    db::tag::delete_resource_from_tag(&mut tx, resource, tag)
         .await
         .bad_request()?;
+    db::audit::append(
+        &mut tx,
+        user,
+        "Tag::Edit",
+        &format!("removed {} from tag {tag}", describe(resource)),
+    )
+    .await
+    .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
 
+fn describe(resource: api::tag::Resource) -> String {
+    match resource {
+        api::tag::Resource::Host(host) => format!("host {host}"),
+        api::tag::Resource::Secret(secret) => format!("secret {secret}"),
+    }
+}
+
 pub async fn list_tags(
     State(state): State<YeetState>,
     User(user): User,
@@ -4,6 +4,7 @@ use axum::{
     http::StatusCode,
 };
 use httpsig_hyper::prelude::VerifyingKey as _;
+use sqlx::Acquire as _;
 
 use crate::{
     YeetState, db,
@@ -24,19 +25,25 @@ pub async fn create_user(
     let mut conn = state.pool.acquire().await.internal_server()?;
 
     // If we do not have any credentials yet we want to allow adding the first key
-    if db::keys::has_any_admin(&mut conn).await.internal_server()? {
+    let creator = if db::keys::has_any_admin(&mut conn).await.internal_server()? {
         let Some(user) = db::user::fetch_by_key(&mut conn, http_key)
             .await
             .internal_server()?
         else {
             return Err((
                 StatusCode::FORBIDDEN,
-                "Key is registered but caller is not an user".to_owned(),
+                format!(
+                    "Key {} is registered but caller is not an user",
+                    api::key_fingerprint(&http_key)
+                ),
             ));
         };
         db::tag::auth_admin(&mut conn, user).await?;
         db::tag::auth_all_tag(&mut conn, user).await?;
-    }
+        Some(user)
+    } else {
+        None
+    };
 
     let httpsig_key = httpsig_hyper::prelude::PublicKey::from_bytes(
         &httpsig_hyper::prelude::AlgorithmName::Ed25519,
@@ -44,18 +51,24 @@ pub async fn create_user(
     )
     .expect("Verifying key already is validated");
 
-    Ok(Json(
-        db::user::create_user(
-            &mut conn,
-            httpsig_key.key_id(),
-            key,
-            username,
-            level,
-            all_tags,
-        )
+    let mut tx = conn.begin().await.internal_server()?;
+    let detail = format!("{username} ({level:?})");
+    let id = db::user::create_user(
+        &mut tx,
+        httpsig_key.key_id(),
+        key,
+        username,
+        level,
+        all_tags,
+    )
+    .await
+    .bad_request()?;
+    // the first admin creates themself
+    db::audit::append(&mut tx, creator.unwrap_or(id), "User::Create", &detail)
         .await
-        .bad_request()?,
-    ))
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
+    Ok(Json(id))
 }
 
 pub async fn rename_user(
@@ -66,9 +79,15 @@ pub async fn rename_user(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
-    db::user::rename_user(&mut conn, user_id, name)
+    let mut tx = conn.begin().await.internal_server()?;
+    let detail = format!("user {user_id} to {name}");
+    db::user::rename_user(&mut tx, user_id, name)
         .await
         .bad_request()?;
+    db::audit::append(&mut tx, user, "User::Rename", &detail)
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(StatusCode::OK)
 }
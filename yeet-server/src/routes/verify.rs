@@ -8,14 +8,18 @@
 /// or via config. An other solution would be that when you run `yeet approve` and input the clients
 /// one time pin that you also have to input the hostname that it should be associated with.
 ///
+use std::str::FromStr as _;
+
 use axum::{
     Json,
     extract::{Path, State},
     http::StatusCode,
 };
+use sqlx::Acquire as _;
 
 use crate::{
-    YeetState, db,
+    YeetState,
+    db::{self, verification::AddVerificationError},
     error::{BadRequest as _, InternalError as _},
     httpsig::{HttpSig, User, VerifiedJson},
 };
@@ -34,10 +38,29 @@ pub async fn add_verification_attempt(
     // Altough this is not a security risk because even if you create an foreign attempt still only the key holder get authorized
     let mut conn = state.pool.acquire().await.internal_server()?;
 
-    let code =
-        db::verification::add_verification_attempt(&mut conn, attempt.key, attempt.nixos_facter)
-            .await
-            .bad_request()?;
+    // store the canonical form so that it can be compared when fetching secrets
+    let recipient = attempt
+        .recipient
+        .map(|recipient| {
+            age::x25519::Recipient::from_str(&recipient).map(|parsed| parsed.to_string())
+        })
+        .transpose()
+        .bad_request()?;
+
+    let code = db::verification::add_verification_attempt(
+        &mut conn,
+        attempt.key,
+        attempt.nixos_facter,
+        recipient,
+    )
+    .await
+    .map_err(|err| match err {
+        // Lets the agent know that its previous attempt is still valid
+        AddVerificationError::KeyPendingVerification => (StatusCode::CONFLICT, err.to_string()),
+        AddVerificationError::KeyAlreadyInUse
+        | AddVerificationError::TooManyAttempts
+        | AddVerificationError::SQLXError(_) => (StatusCode::BAD_REQUEST, err.to_string()),
+    })?;
 
     Ok(Json(code))
 }
@@ -52,11 +75,18 @@ pub async fn accept_attempt(
     let mut conn = state.pool.acquire().await.internal_server()?;
     db::tag::auth_admin(&mut conn, user).await?;
     db::tag::auth_all_tag(&mut conn, user).await?;
+    state.hostnames.check(&hostname).bad_request()?;
 
     // TODO: return Bad request if key does not exist
-    let facter = db::verification::accept_attempt(&mut conn, i64::from(id), hostname)
+    let mut tx = conn.begin().await.internal_server()?;
+    let detail = format!("attempt {id} as {hostname}");
+    let facter = db::verification::accept_attempt(&mut tx, i64::from(id), hostname)
         .await
         .bad_request()?;
+    db::audit::append(&mut tx, user, "Verification::Accept", &detail)
+        .await
+        .internal_server()?;
+    tx.commit().await.internal_server()?;
 
     Ok(Json(facter))
 }
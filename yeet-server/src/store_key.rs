@@ -0,0 +1,175 @@
+//! The store key encrypts all secrets at rest (see `db::secrets`)
+//! By default this is a x25519 identity stored in `age.key`.
+//! With the `age-plugin` feature the key can also live in an age plugin (e.g. `age-plugin-yubikey`)
+//! Replicas with their own store keys share secrets through `StoreKeys`
+
+/// Backend of the key that encrypts all secrets at rest
+pub trait StoreKey: Send + Sync {
+    /// Recipient clients use to encrypt new secrets. Served via GET `/secret/server_key`
+    fn recipient(&self) -> String;
+
+    /// Decrypt a secret that was encrypted for `recipient`
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, age::DecryptError>;
+
+    /// Every recipient a stored secret has to be encrypted for
+    fn recipients(&self) -> Vec<String> {
+        vec![self.recipient()]
+    }
+}
+
+impl StoreKey for age::x25519::Identity {
+    fn recipient(&self) -> String {
+        self.to_public().to_string()
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, age::DecryptError> {
+        age::decrypt(self, ciphertext)
+    }
+}
+
+impl<K: StoreKey + ?Sized> StoreKey for Box<K> {
+    fn recipient(&self) -> String {
+        (**self).recipient()
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, age::DecryptError> {
+        (**self).decrypt(ciphertext)
+    }
+
+    fn recipients(&self) -> Vec<String> {
+        (**self).recipients()
+    }
+}
+
+/// Store keys of a server that runs next to other replicas.
+/// Secrets are encrypted for the recipients of all replicas so that each of them can serve them.
+/// Decryption also tries the retired identities of this replica e.g. an old key after a key rotation
+pub struct StoreKeys {
+    primary: Box<dyn StoreKey>,
+    identities: Vec<Box<dyn StoreKey>>,
+    replicas: Vec<String>,
+}
+
+impl StoreKeys {
+    /// `primary` is the recipient served via GET `/secret/server_key`
+    #[must_use]
+    pub fn new(primary: Box<dyn StoreKey>) -> Self {
+        Self {
+            primary,
+            identities: Vec::new(),
+            replicas: Vec::new(),
+        }
+    }
+
+    /// A retired identity of this replica. It only decrypts, new secrets are not encrypted for it
+    #[must_use]
+    pub fn with_identity(mut self, identity: Box<dyn StoreKey>) -> Self {
+        self.identities.push(identity);
+        self
+    }
+
+    /// Recipient of another replica. This replica can not decrypt secrets only sealed for it
+    #[must_use]
+    pub fn with_replica(mut self, recipient: String) -> Self {
+        self.replicas.push(recipient);
+        self
+    }
+}
+
+impl StoreKey for StoreKeys {
+    fn recipient(&self) -> String {
+        self.primary.recipient()
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, age::DecryptError> {
+        let mut err = match self.primary.decrypt(ciphertext) {
+            Ok(plaintext) => return Ok(plaintext),
+            Err(err) => err,
+        };
+        for identity in &self.identities {
+            match identity.decrypt(ciphertext) {
+                Ok(plaintext) => return Ok(plaintext),
+                Err(next) => err = next,
+            }
+        }
+        Err(err)
+    }
+
+    fn recipients(&self) -> Vec<String> {
+        let mut recipients = self.primary.recipients();
+        for recipient in self.replicas.iter().cloned() {
+            if !recipients.contains(&recipient) {
+                recipients.push(recipient);
+            }
+        }
+        recipients
+    }
+}
+
+#[cfg(feature = "age-plugin")]
+error_set::error_set! {
+    PluginStoreKeyError := {
+        #[display("Could not parse the plugin identity: {reason}")]
+        Identity { reason: String },
+        #[display("Could not parse the plugin recipient: {reason}")]
+        Recipient { reason: String },
+        #[display("Identity is for plugin `{identity}` but recipient is for plugin `{recipient}`")]
+        PluginMismatch { identity: String, recipient: String },
+        Plugin(age::DecryptError),
+    }
+}
+
+/// Store key that delegates decryption to an age plugin binary (`age-plugin-<name>` in `$PATH`)
+/// The server has no way to interact with the plugin. Hardware keys have to be set up so
+/// that they do not require a PIN or touch for every decryption
+#[cfg(feature = "age-plugin")]
+pub struct PluginStoreKey {
+    identity: age::plugin::IdentityPluginV1<age::NoCallbacks>,
+    recipient: age::plugin::Recipient,
+}
+
+#[cfg(feature = "age-plugin")]
+impl PluginStoreKey {
+    /// `identity` is the `AGE-PLUGIN-...` identity and `recipient` the matching `age1<plugin>1...` recipient
+    pub fn new(identity: &str, recipient: &str) -> Result<Self, PluginStoreKeyError> {
+        use std::str::FromStr as _;
+
+        let identity = age::plugin::Identity::from_str(identity).map_err(|reason| {
+            PluginStoreKeyError::Identity {
+                reason: reason.to_owned(),
+            }
+        })?;
+        let recipient = age::plugin::Recipient::from_str(recipient).map_err(|reason| {
+            PluginStoreKeyError::Recipient {
+                reason: reason.to_owned(),
+            }
+        })?;
+
+        if identity.plugin() != recipient.plugin() {
+            return Err(PluginStoreKeyError::PluginMismatch {
+                identity: identity.plugin().to_owned(),
+                recipient: recipient.plugin().to_owned(),
+            });
+        }
+
+        Ok(Self {
+            identity: age::plugin::IdentityPluginV1::new(
+                identity.plugin(),
+                std::slice::from_ref(&identity),
+                age::NoCallbacks,
+            )?,
+            recipient,
+        })
+    }
+}
+
+#[cfg(feature = "age-plugin")]
+impl StoreKey for PluginStoreKey {
+    fn recipient(&self) -> String {
+        self.recipient.to_string()
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, age::DecryptError> {
+        age::decrypt(&self.identity, ciphertext)
+    }
+}
@@ -0,0 +1,357 @@
+//! POSTs host and secret events to the webhooks listed in `YEET_WEBHOOKS`
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use hmac::{Hmac, Mac as _};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::db;
+
+/// `sha256=<hex>` HMAC of the body. Only sent to webhooks with a `secret`
+pub const SIGNATURE_HEADER: &str = "X-Yeet-Signature";
+
+/// How often the last pings are checked for `HostStale`
+const STALE_CHECK: Duration = Duration::from_mins(1);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebhookEvent {
+    HostActivated,
+    HostFailed,
+    HostStale,
+    SecretUpdated,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WebhookConfig {
+    pub url: url::Url,
+    pub events: Vec<WebhookEvent>,
+    /// Signs every delivery with [`SIGNATURE_HEADER`]
+    pub secret: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Webhooks {
+    pub hooks: Vec<WebhookConfig>,
+    /// Per delivery
+    pub timeout: Duration,
+    /// Hosts without a ping for this long are `HostStale`
+    pub stale_after: Duration,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Self {
+            hooks: Vec::new(),
+            timeout: Duration::from_secs(5),
+            stale_after: Duration::from_hours(1),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "event")]
+pub enum Event {
+    HostActivated {
+        host: api::HostID,
+        hostname: String,
+        store_path: api::StorePath,
+    },
+    HostFailed {
+        host: api::HostID,
+        hostname: String,
+        store_path: api::StorePath,
+        error: Option<String>,
+    },
+    /// Sent once until the host pings again
+    HostStale {
+        host: api::HostID,
+        hostname: String,
+        last_ping: jiff::Timestamp,
+    },
+    /// The secret was created or rotated
+    SecretUpdated { secret: api::SecretID },
+}
+
+impl Event {
+    fn kind(&self) -> WebhookEvent {
+        match self {
+            Self::HostActivated { .. } => WebhookEvent::HostActivated,
+            Self::HostFailed { .. } => WebhookEvent::HostFailed,
+            Self::HostStale { .. } => WebhookEvent::HostStale,
+            Self::SecretUpdated { .. } => WebhookEvent::SecretUpdated,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'event> {
+    #[serde(flatten)]
+    event: &'event Event,
+    time: jiff::Timestamp,
+}
+
+pub async fn run(
+    webhooks: Webhooks,
+    mut receiver: tokio::sync::mpsc::Receiver<Event>,
+    pool: sqlx::SqlitePool,
+) -> Result<(), reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .timeout(webhooks.timeout)
+        .build()?;
+    let hooks: Arc<[WebhookConfig]> = webhooks.hooks.into();
+
+    if hooks
+        .iter()
+        .any(|hook| hook.events.contains(&WebhookEvent::HostStale))
+    {
+        let client = client.clone();
+        let hooks = Arc::clone(&hooks);
+        let _detached = tokio::spawn(async move {
+            watch_stale(&client, &hooks, &pool, webhooks.stale_after).await;
+        });
+    }
+
+    while let Some(event) = receiver.recv().await {
+        deliver(&client, &hooks, &event);
+    }
+    Ok(())
+}
+
+#[expect(clippy::infinite_loop, reason = "runs as long as the server")]
+async fn watch_stale(
+    client: &reqwest::Client,
+    hooks: &[WebhookConfig],
+    pool: &sqlx::SqlitePool,
+    stale_after: Duration,
+) {
+    let mut reported = HashSet::new();
+    let mut stale_check = tokio::time::interval(STALE_CHECK);
+    loop {
+        stale_check.tick().await;
+        let last_pings = match pool.acquire().await {
+            Ok(mut conn) => db::hosts::last_pings(&mut conn).await,
+            Err(err) => Err(err),
+        };
+        let last_pings = match last_pings {
+            Ok(last_pings) => last_pings,
+            Err(err) => {
+                log::error!("Could not check for stale hosts: {err}");
+                continue;
+            }
+        };
+        let cutoff = jiff::Timestamp::now()
+            .checked_sub(stale_after)
+            .unwrap_or(jiff::Timestamp::MIN);
+        for event in newly_stale(&mut reported, last_pings, cutoff) {
+            deliver(client, hooks, &event);
+        }
+    }
+}
+
+/// Every delivery runs detached. A slow webhook never holds up the others
+fn deliver(client: &reqwest::Client, hooks: &[WebhookConfig], event: &Event) {
+    let body = match serde_json::to_vec(&Payload {
+        event,
+        time: jiff::Timestamp::now(),
+    }) {
+        Ok(body) => body,
+        Err(err) => {
+            log::error!("Could not serialize webhook event: {err}");
+            return;
+        }
+    };
+
+    for hook in hooks
+        .iter()
+        .filter(|hook| hook.events.contains(&event.kind()))
+    {
+        let mut request = client
+            .post(hook.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(secret) = &hook.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &body));
+        }
+        let url = hook.url.clone();
+        let _detached = tokio::spawn(async move {
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => log::error!("Webhook {url} responded with {}", response.status()),
+                Err(err) => log::error!("Could not deliver webhook to {url}: {err}"),
+            }
+        });
+    }
+}
+
+#[expect(clippy::expect_used, reason = "HMAC takes keys of any length")]
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Hosts whose last ping is before `cutoff` and that were not reported yet.
+/// Hosts that pinged again are forgotten so that they are reported when they go stale again
+fn newly_stale(
+    reported: &mut HashSet<api::HostID>,
+    last_pings: Vec<(api::HostID, String, jiff::Timestamp)>,
+    cutoff: jiff::Timestamp,
+) -> Vec<Event> {
+    let stale = last_pings
+        .into_iter()
+        .filter(|(_, _, last_ping)| *last_ping < cutoff)
+        .collect::<Vec<_>>();
+    reported.retain(|reported| stale.iter().any(|(host, _, _)| host == reported));
+    stale
+        .into_iter()
+        .filter(|(host, _, _)| reported.insert(*host))
+        .map(|(host, hostname, last_ping)| Event::HostStale {
+            host,
+            hostname,
+            last_ping,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test_webhook {
+    use std::collections::HashSet;
+
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+    };
+    use tokio::sync::mpsc;
+
+    use super::{Event, SIGNATURE_HEADER, WebhookConfig, WebhookEvent, Webhooks};
+
+    #[test]
+    fn sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            super::sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn payload() {
+        let event = Event::HostFailed {
+            host: api::HostID::new(1),
+            hostname: "web".to_owned(),
+            store_path: "/nix/store/abc".to_owned(),
+            error: Some("activation failed".to_owned()),
+        };
+        let payload = serde_json::to_value(super::Payload {
+            event: &event,
+            time: jiff::Timestamp::UNIX_EPOCH,
+        })
+        .unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "event": "HostFailed",
+                "host": 1,
+                "hostname": "web",
+                "store_path": "/nix/store/abc",
+                "error": "activation failed",
+                "time": "1970-01-01T00:00:00Z"
+            })
+        );
+    }
+
+    #[test]
+    fn newly_stale() {
+        let web = api::HostID::new(1);
+        let db = api::HostID::new(2);
+        let old = jiff::Timestamp::UNIX_EPOCH;
+        let cutoff = jiff::Timestamp::from_second(100).unwrap();
+        let fresh = jiff::Timestamp::from_second(200).unwrap();
+        let mut reported = HashSet::new();
+
+        let stale = super::newly_stale(
+            &mut reported,
+            vec![(web, "web".to_owned(), old), (db, "db".to_owned(), fresh)],
+            cutoff,
+        );
+        assert_eq!(
+            stale,
+            [Event::HostStale {
+                host: web,
+                hostname: "web".to_owned(),
+                last_ping: old
+            }]
+        );
+
+        // reported once
+        let stale = super::newly_stale(
+            &mut reported,
+            vec![(web, "web".to_owned(), old), (db, "db".to_owned(), fresh)],
+            cutoff,
+        );
+        assert_eq!(stale, []);
+
+        // web pinged again and goes stale later
+        let stale = super::newly_stale(&mut reported, vec![(web, "web".to_owned(), fresh)], cutoff);
+        assert_eq!(stale, []);
+        let stale = super::newly_stale(&mut reported, vec![(web, "web".to_owned(), old)], cutoff);
+        assert_eq!(stale.len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn delivery(pool: sqlx::SqlitePool) {
+        let (received, mut deliveries) = mpsc::channel(5);
+        let hook = axum::Router::new()
+            .route(
+                "/hook",
+                post(
+                    async |State(received): State<mpsc::Sender<(HeaderMap, String)>>,
+                           headers: HeaderMap,
+                           body: String| {
+                        received.send((headers, body)).await.unwrap();
+                        StatusCode::OK
+                    },
+                ),
+            )
+            .with_state(received);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let _server = tokio::spawn(async move { axum::serve(listener, hook).await });
+
+        let (events, receiver) = mpsc::channel(5);
+        let webhooks = Webhooks {
+            hooks: vec![
+                WebhookConfig {
+                    url: url.parse().unwrap(),
+                    events: vec![WebhookEvent::HostStale],
+                    secret: None,
+                },
+                WebhookConfig {
+                    url: url.parse().unwrap(),
+                    events: vec![WebhookEvent::SecretUpdated],
+                    secret: Some("hunter2".to_owned()),
+                },
+            ],
+            ..Webhooks::default()
+        };
+        let _runner = tokio::spawn(super::run(webhooks, receiver, pool));
+
+        events
+            .send(Event::SecretUpdated {
+                secret: api::SecretID::new(7),
+            })
+            .await
+            .unwrap();
+        let (headers, body) = deliveries.recv().await.unwrap();
+        assert_eq!(
+            headers.get(SIGNATURE_HEADER).unwrap(),
+            super::sign("hunter2", body.as_bytes()).as_str()
+        );
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body.get("event").unwrap(), "SecretUpdated");
+        assert_eq!(body.get("secret").unwrap(), 7);
+    }
+}
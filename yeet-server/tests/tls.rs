@@ -0,0 +1,49 @@
+//! `tls/cert.pem` is a self-signed certificate for `localhost` valid for 100 years
+
+use axum_server::tls_rustls::RustlsConfig;
+
+const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/cert.pem");
+const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/key.pem");
+
+#[sqlx::test]
+fn health_over_tls(pool: sqlx::SqlitePool) {
+    let tls = RustlsConfig::from_pem_file(CERT, KEY).await.unwrap();
+    let _handle = yeetd::launch(
+        4350,
+        [std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
+        pool,
+        age::x25519::Identity::generate(),
+        Some(tls),
+        None,
+        None,
+        None,
+        yeetd::BodyLimits::default(),
+        yeetd::Lockout::default(),
+        false,
+        yeetd::hostname::HostnameRules::default(),
+        None,
+        yeetd::webhook::Webhooks::default(),
+        false,
+        false,
+    )
+    .await;
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+    let cert = reqwest::Certificate::from_pem(&std::fs::read(CERT).unwrap()).unwrap();
+    let client = reqwest::Client::builder()
+        .tls_certs_only([cert])
+        .build()
+        .unwrap();
+
+    let response = client
+        .get("https://localhost:4350/health")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // plain HTTP is not served next to TLS
+    reqwest::get("http://localhost:4350/health")
+        .await
+        .unwrap_err();
+}